@@ -29,9 +29,9 @@ use solana_sdk::{
 use spl_stake_pool::{
     instruction::{
         add_validator_stake_account, create_validator_stake_account, deposit,
-        initialize as initialize_pool, remove_validator_stake_account, set_owner,
-        set_staking_authority, update_list_balance, update_pool_balance, withdraw, Fee as PoolFee,
-        InitArgs as PoolInitArgs,
+        initialize as initialize_pool, remove_validator_stake_account, set_fee_manager, set_owner,
+        set_staking_authority, update_list_balance, update_pool_balance, with_depositor, withdraw,
+        Fee as PoolFee, InitArgs as PoolInitArgs,
     },
     processor::Processor as PoolProcessor,
     stake::authorize as authorize_stake,
@@ -60,7 +60,8 @@ type Error = Box<dyn std::error::Error>;
 type CommandResult = Result<Option<Transaction>, Error>;
 
 const STAKE_STATE_LEN: usize = 200;
-const MAX_ACCOUNTS_TO_UPDATE: usize = 10;
+// each validator takes two accounts, its stake and transient stake accounts
+const MAX_ACCOUNTS_TO_UPDATE: usize = 5;
 lazy_static! {
     static ref MIN_STAKE_BALANCE: u64 = sol_to_lamports(1.0);
 }
@@ -109,6 +110,47 @@ fn get_authority_accounts(config: &Config, authority: &Pubkey) -> Vec<(Pubkey, A
         .unwrap()
 }
 
+/// Stake pool record together with the fields kept in its extension account
+fn get_stake_pool(config: &Config, pool: &Pubkey) -> Result<StakePool, Error> {
+    let (extension, _) =
+        PoolProcessor::find_stake_pool_extension_address(&spl_stake_pool::id(), pool);
+    let pool_data = config.rpc_client.get_account_data(pool)?;
+    let extension_data = config.rpc_client.get_account_data(&extension)?;
+    Ok(StakePool::deserialize(
+        pool_data.as_slice(),
+        extension_data.as_slice(),
+    )?)
+}
+
+/// Validator stake list pages linked to the pool, in the pool order
+fn get_validator_stake_list_pages(
+    config: &Config,
+    pool_data: &StakePool,
+) -> Result<Vec<(Pubkey, ValidatorStakeList)>, Error> {
+    pool_data
+        .validator_stake_list_pages()
+        .map(|page| {
+            let page_data = config.rpc_client.get_account_data(page)?;
+            Ok((
+                *page,
+                ValidatorStakeList::deserialize(page_data.as_slice())?,
+            ))
+        })
+        .collect()
+}
+
+/// Validator stake list page holding `validator`
+fn find_validator_stake_list_page(
+    pages: &[(Pubkey, ValidatorStakeList)],
+    validator: &Pubkey,
+) -> Result<Pubkey, Error> {
+    pages
+        .iter()
+        .find(|(_, list)| list.contains(validator))
+        .map(|(page, _)| *page)
+        .ok_or_else(|| format!("Validator {} is not in the pool.", validator).into())
+}
+
 fn _check_owner_balance(config: &Config, required_balance: u64) -> Result<(), Error> {
     let balance = config.rpc_client.get_balance(&config.owner.pubkey())?;
     if balance < required_balance {
@@ -124,7 +166,13 @@ fn _check_owner_balance(config: &Config, required_balance: u64) -> Result<(), Er
     }
 }
 
-fn command_create_pool(config: &Config, fee: PoolFee) -> CommandResult {
+fn command_create_pool(
+    config: &Config,
+    fee: PoolFee,
+    reward_fee: PoolFee,
+    withdrawal_fee: PoolFee,
+    withdrawal_fee_to_owner: bool,
+) -> CommandResult {
     let mint_account = Keypair::new();
     println!("Creating mint {}", mint_account.pubkey());
 
@@ -148,12 +196,20 @@ fn command_create_pool(config: &Config, fee: PoolFee) -> CommandResult {
     let pool_account_balance = config
         .rpc_client
         .get_minimum_balance_for_rent_exemption(StakePool::LEN)?;
+    let pool_extension_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(StakePool::EXTENSION_LEN)?;
+    let pool_reserve_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(std::mem::size_of::<StakeState>())?;
     let validator_stake_list_balance = config
         .rpc_client
         .get_minimum_balance_for_rent_exemption(ValidatorStakeList::LEN)?;
     let total_rent_free_balances = mint_account_balance
         + pool_fee_account_balance
         + pool_account_balance
+        + pool_extension_balance
+        + pool_reserve_balance
         + validator_stake_list_balance;
 
     let default_decimals = native_mint::DECIMALS;
@@ -195,6 +251,12 @@ fn command_create_pool(config: &Config, fee: PoolFee) -> CommandResult {
                 StakePool::LEN as u64,
                 &spl_stake_pool::id(),
             ),
+            // The owner pays the stake pool extension and reserve rent
+            system_instruction::transfer(
+                &config.fee_payer.pubkey(),
+                &config.owner.pubkey(),
+                pool_extension_balance + pool_reserve_balance,
+            ),
             // Validator stake account list storage
             system_instruction::create_account(
                 &config.fee_payer.pubkey(),
@@ -227,7 +289,12 @@ fn command_create_pool(config: &Config, fee: PoolFee) -> CommandResult {
                 &mint_account.pubkey(),
                 &pool_fee_account.pubkey(),
                 &spl_token::id(),
-                PoolInitArgs { fee },
+                PoolInitArgs {
+                    fee,
+                    reward_fee,
+                    withdrawal_fee,
+                    withdrawal_fee_to_owner: withdrawal_fee_to_owner as u8,
+                },
             )?,
         ],
         Some(&config.fee_payer.pubkey()),
@@ -288,8 +355,7 @@ fn command_vsa_add(
     token_receiver: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let mut total_rent_free_balances: u64 = 0;
 
@@ -327,6 +393,19 @@ fn command_vsa_add(
     )
     .unwrap();
 
+    // First page with room left, a v1 list upgraded in place holds fewer validators
+    let mut validator_stake_list = None;
+    for page in pool_data.validator_stake_list_pages() {
+        let page_data = config.rpc_client.get_account_data(page)?;
+        let list = ValidatorStakeList::deserialize(page_data.as_slice())?;
+        if list.validators.len() < ValidatorStakeList::capacity(page_data.len()) {
+            validator_stake_list = Some(*page);
+            break;
+        }
+    }
+    let validator_stake_list =
+        validator_stake_list.ok_or("All validator stake list pages are full")?;
+
     instructions.extend(vec![
         // Set Withdrawer on stake account to Deposit authority of the stake pool
         authorize_stake(
@@ -349,7 +428,7 @@ fn command_vsa_add(
             &config.owner.pubkey(),
             &pool_deposit_authority,
             &pool_withdraw_authority,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &stake,
             &token_receiver,
             &pool_data.pool_mint,
@@ -379,8 +458,7 @@ fn command_vsa_remove(
     new_authority: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -397,6 +475,15 @@ fn command_vsa_remove(
     let stake_account = config.rpc_client.get_account(&stake)?;
     let tokens_to_burn = stake_amount_to_pool_tokens(&pool_data, stake_account.lamports);
 
+    let validator: Pubkey = match deserialize(stake_account.data.as_slice()) {
+        Ok(StakeState::Stake(_, stake)) => Ok(stake.delegation.voter_pubkey),
+        _ => Err("Wrong stake account state, must be delegated to validator"),
+    }?;
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        &validator,
+    )?;
+
     // Check balance and mint
     let account_data = config.rpc_client.get_account_data(&burn_from)?;
     let account_data: TokenAccount =
@@ -431,7 +518,7 @@ fn command_vsa_remove(
                 &config.owner.pubkey(),
                 &pool_withdraw_authority,
                 &new_authority,
-                &pool_data.validator_stake_list,
+                &validator_stake_list,
                 &stake,
                 &burn_from,
                 &pool_data.pool_mint,
@@ -505,8 +592,7 @@ fn command_deposit(
     token_receiver: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     // Get stake account data
     let stake_data = config.rpc_client.get_account_data(&stake)?;
@@ -518,14 +604,13 @@ fn command_deposit(
     }?;
 
     // Check if this validator has staking account in the pool
-    let validator_stake_list_data = config
-        .rpc_client
-        .get_account_data(&pool_data.validator_stake_list)?;
-    let validator_stake_list_data =
-        ValidatorStakeList::deserialize(&validator_stake_list_data.as_slice())?;
-    if !validator_stake_list_data.contains(&validator) {
-        return Err("Stake account for this validator does not exist in the pool.".into());
-    }
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        &validator,
+    )
+    .or(Err(
+        "Stake account for this validator does not exist in the pool.",
+    ))?;
 
     // Calculate validator stake account address linked to the pool
     let (validator_stake_account, _) =
@@ -586,7 +671,7 @@ fn command_deposit(
         deposit(
             &spl_stake_pool::id(),
             &pool,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &pool_deposit_authority,
             &pool_withdraw_authority,
             &stake,
@@ -598,6 +683,16 @@ fn command_deposit(
             &stake_program_id(),
         )?,
     ]);
+    // Pools with a deposit authority check the signer is the authority or a listed depositor
+    if pool_data.has_deposit_authority() {
+        let deposit = instructions.pop().unwrap();
+        instructions.push(with_depositor(
+            deposit,
+            pool,
+            &config.owner.pubkey(),
+            pool_data.deposit_authority == config.owner.pubkey(),
+        ));
+    }
 
     let mut transaction =
         Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
@@ -614,8 +709,7 @@ fn command_deposit(
 
 fn command_list(config: &Config, pool: &Pubkey) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -644,36 +738,52 @@ fn command_list(config: &Config, pool: &Pubkey) -> CommandResult {
 
 fn command_update(config: &Config, pool: &Pubkey) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
-    let validator_stake_list_data = config
-        .rpc_client
-        .get_account_data(&pool_data.validator_stake_list)?;
-    let validator_stake_list_data =
-        ValidatorStakeList::deserialize(&validator_stake_list_data.as_slice())?;
+    let pool_data = get_stake_pool(config, pool)?;
+    let pages = get_validator_stake_list_pages(config, &pool_data)?;
 
     let epoch_info = config.rpc_client.get_epoch_info()?;
 
-    let accounts_to_update: Vec<&Pubkey> = validator_stake_list_data
-        .validators
-        .iter()
-        .filter_map(|item| {
-            if item.last_update_epoch >= epoch_info.epoch {
-                None
-            } else {
-                Some(&item.validator_account)
-            }
-        })
-        .collect();
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
 
     let mut instructions: Vec<Instruction> = vec![];
 
-    for chunk in accounts_to_update.chunks(MAX_ACCOUNTS_TO_UPDATE) {
-        instructions.push(update_list_balance(
-            &spl_stake_pool::id(),
-            &pool_data.validator_stake_list,
-            &chunk,
-        )?);
+    // each page is updated with its own validators
+    for (validator_stake_list, list) in &pages {
+        let accounts_to_update: Vec<Pubkey> = list
+            .validators
+            .iter()
+            .filter_map(|item| {
+                if item.last_update_epoch >= epoch_info.epoch {
+                    None
+                } else {
+                    Some(item.validator_account)
+                }
+            })
+            .collect();
+
+        for chunk in accounts_to_update.chunks(MAX_ACCOUNTS_TO_UPDATE) {
+            instructions.push(update_list_balance(
+                &spl_stake_pool::id(),
+                pool,
+                validator_stake_list,
+                &pool_withdraw_authority,
+                &reserve_stake,
+                chunk,
+            )?);
+        }
     }
 
     if instructions.is_empty() {
@@ -684,6 +794,13 @@ fn command_update(config: &Config, pool: &Pubkey) -> CommandResult {
             &spl_stake_pool::id(),
             pool,
             &pool_data.validator_stake_list,
+            &reserve_stake,
+            &pool_withdraw_authority,
+            &pool_data.owner_fee_account,
+            &pool_data.pool_mint,
+            &spl_token::id(),
+            &pool_data.validator_stake_list_pages
+                [..pool_data.validator_stake_list_pages_len as usize],
         )?);
 
         let mut transaction =
@@ -788,8 +905,7 @@ fn command_withdraw(
     stake_receiver_param: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -847,6 +963,8 @@ fn command_withdraw(
     // Use separate mutable variable because withdraw might create a new account
     let mut stake_receiver: Option<Pubkey> = *stake_receiver_param;
 
+    let pages = get_validator_stake_list_pages(config, &pool_data)?;
+
     // Go through prepared accounts and withdraw/claim them
     for withdraw_stake in withdraw_from {
         println!(
@@ -884,15 +1002,22 @@ fn command_withdraw(
             stake_receiver = Some(stake_receiver_account.pubkey());
         }
 
+        let validator_stake_list = match deserialize(withdraw_stake.account.data.as_slice()) {
+            Ok(StakeState::Stake(_, stake)) => {
+                find_validator_stake_list_page(&pages, &stake.delegation.voter_pubkey)?
+            }
+            _ => pool_data.validator_stake_list,
+        };
         instructions.push(withdraw(
             &spl_stake_pool::id(),
             &pool,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &pool_withdraw_authority,
             &withdraw_stake.pubkey,
             &stake_receiver.unwrap(), // Cannot be none at this point
             &config.owner.pubkey(),
             &burn_from,
+            &pool_data.owner_fee_account,
             &pool_data.pool_mint,
             &spl_token::id(),
             &stake_program_id(),
@@ -920,8 +1045,7 @@ fn command_set_staking_auth(
     stake_account: &Pubkey,
     new_staker: &Pubkey,
 ) -> CommandResult {
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -958,45 +1082,46 @@ fn command_set_owner(
     new_owner: &Option<Pubkey>,
     new_fee_receiver: &Option<Pubkey>,
 ) -> CommandResult {
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
-    // If new accounts are missing in the arguments use the old ones
-    let new_owner: Pubkey = match new_owner {
-        None => pool_data.owner,
-        Some(value) => *value,
-    };
-    let new_fee_receiver: Pubkey = match new_fee_receiver {
-        None => pool_data.owner_fee_account,
-        Some(value) => {
-            // Check for fee receiver being a valid token account and have to same mint as the stake pool
-            let account_data = config.rpc_client.get_account_data(value)?;
-            let account_data: TokenAccount =
-                match TokenAccount::unpack_from_slice(account_data.as_slice()) {
-                    Ok(data) => data,
-                    Err(_) => {
-                        return Err(format!("{} is not a token account", value).into());
-                    }
-                };
-            if account_data.mint != pool_data.pool_mint {
-                return Err("Fee receiver account belongs to a different mint"
-                    .to_string()
-                    .into());
-            }
-            *value
-        }
-    };
+    let mut instructions: Vec<Instruction> = vec![];
 
-    let mut transaction = Transaction::new_with_payer(
-        &[set_owner(
+    // The new owner takes over once it accepts the ownership
+    if let Some(new_owner) = new_owner {
+        instructions.push(set_owner(
             &spl_stake_pool::id(),
             &pool,
             &config.owner.pubkey(),
-            &new_owner,
-            &new_fee_receiver,
-        )?],
-        Some(&config.fee_payer.pubkey()),
-    );
+            new_owner,
+        )?);
+    }
+    if let Some(value) = new_fee_receiver {
+        // Check for fee receiver being a valid token account and have to same mint as the stake pool
+        let account_data = config.rpc_client.get_account_data(value)?;
+        let account_data: TokenAccount =
+            match TokenAccount::unpack_from_slice(account_data.as_slice()) {
+                Ok(data) => data,
+                Err(_) => {
+                    return Err(format!("{} is not a token account", value).into());
+                }
+            };
+        if account_data.mint != pool_data.pool_mint {
+            return Err("Fee receiver account belongs to a different mint"
+                .to_string()
+                .into());
+        }
+        // The fee receiver is set with the fee manager, keep the current one
+        instructions.push(set_fee_manager(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+            &pool_data.fee_manager,
+            value,
+        )?);
+    }
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
 
     let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
     check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
@@ -1086,6 +1211,48 @@ fn main() {
                     .required(true)
                     .help("Fee denominator, fee amount is numerator divided by denominator."),
             )
+            .arg(
+                Arg::with_name("reward_fee_numerator")
+                    .long("reward-fee-numerator")
+                    .validator(is_parsable::<u64>)
+                    .value_name("NUMERATOR")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Reward fee numerator, taken from epoch staking rewards."),
+            )
+            .arg(
+                Arg::with_name("reward_fee_denominator")
+                    .long("reward-fee-denominator")
+                    .validator(is_parsable::<u64>)
+                    .value_name("DENOMINATOR")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Reward fee denominator, 0 disables the reward fee."),
+            )
+            .arg(
+                Arg::with_name("withdrawal_fee_numerator")
+                    .long("withdrawal-fee-numerator")
+                    .validator(is_parsable::<u64>)
+                    .value_name("NUMERATOR")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Withdrawal fee numerator, taken from the pool tokens withdrawn."),
+            )
+            .arg(
+                Arg::with_name("withdrawal_fee_denominator")
+                    .long("withdrawal-fee-denominator")
+                    .validator(is_parsable::<u64>)
+                    .value_name("DENOMINATOR")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Withdrawal fee denominator, 0 disables the withdrawal fee."),
+            )
+            .arg(
+                Arg::with_name("withdrawal_fee_to_owner")
+                    .long("withdrawal-fee-to-owner")
+                    .takes_value(false)
+                    .help("Send the withdrawal fee to the owner fee account instead of leaving it in the pool."),
+            )
         )
         .subcommand(SubCommand::with_name("create-validator-stake").about("Create a new validator stake account to use with the pool")
             .arg(
@@ -1107,7 +1274,7 @@ fn main() {
                     .help("Validator this stake account will vote for"),
             )
         )
-        .subcommand(SubCommand::with_name("add-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool owner.")
+        .subcommand(SubCommand::with_name("add-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool staker.")
             .arg(
                 Arg::with_name("pool")
                     .long("pool")
@@ -1135,7 +1302,7 @@ fn main() {
                     .help("Account to receive pool token. Must be initialized account of the stake pool token. Defaults to the new pool token account."),
             )
         )
-        .subcommand(SubCommand::with_name("remove-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool owner.")
+        .subcommand(SubCommand::with_name("remove-validator-stake").about("Remove validator stake account from the stake pool. Must be signed by the pool staker.")
             .arg(
                 Arg::with_name("pool")
                     .long("pool")
@@ -1288,7 +1455,7 @@ fn main() {
                     .help("Public key of the new staker account."),
             )
         )
-        .subcommand(SubCommand::with_name("set-owner").about("Changes owner or fee receiver account for the stake pool.")
+        .subcommand(SubCommand::with_name("set-owner").about("Proposes a new owner, or changes the fee receiver account for the stake pool. The new owner takes over once it accepts.")
             .arg(
                 Arg::with_name("pool")
                     .long("pool")
@@ -1370,12 +1537,27 @@ fn main() {
         ("create-pool", Some(arg_matches)) => {
             let numerator = value_t_or_exit!(arg_matches, "fee_numerator", u64);
             let denominator = value_t_or_exit!(arg_matches, "fee_denominator", u64);
+            let reward_numerator = value_t_or_exit!(arg_matches, "reward_fee_numerator", u64);
+            let reward_denominator = value_t_or_exit!(arg_matches, "reward_fee_denominator", u64);
+            let withdrawal_numerator =
+                value_t_or_exit!(arg_matches, "withdrawal_fee_numerator", u64);
+            let withdrawal_denominator =
+                value_t_or_exit!(arg_matches, "withdrawal_fee_denominator", u64);
             command_create_pool(
                 &config,
                 PoolFee {
                     numerator,
                     denominator,
                 },
+                PoolFee {
+                    numerator: reward_numerator,
+                    denominator: reward_denominator,
+                },
+                PoolFee {
+                    numerator: withdrawal_numerator,
+                    denominator: withdrawal_denominator,
+                },
+                arg_matches.is_present("withdrawal_fee_to_owner"),
             )
         }
         ("create-validator-stake", Some(arg_matches)) => {
@@ -38,12 +38,12 @@ extern crate lazy_static;
 use bincode::deserialize;
 use clap::{
     crate_description, crate_name, crate_version, value_t, value_t_or_exit, App, AppSettings, Arg,
-    ArgGroup, SubCommand,
+    ArgGroup, ArgMatches, SubCommand,
 };
 use solana_account_decoder::UiAccountEncoding;
 use solana_clap_utils::{
     input_parsers::pubkey_of,
-    input_validators::{is_amount, is_keypair,  is_pubkey, is_url}, //is_parsable,
+    input_validators::{is_amount, is_keypair, is_parsable, is_pubkey, is_url},
     keypair::signer_from_path
 };
 use solana_client::{
@@ -52,7 +52,9 @@ use solana_client::{
     rpc_config::RpcProgramAccountsConfig,
     rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
 };
-use solana_program::{instruction::Instruction, program_pack::Pack, pubkey::Pubkey};
+use solana_program::{
+    clock::DEFAULT_MS_PER_SLOT, instruction::Instruction, program_pack::Pack, pubkey::Pubkey,
+};
 use solana_sdk::{
     account::Account,
     commitment_config::CommitmentConfig,
@@ -63,10 +65,16 @@ use solana_sdk::{
 };
 use spl_stake_pool::{
     instruction::{
-        add_validator_stake_account, create_validator_stake_account, deposit, //
+        add_validator_stake_account, create_validator_stake_account, deposit, deposit_sol, //
          remove_validator_stake_account, set_owner, //initialize as initialize_pool,
-        set_staking_authority, update_list_balance, update_pool_balance, withdraw, //, Fee as PoolFee,
-        instruction_add_liquidity, instruction_sell_stsol
+        set_staking_authority, update_list_balance, update_pool_balance, withdraw,
+        instruction_add_liquidity, instruction_sell_stsol, instruction_remove_liquidity,
+        initialize_liquidity_pool, set_sell_fee, set_treasury, instruction_buy_stsol, set_buy_fee,
+        set_fee, Fee as PoolFee, increase_validator_stake, decrease_validator_stake,
+        set_validator_weights, rebalance, add_validator_stake_list_page, migrate_state,
+        accept_owner, set_staker, set_fee_manager, set_deposit_authority, set_depositor,
+        with_depositor, deactivate_delinquent_validator, set_delinquent_slots,
+        create_rate_history, with_rate_history,
         //InitArgs as PoolInitArgs,
     },
     processor::Processor as PoolProcessor,
@@ -74,6 +82,11 @@ use spl_stake_pool::{
     stake::id as stake_program_id,
     stake::StakeAuthorize,
     stake::StakeState,
+    state::LiquidityPool,
+    state::RebalanceMove,
+    state::SellFeeCurve,
+    state::DepositorList,
+    state::RateHistory,
     state::StakePool,
     state::ValidatorStakeList,
 };
@@ -106,7 +119,10 @@ type Error = Box<dyn std::error::Error>;
 type CommandResult = Result<Option<Transaction>, Error>;
 
 const STAKE_STATE_LEN: usize = 200;
-const MAX_ACCOUNTS_TO_UPDATE: usize = 10;
+// each validator takes two accounts, its stake and transient stake accounts
+const MAX_ACCOUNTS_TO_UPDATE: usize = 5;
+// Rebalance instructions that fit in one transaction
+const MAX_REBALANCE_MOVES: usize = 4;
 lazy_static! {
     static ref MIN_STAKE_BALANCE: u64 = sol_to_lamports(1.0);
 }
@@ -135,6 +151,23 @@ fn check_fee_payer_balance(config: &Config, required_balance: u64) -> Result<(),
     }
 }
 
+fn get_token_balance(config: &Config, token_account: &Pubkey) -> Result<u64, Error> {
+    let account_data = config.rpc_client.get_account_data(token_account)?;
+    Ok(TokenAccount::unpack_from_slice(account_data.as_slice())?.amount)
+}
+
+/// min amount to receive: --min-out if given, else the quote minus --max-slippage-bps, else no bound
+fn min_out_of(quote: u64, min_out: Option<u64>, max_slippage_bps: Option<u64>) -> Result<u64, Error> {
+    if let Some(min_out) = min_out {
+        return Ok(min_out);
+    }
+    match max_slippage_bps {
+        None => Ok(0),
+        Some(bps) if bps > 10_000 => Err("--max-slippage-bps must be <= 10000".into()),
+        Some(bps) => Ok((quote as u128 * (10_000 - bps) as u128 / 10_000) as u64),
+    }
+}
+
 fn get_authority_accounts(config: &Config, authority: &Pubkey) -> Vec<(Pubkey, Account)> {
     config
         .rpc_client
@@ -157,6 +190,41 @@ fn get_authority_accounts(config: &Config, authority: &Pubkey) -> Vec<(Pubkey, A
         .unwrap()
 }
 
+/// Stake pool record together with the fields kept in its extension account
+fn get_stake_pool(config: &Config, pool: &Pubkey) -> Result<StakePool, Error> {
+    let (extension, _) =
+        PoolProcessor::find_stake_pool_extension_address(&spl_stake_pool::id(), pool);
+    let pool_data = config.rpc_client.get_account_data(pool)?;
+    let extension_data = config.rpc_client.get_account_data(&extension)?;
+    Ok(StakePool::deserialize(pool_data.as_slice(), extension_data.as_slice())?)
+}
+
+/// Validator stake list pages linked to the pool, in the pool order
+fn get_validator_stake_list_pages(
+    config: &Config,
+    pool_data: &StakePool,
+) -> Result<Vec<(Pubkey, ValidatorStakeList)>, Error> {
+    pool_data
+        .validator_stake_list_pages()
+        .map(|page| {
+            let page_data = config.rpc_client.get_account_data(page)?;
+            Ok((*page, ValidatorStakeList::deserialize(page_data.as_slice())?))
+        })
+        .collect()
+}
+
+/// Validator stake list page holding `validator`
+fn find_validator_stake_list_page(
+    pages: &[(Pubkey, ValidatorStakeList)],
+    validator: &Pubkey,
+) -> Result<Pubkey, Error> {
+    pages
+        .iter()
+        .find(|(_, list)| list.contains(validator))
+        .map(|(page, _)| *page)
+        .ok_or_else(|| format!("Validator {} is not in the pool.", validator).into())
+}
+
 fn _check_owner_balance(config: &Config, required_balance: u64) -> Result<(), Error> {
     let balance = config.rpc_client.get_balance(&config.owner.pubkey())?;
     if balance < required_balance {
@@ -301,7 +369,11 @@ fn command_create_pool(config: &Config, fee: PoolFee) -> CommandResult {
 }
 */
 
-fn command_create_liquidity_pool(config: &Config) -> CommandResult {
+fn command_create_liquidity_pool(
+    config: &Config,
+    sell_fee: SellFeeCurve,
+    treasury: &Option<Pubkey>,
+) -> CommandResult {
 
     //we need to create
     // 1. liq-pool state acc
@@ -312,16 +384,15 @@ fn command_create_liquidity_pool(config: &Config) -> CommandResult {
     // so we need 
     // META_LP_MINT_acc
 
-    // let pool_fee_account = Keypair::new();
-    // println!(
-    //     "Creating pool fee collection account {}",
-    //     pool_fee_account.pubkey()
-    // );
-
     let liq_pool_account = Keypair::new();
     println!("Creating liquidity pool {}", liq_pool_account.pubkey());
 
-    //let validator_stake_list = Keypair::new();
+    // the PDA authority of the *new* liq pool, owner of both legs and of the $METALP mint
+    let (pda_liq_pool_authority, _) = PoolProcessor::find_authority_bump_seed(
+        &config.smart_pool_program_id,
+        &liq_pool_account.pubkey(),
+        PoolProcessor::AUTHORITY,
+    );
 
     let mint_account_balance = config
         .rpc_client
@@ -333,17 +404,17 @@ fn command_create_liquidity_pool(config: &Config) -> CommandResult {
 
     let pool_account_balance = config
         .rpc_client
-        .get_minimum_balance_for_rent_exemption(StakePool::LEN)?;
+        .get_minimum_balance_for_rent_exemption(LiquidityPool::LEN)?;
 
-    let total_rent_free_balances = mint_account_balance
-        + pool_fee_account_balance
+    let mut total_rent_free_balances = mint_account_balance
+        + pool_fee_account_balance * 2
         + pool_account_balance;
 
     let default_decimals = native_mint::DECIMALS;
 
     if config.verbose {
-        println!("liq pool state acc {}", &config.liq_pool_state_account);
-        println!("liq pool PDA authority {}", &config.pda_liq_pool_authority);
+        println!("liq pool state acc {}", &liq_pool_account.pubkey());
+        println!("liq pool PDA authority {}", &pda_liq_pool_authority);
     }
 
     println!(
@@ -363,91 +434,102 @@ fn command_create_liquidity_pool(config: &Config) -> CommandResult {
     let meta_lp_mint_account = Keypair::new();
     println!("Creating meta_lp_mint_account {}", meta_lp_mint_account.pubkey());
 
-    let mut transaction = Transaction::new_with_payer(
-        &[
-            // Account for the liq pool wSOL
-            system_instruction::create_account(
-                &config.fee_payer.pubkey(),
-                &liq_pool_account.pubkey(),
-                pool_account_balance,
-                StakePool::LEN as u64,
-                &spl_stake_pool::id(),
-            ),
+    let mut instructions: Vec<Instruction> = vec![];
+    let treasury_account = Keypair::new();
+    let mut treasury_created = false;
+    // Create the treasury wSOL account, owned by the owner, if not specified
+    let treasury = unwrap_create_token_account(
+        &config,
+        &treasury,
+        &treasury_account,
+        &pubkey_from(W_SOL_1111111_MINT_ACCOUNT),
+        &mut instructions,
+        |balance| {
+            treasury_created = true;
+            total_rent_free_balances += balance;
+        },
+    )?;
+    println!("liquidity pool treasury wsol account: {}", treasury);
 
-            // One side of the liq-pool, the wSOL side - TokenAccount::LEN as u64, native empty account to initialize 
-             system_instruction::create_account(
-                 &config.fee_payer.pubkey(),
-                 &pool_wsol_account.pubkey(),
-                 pool_fee_account_balance,
-                 TokenAccount::LEN as u64,
-                 &spl_token::id(),
-             ),
-            // the other  - TokenAccount::LEN as u64, native empty account to initialize 
-            system_instruction::create_account(
-                &config.fee_payer.pubkey(),
-                &pool_st_sol_account.pubkey(),
-                pool_fee_account_balance,
-                TokenAccount::LEN as u64,
-                &spl_token::id(),
-            ),
+    instructions.extend(vec![
+        // Account for the liq pool state
+        system_instruction::create_account(
+            &config.fee_payer.pubkey(),
+            &liq_pool_account.pubkey(),
+            pool_account_balance,
+            LiquidityPool::LEN as u64,
+            &config.smart_pool_program_id,
+        ),
 
-            // Validator stake account list storage
-            // system_instruction::create_account(
-                // &config.fee_payer.pubkey(),
-                // &validator_stake_list.pubkey(),
-                // validator_stake_list_balance,
-                // ValidatorStakeList::LEN as u64,
-                // &spl_stake_pool::id(),
-            // ),
-
-            // Initialize both sides of the pool, 
-            // w-sol side
-            initialize_token_account(
-                &spl_token::id(),
-                &pool_wsol_account.pubkey(),
-                &pubkey_from(W_SOL_1111111_MINT_ACCOUNT),
-                &config.pda_liq_pool_authority, //"token-owner" is like authorithy, we need a PDA so the program can "sign but not sign" txns
-            )?,
-            // st-sol side
-            initialize_token_account(
-                &spl_token::id(),
-                &pool_st_sol_account.pubkey(),
-                &config.st_sol_mint_account,
-                &config.pda_liq_pool_authority, //"token-owner" is like authorithy, we need a PDA so the program can "sign but not sign" txns
-            )?,
+        // One side of the liq-pool, the wSOL side - TokenAccount::LEN as u64, native empty account to initialize 
+         system_instruction::create_account(
+             &config.fee_payer.pubkey(),
+             &pool_wsol_account.pubkey(),
+             pool_fee_account_balance,
+             TokenAccount::LEN as u64,
+             &spl_token::id(),
+         ),
+        // the other  - TokenAccount::LEN as u64, native empty account to initialize 
+        system_instruction::create_account(
+            &config.fee_payer.pubkey(),
+            &pool_st_sol_account.pubkey(),
+            pool_fee_account_balance,
+            TokenAccount::LEN as u64,
+            &spl_token::id(),
+        ),
 
-            // Account for the META LP mint
-            system_instruction::create_account(
-                &config.fee_payer.pubkey(),
-                &meta_lp_mint_account.pubkey(),
-                mint_account_balance,
-                TokenMint::LEN as u64,
-                &spl_token::id(),
-            ),
+        // Initialize both sides of the pool, 
+        // w-sol side
+        initialize_token_account(
+            &spl_token::id(),
+            &pool_wsol_account.pubkey(),
+            &pubkey_from(W_SOL_1111111_MINT_ACCOUNT),
+            &pda_liq_pool_authority, //"token-owner" is like authorithy, we need a PDA so the program can "sign but not sign" txns
+        )?,
+        // st-sol side
+        initialize_token_account(
+            &spl_token::id(),
+            &pool_st_sol_account.pubkey(),
+            &config.st_sol_mint_account,
+            &pda_liq_pool_authority, //"token-owner" is like authorithy, we need a PDA so the program can "sign but not sign" txns
+        )?,
 
-            // Initialize pool token mint account
-            initialize_mint(
-                &spl_token::id(),
-                &meta_lp_mint_account.pubkey(),
-                &config.pda_liq_pool_authority,
-                None,
-                default_decimals,
-            )?,
+        // Account for the META LP mint
+        system_instruction::create_account(
+            &config.fee_payer.pubkey(),
+            &meta_lp_mint_account.pubkey(),
+            mint_account_balance,
+            TokenMint::LEN as u64,
+            &spl_token::id(),
+        ),
 
-            // Initialize stake pool account
-            // initialize_pool(
-            //     &spl_stake_pool::id(),
-            //     &liq_pool_account.pubkey(),
-            //     &config.owner.pubkey(),
-            //     &validator_stake_list.pubkey(),
-            //     &mint_account.pubkey(),
-            //     &pool_fee_account.pubkey(),
-            //     &spl_token::id(),
-            //     PoolInitArgs { fee },
-            // )?,
-        ],
-        Some(&config.fee_payer.pubkey()),
-    );
+        // Initialize pool token mint account
+        initialize_mint(
+            &spl_token::id(),
+            &meta_lp_mint_account.pubkey(),
+            &pda_liq_pool_authority,
+            None,
+            default_decimals,
+        )?,
+
+        // Initialize liq pool state account
+        initialize_liquidity_pool(
+            &config.smart_pool_program_id,
+            &liq_pool_account.pubkey(),
+            &config.stake_pool_state_account,
+            &config.owner.pubkey(),
+            &pool_wsol_account.pubkey(),
+            &pool_st_sol_account.pubkey(),
+            &meta_lp_mint_account.pubkey(),
+            &pda_liq_pool_authority,
+            &spl_token::id(),
+            &treasury,
+            sell_fee,
+        )?,
+    ]);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
 
     let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
     check_fee_payer_balance(
@@ -458,12 +540,14 @@ fn command_create_liquidity_pool(config: &Config) -> CommandResult {
     let mut signers = vec![
         config.fee_payer.as_ref(),
         &liq_pool_account,
-        //&validator_stake_list,
         &meta_lp_mint_account,
         &pool_st_sol_account,
         &pool_wsol_account,
         config.owner.as_ref(),
     ];
+    if treasury_created {
+        signers.push(&treasury_account);
+    }
 
     unique_signers!(signers);
 
@@ -510,8 +594,7 @@ fn command_vsa_add(
     token_receiver: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let mut total_rent_free_balances: u64 = 0;
 
@@ -550,6 +633,19 @@ fn command_vsa_add(
     )
     .unwrap();
 
+    // First page with room left, a v1 list upgraded in place holds fewer validators
+    let mut validator_stake_list = None;
+    for page in pool_data.validator_stake_list_pages() {
+        let page_data = config.rpc_client.get_account_data(page)?;
+        let list = ValidatorStakeList::deserialize(page_data.as_slice())?;
+        if list.validators.len() < ValidatorStakeList::capacity(page_data.len()) {
+            validator_stake_list = Some(*page);
+            break;
+        }
+    }
+    let validator_stake_list = validator_stake_list
+        .ok_or("All validator stake list pages are full, add one with add-validator-list-page")?;
+
     instructions.extend(vec![
         // Set Withdrawer on stake account to Deposit authority of the stake pool
         authorize_stake(
@@ -572,7 +668,7 @@ fn command_vsa_add(
             &config.owner.pubkey(),
             &pool_deposit_authority,
             &pool_withdraw_authority,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &stake,
             &token_receiver,
             &pool_data.pool_mint,
@@ -603,8 +699,7 @@ fn command_vsa_remove(
     new_authority: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -621,6 +716,15 @@ fn command_vsa_remove(
     let stake_account = config.rpc_client.get_account(&stake)?;
     let tokens_to_burn = stake_amount_to_pool_tokens(&pool_data, stake_account.lamports);
 
+    let validator: Pubkey = match deserialize(stake_account.data.as_slice()) {
+        Ok(StakeState::Stake(_, stake)) => Ok(stake.delegation.voter_pubkey),
+        _ => Err("Wrong stake account state, must be delegated to validator"),
+    }?;
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        &validator,
+    )?;
+
     // Check balance and mint
     let account_data = config.rpc_client.get_account_data(&burn_from)?;
     let account_data: TokenAccount =
@@ -655,7 +759,7 @@ fn command_vsa_remove(
                 &config.owner.pubkey(),
                 &pool_withdraw_authority,
                 &new_authority,
-                &pool_data.validator_stake_list,
+                &validator_stake_list,
                 &stake,
                 &burn_from,
                 &pool_data.pool_mint,
@@ -729,8 +833,7 @@ fn command_deposit(
     token_receiver: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     // Get stake account data
     let stake_data = config.rpc_client.get_account_data(&stake_account)?;
@@ -742,14 +845,11 @@ fn command_deposit(
     }?;
 
     // Check if this validator has staking account in the pool
-    let validator_stake_list_data = config
-        .rpc_client
-        .get_account_data(&pool_data.validator_stake_list)?;
-    let validator_stake_list_data =
-        ValidatorStakeList::deserialize(&validator_stake_list_data.as_slice())?;
-    if !validator_stake_list_data.contains(&validator) {
-        return Err("Stake account for this validator does not exist in the pool.".into());
-    }
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        &validator,
+    )
+    .or(Err("Stake account for this validator does not exist in the pool."))?;
 
     // Calculate validator stake account address linked to the pool
     let (validator_stake_account, _) =
@@ -811,18 +911,19 @@ fn command_deposit(
         deposit(
             &spl_stake_pool::id(),
             &pool,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &pool_deposit_authority,
             &withdraw_authority,
             &stake_account,
             &validator_stake_account,
             &token_receiver,
-            &token_receiver, //&pool_data.owner_fee_account,
+            &pool_data.owner_fee_account,
             &pool_data.pool_mint,
             &spl_token::id(),
             &stake_program_id(),
         )?,
     ]);
+    add_depositor_accounts(config, pool, &pool_data, &mut instructions);
 
     let mut transaction =
         Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
@@ -837,20 +938,140 @@ fn command_deposit(
     Ok(Some(transaction))
 }
 
+/// Pools with a deposit authority check the signer is the authority or a listed depositor
+fn add_depositor_accounts(
+    config: &Config,
+    pool: &Pubkey,
+    pool_data: &StakePool,
+    instructions: &mut Vec<Instruction>,
+) {
+    if pool_data.has_deposit_authority() {
+        let deposit = instructions.pop().unwrap();
+        instructions.push(with_depositor(
+            deposit,
+            pool,
+            &config.owner.pubkey(),
+            pool_data.deposit_authority == config.owner.pubkey(),
+        ));
+    }
+}
+
+fn command_deposit_sol(
+    config: &Config,
+    pool: &Pubkey,
+    amount: u64,
+    token_receiver: &Option<Pubkey>,
+) -> CommandResult {
+    // Get stake pool state
+    let pool_data = get_stake_pool(config, pool)?;
+
+    let pool_amount = pool_data.calc_pool_deposit_amount(amount).ok_or("Calculation failure")?;
+    let fee_amount = pool_data.calc_fee_amount(pool_amount).ok_or("Calculation failure")?;
+    println!(
+        "Depositing {} SOL, you receive {} stSOL (fee {} stSOL)",
+        lamports_to_sol(amount),
+        lamports_to_sol(pool_amount - fee_amount),
+        lamports_to_sol(fee_amount),
+    );
+
+    let mut instructions: Vec<Instruction> = vec![];
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+
+    let mut total_rent_free_balances: u64 = 0;
+
+    let token_receiver_account = Keypair::new();
+
+    // Create token account if not specified
+    let token_receiver = unwrap_create_token_account(
+        &config,
+        &token_receiver,
+        &token_receiver_account,
+        &pool_data.pool_mint,
+        &mut instructions,
+        |balance| {
+            signers.push(&token_receiver_account);
+            total_rent_free_balances += balance;
+        },
+    )?;
+
+    let withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+
+    instructions.push(deposit_sol(
+        &spl_stake_pool::id(),
+        &pool,
+        &withdraw_authority,
+        &reserve_stake,
+        &config.owner.pubkey(),
+        &token_receiver,
+        &pool_data.owner_fee_account,
+        &pool_data.pool_mint,
+        &spl_token::id(),
+        amount,
+    )?);
+    add_depositor_accounts(config, pool, &pool_data, &mut instructions);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        total_rent_free_balances + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
 
 //-------------------------------------
 fn command_add_liquidity(
     config: &Config,
     w_sol_source_account: &Pubkey,
     amount: u64,
+    min_out: Option<u64>,
+    max_slippage_bps: Option<u64>,
     token_receiver: &Option<Pubkey>,
 
 ) -> CommandResult {
 
     // Get stake pool state
     println!("&config.stake_pool_state_account {}",&config.stake_pool_state_account);
-    // let meta_pool_data_raw = config.rpc_client.get_account_data(&config.stake_pool_state_account)?;
-    // let meta_pool_data: StakePool = StakePool::deserialize(meta_pool_data_raw.as_slice()).unwrap();
+    let meta_pool_data = get_stake_pool(config, &config.stake_pool_state_account)?;
+
+    // Quote the $METALP to receive, same valuation the program applies
+    let metalp_mint_data = config.rpc_client.get_account_data(&config.meta_lp_mint_account)?;
+    let metalp_supply = TokenMint::unpack_from_slice(metalp_mint_data.as_slice())?.supply;
+    // pending unstake tickets count as wSOL
+    let liq_pool_data_raw = config.rpc_client.get_account_data(&config.liq_pool_state_account)?;
+    let liq_pool_data: LiquidityPool = LiquidityPool::deserialize(liq_pool_data_raw.as_slice()).unwrap();
+    let total_value = LiquidityPool::calc_total_value(
+        get_token_balance(config, &config.liq_pool_wsol_account)?,
+        get_token_balance(config, &config.liq_pool_stsol_account)?,
+        &meta_pool_data,
+    )
+    .and_then(|value| value.checked_add(liq_pool_data.calc_pending_unstake_lamports()?))
+    .ok_or("Calculation failure")?;
+    let metalp_quote = LiquidityPool::calc_metalp_amount(amount, total_value, metalp_supply).ok_or("Calculation failure")?;
+    let min_metalp_out = min_out_of(metalp_quote, min_out, max_slippage_bps)?;
+    println!(
+        "Adding {} wSOL, you receive {} $METALP (min {})",
+        lamports_to_sol(amount),
+        lamports_to_sol(metalp_quote),
+        lamports_to_sol(min_metalp_out),
+    );
 
     let mut instructions: Vec<Instruction> = vec![];
     let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
@@ -875,7 +1096,9 @@ fn command_add_liquidity(
         // add liq
         instruction_add_liquidity(
             amount,
+            min_metalp_out,
             &config.smart_pool_program_id,
+            &config.stake_pool_state_account,
             &config.liq_pool_state_account,
             &spl_token::id(),
             &config.meta_lp_mint_account,
@@ -883,6 +1106,7 @@ fn command_add_liquidity(
             &w_sol_source_account,
             &config.fee_payer.pubkey(),
             &config.liq_pool_wsol_account,
+            &config.liq_pool_stsol_account,
             &token_receiver
         ).unwrap()
     ]);
@@ -908,6 +1132,8 @@ fn command_sell_st_sol(
     config: &Config,
     user_st_sol_source_account: &Pubkey,
     amount: u64,
+    min_out: Option<u64>,
+    max_slippage_bps: Option<u64>,
     token_receiver: &Option<Pubkey>,
 
 ) -> CommandResult {
@@ -922,6 +1148,24 @@ fn command_sell_st_sol(
 
     let mut total_rent_free_balances: u64 = 0;
 
+    // Quote the sell fee, same curve the program applies
+    let meta_pool_data = get_stake_pool(config, &config.stake_pool_state_account)?;
+    let liq_pool_data_raw = config.rpc_client.get_account_data(&config.liq_pool_state_account)?;
+    let liq_pool_data: LiquidityPool = LiquidityPool::deserialize(liq_pool_data_raw.as_slice()).unwrap();
+    let liq_pool_wsol_balance = get_token_balance(config, &config.liq_pool_wsol_account)?;
+    let sol_value = meta_pool_data.calc_lamports_amount(amount).ok_or("Calculation failure")?;
+    let fee = liq_pool_data.sell_fee.calc_sell_fee(sol_value, liq_pool_wsol_balance).ok_or("Calculation failure")?;
+    let wsol_quote = sol_value.saturating_sub(fee);
+    let min_wsol_out = min_out_of(wsol_quote, min_out, max_slippage_bps)?;
+    println!(
+        "Selling {} stSOL, value {} SOL, fee {} SOL, you receive {} wSOL (min {})",
+        lamports_to_sol(amount),
+        lamports_to_sol(sol_value),
+        lamports_to_sol(fee),
+        lamports_to_sol(wsol_quote),
+        lamports_to_sol(min_wsol_out),
+    );
+
     let token_receiver_account = Keypair::new();
     // Create token account if not specified
     let token_receiver = unwrap_create_token_account(
@@ -940,6 +1184,7 @@ fn command_sell_st_sol(
         // add liq
         instruction_sell_stsol(
             amount,
+            min_wsol_out,
             &config.smart_pool_program_id,
             &config.stake_pool_state_account,
             &config.liq_pool_state_account,
@@ -950,6 +1195,7 @@ fn command_sell_st_sol(
             &token_receiver,
             &user_st_sol_source_account,
             &config.fee_payer.pubkey(),
+            &liq_pool_data.treasury_wsol_account,
         ).unwrap()
     ]);
 
@@ -969,123 +1215,904 @@ fn command_sell_st_sol(
     Ok(Some(transaction))
 }
 
-fn command_list(config: &Config, stake_pool_state: &Pubkey) -> CommandResult {
-
-    println!("Program {:?}",&spl_stake_pool::id());
-    println!("State {:?}",&stake_pool_state);
-    // Get stake pool state
-    let stake_pool_state_acc = config.rpc_client.get_account_data(&stake_pool_state)?;
-    let stake_pool_data: StakePool = StakePool::deserialize(stake_pool_state_acc.as_slice()).unwrap();
-    println!("{:?}",stake_pool_data);
-
-    println!("PDA_LIQ_POOL_authority {}",&config.pda_liq_pool_authority);
+//-------------------------------------
+fn command_buy_st_sol(
+    config: &Config,
+    user_wsol_source_account: &Pubkey,
+    amount: u64,
+    min_out: Option<u64>,
+    max_slippage_bps: Option<u64>,
+    token_receiver: &Option<Pubkey>,
 
-    // Calculate Deposit and Withdraw stake pool authorities
-    let pool_deposit_authority: Pubkey = PoolProcessor::authority_id(
-        &spl_stake_pool::id(),
-        stake_pool_state,
-        PoolProcessor::AUTHORITY_DEPOSIT,
-        stake_pool_data.deposit_bump_seed,
-    )
-    .unwrap();
-    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
-        &spl_stake_pool::id(),
-        stake_pool_state,
-        PoolProcessor::AUTHORITY_WITHDRAW,
-        stake_pool_data.withdraw_bump_seed,
-    )
-    .unwrap();
+) -> CommandResult {
 
-    println!("stake_pool_deposit_authority {}",pool_deposit_authority);
-    println!("stake_pool_withdraw_authority {}",pool_withdraw_authority);
-    println!("validator_stake_list {}",stake_pool_data.validator_stake_list);
-    
-    let validator_stake_list_acc = config
-        .rpc_client
-        .get_account_data(&stake_pool_data.validator_stake_list)?;
-    let validator_stake_list_data =
-        ValidatorStakeList::deserialize(&validator_stake_list_acc.as_slice())?;
-    println!("----------------");
-    println!("Validators ({})",validator_stake_list_data.validators.len());
-    println!("----------------");
-    for info in validator_stake_list_data.validators {
-        println!("Validator vote Acc {}\tBalance:{}", info.validator_account, info.balance);
+    // Quote the buy, same pricing the program applies
+    let meta_pool_data = get_stake_pool(config, &config.stake_pool_state_account)?;
+    let liq_pool_data_raw = config.rpc_client.get_account_data(&config.liq_pool_state_account)?;
+    let liq_pool_data: LiquidityPool = LiquidityPool::deserialize(liq_pool_data_raw.as_slice()).unwrap();
+    let liq_pool_stsol_balance = get_token_balance(config, &config.liq_pool_stsol_account)?;
+    let (wsol_in, stsol_quote) = liq_pool_data
+        .calc_buy(amount, liq_pool_stsol_balance, &meta_pool_data)
+        .ok_or("Calculation failure")?;
+    if stsol_quote == 0 {
+        return Err("The liquidity pool has no stSOL to sell".into());
     }
-    
-        
-    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
-        &config.smart_pool_program_id,
-        stake_pool_state,
-        PoolProcessor::AUTHORITY_WITHDRAW,
-        stake_pool_data.withdraw_bump_seed,
-    )
-    .unwrap();
-
-    let accounts = get_authority_accounts(config, &pool_withdraw_authority);
-
-    if accounts.is_empty() {
-        return Err("No get_authority_accounts found.".to_string().into());
+    let min_stsol_out = min_out_of(stsol_quote, min_out, max_slippage_bps)?;
+    println!(
+        "Paying {} wSOL (fee {} bps), you receive {} stSOL (min {})",
+        lamports_to_sol(wsol_in),
+        liq_pool_data.buy_fee_bps,
+        lamports_to_sol(stsol_quote),
+        lamports_to_sol(min_stsol_out),
+    );
+    if wsol_in < amount {
+        println!("The liquidity pool holds only {} stSOL, the buy is capped", lamports_to_sol(liq_pool_stsol_balance));
     }
 
-    println!("----------------");
-    println!("Accounts with authorithy = pool_withdraw_authority ({})",&pool_withdraw_authority);
-    println!("----------------");
-    let mut total_balance: u64 = 0;
-    for (pubkey, account) in accounts {
-        let balance = account.lamports;
-        total_balance += balance;
-        println!("Stake Acc {}\t{} SOL", pubkey, lamports_to_sol(balance));
-    }
-    println!("Total: {} SOL", lamports_to_sol(total_balance));
+    let mut instructions: Vec<Instruction> = vec![];
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
 
-    Ok(None)
-}
+    let mut total_rent_free_balances: u64 = 0;
 
-fn command_update(config: &Config, pool: &Pubkey, force:bool) -> CommandResult {
-    // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
-    let validator_stake_list_data = config
-        .rpc_client
+    let token_receiver_account = Keypair::new();
+    // Create token account if not specified
+    let token_receiver = unwrap_create_token_account(
+        &config,
+        &token_receiver,
+        &token_receiver_account,
+        &config.st_sol_mint_account,
+        &mut instructions,
+        |balance| {
+            signers.push(&token_receiver_account);
+            total_rent_free_balances += balance;
+        },
+    )?;
+
+    instructions.extend(vec![
+        instruction_buy_stsol(
+            amount,
+            min_stsol_out,
+            &config.smart_pool_program_id,
+            &config.stake_pool_state_account,
+            &config.liq_pool_state_account,
+            &spl_token::id(),
+            &config.liq_pool_wsol_account,
+            &config.liq_pool_stsol_account,
+            &config.pda_liq_pool_authority,
+            &user_wsol_source_account,
+            &token_receiver,
+            &config.fee_payer.pubkey(),
+        ).unwrap()
+    ]);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        total_rent_free_balances + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+
+    unique_signers!(signers);
+
+    transaction.sign(&signers, recent_blockhash);
+
+    Ok(Some(transaction))
+}
+
+//-------------------------------------
+fn command_remove_liquidity(
+    config: &Config,
+    user_meta_lp_source_account: &Pubkey,
+    amount: u64,
+    wsol_receiver: &Option<Pubkey>,
+    st_sol_receiver: &Option<Pubkey>,
+
+) -> CommandResult {
+
+    let mut instructions: Vec<Instruction> = vec![];
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+
+    let mut total_rent_free_balances: u64 = 0;
+
+    let wsol_receiver_account = Keypair::new();
+    // Create wSOL token account if not specified
+    let wsol_receiver = unwrap_create_token_account(
+        &config,
+        &wsol_receiver,
+        &wsol_receiver_account,
+        &pubkey_from(W_SOL_1111111_MINT_ACCOUNT),
+        &mut instructions,
+        |balance| {
+            signers.push(&wsol_receiver_account);
+            total_rent_free_balances += balance;
+        },
+    )?;
+
+    let st_sol_receiver_account = Keypair::new();
+    // Create stSOL token account if not specified
+    let st_sol_receiver = unwrap_create_token_account(
+        &config,
+        &st_sol_receiver,
+        &st_sol_receiver_account,
+        &config.st_sol_mint_account,
+        &mut instructions,
+        |balance| {
+            signers.push(&st_sol_receiver_account);
+            total_rent_free_balances += balance;
+        },
+    )?;
+
+    instructions.extend(vec![
+        // remove liq
+        instruction_remove_liquidity(
+            amount,
+            &config.smart_pool_program_id,
+            &config.liq_pool_state_account,
+            &spl_token::id(),
+            &config.meta_lp_mint_account,
+            &config.liq_pool_wsol_account,
+            &config.liq_pool_stsol_account,
+            &config.pda_liq_pool_authority,
+            &user_meta_lp_source_account,
+            &config.owner.pubkey(),
+            &wsol_receiver,
+            &st_sol_receiver,
+        ).unwrap()
+    ]);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        total_rent_free_balances + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+
+    unique_signers!(signers);
+
+    transaction.sign(&signers, recent_blockhash);
+
+    Ok(Some(transaction))
+}
+
+//-------------------------------------
+fn command_set_sell_fee(config: &Config, sell_fee: SellFeeCurve) -> CommandResult {
+    if !sell_fee.is_valid() {
+        return Err("Invalid sell fee curve, min fee must be <= max fee <= 10000 bps".into());
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_sell_fee(
+            &config.smart_pool_program_id,
+            &config.liq_pool_state_account,
+            &config.stake_pool_state_account,
+            &config.owner.pubkey(),
+            sell_fee,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+//-------------------------------------
+fn command_set_buy_fee(config: &Config, buy_fee_bps: u64) -> CommandResult {
+    if buy_fee_bps >= 10_000 {
+        return Err("Buy fee must be < 10000 bps".into());
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_buy_fee(
+            &config.smart_pool_program_id,
+            &config.liq_pool_state_account,
+            &config.stake_pool_state_account,
+            &config.owner.pubkey(),
+            buy_fee_bps,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+//-------------------------------------
+fn command_set_fee(config: &Config, fee: PoolFee) -> CommandResult {
+    if fee.numerator > fee.denominator {
+        return Err("Fee numerator must be <= denominator".into());
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_fee(
+            &config.smart_pool_program_id,
+            &config.stake_pool_state_account,
+            &config.owner.pubkey(),
+            fee,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_increase_validator_stake(
+    config: &Config,
+    pool: &Pubkey,
+    validator: &Pubkey,
+    lamports: u64,
+) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    println!("Moving {} SOL from the reserve to validator {}", lamports_to_sol(lamports), validator);
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        validator,
+    )?;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[increase_validator_stake(
+            &spl_stake_pool::id(),
+            pool,
+            &config.owner.pubkey(),
+            &pool_withdraw_authority,
+            &validator_stake_list,
+            &reserve_stake,
+            validator,
+            lamports,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_decrease_validator_stake(
+    config: &Config,
+    pool: &Pubkey,
+    validator: &Pubkey,
+    lamports: u64,
+) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    println!("Moving {} SOL from validator {} to the reserve", lamports_to_sol(lamports), validator);
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        validator,
+    )?;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[decrease_validator_stake(
+            &spl_stake_pool::id(),
+            pool,
+            &config.owner.pubkey(),
+            &pool_withdraw_authority,
+            &validator_stake_list,
+            &reserve_stake,
+            validator,
+            lamports,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_set_validator_weight(
+    config: &Config,
+    pool: &Pubkey,
+    validator: &Pubkey,
+    weight: u64,
+) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        validator,
+    )?;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_validator_weights(
+            &spl_stake_pool::id(),
+            pool,
+            &config.owner.pubkey(),
+            &validator_stake_list,
+            &[(*validator, weight)],
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_add_validator_list_page(config: &Config, pool: &Pubkey) -> CommandResult {
+    let page = Keypair::new();
+    println!("Creating validator stake list page {}", page.pubkey());
+
+    let page_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(ValidatorStakeList::LEN)?;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            system_instruction::create_account(
+                &config.fee_payer.pubkey(),
+                &page.pubkey(),
+                page_balance,
+                ValidatorStakeList::LEN as u64,
+                &spl_stake_pool::id(),
+            ),
+            add_validator_stake_list_page(
+                &spl_stake_pool::id(),
+                pool,
+                &config.owner.pubkey(),
+                &page.pubkey(),
+            )?,
+        ],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        page_balance + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref(), &page];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_migrate_state(config: &Config, pool: &Pubkey) -> CommandResult {
+    let pool_data = config.rpc_client.get_account_data(&pool)?;
+    if pool_data.first() != Some(&StakePool::STAKE_POOL_VERSION_V1) {
+        println!("Stake pool state is not v1, no migration required.");
+        return Ok(None);
+    }
+    let pool_data = StakePool::deserialize_v1(pool_data.as_slice())?;
+
+    // the v1 list keeps the validators it can hold, the others go to a new page
+    let list_data = config
+        .rpc_client
         .get_account_data(&pool_data.validator_stake_list)?;
-    let validator_stake_list_data =
-        ValidatorStakeList::deserialize(&validator_stake_list_data.as_slice())?;
+    let validators = u16::from_le_bytes([list_data[1], list_data[2]]) as usize;
+    let page = if validators > ValidatorStakeList::capacity(list_data.len()) {
+        let page = Keypair::new();
+        println!("Creating validator stake list page {}", page.pubkey());
+        Some(page)
+    } else {
+        None
+    };
+
+    let extension_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(StakePool::EXTENSION_LEN)?;
+    let reserve_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(std::mem::size_of::<StakeState>())?;
+    let page_balance = match page {
+        Some(_) => config
+            .rpc_client
+            .get_minimum_balance_for_rent_exemption(ValidatorStakeList::LEN)?,
+        None => 0,
+    };
+
+    let mut instructions: Vec<Instruction> = vec![
+        // The owner pays the stake pool extension and reserve rent
+        system_instruction::transfer(
+            &config.fee_payer.pubkey(),
+            &config.owner.pubkey(),
+            extension_balance + reserve_balance,
+        ),
+    ];
+    if let Some(page) = &page {
+        instructions.push(system_instruction::create_account(
+            &config.fee_payer.pubkey(),
+            &page.pubkey(),
+            page_balance,
+            ValidatorStakeList::LEN as u64,
+            &spl_stake_pool::id(),
+        ));
+    }
+    instructions.push(migrate_state(
+        &spl_stake_pool::id(),
+        pool,
+        &config.owner.pubkey(),
+        &pool_data.validator_stake_list,
+        page.as_ref().map(|page| page.pubkey()).as_ref(),
+    )?);
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        extension_balance + reserve_balance + page_balance + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    if let Some(page) = &page {
+        signers.push(page);
+    }
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_deactivate_delinquent(config: &Config, pool: &Pubkey, validator: &Pubkey) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    let validator_stake_list = find_validator_stake_list_page(
+        &get_validator_stake_list_pages(config, &pool_data)?,
+        validator,
+    )?;
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[deactivate_delinquent_validator(
+            &spl_stake_pool::id(),
+            pool,
+            &pool_withdraw_authority,
+            &validator_stake_list,
+            validator,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_set_delinquent_slots(config: &Config, pool: &Pubkey, delinquent_slots: u64) -> CommandResult {
+    let mut transaction = Transaction::new_with_payer(
+        &[set_delinquent_slots(
+            &spl_stake_pool::id(),
+            pool,
+            &config.owner.pubkey(),
+            delinquent_slots,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_rebalance(config: &Config, pool: &Pubkey) -> CommandResult {
+    let mut pool_data = get_stake_pool(config, pool)?;
+    let pages = get_validator_stake_list_pages(config, &pool_data)?;
+
+    let epoch_info = config.rpc_client.get_epoch_info()?;
+    if pool_data.last_update_epoch < epoch_info.epoch {
+        return Err("Stake pool is out of date, run update first".into());
+    }
+    let total_weight = pool_data.total_target_weight;
+    if total_weight == 0 {
+        return Err("No validator target weights set, use set-validator-weight".into());
+    }
+
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+
+    // same computation as the program, validators with stake moving are skipped.
+    // The program also bounds the moves by the reserve and validator stake balances
+    let mut instructions: Vec<Instruction> = vec![];
+    for (validator_stake_list, info) in pages
+        .iter()
+        .flat_map(|(page, list)| list.validators.iter().map(move |info| (page, info)))
+    {
+        if instructions.len() == MAX_REBALANCE_MOVES {
+            println!("More moves pending, run rebalance again");
+            break;
+        }
+        if info.leaving || info.transient_balance > 0 || info.last_update_epoch < epoch_info.epoch {
+            continue;
+        }
+        let lamports = match pool_data
+            .calc_rebalance_move(info, total_weight, epoch_info.epoch)
+            .ok_or("Calculation failure")?
+        {
+            RebalanceMove::Increase(lamports) => {
+                println!("Validator {}\tincrease {} SOL", info.validator_account, lamports_to_sol(lamports));
+                lamports
+            }
+            RebalanceMove::Decrease(lamports) => {
+                println!("Validator {}\tdecrease {} SOL", info.validator_account, lamports_to_sol(lamports));
+                lamports
+            }
+            RebalanceMove::None => continue,
+        };
+        pool_data.rebalance_epoch = epoch_info.epoch;
+        pool_data.rebalanced_lamports += lamports;
+        instructions.push(rebalance(
+            &spl_stake_pool::id(),
+            pool,
+            &pool_withdraw_authority,
+            validator_stake_list,
+            &reserve_stake,
+            &info.validator_account,
+        )?);
+    }
+
+    if instructions.is_empty() {
+        println!("Validators are at their targets or the epoch limit is reached, no rebalance required.");
+        return Ok(None);
+    }
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(&[config.fee_payer.as_ref()], recent_blockhash);
+    Ok(Some(transaction))
+}
+
+//-------------------------------------
+fn command_set_treasury(
+    config: &Config,
+    treasury_share_bps: u64,
+    treasury: &Option<Pubkey>,
+) -> CommandResult {
+    if treasury_share_bps > 10_000 {
+        return Err("Treasury share must be <= 10000 bps".into());
+    }
+    // If the treasury account is missing in the arguments use the current one
+    let treasury: Pubkey = match treasury {
+        None => {
+            let liq_pool_data = config.rpc_client.get_account_data(&config.liq_pool_state_account)?;
+            LiquidityPool::deserialize(liq_pool_data.as_slice())?.treasury_wsol_account
+        }
+        Some(value) => *value,
+    };
+
+    let mut transaction = Transaction::new_with_payer(
+        &[set_treasury(
+            &config.smart_pool_program_id,
+            &config.liq_pool_state_account,
+            &config.stake_pool_state_account,
+            &config.owner.pubkey(),
+            &treasury,
+            treasury_share_bps,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_create_rate_history(config: &Config, pool: &Pubkey) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    if pool_data.has_rate_history() {
+        println!("Stake pool already has a rate history.");
+        return Ok(None);
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[create_rate_history(
+            &spl_stake_pool::id(),
+            pool,
+            &config.owner.pubkey(),
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    // the owner pays the rate history rent
+    let rent = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(RateHistory::LEN)?;
+    if config.rpc_client.get_balance(&config.owner.pubkey())? < rent {
+        return Err(format!(
+            "Owner, {}, has insufficient balance: {} required for the rate history rent",
+            config.owner.pubkey(),
+            lamports_to_sol(rent)
+        )
+        .into());
+    }
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_stats(config: &Config, pool: &Pubkey, epochs: u64) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    if !pool_data.has_rate_history() {
+        return Err("Stake pool has no rate history, create it with create-rate-history".into());
+    }
+    let (rate_history, _) = PoolProcessor::find_rate_history_address(&spl_stake_pool::id(), pool);
+    let rate_history = config.rpc_client.get_account_data(&rate_history)?;
+    let rate_history = RateHistory::deserialize(rate_history.as_slice())?;
+
+    println!("----------------");
+    println!("Rate history ({} epochs)", rate_history.entries.len());
+    println!("----------------");
+    for entry in rate_history.iter() {
+        println!(
+            "Epoch {}\tstake_total:{} SOL\tpool_total:{}\tSOL per token:{}",
+            entry.epoch,
+            lamports_to_sol(entry.stake_total),
+            lamports_to_sol(entry.pool_total),
+            entry.rate().map_or("-".to_string(), |rate| format!("{:.9}", rate)),
+        );
+    }
 
-    let epoch_info = config.rpc_client.get_epoch_info()?;
+    // nominal slot duration, the cluster is usually a bit slower
+    let epoch_schedule = config.rpc_client.get_epoch_schedule()?;
+    let epochs_per_year = 365.25 * 24.0 * 3600.0 * 1000.0
+        / (epoch_schedule.slots_per_epoch * DEFAULT_MS_PER_SLOT) as f64;
+    match rate_history.annualized_yield(epochs, epochs_per_year) {
+        Some(apy) => println!("APY over {} epochs: {:.2}%", epochs, apy * 100.0),
+        None => println!("APY over {} epochs: not enough history", epochs),
+    }
+
+    Ok(None)
+}
+
+fn command_list(config: &Config, stake_pool_state: &Pubkey) -> CommandResult {
+
+    println!("Program {:?}",&spl_stake_pool::id());
+    println!("State {:?}",&stake_pool_state);
+    // Get stake pool state
+    let stake_pool_data = get_stake_pool(config, stake_pool_state)?;
+    println!("{:?}",stake_pool_data);
+    println!("deposit fee {}/{}",stake_pool_data.fee.numerator,stake_pool_data.fee.denominator);
+    if stake_pool_data.has_pending_fee() {
+        println!("pending deposit fee {}/{} from epoch {}",
+            stake_pool_data.next_fee.numerator,stake_pool_data.next_fee.denominator,stake_pool_data.next_fee_epoch);
+    }
+
+    println!("delinquent after {} slots without a vote",stake_pool_data.delinquent_slots_threshold());
+
+    println!("PDA_LIQ_POOL_authority {}",&config.pda_liq_pool_authority);
+
+    // Calculate Deposit and Withdraw stake pool authorities
+    let pool_deposit_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        stake_pool_state,
+        PoolProcessor::AUTHORITY_DEPOSIT,
+        stake_pool_data.deposit_bump_seed,
+    )
+    .unwrap();
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        stake_pool_state,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        stake_pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+
+    println!("stake_pool_deposit_authority {}",pool_deposit_authority);
+    println!("stake_pool_withdraw_authority {}",pool_withdraw_authority);
+    for page in stake_pool_data.validator_stake_list_pages() {
+        println!("validator_stake_list {}",page);
+    }
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        stake_pool_state,
+        PoolProcessor::RESERVE,
+        stake_pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    println!("reserve_stake {}\tBalance:{}",reserve_stake, config.rpc_client.get_balance(&reserve_stake)?);
+    if stake_pool_data.has_deposit_authority() {
+        let (depositor_list, _) =
+            PoolProcessor::find_depositor_list_address(&spl_stake_pool::id(), stake_pool_state);
+        println!("deposit_authority {}", stake_pool_data.deposit_authority);
+        println!("depositor_list {}", depositor_list);
+        if let Ok(data) = config.rpc_client.get_account_data(&depositor_list) {
+            for depositor in DepositorList::deserialize(data.as_slice())?.depositors {
+                println!("depositor {}", depositor);
+            }
+        }
+    }
+    if stake_pool_data.has_rate_history() {
+        let (rate_history, _) =
+            PoolProcessor::find_rate_history_address(&spl_stake_pool::id(), stake_pool_state);
+        println!("rate_history {}", rate_history);
+    }
+    
+    let pages = get_validator_stake_list_pages(config, &stake_pool_data)?;
+    println!("----------------");
+    println!("Validators ({})",pages.iter().map(|(_, list)| list.validators.len()).sum::<usize>());
+    println!("----------------");
+    for (_, list) in pages {
+        for info in list.validators {
+            println!("Validator vote Acc {}\tBalance:{}{}", info.validator_account, info.balance,
+                if info.leaving { "\tleaving" } else { "" });
+        }
+    }
+    
+        
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &config.smart_pool_program_id,
+        stake_pool_state,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        stake_pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+
+    let accounts = get_authority_accounts(config, &pool_withdraw_authority);
+
+    if accounts.is_empty() {
+        return Err("No get_authority_accounts found.".to_string().into());
+    }
 
-    println!("Validators({})",&validator_stake_list_data.validators.len());
-    for info in &validator_stake_list_data.validators {
-        println!("Validator {}\tBalance:{}", info.validator_account, info.balance);
+    println!("----------------");
+    println!("Accounts with authorithy = pool_withdraw_authority ({})",&pool_withdraw_authority);
+    println!("----------------");
+    let mut total_balance: u64 = 0;
+    for (pubkey, account) in accounts {
+        let balance = account.lamports;
+        total_balance += balance;
+        println!("Stake Acc {}\t{} SOL", pubkey, lamports_to_sol(balance));
     }
+    println!("Total: {} SOL", lamports_to_sol(total_balance));
+
+    Ok(None)
+}
+
+fn command_update(config: &Config, pool: &Pubkey, force:bool) -> CommandResult {
+    // Get stake pool state
+    let pool_data = get_stake_pool(config, pool)?;
+    let pages = get_validator_stake_list_pages(config, &pool_data)?;
+    let validators_len: usize = pages.iter().map(|(_, list)| list.validators.len()).sum();
+
+    let epoch_info = config.rpc_client.get_epoch_info()?;
 
-    let mut accounts_to_update: Vec<&Pubkey> = Vec::new();
-    for item in validator_stake_list_data.validators.iter(){
-        if force || item.last_update_epoch < epoch_info.epoch {
-            accounts_to_update.push(&item.validator_account);
+    println!("Validators({})",validators_len);
+    for (_, list) in &pages {
+        for info in &list.validators {
+            println!("Validator {}\tBalance:{}\tTransient:{}\tWeight:{}", info.validator_account, info.balance, info.transient_balance, info.target_weight);
         }
     }
 
+    let reserve_stake: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::RESERVE,
+        pool_data.reserve_bump_seed,
+    )
+    .unwrap();
+    let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
+        &spl_stake_pool::id(),
+        pool,
+        PoolProcessor::AUTHORITY_WITHDRAW,
+        pool_data.withdraw_bump_seed,
+    )
+    .unwrap();
+
     let mut instructions: Vec<Instruction> = vec![];
-    println!("--- {} accounts_to_update", accounts_to_update.len());
-    for chunk in accounts_to_update.chunks(MAX_ACCOUNTS_TO_UPDATE) {
-        instructions.push(update_list_balance(
-            &spl_stake_pool::id(),
-            &pool_data.validator_stake_list,
-            &chunk,
-        )?);
+    for (validator_stake_list, list) in &pages {
+        let mut accounts_to_update: Vec<Pubkey> = Vec::new();
+        for item in list.validators.iter(){
+            if force || item.last_update_epoch < epoch_info.epoch {
+                accounts_to_update.push(item.validator_account);
+            }
+        }
+
+        println!("--- {} accounts_to_update in {}", accounts_to_update.len(), validator_stake_list);
+        for chunk in accounts_to_update.chunks(MAX_ACCOUNTS_TO_UPDATE) {
+            instructions.push(update_list_balance(
+                &spl_stake_pool::id(),
+                pool,
+                validator_stake_list,
+                &pool_withdraw_authority,
+                &reserve_stake,
+                chunk,
+            )?);
+        }
     }
 
     // LMT: If there are no validators registered, we need to call update_pool_balance ANYWAY to update stake_pool.last_update_epoch = clock.epoch
     // in case the pool was create in the prev-epoch and no validators were added in that epoch
-    if instructions.is_empty() && validator_stake_list_data.validators.len()>0 {
+    if instructions.is_empty() && validators_len>0 {
         println!("Stake pool balances are up to date, no update required.");
         Ok(None)
     } else {
-        instructions.push(update_pool_balance(
+        let mut update_pool_balance_instruction = update_pool_balance(
             &spl_stake_pool::id(),
             pool,
             &pool_data.validator_stake_list,
-        )?);
+            &reserve_stake,
+            &pool_withdraw_authority,
+            &pool_data.owner_fee_account,
+            &pool_data.pool_mint,
+            &spl_token::id(),
+            &pool_data.validator_stake_list_pages[..pool_data.validator_stake_list_pages_len as usize],
+        )?;
+        if pool_data.has_rate_history() {
+            update_pool_balance_instruction = with_rate_history(update_pool_balance_instruction, pool);
+        }
+        instructions.push(update_pool_balance_instruction);
         println!("-- sending {} instructions", &instructions.len());
         let mut transaction =
             Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
@@ -1189,8 +2216,7 @@ fn command_withdraw(
     stake_receiver_param: &Option<Pubkey>,
 ) -> CommandResult {
     // Get stake pool state
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -1248,6 +2274,8 @@ fn command_withdraw(
     // Use separate mutable variable because withdraw might create a new account
     let mut stake_receiver: Option<Pubkey> = *stake_receiver_param;
 
+    let pages = get_validator_stake_list_pages(config, &pool_data)?;
+
     // Go through prepared accounts and withdraw/claim them
     for withdraw_stake in withdraw_from {
         println!(
@@ -1285,15 +2313,22 @@ fn command_withdraw(
             stake_receiver = Some(stake_receiver_account.pubkey());
         }
 
+        let validator_stake_list = match deserialize(withdraw_stake.account.data.as_slice()) {
+            Ok(StakeState::Stake(_, stake)) => {
+                find_validator_stake_list_page(&pages, &stake.delegation.voter_pubkey)?
+            }
+            _ => pool_data.validator_stake_list,
+        };
         instructions.push(withdraw(
             &spl_stake_pool::id(),
             &pool,
-            &pool_data.validator_stake_list,
+            &validator_stake_list,
             &pool_withdraw_authority,
             &withdraw_stake.pubkey,
             &stake_receiver.unwrap(), // Cannot be none at this point
             &config.owner.pubkey(),
             &burn_from,
+            &pool_data.owner_fee_account,
             &pool_data.pool_mint,
             &spl_token::id(),
             &stake_program_id(),
@@ -1321,8 +2356,7 @@ fn command_set_staking_auth(
     stake_account: &Pubkey,
     new_staker: &Pubkey,
 ) -> CommandResult {
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     let pool_withdraw_authority: Pubkey = PoolProcessor::authority_id(
         &spl_stake_pool::id(),
@@ -1353,18 +2387,83 @@ fn command_set_staking_auth(
     Ok(Some(transaction))
 }
 
-fn command_set_owner(
+fn command_set_owner(config: &Config, pool: &Pubkey, new_owner: &Pubkey) -> CommandResult {
+    // The new owner takes over once it runs accept-owner
+    let mut transaction = Transaction::new_with_payer(
+        &[set_owner(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+            new_owner,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_accept_owner(config: &Config, pool: &Pubkey) -> CommandResult {
+    let pool_data = get_stake_pool(config, pool)?;
+    if pool_data.pending_owner != config.owner.pubkey() {
+        return Err(format!(
+            "{} is not the pending owner of the stake pool",
+            config.owner.pubkey()
+        )
+        .into());
+    }
+
+    let mut transaction = Transaction::new_with_payer(
+        &[accept_owner(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_set_staker(config: &Config, pool: &Pubkey, new_staker: &Pubkey) -> CommandResult {
+    let mut transaction = Transaction::new_with_payer(
+        &[set_staker(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+            new_staker,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_set_fee_manager(
     config: &Config,
     pool: &Pubkey,
-    new_owner: &Option<Pubkey>,
+    new_fee_manager: &Option<Pubkey>,
     new_fee_receiver: &Option<Pubkey>,
 ) -> CommandResult {
-    let pool_data = config.rpc_client.get_account_data(&pool)?;
-    let pool_data: StakePool = StakePool::deserialize(pool_data.as_slice()).unwrap();
+    let pool_data = get_stake_pool(config, pool)?;
 
     // If new accounts are missing in the arguments use the old ones
-    let new_owner: Pubkey = match new_owner {
-        None => pool_data.owner,
+    let new_fee_manager: Pubkey = match new_fee_manager {
+        None => pool_data.fee_manager,
         Some(value) => *value,
     };
     let new_fee_receiver: Pubkey = match new_fee_receiver {
@@ -1389,11 +2488,11 @@ fn command_set_owner(
     };
 
     let mut transaction = Transaction::new_with_payer(
-        &[set_owner(
+        &[set_fee_manager(
             &spl_stake_pool::id(),
             &pool,
             &config.owner.pubkey(),
-            &new_owner,
+            &new_fee_manager,
             &new_fee_receiver,
         )?],
         Some(&config.fee_payer.pubkey()),
@@ -1407,6 +2506,80 @@ fn command_set_owner(
     Ok(Some(transaction))
 }
 
+fn command_set_deposit_authority(
+    config: &Config,
+    pool: &Pubkey,
+    new_deposit_authority: &Pubkey,
+) -> CommandResult {
+    let mut transaction = Transaction::new_with_payer(
+        &[set_deposit_authority(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+            new_deposit_authority,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+fn command_set_depositor(
+    config: &Config,
+    pool: &Pubkey,
+    depositor: &Pubkey,
+    allowed: bool,
+) -> CommandResult {
+    let mut transaction = Transaction::new_with_payer(
+        &[set_depositor(
+            &spl_stake_pool::id(),
+            &pool,
+            &config.owner.pubkey(),
+            depositor,
+            allowed,
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    // the owner pays the depositor list rent, when the first depositor is added
+    let (depositor_list, _) = PoolProcessor::find_depositor_list_address(&spl_stake_pool::id(), pool);
+    if allowed && config.rpc_client.get_balance(&depositor_list)? == 0 {
+        let rent = config
+            .rpc_client
+            .get_minimum_balance_for_rent_exemption(DepositorList::LEN)?;
+        if config.rpc_client.get_balance(&config.owner.pubkey())? < rent {
+            return Err(format!(
+                "Owner, {}, has insufficient balance: {} required for the depositor list rent",
+                config.owner.pubkey(),
+                lamports_to_sol(rent)
+            )
+            .into());
+        }
+    }
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    unique_signers!(signers);
+    transaction.sign(&signers, recent_blockhash);
+    Ok(Some(transaction))
+}
+
+/// Sell fee curve from the --min-fee-bps, --max-fee-bps & --target-liquidity args
+fn sell_fee_curve_of(arg_matches: &ArgMatches) -> SellFeeCurve {
+    SellFeeCurve {
+        min_fee_bps: value_t_or_exit!(arg_matches, "min_fee_bps", u64),
+        max_fee_bps: value_t_or_exit!(arg_matches, "max_fee_bps", u64),
+        // convert from float to int, using sol_to_lamports because they have the same precision as SOL
+        target_liquidity: sol_to_lamports(value_t_or_exit!(arg_matches, "target_liquidity", f64)),
+    }
+}
+
 fn main() {
     let matches = App::new(crate_name!())
         .about(crate_description!())
@@ -1489,6 +2662,122 @@ fn main() {
         //     )
         // )
         .subcommand(SubCommand::with_name("create-liq-pool").about("Create the liquidity pool & $METALP token")
+            .arg(
+                Arg::with_name("min_fee_bps")
+                    .long("min-fee-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .default_value("30")
+                    .help("Sell fee in basis points when the wSOL leg is at or above the target liquidity."),
+            )
+            .arg(
+                Arg::with_name("max_fee_bps")
+                    .long("max-fee-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .default_value("300")
+                    .help("Sell fee in basis points when the wSOL leg is empty."),
+            )
+            .arg(
+                Arg::with_name("target_liquidity")
+                    .long("target-liquidity")
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .default_value("100")
+                    .help("wSOL in the pool (in SOL) at and above which the min fee applies. The fee grows linearly to the max fee below it."),
+            )
+            .arg(
+                Arg::with_name("treasury")
+                    .long("treasury")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("wSOL token account to receive the treasury share of the sell fee. Defaults to a new token account owned by the owner."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-treasury").about("Set the treasury wSOL account and its share of the sell fee. Must be signed by the pool owner.")
+            .arg(
+                Arg::with_name("share_bps")
+                    .long("share-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Share of the sell fee sent to the treasury, in basis points. The rest stays in the liquidity pool."),
+            )
+            .arg(
+                Arg::with_name("treasury")
+                    .long("treasury")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("wSOL token account to receive the treasury share. Defaults to the current treasury account."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-buy-fee").about("Set the fee applied when buying stSOL from the liquidity pool. Must be signed by the pool fee manager.")
+            .arg(
+                Arg::with_name("fee_bps")
+                    .long("fee-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Buy fee in basis points, the fee stays in the liquidity pool."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-fee").about("Schedule a new deposit fee, it takes effect 2 epochs later. Must be signed by the pool fee manager.")
+            .arg(
+                Arg::with_name("fee_numerator")
+                    .long("fee-numerator")
+                    .short("n")
+                    .validator(is_parsable::<u64>)
+                    .value_name("NUMERATOR")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Fee numerator, fee amount is numerator divided by denominator."),
+            )
+            .arg(
+                Arg::with_name("fee_denominator")
+                    .long("fee-denominator")
+                    .short("d")
+                    .validator(is_parsable::<u64>)
+                    .value_name("DENOMINATOR")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Fee denominator, fee amount is numerator divided by denominator."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-sell-fee").about("Update the liquidity pool sell fee curve. Must be signed by the pool fee manager.")
+            .arg(
+                Arg::with_name("min_fee_bps")
+                    .long("min-fee-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Sell fee in basis points when the wSOL leg is at or above the target liquidity."),
+            )
+            .arg(
+                Arg::with_name("max_fee_bps")
+                    .long("max-fee-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Sell fee in basis points when the wSOL leg is empty."),
+            )
+            .arg(
+                Arg::with_name("target_liquidity")
+                    .long("target-liquidity")
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("wSOL in the pool (in SOL) at and above which the min fee applies. The fee grows linearly to the max fee below it."),
+            )
         )
         .subcommand(SubCommand::with_name("create-validator-stake").about("Create a new validator stake account to use with the pool")
             //    
@@ -1512,7 +2801,7 @@ fn main() {
                     .help("Validator this stake account will vote for"),
             )
         )
-        .subcommand(SubCommand::with_name("add-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool owner.")
+        .subcommand(SubCommand::with_name("add-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool staker.")
             // .arg(
             //     Arg::with_name("pool")
             //         .long("pool")
@@ -1540,7 +2829,7 @@ fn main() {
                     .help("Account to receive pool token. Must be initialized account of the stake pool token. Defaults to the new pool token account."),
             )
         )
-        .subcommand(SubCommand::with_name("remove-validator-stake").about("Add validator stake account to the stake pool. Must be signed by the pool owner.")
+        .subcommand(SubCommand::with_name("remove-validator-stake").about("Remove validator stake account from the stake pool. Must be signed by the pool staker.")
             // .arg(
             //     Arg::with_name("pool")
             //         .long("pool")
@@ -1605,6 +2894,126 @@ fn main() {
                     .help("Account to receive pool token. Must be initialized account of the stake pool token. Defaults to the new pool token account."),
             )
         )
+        .subcommand(SubCommand::with_name("deposit-sol").about("Deposit SOL into the stake pool reserve and receive stSOL")
+            .arg(
+                Arg::with_name("amount")
+                    .index(1)
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Amount of SOL to deposit, taken from the owner account."),
+            )
+            .arg(
+                Arg::with_name("token_receiver")
+                    .long("token-receiver")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("Account to receive pool token. Must be initialized account of the stake pool token. Defaults to the new pool token account."),
+            )
+        )
+        .subcommand(SubCommand::with_name("increase-validator-stake").about("Move SOL from the reserve to a validator, it is delegated at the next epoch. Must be signed by the pool staker.")
+            .arg(
+                Arg::with_name("validator")
+                    .long("validator")
+                    .validator(is_pubkey)
+                    .value_name("VOTE_ACCOUNT_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Validator vote account address."),
+            )
+            .arg(
+                Arg::with_name("amount")
+                    .index(1)
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Amount of SOL to move from the reserve."),
+            )
+        )
+        .subcommand(SubCommand::with_name("decrease-validator-stake").about("Move SOL from a validator back to the reserve, it is deactivated at the next epoch. Must be signed by the pool staker.")
+            .arg(
+                Arg::with_name("validator")
+                    .long("validator")
+                    .validator(is_pubkey)
+                    .value_name("VOTE_ACCOUNT_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Validator vote account address."),
+            )
+            .arg(
+                Arg::with_name("amount")
+                    .index(1)
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Amount of SOL to move from the validator stake account."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-validator-weight").about("Set the validator target weight, its share of the pool stake for rebalance. Must be signed by the pool staker.")
+            .arg(
+                Arg::with_name("validator")
+                    .long("validator")
+                    .validator(is_pubkey)
+                    .value_name("VOTE_ACCOUNT_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Validator vote account address."),
+            )
+            .arg(
+                Arg::with_name("weight")
+                    .index(1)
+                    .validator(is_parsable::<u64>)
+                    .value_name("WEIGHT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Target weight, relative to the sum of all validator weights."),
+            )
+        )
+        .subcommand(SubCommand::with_name("add-validator-list-page").about("Link a new validator stake list page to the pool, once the existing pages are full. Must be signed by the pool staker.")
+        )
+        .subcommand(SubCommand::with_name("migrate-state").about("Upgrade a v1 stake pool and its validator stake list in place to the current state layout. Must be signed by the pool owner.")
+        )
+        .subcommand(SubCommand::with_name("rebalance").about("Move stake towards the validators target weights, bounded per epoch. Anyone can run it.")
+        )
+        .subcommand(SubCommand::with_name("deactivate-delinquent").about("Deactivate the stake of a validator that stopped voting, it is removed from the pool once inactive. Anyone can run it.")
+            .arg(
+                Arg::with_name("validator")
+                    .long("validator")
+                    .validator(is_pubkey)
+                    .value_name("VOTE_ACCOUNT_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Validator vote account address."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-delinquent-slots").about("Set how many slots without a vote make a validator delinquent, 0 for the default. Must be signed by the pool staker.")
+            .arg(
+                Arg::with_name("slots")
+                    .index(1)
+                    .validator(is_parsable::<u64>)
+                    .value_name("SLOTS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Slots without a vote."),
+            )
+        )
+        .subcommand(SubCommand::with_name("create-rate-history").about("Create the pool rate history, update records the pool balances of each epoch there. Must be signed by the pool owner.")
+        )
+        .subcommand(SubCommand::with_name("stats").about("Show the pool rate history and the annualized yield")
+            .arg(
+                Arg::with_name("epochs")
+                    .long("epochs")
+                    .validator(is_parsable::<u64>)
+                    .value_name("EPOCHS")
+                    .takes_value(true)
+                    .default_value("10")
+                    .help("Epochs the annualized yield is computed over."),
+            )
+        )
         .subcommand(SubCommand::with_name("list").about("List stake accounts managed by this pool")
             // .arg(
             //     Arg::with_name("pool")
@@ -1695,24 +3104,39 @@ fn main() {
                     .help("Public key of the new staker account."),
             )
         )
-        .subcommand(SubCommand::with_name("set-owner").about("Changes owner or fee receiver account for the stake pool.")
-            // .arg(
-            //     Arg::with_name("pool")
-            //         .long("pool")
-            //         .validator(is_pubkey)
-            //         .value_name("ADDRESS")
-            //         .takes_value(true)
-            //         .required(true)
-            //         .help("Stake pool address."),
-            // )
+        .subcommand(SubCommand::with_name("set-owner").about("Propose a new owner for the stake pool, it takes over once it runs accept-owner. Must be signed by the pool owner.")
             .arg(
                 Arg::with_name("new_owner")
                     .long("new-owner")
                     .validator(is_pubkey)
                     .value_name("ADDRESS")
                     .takes_value(true)
+                    .required(true)
                     .help("Public key for the new stake pool owner."),
             )
+        )
+        .subcommand(SubCommand::with_name("accept-owner").about("Become the stake pool owner proposed by set-owner. Must be signed by the new owner.")
+        )
+        .subcommand(SubCommand::with_name("set-staker").about("Changes the staker, it manages the validators and their stake. Must be signed by the pool owner or staker.")
+            .arg(
+                Arg::with_name("new_staker")
+                    .long("new-staker")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Public key for the new stake pool staker."),
+            )
+        )
+        .subcommand(SubCommand::with_name("set-fee-manager").about("Changes the fee manager or the fee receiver account. Must be signed by the pool owner or fee manager.")
+            .arg(
+                Arg::with_name("new_fee_manager")
+                    .long("new-fee-manager")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("Public key for the new stake pool fee manager."),
+            )
             .arg(
                 Arg::with_name("new_fee_receiver")
                     .long("new-fee-receiver")
@@ -1722,12 +3146,50 @@ fn main() {
                     .help("Public key for the new account to set as the stake pool fee receiver."),
             )
             .group(ArgGroup::with_name("new_accounts")
-                .arg("new_owner")
+                .arg("new_fee_manager")
                 .arg("new_fee_receiver")
                 .required(true)
                 .multiple(true)
             )
         )
+        .subcommand(SubCommand::with_name("set-deposit-authority").about("Restricts deposits to the deposit authority and the depositors added by set-depositor, or opens the pool to anyone. Must be signed by the pool owner.")
+            .arg(
+                Arg::with_name("new_deposit_authority")
+                    .long("new-deposit-authority")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("Public key for the new stake pool deposit authority."),
+            )
+            .arg(
+                Arg::with_name("permissionless")
+                    .long("permissionless")
+                    .takes_value(false)
+                    .help("Remove the deposit authority, anyone can deposit."),
+            )
+            .group(ArgGroup::with_name("deposit_authority")
+                .arg("new_deposit_authority")
+                .arg("permissionless")
+                .required(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("set-depositor").about("Adds a depositor to the pool depositor list, or removes it. Must be signed by the pool owner.")
+            .arg(
+                Arg::with_name("depositor")
+                    .long("depositor")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Public key of the depositor."),
+            )
+            .arg(
+                Arg::with_name("remove")
+                    .long("remove")
+                    .takes_value(false)
+                    .help("Remove the depositor from the list."),
+            )
+        )
         .subcommand(SubCommand::with_name("add-liquidity").about("Add wSOL amount to wSOL/stSOL Liquidity pool")
             .arg(
                 Arg::with_name("amount")
@@ -1749,6 +3211,23 @@ fn main() {
                     .required(true)
                     .help("wSOL token account to take wSOL from. Must be owned by the signer."),
             )
+            .arg(
+                Arg::with_name("min_out")
+                    .long("min-out")
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .conflicts_with("max_slippage_bps")
+                    .help("Minimum $METALP to receive, the transaction fails if the output is lower."),
+            )
+            .arg(
+                Arg::with_name("max_slippage_bps")
+                    .long("max-slippage-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .help("Maximum slippage from the current quote, in basis points. Sets the minimum output."),
+            )
         )
         .subcommand(SubCommand::with_name("sell").about("Sell stSOL for wSOL using the liquidity pool")
             .arg(
@@ -1771,6 +3250,100 @@ fn main() {
                     .required(true)
                     .help("stSOL token account to take stSOL from. Must be owned by the signer."),
             )
+            .arg(
+                Arg::with_name("min_out")
+                    .long("min-out")
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .conflicts_with("max_slippage_bps")
+                    .help("Minimum wSOL to receive, the transaction fails if the output is lower."),
+            )
+            .arg(
+                Arg::with_name("max_slippage_bps")
+                    .long("max-slippage-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .help("Maximum slippage from the current quote, in basis points. Sets the minimum output."),
+            )
+        )
+        .subcommand(SubCommand::with_name("buy").about("Buy stSOL from the liquidity pool with wSOL")
+            .arg(
+                Arg::with_name("amount")
+                    .short("a")
+                    .index(1)
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Amount of wSOL to pay."),
+            )
+            .arg(
+                Arg::with_name("source")
+                    .short("s")
+                    .long("source")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("wSOL token account to take wSOL from. Must be owned by the signer."),
+            )
+            .arg(
+                Arg::with_name("min_out")
+                    .long("min-out")
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .conflicts_with("max_slippage_bps")
+                    .help("Minimum stSOL to receive, the transaction fails if the output is lower."),
+            )
+            .arg(
+                Arg::with_name("max_slippage_bps")
+                    .long("max-slippage-bps")
+                    .validator(is_parsable::<u64>)
+                    .value_name("BPS")
+                    .takes_value(true)
+                    .help("Maximum slippage from the current quote, in basis points. Sets the minimum output."),
+            )
+        )
+        .subcommand(SubCommand::with_name("remove-liquidity").about("Burn $METALP and receive the corresponding wSOL & stSOL from the Liquidity pool")
+            .arg(
+                Arg::with_name("amount")
+                    .short("a")
+                    .index(1)
+                    .validator(is_amount)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Amount of $METALP to burn."),
+            )
+            .arg(
+                Arg::with_name("source")
+                    .short("s")
+                    .long("source")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .help("$METALP token account to burn from. Must be owned by the signer."),
+            )
+            .arg(
+                Arg::with_name("wsol_receiver")
+                    .long("wsol-receiver")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("wSOL token account to receive wSOL. Defaults to a new token account."),
+            )
+            .arg(
+                Arg::with_name("st_sol_receiver")
+                    .long("stsol-receiver")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .help("stSOL token account to receive stSOL. Defaults to a new token account."),
+            )
         )
         .get_matches();
 
@@ -1852,11 +3425,33 @@ fn main() {
         //         },
         //     )
         // }
-        ("create-liq-pool", Some(_arg_matches)) => {
-            command_create_liquidity_pool(
-                &config
+        ("create-liq-pool", Some(arg_matches)) => {
+            let treasury: Option<Pubkey> = pubkey_of(arg_matches, "treasury");
+            command_create_liquidity_pool(&config, sell_fee_curve_of(arg_matches), &treasury)
+        }
+        ("set-treasury", Some(arg_matches)) => {
+            let share_bps = value_t_or_exit!(arg_matches, "share_bps", u64);
+            let treasury: Option<Pubkey> = pubkey_of(arg_matches, "treasury");
+            command_set_treasury(&config, share_bps, &treasury)
+        }
+        ("set-buy-fee", Some(arg_matches)) => {
+            let fee_bps = value_t_or_exit!(arg_matches, "fee_bps", u64);
+            command_set_buy_fee(&config, fee_bps)
+        }
+        ("set-fee", Some(arg_matches)) => {
+            let numerator = value_t_or_exit!(arg_matches, "fee_numerator", u64);
+            let denominator = value_t_or_exit!(arg_matches, "fee_denominator", u64);
+            command_set_fee(
+                &config,
+                PoolFee {
+                    numerator,
+                    denominator,
+                },
             )
         }
+        ("set-sell-fee", Some(arg_matches)) => {
+            command_set_sell_fee(&config, sell_fee_curve_of(arg_matches))
+        }
         ("create-validator-stake", Some(arg_matches)) => {
             //let pool_account: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
             let validator_account: Pubkey = pubkey_of(arg_matches, "validator").unwrap();
@@ -1889,6 +3484,50 @@ fn main() {
             let token_receiver: Option<Pubkey> = pubkey_of(arg_matches, "token_receiver");
             command_deposit(&config, &pool_account, &stake_account, &token_receiver)
         }
+        ("deposit-sol", Some(arg_matches)) => {
+            let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
+            let token_receiver: Option<Pubkey> = pubkey_of(arg_matches, "token_receiver");
+            command_deposit_sol(&config, &pool_account, amount, &token_receiver)
+        }
+        ("increase-validator-stake", Some(arg_matches)) => {
+            let validator: Pubkey = pubkey_of(arg_matches, "validator").unwrap();
+            let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
+            command_increase_validator_stake(&config, &pool_account, &validator, amount)
+        }
+        ("decrease-validator-stake", Some(arg_matches)) => {
+            let validator: Pubkey = pubkey_of(arg_matches, "validator").unwrap();
+            let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
+            command_decrease_validator_stake(&config, &pool_account, &validator, amount)
+        }
+        ("set-validator-weight", Some(arg_matches)) => {
+            let validator: Pubkey = pubkey_of(arg_matches, "validator").unwrap();
+            let weight = value_t_or_exit!(arg_matches, "weight", u64);
+            command_set_validator_weight(&config, &pool_account, &validator, weight)
+        }
+        ("add-validator-list-page", Some(_arg_matches)) => {
+            command_add_validator_list_page(&config, &pool_account)
+        }
+        ("migrate-state", Some(_arg_matches)) => {
+            command_migrate_state(&config, &pool_account)
+        }
+        ("rebalance", Some(_arg_matches)) => {
+            command_rebalance(&config, &pool_account)
+        }
+        ("deactivate-delinquent", Some(arg_matches)) => {
+            let validator: Pubkey = pubkey_of(arg_matches, "validator").unwrap();
+            command_deactivate_delinquent(&config, &pool_account, &validator)
+        }
+        ("set-delinquent-slots", Some(arg_matches)) => {
+            let delinquent_slots = value_t_or_exit!(arg_matches, "slots", u64);
+            command_set_delinquent_slots(&config, &pool_account, delinquent_slots)
+        }
+        ("create-rate-history", Some(_arg_matches)) => {
+            command_create_rate_history(&config, &pool_account)
+        }
+        ("stats", Some(arg_matches)) => {
+            let epochs = value_t_or_exit!(arg_matches, "epochs", u64);
+            command_stats(&config, &pool_account, epochs)
+        }
         ("list", Some(_arg_matches)) => {
             //let pool_account: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
             command_list(&config, &pool_account)
@@ -1916,7 +3555,9 @@ fn main() {
             let source: Pubkey = pubkey_of(arg_matches, "source").unwrap();
             // convert from float to int, using sol_to_lamports because they have the same precision as SOL
             let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
-            command_add_liquidity(&config, &source, amount, &None)
+            let min_out: Option<u64> = value_t!(arg_matches, "min_out", f64).ok().map(sol_to_lamports);
+            let max_slippage_bps: Option<u64> = value_t!(arg_matches, "max_slippage_bps", u64).ok();
+            command_add_liquidity(&config, &source, amount, min_out, max_slippage_bps, &None)
         }
         
         ("sell", Some(arg_matches)) => {
@@ -1924,7 +3565,29 @@ fn main() {
             let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
             // source token acc with stSOL
             let source: Pubkey = pubkey_of(arg_matches, "source").unwrap();
-            command_sell_st_sol(&config, &source, amount, &None)
+            let min_out: Option<u64> = value_t!(arg_matches, "min_out", f64).ok().map(sol_to_lamports);
+            let max_slippage_bps: Option<u64> = value_t!(arg_matches, "max_slippage_bps", u64).ok();
+            command_sell_st_sol(&config, &source, amount, min_out, max_slippage_bps, &None)
+        }
+
+        ("buy", Some(arg_matches)) => {
+            // convert from float to int, using sol_to_lamports because they have the same precision as SOL
+            let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
+            // source token acc with wSOL
+            let source: Pubkey = pubkey_of(arg_matches, "source").unwrap();
+            let min_out: Option<u64> = value_t!(arg_matches, "min_out", f64).ok().map(sol_to_lamports);
+            let max_slippage_bps: Option<u64> = value_t!(arg_matches, "max_slippage_bps", u64).ok();
+            command_buy_st_sol(&config, &source, amount, min_out, max_slippage_bps, &None)
+        }
+
+        ("remove-liquidity", Some(arg_matches)) => {
+            // convert from float to int, using sol_to_lamports because they have the same precision as SOL
+            let amount: u64 = sol_to_lamports(value_t_or_exit!(arg_matches, "amount", f64));
+            // source token acc with $METALP
+            let source: Pubkey = pubkey_of(arg_matches, "source").unwrap();
+            let wsol_receiver: Option<Pubkey> = pubkey_of(arg_matches, "wsol_receiver");
+            let st_sol_receiver: Option<Pubkey> = pubkey_of(arg_matches, "st_sol_receiver");
+            command_remove_liquidity(&config, &source, amount, &wsol_receiver, &st_sol_receiver)
         }
 
         ("set-staking-auth", Some(arg_matches)) => {
@@ -1935,11 +3598,30 @@ fn main() {
             command_set_staking_auth(&config, &pool_account, &stake_account, &new_staker)
         }
         ("set-owner", Some(arg_matches)) => {
-            //let pool_account: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
-            
-            let new_owner: Option<Pubkey> = pubkey_of(arg_matches, "new_owner");
+            let new_owner: Pubkey = pubkey_of(arg_matches, "new_owner").unwrap();
+            command_set_owner(&config, &pool_account, &new_owner)
+        }
+        ("accept-owner", Some(_arg_matches)) => {
+            command_accept_owner(&config, &pool_account)
+        }
+        ("set-staker", Some(arg_matches)) => {
+            let new_staker: Pubkey = pubkey_of(arg_matches, "new_staker").unwrap();
+            command_set_staker(&config, &pool_account, &new_staker)
+        }
+        ("set-fee-manager", Some(arg_matches)) => {
+            let new_fee_manager: Option<Pubkey> = pubkey_of(arg_matches, "new_fee_manager");
             let new_fee_receiver: Option<Pubkey> = pubkey_of(arg_matches, "new_fee_receiver");
-            command_set_owner(&config, &pool_account, &new_owner, &new_fee_receiver)
+            command_set_fee_manager(&config, &pool_account, &new_fee_manager, &new_fee_receiver)
+        }
+        ("set-deposit-authority", Some(arg_matches)) => {
+            let new_deposit_authority: Pubkey =
+                pubkey_of(arg_matches, "new_deposit_authority").unwrap_or_default();
+            command_set_deposit_authority(&config, &pool_account, &new_deposit_authority)
+        }
+        ("set-depositor", Some(arg_matches)) => {
+            let depositor: Pubkey = pubkey_of(arg_matches, "depositor").unwrap();
+            let allowed = !arg_matches.is_present("remove");
+            command_set_depositor(&config, &pool_account, &depositor, allowed)
         }
         _ => unreachable!(),
     }
@@ -91,6 +91,84 @@ pub enum StakePoolError {
     /// NotEnoughTokensInThePool
     #[error("NotEnoughTokensInThePool")]
     NotEnoughTokensInThePool,
+    /// Stake pool account does not match the one the liquidity pool was created for.
+    #[error("WrongStakePool")]
+    WrongStakePool,
+    /// Liquidity pool leg or $METALP mint does not match the liquidity pool state.
+    #[error("WrongLiquidityPoolAccount")]
+    WrongLiquidityPoolAccount,
+    /// Sell fee curve is invalid: min fee must be <= max fee <= 100%.
+    #[error("InvalidSellFeeCurve")]
+    InvalidSellFeeCurve,
+    /// Output amount is below the minimum requested by the user.
+    #[error("SlippageExceeded")]
+    SlippageExceeded,
+    /// Treasury account does not match the liquidity pool state.
+    #[error("WrongTreasuryAccount")]
+    WrongTreasuryAccount,
+    /// Unstake ticket index is out of range, the ticket is not pending, or an account does not match it.
+    #[error("InvalidUnstakeTicket")]
+    InvalidUnstakeTicket,
+    /// Unstake ticket is already pending.
+    #[error("UnstakeTicketInUse")]
+    UnstakeTicketInUse,
+    /// Unstake ticket stake is still cooling down.
+    #[error("UnstakeTicketNotReady")]
+    UnstakeTicketNotReady,
+    /// Validator transient stake account is still being activated or deactivated.
+    #[error("TransientAccountInUse")]
+    TransientAccountInUse,
+    /// Reserve stake account does not have enough lamports above its rent.
+    #[error("ReserveStakeTooLow")]
+    ReserveStakeTooLow,
+    /// Validator stake is at its target, or the epoch rebalance limit is reached.
+    #[error("RebalanceNotNeeded")]
+    RebalanceNotNeeded,
+    /// Validator stake list page is full, add a new page to the pool.
+    #[error("ValidatorStakeListFull")]
+    ValidatorStakeListFull,
+    /// Stake pool already links the maximum number of validator stake list pages.
+    #[error("TooManyValidatorStakeListPages")]
+    TooManyValidatorStakeListPages,
+    /// Stake pool state uses an older layout, run MigrateState.
+    #[error("StateNeedsMigration")]
+    StateNeedsMigration,
+    /// State version is unknown, or not the one expected by the instruction.
+    #[error("InvalidStateVersion")]
+    InvalidStateVersion,
+    /// Staker does not match.
+    #[error("WrongStaker")]
+    WrongStaker,
+    /// Fee manager does not match.
+    #[error("WrongFeeManager")]
+    WrongFeeManager,
+    /// Signer is not the pending owner, or no owner transfer is pending.
+    #[error("WrongPendingOwner")]
+    WrongPendingOwner,
+    /// Depositor is not the deposit authority and is not in the pool depositor list.
+    #[error("DepositorNotAllowed")]
+    DepositorNotAllowed,
+    /// Depositor list account does not match the stake pool.
+    #[error("InvalidDepositorList")]
+    InvalidDepositorList,
+    /// Depositor list is full.
+    #[error("DepositorListFull")]
+    DepositorListFull,
+    /// Validator is leaving the pool, its stake is being deactivated.
+    #[error("ValidatorLeaving")]
+    ValidatorLeaving,
+    /// Validator voted within the pool delinquency threshold.
+    #[error("ValidatorNotDelinquent")]
+    ValidatorNotDelinquent,
+    /// Rate history account does not match the stake pool.
+    #[error("InvalidRateHistory")]
+    InvalidRateHistory,
+    /// Stake pool extension account does not match the stake pool.
+    #[error("InvalidStakePoolExtension")]
+    InvalidStakePoolExtension,
+    /// Unstake amount is below the liquidity pool stSOL balance share of one ticket.
+    #[error("UnstakeAmountTooLow")]
+    UnstakeAmountTooLow,
 }
 
 impl From<StakePoolError> for ProgramError {
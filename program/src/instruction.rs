@@ -2,17 +2,18 @@
 
 #![allow(clippy::too_many_arguments)]
 
+use crate::{processor::Processor, stake, state::SellFeeCurve};
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::instruction::AccountMeta;
 use solana_program::instruction::Instruction;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
 use solana_program::sysvar;
-use std::mem::size_of;
 
 /// Fee rate as a ratio
-/// Fee is minted on deposit
-#[repr(C)]
-#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// Fee is minted on deposit (and on rewards, for the reward fee)
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
 pub struct Fee {
     /// denominator of the fee ratio
     pub denominator: u64,
@@ -21,27 +22,85 @@ pub struct Fee {
 }
 
 /// Inital values for the Stake Pool
-#[repr(C)]
-#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
 pub struct InitArgs {
     /// Fee paid to the owner in pool tokens
     pub fee: Fee,
+    /// Fee taken by the owner from epoch staking rewards
+    pub reward_fee: Fee,
+    /// Fee applied to withdrawals
+    pub withdrawal_fee: Fee,
+    /// 0: withdrawal fee stays in the pool, otherwise it goes to the owner fee account
+    pub withdrawal_fee_to_owner: u8,
+}
+
+/// Arguments for AddLiquidity
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct AddLiquidityArgs {
+    /// wSOL amount to add
+    pub amount: u64,
+    /// minimum $METALP to receive, the instruction fails if less would be minted
+    pub min_metalp_out: u64,
+}
+
+/// Arguments for SellstSOL
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct SellArgs {
+    /// stSOL amount to sell
+    pub amount: u64,
+    /// minimum wSOL to receive after the fee, the instruction fails if less would be paid
+    pub min_wsol_out: u64,
+}
+
+/// Arguments for BuyStSol
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct BuyArgs {
+    /// wSOL amount to pay
+    pub amount: u64,
+    /// minimum stSOL to receive, the instruction fails if less would be paid
+    pub min_stsol_out: u64,
+}
+
+/// Arguments for LiquidityPoolUnstake
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct UnstakeArgs {
+    /// stSOL amount to unstake from the liq pool stSOL leg
+    pub amount: u64,
+    /// index of the free unstake ticket to use
+    pub ticket: u64,
+}
+
+/// Max validators in one SetValidatorWeights instruction
+pub const MAX_VALIDATOR_WEIGHTS: usize = 8;
+
+/// Arguments for SetValidatorWeights
+#[derive(Clone, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ValidatorWeights {
+    /// target weight of each validator vote account passed, in order, one per account
+    pub weights: Vec<u64>,
 }
 
 /// Instructions supported by the StakePool program.
-#[repr(C)]
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Instruction data is the Borsh encoding of the variant: the variant index as a u8
+/// followed by its fields, integers little endian. `interface.json` at the program
+/// root lists every instruction with its data fields and accounts.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum StakePoolInstruction {
     ///   Admin: Initializes a new StakePool.
     ///
     ///   0. `[w]` New StakePool to create.
-    ///   1. `[s]` Owner
-    ///   2. `[w]` Uninitialized validator stake list storage account
-    ///   3. `[]` pool token Mint. Must be non zero, owned by withdraw authority.
-    ///   4. `[]` Pool Account to deposit the generated fee for owner.
-    ///   5. `[]` Clock sysvar
-    ///   6. `[]` Rent sysvar
-    ///   7. `[]` Token program id
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[ws]` Owner, pays the stake pool extension and reserve rent
+    ///   3. `[w]` Uninitialized validator stake list storage account
+    ///   4. `[]` pool token Mint. Must be non zero, owned by withdraw authority.
+    ///   5. `[]` Pool Account to deposit the generated fee for owner.
+    ///   6. `[]` Clock sysvar
+    ///   7. `[]` Rent sysvar
+    ///   8. `[]` Token program id
+    ///   9. `[]` System program id
+    ///   10. `[w]` Reserve stake account, PDA of (stake pool, "reserve"), created here
+    ///   11. `[]` Stake program id
     Initialize(InitArgs),
 
     ///   Admin: Creates new program account for accumulating stakes for a particular validator
@@ -57,241 +116,534 @@ pub enum StakePoolInstruction {
     ///   8. `[]` Stake program
     CreateValidatorStakeAccount,
 
-    ///   Admin: Adds validator stake account to the pool
+    ///   Staker: Adds validator stake account to the pool
     ///
     ///   0. `[w]` Stake pool
-    ///   1. `[s]` Owner
-    ///   2. `[]` Stake pool deposit authority
-    ///   3. `[]` Stake pool withdraw authority
-    ///   4. `[w]` Validator stake list storage account
-    ///   5. `[w]` Stake account to add to the pool, its withdraw authority should be set to stake pool deposit
-    ///   6. `[w]` User account to receive pool tokens
-    ///   7. `[w]` Pool token mint account
-    ///   8. `[]` Clock sysvar (required)
-    ///   9. '[]' Sysvar stake history account
-    ///  10. `[]` Pool token program id,
-    ///  11. `[]` Stake program id,
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Staker
+    ///   3. `[]` Stake pool deposit authority
+    ///   4. `[]` Stake pool withdraw authority
+    ///   5. `[w]` Validator stake list storage account
+    ///   6. `[w]` Stake account to add to the pool, its withdraw authority should be set to stake pool deposit
+    ///   7. `[w]` User account to receive pool tokens
+    ///   8. `[w]` Pool token mint account
+    ///   9. `[]` Clock sysvar (required)
+    ///  10. '[]' Sysvar stake history account
+    ///  11. `[]` Pool token program id,
+    ///  12. `[]` Stake program id,
     AddValidatorStakeAccount,
 
-    ///   Admin: Removes validator stake account from the pool
+    ///   Staker: Removes validator stake account from the pool
     ///
     ///   0. `[w]` Stake pool
-    ///   1. `[s]` Owner
-    ///   2. `[]` Stake pool withdraw authority
-    ///   3. `[]` New withdraw/staker authority to set in the stake account
-    ///   4. `[w]` Validator stake list storage account
-    ///   5. `[w]` Stake account to remove from the pool
-    ///   6. `[w]` User account with pool tokens to burn from
-    ///   7. `[w]` Pool token mint account
-    ///   8. '[]' Sysvar clock account (required)
-    ///   9. `[]` Pool token program id
-    ///  10. `[]` Stake program id,
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Staker
+    ///   3. `[]` Stake pool withdraw authority
+    ///   4. `[]` New withdraw/staker authority to set in the stake account
+    ///   5. `[w]` Validator stake list storage account
+    ///   6. `[w]` Stake account to remove from the pool
+    ///   7. `[w]` User account with pool tokens to burn from
+    ///   8. `[w]` Pool token mint account
+    ///   9. '[]' Sysvar clock account (required)
+    ///  10. `[]` Pool token program id
+    ///  11. `[]` Stake program id,
     RemoveValidatorStakeAccount,
 
-    ///   Anyone: Updates balances of validator stake accounts in the pool
-    ///   
-    ///   0. `[w]` Validator stake list storage account
-    ///   1. `[]` Sysvar clock account
-    ///   2. ..2+N ` [] N validator stake accounts to update balances
+    ///   Anyone: Updates balances of validator stake accounts in the pool.
+    ///   Transient stake accounts are settled: merged into the validator stake account
+    ///   once activated, or withdrawn to the reserve once deactivated.
+    ///   The balance is read from the delegation, lamports above it are moved to the reserve.
+    ///   The stake of a leaving validator is withdrawn to the reserve once deactivated,
+    ///   and the validator removed from the list
+    ///
+    ///   0. `[]` Stake pool
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[w]` Validator stake list storage account
+    ///   3. `[]` Stake pool withdraw authority
+    ///   4. `[w]` Reserve stake account
+    ///   5. `[]` Sysvar clock account
+    ///   6. `[]` Sysvar stake history account
+    ///   7. `[]` Stake program id
+    ///   8. ..8+2N `[w]` N pairs of (validator stake account, validator transient stake account),
+    ///      validator stake accounts must be the pool's program addresses of listed validators
     UpdateListBalance,
 
     ///   Anyone: Updates total pool balance based on balances in validator stake account list storage
     ///
     ///   0. `[w]` Stake pool
-    ///   1. `[]` Validator stake list storage account
-    ///   2. `[]` Sysvar clock account
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Validator stake list storage account
+    ///   3. `[]` Sysvar clock account
+    ///   4. `[]` Reserve stake account, its lamports count until delegated
+    ///   5. `[]` Stake pool withdraw authority
+    ///   6. `[w]` Account to receive the reward fee tokens
+    ///   7. `[w]` Pool token mint account
+    ///   8. `[]` Pool token program id
+    ///   9. ..9+N `[]` N validator stake list pages, all of the pool pages in `StakePool` order
+    ///      9+N. `[w]` Rate history, PDA of (stake pool, "history"), only when the pool has one.
+    ///      The epoch balances are recorded there
     UpdatePoolBalance,
 
     ///   User: Deposit some stake into the pool.  The output is a "pool" token representing ownership
     ///   into the pool. Inputs are converted to the current ratio.
     ///
     ///   0. `[w]` Stake pool
-    ///   1. `[w]` Validator stake list storage account
-    ///   2. `[]` Stake pool deposit authority
-    ///   3. `[]` Stake pool withdraw authority
-    ///   4. `[w]` Stake account to join the pool (withdraw should be set to stake pool deposit)
-    ///   5. `[w]` Validator stake account for the stake account to be merged with
-    ///   6. `[w]` User account to receive pool tokens
-    ///   7. `[w]` Account to receive pool fee tokens
-    ///   8. `[w]` Pool token mint account
-    ///   9. '[]' Sysvar clock account (required)
-    ///   10. '[]' Sysvar stake history account
-    ///   11. `[]` Pool token program id,
-    ///   12. `[]` Stake program id,
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[w]` Validator stake list storage account
+    ///   3. `[]` Stake pool deposit authority
+    ///   4. `[]` Stake pool withdraw authority
+    ///   5. `[w]` Stake account to join the pool (withdraw should be set to stake pool deposit)
+    ///   6. `[w]` Validator stake account for the stake account to be merged with
+    ///   7. `[w]` User account to receive pool tokens
+    ///   8. `[w]` Account to receive pool fee tokens
+    ///   9. `[w]` Pool token mint account
+    ///   10. '[]' Sysvar clock account (required)
+    ///   11. '[]' Sysvar stake history account
+    ///   12. `[]` Pool token program id,
+    ///   13. `[]` Stake program id,
+    ///   14. `[s]` Depositor, only when the pool has a deposit authority:
+    ///       the deposit authority or a depositor of the depositor list
+    ///   15. `[]` Depositor list, PDA of (stake pool, "depositors"), only when
+    ///       the depositor is not the deposit authority
     Deposit,
 
     ///   User: "Withdraw". Burn the token and return a staked account whose value reflects burned tokens value
     ///   How: move staked acc into (4.Unitialized stake account to receive withdrawal) and assigns authority to (5. `[]` User account to set as a new withdraw authority)
     ///   Basic asserts: amount withdrawn <= stake size
+    ///   The withdrawal fee is taken from the amount: it is burned without taking stake
+    ///   or transferred to the owner fee account, see `StakePool.withdrawal_fee_to_owner`
     ///
     ///   0. `[w]` Stake pool
-    ///   1. `[w]` Validator stake list storage account
-    ///   2. `[]` Stake pool withdraw authority
-    ///   3. `[w]` Validator stake account to split
-    ///   4. `[w]` Unitialized stake account to receive withdrawal
-    ///   5. `[]` User account to set as a new withdraw authority
-    ///   6. `[w]` User account with pool tokens to burn from
-    ///   7. `[w]` Pool token mint account
-    ///   8. '[]' Sysvar clock account (required)
-    ///   9. `[]` Pool token program id
-    ///   10. `[]` Stake program id,
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[w]` Validator stake list storage account
+    ///   3. `[]` Stake pool withdraw authority
+    ///   4. `[w]` Validator stake account to split
+    ///   5. `[w]` Unitialized stake account to receive withdrawal
+    ///   6. `[]` User account to set as a new withdraw authority
+    ///   7. `[w]` User account with pool tokens to burn from
+    ///   8. `[w]` Pool token mint account
+    ///   9. '[]' Sysvar clock account (required)
+    ///   10. `[]` Pool token program id
+    ///   11. `[]` Stake program id,
+    ///   12. `[w]` Account to receive the withdrawal fee tokens (owner fee account)
     ///   userdata: amount to withdraw
     Withdraw(u64),
 
     ///   Admin: Update the staking pubkey for a stake
     ///
     ///   0. `[w]` StakePool
-    ///   1. `[s]` Owner
-    ///   2. `[]` withdraw authority
-    ///   3. `[w]` Stake to update the staking pubkey
-    ///   4. '[]` Staking pubkey.
-    ///   5. '[]' Sysvar clock account (reserved for future use)
-    ///   6. `[]` Stake program id,
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Owner
+    ///   3. `[]` withdraw authority
+    ///   4. `[w]` Stake to update the staking pubkey
+    ///   5. '[]` Staking pubkey.
+    ///   6. '[]' Sysvar clock account (reserved for future use)
+    ///   7. `[]` Stake program id,
     SetStakingAuthority,
 
-    ///   Admin: Update owner
+    ///   Admin: Propose a new owner, it takes over once it signs `AcceptOwner`.
+    ///   Replaces any pending proposal
     ///
     ///   0. `[w]` StakePool
-    ///   1. `[s]` Owner
-    ///   2. '[]` New owner pubkey
-    ///   3. '[]` New owner fee account
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Owner
+    ///   3. '[]` New owner pubkey
     SetOwner,
 
     ///   Liq.Provider: Deposit some wSOL into the stSOL->wSOL LP. The output is a "LP" token representing LP shares
     ///
     ///   Deposit wsol into the LP. The output is a "metalp" token
     ///   representing ownership into the LP.
+    ///   The LP is valued as wSOL + stSOL (at the stake pool rate) to compute the $METALP to mint
     ///
-    ///   0. `[]` Stake pool (meta Stake pool program instance state)
-    ///   1. `[]` SPL Token Program
-    ///   2. `[w]` $METALP token mint account
-    ///   3. `[]` $METALP mint/withdraw authority
-    ///   4. `[w]` User account with wsol to transfer from
-    ///   5. `[]` withdraw authority to remove wsol from user account
-    ///   4. `[w]` Liq-pool dest account - Liq.pool acc to receive wSOL
-    ///   6. `[w]` user Unitialized account to receive METALP
-    ///   userdata: amount to withdraw
-    AddLiquidity(u64),
+    ///   0. `[]` Stake Pool (Stake pool state)
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Liq Pool (Liq pool state)
+    ///   3. `[]` SPL Token Program
+    ///   4. `[w]` $METALP token mint account
+    ///   5. `[]` $METALP mint authority (liq pool authority)
+    ///   6. `[w]` User account with wsol to transfer from
+    ///   7. `[s]` User authority to remove wsol from user account
+    ///   8. `[w]` Liq-pool wSOL account - to receive wSOL
+    ///   9. `[]` Liq-pool stSOL account
+    ///   10. `[w]` user account to receive METALP
+    ///   11. `[]` Sysvar clock account, the stake pool must be updated this epoch
+    ///   userdata: amount to add, min $METALP to receive
+    AddLiquidity(AddLiquidityArgs),
 
     ///   User: "Sell stSOL". Burn the token and return a wSOL account whose value reflects burned tokens value
     ///   How: move wSOL from LP to user acc (4.Unitialized account to receive withdrawal) and assigns authority to (5. `[]` User account to set as a new withdraw authority)
     ///   Basic asserts: amount withdrawn <= wSOL in the pool
     ///
     ///   0. `[]` Stake Pool (Stake pool state)
-    ///   1. `[]` Liq Pool (Liq pool state)
-    ///   2. `[]` SPL Token Program
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Liq Pool (Liq pool state)
+    ///   3. `[]` SPL Token Program
+    ///   4. `[w]` liq pool wSOL account
+    ///   5. `[w]` liq pool stSOL account
+    ///   6. `[]` liq pool authority
+    ///   7. `[w]` User wSOL account (unitialized, to receive)
+    ///   8. `[w]` User stSOL account (to take tokens from)
+    ///   9. `[]` User authority (signer)
+    ///   10. `[w]` Treasury wSOL account, receives the treasury share of the fee
+    ///   11. `[]` Sysvar clock account, the stake pool must be updated this epoch
+    ///   userdata: amount to sell, min wSOL to receive
+    SellstSOL(SellArgs),
+
+    ///   Liq.Provider: Burn $METALP and receive the pro-rata share of both LP legs (wSOL and stSOL)
+    ///
+    ///   0. `[]` Liq Pool (Liq pool state)
+    ///   1. `[]` SPL Token Program
+    ///   2. `[w]` $METALP token mint account
     ///   3. `[w]` liq pool wSOL account
     ///   4. `[w]` liq pool stSOL account
     ///   5. `[]` liq pool authority
-    ///   6. `[w]` User wSOL account (unitialized, to receive)
-    ///   7. `[w]` User stSOL account (to take tokens from)
-    ///   8. `[]` User authority (signer)
-    ///   userdata: amount to sell
-    SellstSOL(u64),
+    ///   6. `[w]` User $METALP account (to burn from)
+    ///   7. `[s]` User authority
+    ///   8. `[w]` User wSOL account (to receive)
+    ///   9. `[w]` User stSOL account (to receive)
+    ///   userdata: amount of $METALP to burn
+    RemoveLiquidity(u64),
+
+    ///   Admin: Initializes the wSOL/stSOL liquidity pool of a stake pool
+    ///
+    ///   0. `[w]` New LiquidityPool to create.
+    ///   1. `[]` Stake pool
+    ///   2. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   3. `[s]` Stake pool owner
+    ///   4. `[]` liq pool wSOL account, owned by the liq pool authority
+    ///   5. `[]` liq pool stSOL account, owned by the liq pool authority
+    ///   6. `[]` $METALP token mint account, mint authority must be the liq pool authority
+    ///   7. `[]` liq pool authority
+    ///   8. `[]` Rent sysvar
+    ///   9. `[]` Token program id
+    ///   10. `[]` Treasury wSOL account, receives the treasury share of the sell fee
+    ///   userdata: sell fee curve
+    InitializeLiquidityPool(SellFeeCurve),
+
+    ///   Fee manager: Update the sell fee curve of the liquidity pool
+    ///
+    ///   0. `[w]` Liq pool
+    ///   1. `[]` Stake pool
+    ///   2. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   3. `[s]` Stake pool fee manager
+    ///   userdata: new sell fee curve
+    SetSellFee(SellFeeCurve),
+
+    ///   Admin: Set the treasury wSOL account and its share of the sell fee
+    ///
+    ///   0. `[w]` Liq pool
+    ///   1. `[]` Stake pool
+    ///   2. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   3. `[s]` Stake pool owner
+    ///   4. `[]` Treasury wSOL account
+    ///   userdata: treasury share of the sell fee, in basis points
+    SetTreasury(u64),
+
+    ///   User: "Buy stSOL". Take wSOL from the user and send stSOL from the LP,
+    ///   at the stake pool rate minus the buy fee. The fee stays in the pool.
+    ///   If the LP holds less stSOL than the wSOL buys, only the LP stSOL is sold
+    ///   and only the wSOL needed for it is taken
+    ///
+    ///   0. `[]` Stake Pool (Stake pool state)
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Liq Pool (Liq pool state)
+    ///   3. `[]` SPL Token Program
+    ///   4. `[w]` liq pool wSOL account
+    ///   5. `[w]` liq pool stSOL account
+    ///   6. `[]` liq pool authority
+    ///   7. `[w]` User wSOL account (to take wSOL from)
+    ///   8. `[w]` User stSOL account (to receive)
+    ///   9. `[s]` User authority
+    ///   10. `[]` Sysvar clock account, the stake pool must be updated this epoch
+    ///   userdata: wSOL amount to pay, min stSOL to receive
+    BuyStSol(BuyArgs),
+
+    ///   Fee manager: Set the fee applied when buying stSOL
+    ///
+    ///   0. `[w]` Liq pool
+    ///   1. `[]` Stake pool
+    ///   2. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   3. `[s]` Stake pool fee manager
+    ///   userdata: buy fee in basis points, < 10_000
+    SetBuyFee(u64),
+
+    ///   Crank: "Unstake" stSOL held by the LP. Burn it like a Withdraw into a ticket stake account
+    ///   owned by the liq pool authority and deactivate it, the withdrawal fee applies. The ticket
+    ///   lamports count in the LP value until claimed. Anyone can call it; the payer funds the ticket
+    ///   stake account rent. The amount must be at least the LP stSOL balance divided by the number
+    ///   of tickets
+    ///
+    ///   0. `[w]` Liq pool
+    ///   1. `[w]` Stake pool
+    ///   2. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   3. `[w]` Validator stake list storage account
+    ///   4. `[]` Stake pool withdraw authority
+    ///   5. `[w]` Validator stake account to split
+    ///   6. `[w]` Ticket stake account, PDA of (liq pool, "unstake", ticket index)
+    ///   7. `[]` liq pool authority
+    ///   8. `[w]` liq pool stSOL account
+    ///   9. `[w]` Pool token mint account
+    ///   10. `[ws]` Payer of the ticket stake account rent, refunded on claim
+    ///   11. '[]' Sysvar clock account
+    ///   12. '[]' Sysvar rent account
+    ///   13. `[]` System program id
+    ///   14. `[]` Pool token program id
+    ///   15. `[]` Stake program id
+    ///   16. `[w]` Account to receive the withdrawal fee tokens
+    ///   userdata: stSOL amount to unstake, free ticket index
+    LiquidityPoolUnstake(UnstakeArgs),
+
+    ///   Crank: "Claim" a ticket once its stake cooled down. Withdraw the lamports
+    ///   and wrap them into the liq pool wSOL account through a temporary wSOL account,
+    ///   then refund both rents. Anyone can call it
+    ///
+    ///   0. `[w]` Liq pool
+    ///   1. `[w]` Ticket stake account
+    ///   2. `[]` liq pool authority
+    ///   3. `[w]` liq pool wSOL account
+    ///   4. `[ws]` Temporary wSOL account, a new keypair, closed before the instruction ends
+    ///   5. `[]` wSOL native mint
+    ///   6. `[ws]` Payer of the temporary account rent
+    ///   7. `[w]` Ticket rent payer, receives the ticket stake account rent
+    ///   8. '[]' Sysvar clock account
+    ///   9. '[]' Sysvar stake history account
+    ///   10. '[]' Sysvar rent account
+    ///   11. `[]` System program id
+    ///   12. `[]` Pool token program id
+    ///   13. `[]` Stake program id
+    ///   userdata: ticket index
+    LiquidityPoolClaim(u64),
+
+    ///   User: "Deposit SOL". Move lamports into the pool reserve stake account and mint
+    ///   stSOL at the current rate, minus the deposit fee (minted to the owner fee account).
+    ///   The reserve is delegated later by rebalancing
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Stake pool withdraw authority
+    ///   3. `[w]` Reserve stake account, PDA of (stake pool, "reserve")
+    ///   4. `[ws]` User account to take the lamports from
+    ///   5. `[w]` User account to receive pool tokens
+    ///   6. `[w]` Account to receive pool fee tokens
+    ///   7. `[w]` Pool token mint account
+    ///   8. '[]' Sysvar clock account
+    ///   9. `[]` System program id
+    ///   10. `[]` Pool token program id
+    ///   11. `[s]` Depositor, only when the pool has a deposit authority, see `Deposit`
+    ///   12. `[]` Depositor list, only when the depositor is not the deposit authority
+    ///   userdata: lamports to deposit
+    DepositSol(u64),
+
+    ///   Fee manager: Schedule a new deposit fee. It is stored as pending and
+    ///   UpdatePoolBalance applies it `StakePool::FEE_CHANGE_EPOCHS` epochs later
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool fee manager
+    ///   3. '[]' Sysvar clock account
+    ///   userdata: new fee
+    SetFee(Fee),
+
+    ///   Staker: Move lamports from the reserve to a validator. They are split into the
+    ///   validator transient stake account and delegated, UpdateListBalance merges them
+    ///   into the validator stake account once active.
+    ///   The transient stake account rent is taken from the reserve
+    ///
+    ///   0. `[]` Stake pool
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool staker
+    ///   3. `[]` Stake pool withdraw authority
+    ///   4. `[w]` Validator stake list storage account
+    ///   5. `[w]` Reserve stake account
+    ///   6. `[w]` Validator transient stake account, PDA of (vote, stake pool, "transient")
+    ///   7. `[]` Validator vote account
+    ///   8. '[]' Sysvar clock account
+    ///   9. '[]' Sysvar rent account
+    ///   10. '[]' Sysvar stake history account
+    ///   11. `[]` Stake config account
+    ///   12. `[]` System program id
+    ///   13. `[]` Stake program id
+    ///   userdata: lamports to move
+    IncreaseValidatorStake(u64),
+
+    ///   Staker: Move lamports from a validator to the reserve. They are split into the
+    ///   validator transient stake account and deactivated, UpdateListBalance withdraws
+    ///   them to the reserve once inactive.
+    ///   The transient stake account rent is taken from the reserve
+    ///
+    ///   0. `[]` Stake pool
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool staker
+    ///   3. `[]` Stake pool withdraw authority
+    ///   4. `[w]` Validator stake list storage account
+    ///   5. `[w]` Reserve stake account
+    ///   6. `[w]` Validator stake account to split from
+    ///   7. `[w]` Validator transient stake account, PDA of (vote, stake pool, "transient")
+    ///   8. '[]' Sysvar clock account
+    ///   9. '[]' Sysvar rent account
+    ///   10. '[]' Sysvar stake history account
+    ///   11. `[]` System program id
+    ///   12. `[]` Stake program id
+    ///   userdata: lamports to move
+    DecreaseValidatorStake(u64),
+
+    ///   Staker: Set the target weight of up to `MAX_VALIDATOR_WEIGHTS` validators,
+    ///   the share of the pool stake `Rebalance` moves them towards
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool staker
+    ///   3. `[w]` Validator stake list storage account
+    ///   4. ..4+N `[]` N validator vote accounts, one per weight, in the same order
+    ///   userdata: weights
+    SetValidatorWeights(ValidatorWeights),
+
+    ///   Anyone: Move stake of a validator towards its target weight, through its
+    ///   transient stake account like Increase/DecreaseValidatorStake. At most
+    ///   `StakePool::MAX_REBALANCE_BPS_PER_EPOCH` of the stake is moved per epoch.
+    ///   The transient stake account rent is taken from the reserve
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Stake pool withdraw authority
+    ///   3. `[w]` Validator stake list storage account
+    ///   4. `[w]` Reserve stake account
+    ///   5. `[w]` Validator stake account
+    ///   6. `[w]` Validator transient stake account
+    ///   7. `[]` Validator vote account
+    ///   8. '[]' Sysvar clock account
+    ///   9. '[]' Sysvar rent account
+    ///   10. '[]' Sysvar stake history account
+    ///   11. `[]` Stake config account
+    ///   12. `[]` System program id
+    ///   13. `[]` Stake program id
+    Rebalance,
+
+    ///   Staker: Link a new validator stake list page to the pool, for more than
+    ///   `MAX_VALIDATOR_STAKE_ACCOUNTS` validators. Instructions taking the validator
+    ///   stake list accept any page, the one listing the validator
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool staker
+    ///   3. `[w]` Uninitialized validator stake list storage account
+    ///   4. '[]' Sysvar rent account
+    AddValidatorStakeListPage,
+
+    ///   Admin: Upgrade a v1 stake pool and its validator stake list in place. The pool
+    ///   keeps its address, so its authorities and validator stake accounts don't change.
+    ///   The fields added since v1 go to the stake pool extension and v1 had no reserve,
+    ///   both are created here and the owner pays their rent. The v1 validator stake list account holds fewer validators with the
+    ///   current entry layout, the ones past `ValidatorStakeList::capacity` of the account
+    ///   are moved to a new page
+    ///
+    ///   0. `[w]` v1 stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension"), created here
+    ///   2. `[ws]` Owner
+    ///   3. `[w]` v1 validator stake list storage account
+    ///   4. '[]' Sysvar rent account
+    ///   5. `[]` System program id
+    ///   6. `[w]` Reserve stake account, PDA of (stake pool, "reserve"), created here
+    ///   7. `[]` Stake program id
+    ///   8. `[w]` Uninitialized validator stake list storage account, linked as the first page,
+    ///      only when the v1 list holds more validators than the upgraded one can
+    MigrateState,
+
+    ///   New owner: Accept the ownership proposed by `SetOwner`
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Pending owner
+    AcceptOwner,
+
+    ///   Admin or staker: Set the staker, it manages the validators and their stake
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Owner or current staker
+    ///   3. `[]` New staker pubkey
+    SetStaker,
+
+    ///   Admin or fee manager: Set the fee manager and the owner fee account
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Owner or current fee manager
+    ///   3. `[]` New fee manager pubkey
+    ///   4. `[]` New owner fee account
+    SetFeeManager,
+
+    ///   Admin: Set the deposit authority. Once set, `Deposit` and `DepositSol` must be
+    ///   signed by it or by a depositor of the depositor list. The default pubkey opens
+    ///   the pool to anyone again
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Owner
+    ///   3. `[]` New deposit authority pubkey
+    SetDepositAuthority,
+
+    ///   Admin: Add (true) or remove (false) a depositor of the depositor list.
+    ///   The first call creates the list, the owner pays its rent
+    ///
+    ///   0. `[]` Stake pool
+    ///   1. `[]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[ws]` Owner
+    ///   3. `[w]` Depositor list, PDA of (stake pool, "depositors")
+    ///   4. `[]` Depositor pubkey
+    ///   5. '[]' Sysvar rent account
+    ///   6. `[]` System program id
+    ///   userdata: true to add the depositor
+    SetDepositor(bool),
+
+    ///   Anyone: Deactivate the stake of a validator that has not voted for more than
+    ///   `StakePool::delinquent_slots`, or closed its vote account. The validator is
+    ///   marked leaving and its target weight is cleared, UpdateListBalance withdraws
+    ///   its stake to the reserve once inactive and removes it from the list
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[]` Stake pool withdraw authority
+    ///   3. `[w]` Validator stake list storage account
+    ///   4. `[w]` Validator stake account
+    ///   5. `[]` Validator vote account
+    ///   6. '[]' Sysvar clock account
+    ///   7. `[]` Stake program id
+    DeactivateDelinquentValidator,
+
+    ///   Staker: Set the slots without a vote after which a validator is delinquent,
+    ///   0 for `StakePool::DEFAULT_DELINQUENT_SLOTS`
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[s]` Stake pool staker
+    ///   userdata: delinquency threshold in slots
+    SetDelinquentSlots(u64),
+
+    ///   Admin: Create the pool rate history account. From then on UpdatePoolBalance
+    ///   records the pool balances of each epoch there. The owner pays its rent
+    ///
+    ///   0. `[w]` Stake pool
+    ///   1. `[w]` Stake pool extension, PDA of (stake pool, "extension")
+    ///   2. `[ws]` Owner
+    ///   3. `[w]` Rate history, PDA of (stake pool, "history")
+    ///   4. '[]' Sysvar rent account
+    ///   5. `[]` System program id
+    CreateRateHistory,
 }
 
 impl StakePoolInstruction {
     /// Deserializes a byte buffer into an [StakePoolInstruction](enum.StakePoolInstruction.html).
-    /// TODO efficient unpacking here
     pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() < size_of::<u8>() {
-            return Err(ProgramError::InvalidAccountData);
-        }
-        Ok(match input[0] {
-            0 => {
-                let val: &InitArgs = unpack(input)?;
-                Self::Initialize(*val)
-            }
-            1 => Self::CreateValidatorStakeAccount,
-            2 => Self::AddValidatorStakeAccount,
-            3 => Self::RemoveValidatorStakeAccount,
-            4 => Self::UpdateListBalance,
-            5 => Self::UpdatePoolBalance,
-            6 => Self::Deposit,
-            7 => {
-                let val: &u64 = unpack(input)?;
-                Self::Withdraw(*val)
-            }
-            8 => Self::SetStakingAuthority,
-            9 => Self::SetOwner,
-            10 => {
-                let val: &u64 = unpack(input)?;
-                Self::AddLiquidity(*val)
-            }
-            11 => {
-                let val: &u64 = unpack(input)?;
-                Self::SellstSOL(*val)
-            }
-            _ => return Err(ProgramError::InvalidAccountData),
-        })
+        Self::try_from_slice(input).map_err(|_| ProgramError::InvalidAccountData)
     }
 
     /// Serializes an [StakePoolInstruction](enum.StakePoolInstruction.html) into a byte buffer.
-    /// TODO efficient packing here
     pub fn serialize(&self) -> Result<Vec<u8>, ProgramError> {
-        let mut output = vec![0u8; size_of::<StakePoolInstruction>()];
-        match self {
-            Self::Initialize(init) => {
-                output[0] = 0;
-                #[allow(clippy::cast_ptr_alignment)]
-                let value = unsafe { &mut *(&mut output[1] as *mut u8 as *mut InitArgs) };
-                *value = *init;
-            }
-            Self::CreateValidatorStakeAccount => {
-                output[0] = 1;
-            }
-            Self::AddValidatorStakeAccount => {
-                output[0] = 2;
-            }
-            Self::RemoveValidatorStakeAccount => {
-                output[0] = 3;
-            }
-            Self::UpdateListBalance => {
-                output[0] = 4;
-            }
-            Self::UpdatePoolBalance => {
-                output[0] = 5;
-            }
-            Self::Deposit => {
-                output[0] = 6;
-            }
-            Self::Withdraw(val) => {
-                output[0] = 7;
-                #[allow(clippy::cast_ptr_alignment)]
-                let value = unsafe { &mut *(&mut output[1] as *mut u8 as *mut u64) };
-                *value = *val;
-            }
-            Self::SetStakingAuthority => {
-                output[0] = 8;
-            }
-            Self::SetOwner => {
-                output[0] = 9;
-            }
-            Self::AddLiquidity(val) => {
-                output[0] = 10;
-                #[allow(clippy::cast_ptr_alignment)]
-                let value = unsafe { &mut *(&mut output[1] as *mut u8 as *mut u64) };
-                *value = *val;
-            }
-            Self::SellstSOL(val) => {
-                output[0] = 11;
-                #[allow(clippy::cast_ptr_alignment)]
-                let value = unsafe { &mut *(&mut output[1] as *mut u8 as *mut u64) };
-                *value = *val;
-            }
-        }
-        Ok(output)
-    }
-}
-
-/// Unpacks a reference from a bytes buffer.
-pub fn unpack<T>(input: &[u8]) -> Result<&T, ProgramError> {
-    if input.len() < size_of::<u8>() + size_of::<T>() {
-        return Err(ProgramError::InvalidAccountData);
+        self.try_to_vec()
+            .map_err(|_| ProgramError::InvalidAccountData)
     }
-    #[allow(clippy::cast_ptr_alignment)]
-    let val: &T = unsafe { &*(&input[1] as *const u8 as *const T) };
-    Ok(val)
 }
 
 /// Creates an 'initialize' instruction.
@@ -307,15 +659,162 @@ pub fn initialize(
 ) -> Result<Instruction, ProgramError> {
     let init_data = StakePoolInstruction::Initialize(init_args);
     let data = init_data.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let (reserve_stake, _) =
+        Processor::find_authority_bump_seed(program_id, stake_pool, Processor::RESERVE);
     let accounts = vec![
         AccountMeta::new(*stake_pool, true),
-        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new(*owner, true),
         AccountMeta::new(*validator_stake_list, false),
         AccountMeta::new_readonly(*pool_mint, false),
         AccountMeta::new_readonly(*owner_pool_account, false),
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
         AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(reserve_stake, false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_liquidity_pool' instruction.
+pub fn initialize_liquidity_pool(
+    program_id: &Pubkey,
+    liq_pool: &Pubkey,
+    stake_pool: &Pubkey,
+    owner: &Pubkey,
+    liq_pool_wsol_account: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
+    meta_lp_mint_account: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    token_program_id: &Pubkey,
+    treasury_wsol_account: &Pubkey,
+    sell_fee: SellFeeCurve,
+) -> Result<Instruction, ProgramError> {
+    let init_data = StakePoolInstruction::InitializeLiquidityPool(sell_fee);
+    let data = init_data.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*liq_pool, false),
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*liq_pool_wsol_account, false),
+        AccountMeta::new_readonly(*liq_pool_st_sol_account, false),
+        AccountMeta::new_readonly(*meta_lp_mint_account, false),
+        AccountMeta::new_readonly(*liq_pool_authority, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*treasury_wsol_account, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_sell_fee' instruction.
+pub fn set_sell_fee(
+    program_id: &Pubkey,
+    liq_pool: &Pubkey,
+    stake_pool: &Pubkey,
+    fee_manager: &Pubkey,
+    sell_fee: SellFeeCurve,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetSellFee(sell_fee);
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*liq_pool, false),
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*fee_manager, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_treasury' instruction.
+pub fn set_treasury(
+    program_id: &Pubkey,
+    liq_pool: &Pubkey,
+    stake_pool: &Pubkey,
+    owner: &Pubkey,
+    treasury_wsol_account: &Pubkey,
+    treasury_share_bps: u64,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetTreasury(treasury_share_bps);
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*liq_pool, false),
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*treasury_wsol_account, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fee' instruction.
+pub fn set_fee(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    fee_manager: &Pubkey,
+    fee: Fee,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetFee(fee);
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*fee_manager, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_buy_fee' instruction.
+pub fn set_buy_fee(
+    program_id: &Pubkey,
+    liq_pool: &Pubkey,
+    stake_pool: &Pubkey,
+    fee_manager: &Pubkey,
+    buy_fee_bps: u64,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetBuyFee(buy_fee_bps);
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*liq_pool, false),
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*fee_manager, true),
     ];
     Ok(Instruction {
         program_id: *program_id,
@@ -358,7 +857,7 @@ pub fn create_validator_stake_account(
 pub fn add_validator_stake_account(
     program_id: &Pubkey,
     stake_pool: &Pubkey,
-    owner: &Pubkey,
+    staker: &Pubkey,
     stake_pool_deposit: &Pubkey,
     stake_pool_withdraw: &Pubkey,
     validator_stake_list: &Pubkey,
@@ -368,9 +867,12 @@ pub fn add_validator_stake_account(
     token_program_id: &Pubkey,
     stake_program_id: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
-        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
         AccountMeta::new_readonly(*stake_pool_deposit, false),
         AccountMeta::new_readonly(*stake_pool_withdraw, false),
         AccountMeta::new(*validator_stake_list, false),
@@ -393,7 +895,7 @@ pub fn add_validator_stake_account(
 pub fn remove_validator_stake_account(
     program_id: &Pubkey,
     stake_pool: &Pubkey,
-    owner: &Pubkey,
+    staker: &Pubkey,
     stake_pool_withdraw: &Pubkey,
     new_stake_authority: &Pubkey,
     validator_stake_list: &Pubkey,
@@ -403,9 +905,12 @@ pub fn remove_validator_stake_account(
     token_program_id: &Pubkey,
     stake_program_id: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
-        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
         AccountMeta::new_readonly(*stake_pool_withdraw, false),
         AccountMeta::new_readonly(*new_stake_authority, false),
         AccountMeta::new(*validator_stake_list, false),
@@ -424,60 +929,288 @@ pub fn remove_validator_stake_account(
 }
 
 /// Creates `UpdateListBalance` instruction (update validator stake account balances)
-
+/// `validators` are vote accounts, their stake and transient stake accounts are derived
 pub fn update_list_balance(
-
     program_id: &Pubkey,
+    stake_pool: &Pubkey,
     validator_stake_list_storage: &Pubkey,
-    validator_stake_list: &[&Pubkey],
-
+    stake_pool_withdraw: &Pubkey,
+    reserve_stake: &Pubkey,
+    validators: &[Pubkey],
 ) -> Result<Instruction, ProgramError> {
-
-    let mut accounts: Vec<AccountMeta> = validator_stake_list
-        .iter()
-        .map(|pubkey| AccountMeta::new_readonly(**pubkey, false))
-        .collect();
-
-    //   Anyone: Updates balances of validator stake accounts in the pool
-    //   
-    //   0. `[w]` Validator stake list storage account
-    //   1. `[]` Sysvar clock account
-    //   2. ..2+N ` [] N validator stake accounts to update balances
-    accounts.insert(0, AccountMeta::new(*validator_stake_list_storage, false));
-    accounts.insert(1, AccountMeta::new_readonly(sysvar::clock::id(), false));
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*reserve_stake, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    for validator in validators {
+        let (stake_account, _) =
+            Processor::find_stake_address_for_validator(program_id, validator, stake_pool);
+        let (transient_stake_account, _) =
+            Processor::find_transient_stake_address(program_id, validator, stake_pool);
+        accounts.push(AccountMeta::new(stake_account, false));
+        accounts.push(AccountMeta::new(transient_stake_account, false));
+    }
 
     Ok(Instruction {
         program_id: *program_id,
         accounts,
         data: StakePoolInstruction::UpdateListBalance.serialize()?,
     })
-
 }
 
-/// Creates `UpdatePoolBalance` instruction (pool balance from the stake account list balances)
-pub fn update_pool_balance(
+/// Creates `IncreaseValidatorStake` instruction (move lamports from the reserve to a validator)
+pub fn increase_validator_stake(
     program_id: &Pubkey,
     stake_pool: &Pubkey,
+    staker: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
     validator_stake_list_storage: &Pubkey,
+    reserve_stake: &Pubkey,
+    validator: &Pubkey,
+    lamports: u64,
 ) -> Result<Instruction, ProgramError> {
+    let (transient_stake_account, _) =
+        Processor::find_transient_stake_address(program_id, validator, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
-        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
         AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new(*reserve_stake, false),
+        AccountMeta::new(transient_stake_account, false),
+        AccountMeta::new_readonly(*validator, false),
         AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(stake::config_id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(stake::id(), false),
     ];
     Ok(Instruction {
         program_id: *program_id,
         accounts,
-        data: StakePoolInstruction::UpdatePoolBalance.serialize()?,
+        data: StakePoolInstruction::IncreaseValidatorStake(lamports).serialize()?,
     })
 }
 
-/// Creates a 'Deposit' instruction.
-pub fn deposit(
+/// Creates `DecreaseValidatorStake` instruction (move lamports from a validator to the reserve)
+pub fn decrease_validator_stake(
     program_id: &Pubkey,
     stake_pool: &Pubkey,
-    validator_stake_list_storage: &Pubkey,
-    stake_pool_deposit: &Pubkey,
+    staker: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    reserve_stake: &Pubkey,
+    validator: &Pubkey,
+    lamports: u64,
+) -> Result<Instruction, ProgramError> {
+    let (stake_account, _) =
+        Processor::find_stake_address_for_validator(program_id, validator, stake_pool);
+    let (transient_stake_account, _) =
+        Processor::find_transient_stake_address(program_id, validator, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new(*reserve_stake, false),
+        AccountMeta::new(stake_account, false),
+        AccountMeta::new(transient_stake_account, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::DecreaseValidatorStake(lamports).serialize()?,
+    })
+}
+
+/// Creates `SetValidatorWeights` instruction (target weights of validator vote accounts)
+pub fn set_validator_weights(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    staker: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    weights: &[(Pubkey, u64)],
+) -> Result<Instruction, ProgramError> {
+    if weights.len() > MAX_VALIDATOR_WEIGHTS {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let args = ValidatorWeights {
+        weights: weights.iter().map(|(_, weight)| *weight).collect(),
+    };
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let mut accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
+        AccountMeta::new(*validator_stake_list_storage, false),
+    ];
+    for (validator, _) in weights {
+        accounts.push(AccountMeta::new_readonly(*validator, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::SetValidatorWeights(args).serialize()?,
+    })
+}
+
+/// Creates `Rebalance` instruction (move a validator stake towards its target weight)
+pub fn rebalance(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    reserve_stake: &Pubkey,
+    validator: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (stake_account, _) =
+        Processor::find_stake_address_for_validator(program_id, validator, stake_pool);
+    let (transient_stake_account, _) =
+        Processor::find_transient_stake_address(program_id, validator, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new(*reserve_stake, false),
+        AccountMeta::new(stake_account, false),
+        AccountMeta::new(transient_stake_account, false),
+        AccountMeta::new_readonly(*validator, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(stake::config_id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::Rebalance.serialize()?,
+    })
+}
+
+/// Creates `AddValidatorStakeListPage` instruction (link a new validator stake list account)
+pub fn add_validator_stake_list_page(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    staker: &Pubkey,
+    validator_stake_list_page: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
+        AccountMeta::new(*validator_stake_list_page, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::AddValidatorStakeListPage.serialize()?,
+    })
+}
+
+/// Creates `MigrateState` instruction (upgrade a v1 stake pool in place).
+/// `validator_stake_list_page` is needed when the v1 list holds more validators
+/// than `ValidatorStakeList::capacity` of its account
+pub fn migrate_state(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    owner: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    validator_stake_list_page: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let (reserve_stake, _) =
+        Processor::find_authority_bump_seed(program_id, stake_pool, Processor::RESERVE);
+    let mut accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(reserve_stake, false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    if let Some(page) = validator_stake_list_page {
+        accounts.push(AccountMeta::new(*page, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::MigrateState.serialize()?,
+    })
+}
+
+/// Creates `UpdatePoolBalance` instruction (pool balance from the stake account list balances)
+pub fn update_pool_balance(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    reserve_stake: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    pool_fee_to: &Pubkey,
+    pool_mint: &Pubkey,
+    token_program_id: &Pubkey,
+    validator_stake_list_pages: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let mut accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(*reserve_stake, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*pool_fee_to, false),
+        AccountMeta::new(*pool_mint, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for page in validator_stake_list_pages {
+        accounts.push(AccountMeta::new_readonly(*page, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::UpdatePoolBalance.serialize()?,
+    })
+}
+
+/// Creates a 'Deposit' instruction.
+pub fn deposit(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    stake_pool_deposit: &Pubkey,
     stake_pool_withdraw: &Pubkey,
     stake_to_join: &Pubkey,
     validator_stake_accont: &Pubkey,
@@ -489,8 +1222,11 @@ pub fn deposit(
 ) -> Result<Instruction, ProgramError> {
     let args = StakePoolInstruction::Deposit;
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
         AccountMeta::new(*validator_stake_list_storage, false),
         AccountMeta::new_readonly(*stake_pool_deposit, false),
         AccountMeta::new_readonly(*stake_pool_withdraw, false),
@@ -514,7 +1250,9 @@ pub fn deposit(
 ///create instruction add_liquidity
 pub fn instruction_add_liquidity(
     amount:u64,
+    min_metalp_out: u64,
     program_id: &Pubkey,
+    stake_pool_state_account: &Pubkey,
     liq_pool_state_account: &Pubkey,
     spl_token_program_id: &Pubkey,
     meta_lp_mint_account: &Pubkey,
@@ -522,21 +1260,31 @@ pub fn instruction_add_liquidity(
     user_wsol_source_account: &Pubkey,
     user_wsol_withdraw_auth: &Pubkey,
     liq_pool_wsol_dest_account: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
     user_dest_meta_lp_account: &Pubkey,
 
 ) -> Result<Instruction, ProgramError> {
 
-    let args = StakePoolInstruction::AddLiquidity(amount);
+    let args = StakePoolInstruction::AddLiquidity(AddLiquidityArgs {
+        amount,
+        min_metalp_out,
+    });
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool_state_account);
     let accounts = vec![
+        AccountMeta::new_readonly(*stake_pool_state_account, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
         AccountMeta::new_readonly(*liq_pool_state_account, false),
         AccountMeta::new_readonly(*spl_token_program_id, false),
         AccountMeta::new(*meta_lp_mint_account, false),
         AccountMeta::new_readonly(*meta_lp_mint_authority, false),
         AccountMeta::new(*user_wsol_source_account, false),
-        AccountMeta::new_readonly(*user_wsol_withdraw_auth, false),
+        AccountMeta::new_readonly(*user_wsol_withdraw_auth, true),
         AccountMeta::new(*liq_pool_wsol_dest_account, false),
+        AccountMeta::new_readonly(*liq_pool_st_sol_account, false),
         AccountMeta::new(*user_dest_meta_lp_account, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
     ];
     Ok(Instruction {
         program_id: *program_id,
@@ -548,6 +1296,7 @@ pub fn instruction_add_liquidity(
 ///create instruction sell_st_sol
 pub fn instruction_sell_stsol(
     amount:u64,
+    min_wsol_out: u64,
     program_id: &Pubkey,
     stake_pool_state_account: &Pubkey,
     liq_pool_state_account: &Pubkey,
@@ -558,13 +1307,20 @@ pub fn instruction_sell_stsol(
     user_wsol_account: &Pubkey,
     user_st_sol_account: &Pubkey,
     user_withdraw_auth: &Pubkey,
+    treasury_wsol_account: &Pubkey,
 
 ) -> Result<Instruction, ProgramError> {
 
-    let args = StakePoolInstruction::SellstSOL(amount);
+    let args = StakePoolInstruction::SellstSOL(SellArgs {
+        amount,
+        min_wsol_out,
+    });
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool_state_account);
     let accounts = vec![
         AccountMeta::new_readonly(*stake_pool_state_account, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
         AccountMeta::new_readonly(*liq_pool_state_account, false),
         AccountMeta::new_readonly(*spl_token_program_id, false),
         AccountMeta::new(*liq_pool_wsol_account, false),
@@ -573,6 +1329,214 @@ pub fn instruction_sell_stsol(
         AccountMeta::new(*user_wsol_account, false),
         AccountMeta::new(*user_st_sol_account, false),
         AccountMeta::new_readonly(*user_withdraw_auth, false),
+        AccountMeta::new(*treasury_wsol_account, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+///create instruction buy_stsol
+pub fn instruction_buy_stsol(
+    amount: u64,
+    min_stsol_out: u64,
+    program_id: &Pubkey,
+    stake_pool_state_account: &Pubkey,
+    liq_pool_state_account: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    liq_pool_wsol_account: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    user_wsol_account: &Pubkey,
+    user_st_sol_account: &Pubkey,
+    user_authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::BuyStSol(BuyArgs {
+        amount,
+        min_stsol_out,
+    });
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool_state_account);
+    let accounts = vec![
+        AccountMeta::new_readonly(*stake_pool_state_account, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new_readonly(*liq_pool_state_account, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+        AccountMeta::new(*liq_pool_wsol_account, false),
+        AccountMeta::new(*liq_pool_st_sol_account, false),
+        AccountMeta::new_readonly(*liq_pool_authority, false),
+        AccountMeta::new(*user_wsol_account, false),
+        AccountMeta::new(*user_st_sol_account, false),
+        AccountMeta::new_readonly(*user_authority, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+///create instruction liquidity_pool_unstake
+pub fn instruction_liquidity_pool_unstake(
+    amount: u64,
+    ticket: u64,
+    program_id: &Pubkey,
+    liq_pool_state_account: &Pubkey,
+    stake_pool: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    stake_to_split: &Pubkey,
+    ticket_stake_account: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
+    pool_mint: &Pubkey,
+    payer: &Pubkey,
+    token_program_id: &Pubkey,
+    stake_program_id: &Pubkey,
+    pool_fee_to: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::LiquidityPoolUnstake(UnstakeArgs { amount, ticket });
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*liq_pool_state_account, false),
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*stake_to_split, false),
+        AccountMeta::new(*ticket_stake_account, false),
+        AccountMeta::new_readonly(*liq_pool_authority, false),
+        AccountMeta::new(*liq_pool_st_sol_account, false),
+        AccountMeta::new(*pool_mint, false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*stake_program_id, false),
+        AccountMeta::new(*pool_fee_to, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+///create instruction liquidity_pool_claim
+pub fn instruction_liquidity_pool_claim(
+    ticket: u64,
+    program_id: &Pubkey,
+    liq_pool_state_account: &Pubkey,
+    ticket_stake_account: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    liq_pool_wsol_account: &Pubkey,
+    temp_wsol_account: &Pubkey,
+    wsol_mint: &Pubkey,
+    payer: &Pubkey,
+    ticket_rent_payer: &Pubkey,
+    token_program_id: &Pubkey,
+    stake_program_id: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::LiquidityPoolClaim(ticket);
+    let data = args.serialize()?;
+    let accounts = vec![
+        AccountMeta::new(*liq_pool_state_account, false),
+        AccountMeta::new(*ticket_stake_account, false),
+        AccountMeta::new_readonly(*liq_pool_authority, false),
+        AccountMeta::new(*liq_pool_wsol_account, false),
+        AccountMeta::new(*temp_wsol_account, true),
+        AccountMeta::new_readonly(*wsol_mint, false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(*ticket_rent_payer, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sysvar::stake_history::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*stake_program_id, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+///create instruction remove_liquidity
+pub fn instruction_remove_liquidity(
+    amount:u64,
+    program_id: &Pubkey,
+    liq_pool_state_account: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    meta_lp_mint_account: &Pubkey,
+    liq_pool_wsol_account: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    user_meta_lp_account: &Pubkey,
+    user_authority: &Pubkey,
+    user_wsol_account: &Pubkey,
+    user_st_sol_account: &Pubkey,
+
+) -> Result<Instruction, ProgramError> {
+
+    let args = StakePoolInstruction::RemoveLiquidity(amount);
+    let data = args.serialize()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*liq_pool_state_account, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+        AccountMeta::new(*meta_lp_mint_account, false),
+        AccountMeta::new(*liq_pool_wsol_account, false),
+        AccountMeta::new(*liq_pool_st_sol_account, false),
+        AccountMeta::new_readonly(*liq_pool_authority, false),
+        AccountMeta::new(*user_meta_lp_account, false),
+        AccountMeta::new_readonly(*user_authority, true),
+        AccountMeta::new(*user_wsol_account, false),
+        AccountMeta::new(*user_st_sol_account, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_sol' instruction.
+pub fn deposit_sol(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    reserve_stake: &Pubkey,
+    lamports_from: &Pubkey,
+    pool_tokens_to: &Pubkey,
+    pool_fee_to: &Pubkey,
+    pool_mint: &Pubkey,
+    token_program_id: &Pubkey,
+    lamports: u64,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::DepositSol(lamports);
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*reserve_stake, false),
+        AccountMeta::new(*lamports_from, true),
+        AccountMeta::new(*pool_tokens_to, false),
+        AccountMeta::new(*pool_fee_to, false),
+        AccountMeta::new(*pool_mint, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(*token_program_id, false),
     ];
     Ok(Instruction {
         program_id: *program_id,
@@ -591,6 +1555,7 @@ pub fn withdraw(
     stake_to_receive: &Pubkey,
     user_withdrawer: &Pubkey,
     burn_from: &Pubkey,
+    pool_fee_to: &Pubkey,
     pool_mint: &Pubkey,
     token_program_id: &Pubkey,
     stake_program_id: &Pubkey,
@@ -598,8 +1563,11 @@ pub fn withdraw(
 ) -> Result<Instruction, ProgramError> {
     let args = StakePoolInstruction::Withdraw(amount);
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
         AccountMeta::new(*validator_stake_list_storage, false),
         AccountMeta::new_readonly(*stake_pool_withdraw, false),
         AccountMeta::new(*stake_to_split, false),
@@ -610,6 +1578,7 @@ pub fn withdraw(
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(*token_program_id, false),
         AccountMeta::new_readonly(*stake_program_id, false),
+        AccountMeta::new(*pool_fee_to, false),
     ];
     Ok(Instruction {
         program_id: *program_id,
@@ -630,8 +1599,11 @@ pub fn set_staking_authority(
 ) -> Result<Instruction, ProgramError> {
     let args = StakePoolInstruction::SetStakingAuthority;
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
         AccountMeta::new_readonly(*stake_pool_owner, true),
         AccountMeta::new_readonly(*stake_pool_withdraw, false),
         AccountMeta::new(*stake_account_to_update, false),
@@ -652,14 +1624,87 @@ pub fn set_owner(
     stake_pool: &Pubkey,
     stake_pool_owner: &Pubkey,
     stake_pool_new_owner: &Pubkey,
-    stake_pool_new_fee_receiver: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
     let args = StakePoolInstruction::SetOwner;
     let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
     let accounts = vec![
         AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
         AccountMeta::new_readonly(*stake_pool_owner, true),
         AccountMeta::new_readonly(*stake_pool_new_owner, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'accept owner' instruction.
+pub fn accept_owner(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_pending_owner: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::AcceptOwner;
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*stake_pool_pending_owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set staker' instruction.
+pub fn set_staker(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    owner_or_staker: &Pubkey,
+    stake_pool_new_staker: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetStaker;
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*owner_or_staker, true),
+        AccountMeta::new_readonly(*stake_pool_new_staker, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set fee manager' instruction.
+pub fn set_fee_manager(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    owner_or_fee_manager: &Pubkey,
+    stake_pool_new_fee_manager: &Pubkey,
+    stake_pool_new_fee_receiver: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetFeeManager;
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*owner_or_fee_manager, true),
+        AccountMeta::new_readonly(*stake_pool_new_fee_manager, false),
         AccountMeta::new_readonly(*stake_pool_new_fee_receiver, false),
     ];
     Ok(Instruction {
@@ -668,3 +1713,934 @@ pub fn set_owner(
         data,
     })
 }
+
+/// Creates a 'set deposit authority' instruction.
+pub fn set_deposit_authority(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_owner: &Pubkey,
+    stake_pool_new_deposit_authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetDepositAuthority;
+    let data = args.serialize()?;
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*stake_pool_owner, true),
+        AccountMeta::new_readonly(*stake_pool_new_deposit_authority, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set depositor' instruction.
+pub fn set_depositor(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_owner: &Pubkey,
+    depositor: &Pubkey,
+    allowed: bool,
+) -> Result<Instruction, ProgramError> {
+    let args = StakePoolInstruction::SetDepositor(allowed);
+    let data = args.serialize()?;
+    let (depositor_list, _) = Processor::find_depositor_list_address(program_id, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new_readonly(*stake_pool, false),
+        AccountMeta::new_readonly(stake_pool_extension, false),
+        AccountMeta::new(*stake_pool_owner, true),
+        AccountMeta::new(depositor_list, false),
+        AccountMeta::new_readonly(*depositor, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates `DeactivateDelinquentValidator` instruction (anyone can call it)
+pub fn deactivate_delinquent_validator(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_withdraw: &Pubkey,
+    validator_stake_list_storage: &Pubkey,
+    validator: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (stake_account, _) =
+        Processor::find_stake_address_for_validator(program_id, validator, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*stake_pool_withdraw, false),
+        AccountMeta::new(*validator_stake_list_storage, false),
+        AccountMeta::new(stake_account, false),
+        AccountMeta::new_readonly(*validator, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(stake::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::DeactivateDelinquentValidator.serialize()?,
+    })
+}
+
+/// Creates `SetDelinquentSlots` instruction (validator delinquency threshold)
+pub fn set_delinquent_slots(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    staker: &Pubkey,
+    delinquent_slots: u64,
+) -> Result<Instruction, ProgramError> {
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new_readonly(*staker, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::SetDelinquentSlots(delinquent_slots).serialize()?,
+    })
+}
+
+/// Creates `CreateRateHistory` instruction (pool balances history)
+pub fn create_rate_history(
+    program_id: &Pubkey,
+    stake_pool: &Pubkey,
+    stake_pool_owner: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (rate_history, _) = Processor::find_rate_history_address(program_id, stake_pool);
+    let (stake_pool_extension, _) =
+        Processor::find_stake_pool_extension_address(program_id, stake_pool);
+    let accounts = vec![
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new(stake_pool_extension, false),
+        AccountMeta::new(*stake_pool_owner, true),
+        AccountMeta::new(rate_history, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data: StakePoolInstruction::CreateRateHistory.serialize()?,
+    })
+}
+
+/// Adds the rate history account to an 'update pool balance' instruction,
+/// for a pool with a rate history
+pub fn with_rate_history(mut instruction: Instruction, stake_pool: &Pubkey) -> Instruction {
+    let (rate_history, _) =
+        Processor::find_rate_history_address(&instruction.program_id, stake_pool);
+    instruction
+        .accounts
+        .push(AccountMeta::new(rate_history, false));
+    instruction
+}
+
+/// Adds the depositor accounts to a 'deposit' or 'deposit sol' instruction,
+/// for a pool with a deposit authority. The depositor list is left out when
+/// the depositor is the deposit authority
+pub fn with_depositor(
+    mut instruction: Instruction,
+    stake_pool: &Pubkey,
+    depositor: &Pubkey,
+    is_deposit_authority: bool,
+) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(*depositor, true));
+    if !is_deposit_authority {
+        let (depositor_list, _) =
+            Processor::find_depositor_list_address(&instruction.program_id, stake_pool);
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(depositor_list, false));
+    }
+    instruction
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_derive::Serialize;
+    use crate::state::MAX_VALIDATOR_STAKE_LIST_PAGES;
+    use solana_program::{hash::hash, message::Message};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const INTERFACE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/interface.json");
+
+    /// Accounts passed once per validator or page
+    const REPEATED: &[&str] = &[
+        "validators",
+        "validator_stake_accounts",
+        "transient_stake_accounts",
+        "validator_stake_list_pages",
+    ];
+
+    /// Instructions taking trailing accounts only for some pools (a deposit authority,
+    /// a v1 list too long for its account), and the index of the first one
+    const OPTIONAL_FROM: &[(&str, usize)] =
+        &[("Deposit", 14), ("DepositSol", 11), ("MigrateState", 8)];
+
+    #[derive(Serialize)]
+    struct Interface {
+        name: &'static str,
+        encoding: &'static str,
+        types: Vec<TypeDef>,
+        instructions: Vec<InstructionDef>,
+    }
+
+    #[derive(Serialize)]
+    struct TypeDef {
+        name: &'static str,
+        fields: Vec<Field>,
+    }
+
+    #[derive(Serialize)]
+    struct InstructionDef {
+        name: String,
+        tag: u8,
+        data: Vec<Field>,
+        accounts: Vec<AccountDef>,
+    }
+
+    #[derive(Serialize)]
+    struct Field {
+        name: &'static str,
+        #[serde(rename = "type")]
+        type_name: &'static str,
+    }
+
+    #[derive(Serialize)]
+    struct AccountDef {
+        name: String,
+        writable: bool,
+        signer: bool,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        repeated: bool,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        optional: bool,
+    }
+
+    fn fields(fields: &[(&'static str, &'static str)]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|&(name, type_name)| Field { name, type_name })
+            .collect()
+    }
+
+    fn types() -> Vec<TypeDef> {
+        vec![
+            TypeDef {
+                name: "Fee",
+                fields: fields(&[("denominator", "u64"), ("numerator", "u64")]),
+            },
+            TypeDef {
+                name: "InitArgs",
+                fields: fields(&[
+                    ("fee", "Fee"),
+                    ("reward_fee", "Fee"),
+                    ("withdrawal_fee", "Fee"),
+                    ("withdrawal_fee_to_owner", "u8"),
+                ]),
+            },
+            TypeDef {
+                name: "AddLiquidityArgs",
+                fields: fields(&[("amount", "u64"), ("min_metalp_out", "u64")]),
+            },
+            TypeDef {
+                name: "SellArgs",
+                fields: fields(&[("amount", "u64"), ("min_wsol_out", "u64")]),
+            },
+            TypeDef {
+                name: "BuyArgs",
+                fields: fields(&[("amount", "u64"), ("min_stsol_out", "u64")]),
+            },
+            TypeDef {
+                name: "UnstakeArgs",
+                fields: fields(&[("amount", "u64"), ("ticket", "u64")]),
+            },
+            TypeDef {
+                name: "SellFeeCurve",
+                fields: fields(&[
+                    ("min_fee_bps", "u64"),
+                    ("max_fee_bps", "u64"),
+                    ("target_liquidity", "u64"),
+                ]),
+            },
+            TypeDef {
+                name: "ValidatorWeights",
+                fields: fields(&[("weights", "Vec<u64>")]),
+            },
+        ]
+    }
+
+    /// Encoded size of an interface type
+    fn encoded_len(types: &[TypeDef], type_name: &str) -> usize {
+        match type_name {
+            "u8" | "bool" => 1,
+            "u64" => 8,
+            // u32 length, then the one weight the SetValidatorWeights example passes
+            "Vec<u64>" => 4 + 8,
+            _ => types
+                .iter()
+                .find(|type_def| type_def.name == type_name)
+                .unwrap_or_else(|| panic!("unknown type {}", type_name))
+                .fields
+                .iter()
+                .map(|field| encoded_len(types, field.type_name))
+                .sum(),
+        }
+    }
+
+    /// Deterministic addresses named after the account they stand for
+    struct Keys {
+        names: RefCell<HashMap<Pubkey, String>>,
+    }
+
+    impl Keys {
+        fn new() -> Self {
+            let keys = Self {
+                names: RefCell::new(HashMap::new()),
+            };
+            keys.insert(sysvar::clock::id(), "clock_sysvar");
+            keys.insert(sysvar::rent::id(), "rent_sysvar");
+            keys.insert(sysvar::stake_history::id(), "stake_history_sysvar");
+            keys.insert(stake::config_id(), "stake_config");
+            keys.insert(stake::id(), "stake_program");
+            keys.insert(system_program::id(), "system_program");
+            keys
+        }
+
+        fn insert(&self, key: Pubkey, name: &str) -> Pubkey {
+            self.names.borrow_mut().insert(key, name.to_string());
+            key
+        }
+
+        fn get(&self, name: &str) -> Pubkey {
+            self.insert(Pubkey::new_from_array(hash(name.as_bytes()).to_bytes()), name)
+        }
+
+        /// Names the stake and transient accounts the builders derive for a validator
+        fn derive_validator_accounts(
+            &self,
+            program_id: &Pubkey,
+            validator: &Pubkey,
+            stake_pool: &Pubkey,
+            stake_account: &str,
+            transient_stake_account: &str,
+        ) {
+            let (stake, _) =
+                Processor::find_stake_address_for_validator(program_id, validator, stake_pool);
+            self.insert(stake, stake_account);
+            let (transient, _) =
+                Processor::find_transient_stake_address(program_id, validator, stake_pool);
+            self.insert(transient, transient_stake_account);
+        }
+
+        fn describe(&self, instruction: Instruction, data: Vec<Field>) -> InstructionDef {
+            let decoded = StakePoolInstruction::deserialize(&instruction.data).unwrap();
+            assert_eq!(decoded.serialize().unwrap(), instruction.data);
+            let name = format!("{:?}", decoded);
+            let name = name.split('(').next().unwrap().to_string();
+            let optional_from = OPTIONAL_FROM
+                .iter()
+                .find(|(optional_name, _)| *optional_name == name)
+                .map_or(instruction.accounts.len(), |(_, index)| *index);
+            let names = self.names.borrow();
+            InstructionDef {
+                name,
+                tag: instruction.data[0],
+                data,
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .enumerate()
+                    .map(|(index, meta)| {
+                        let name = names
+                            .get(&meta.pubkey)
+                            .unwrap_or_else(|| panic!("unnamed account {}", meta.pubkey))
+                            .clone();
+                        AccountDef {
+                            repeated: REPEATED.contains(&name.as_str()),
+                            optional: index >= optional_from,
+                            name,
+                            writable: meta.is_writable,
+                            signer: meta.is_signer,
+                        }
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    /// Builds every instruction through its builder and describes it
+    fn interface() -> Interface {
+        let keys = Keys::new();
+        let program_id = keys.get("program_id");
+        let stake_pool = keys.get("stake_pool");
+        let validator = keys.get("validator");
+        keys.derive_validator_accounts(
+            &program_id,
+            &validator,
+            &stake_pool,
+            "validator_stake_account",
+            "transient_stake_account",
+        );
+        let validators = keys.get("validators");
+        keys.derive_validator_accounts(
+            &program_id,
+            &validators,
+            &stake_pool,
+            "validator_stake_accounts",
+            "transient_stake_accounts",
+        );
+        let (depositor_list, _) = Processor::find_depositor_list_address(&program_id, &stake_pool);
+        keys.insert(depositor_list, "depositor_list");
+        let (rate_history, _) = Processor::find_rate_history_address(&program_id, &stake_pool);
+        keys.insert(rate_history, "rate_history");
+        let (stake_pool_extension, _) =
+            Processor::find_stake_pool_extension_address(&program_id, &stake_pool);
+        keys.insert(stake_pool_extension, "stake_pool_extension");
+        let (reserve_stake, _) =
+            Processor::find_authority_bump_seed(&program_id, &stake_pool, Processor::RESERVE);
+        keys.insert(reserve_stake, "reserve_stake");
+        let k = |name| keys.get(name);
+        let depositor = k("depositor");
+        let with_depositor =
+            |instruction| with_depositor(instruction, &stake_pool, &depositor, false);
+
+        let instructions = vec![
+            (
+                initialize(
+                    &program_id,
+                    &stake_pool,
+                    &k("owner"),
+                    &k("validator_stake_list"),
+                    &k("pool_mint"),
+                    &k("owner_fee_account"),
+                    &k("token_program"),
+                    InitArgs::default(),
+                ),
+                fields(&[("args", "InitArgs")]),
+            ),
+            (
+                create_validator_stake_account(
+                    &program_id,
+                    &stake_pool,
+                    &k("funder"),
+                    &k("validator_stake_account"),
+                    &validator,
+                    &k("stake_authority"),
+                    &k("withdraw_authority"),
+                    &system_program::id(),
+                    &stake::id(),
+                ),
+                vec![],
+            ),
+            (
+                add_validator_stake_account(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("deposit_authority"),
+                    &k("withdraw_authority"),
+                    &k("validator_stake_list"),
+                    &k("validator_stake_account"),
+                    &k("pool_tokens_to"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    &stake::id(),
+                ),
+                vec![],
+            ),
+            (
+                remove_validator_stake_account(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("withdraw_authority"),
+                    &k("new_stake_authority"),
+                    &k("validator_stake_list"),
+                    &k("validator_stake_account"),
+                    &k("burn_from"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    &stake::id(),
+                ),
+                vec![],
+            ),
+            (
+                update_list_balance(
+                    &program_id,
+                    &stake_pool,
+                    &k("validator_stake_list"),
+                    &k("withdraw_authority"),
+                    &k("reserve_stake"),
+                    &[validators],
+                ),
+                vec![],
+            ),
+            (
+                update_pool_balance(
+                    &program_id,
+                    &stake_pool,
+                    &k("validator_stake_list"),
+                    &k("reserve_stake"),
+                    &k("withdraw_authority"),
+                    &k("owner_fee_account"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    &[k("validator_stake_list_pages")],
+                )
+                .map(|instruction| with_rate_history(instruction, &stake_pool)),
+                vec![],
+            ),
+            (
+                deposit(
+                    &program_id,
+                    &stake_pool,
+                    &k("validator_stake_list"),
+                    &k("deposit_authority"),
+                    &k("withdraw_authority"),
+                    &k("stake_to_join"),
+                    &k("validator_stake_account"),
+                    &k("pool_tokens_to"),
+                    &k("owner_fee_account"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    &stake::id(),
+                )
+                .map(with_depositor),
+                vec![],
+            ),
+            (
+                withdraw(
+                    &program_id,
+                    &stake_pool,
+                    &k("validator_stake_list"),
+                    &k("withdraw_authority"),
+                    &k("stake_to_split"),
+                    &k("stake_to_receive"),
+                    &k("user_withdrawer"),
+                    &k("burn_from"),
+                    &k("owner_fee_account"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    &stake::id(),
+                    1,
+                ),
+                fields(&[("amount", "u64")]),
+            ),
+            (
+                set_staking_authority(
+                    &program_id,
+                    &stake_pool,
+                    &k("owner"),
+                    &k("withdraw_authority"),
+                    &k("stake_account_to_update"),
+                    &k("new_stake_authority"),
+                    &stake::id(),
+                ),
+                vec![],
+            ),
+            (
+                set_owner(&program_id, &stake_pool, &k("owner"), &k("new_owner")),
+                vec![],
+            ),
+            (
+                instruction_add_liquidity(
+                    1,
+                    1,
+                    &program_id,
+                    &stake_pool,
+                    &k("liq_pool"),
+                    &k("token_program"),
+                    &k("meta_lp_mint"),
+                    &k("meta_lp_mint_authority"),
+                    &k("user_wsol_account"),
+                    &k("user_authority"),
+                    &k("liq_pool_wsol_account"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("user_meta_lp_account"),
+                ),
+                fields(&[("args", "AddLiquidityArgs")]),
+            ),
+            (
+                instruction_sell_stsol(
+                    1,
+                    1,
+                    &program_id,
+                    &stake_pool,
+                    &k("liq_pool"),
+                    &k("token_program"),
+                    &k("liq_pool_wsol_account"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("liq_pool_authority"),
+                    &k("user_wsol_account"),
+                    &k("user_st_sol_account"),
+                    &k("user_authority"),
+                    &k("treasury_wsol_account"),
+                ),
+                fields(&[("args", "SellArgs")]),
+            ),
+            (
+                instruction_remove_liquidity(
+                    1,
+                    &program_id,
+                    &k("liq_pool"),
+                    &k("token_program"),
+                    &k("meta_lp_mint"),
+                    &k("liq_pool_wsol_account"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("liq_pool_authority"),
+                    &k("user_meta_lp_account"),
+                    &k("user_authority"),
+                    &k("user_wsol_account"),
+                    &k("user_st_sol_account"),
+                ),
+                fields(&[("amount", "u64")]),
+            ),
+            (
+                initialize_liquidity_pool(
+                    &program_id,
+                    &k("liq_pool"),
+                    &stake_pool,
+                    &k("owner"),
+                    &k("liq_pool_wsol_account"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("meta_lp_mint"),
+                    &k("liq_pool_authority"),
+                    &k("token_program"),
+                    &k("treasury_wsol_account"),
+                    SellFeeCurve::default(),
+                ),
+                fields(&[("sell_fee", "SellFeeCurve")]),
+            ),
+            (
+                set_sell_fee(
+                    &program_id,
+                    &k("liq_pool"),
+                    &stake_pool,
+                    &k("fee_manager"),
+                    SellFeeCurve::default(),
+                ),
+                fields(&[("sell_fee", "SellFeeCurve")]),
+            ),
+            (
+                set_treasury(
+                    &program_id,
+                    &k("liq_pool"),
+                    &stake_pool,
+                    &k("owner"),
+                    &k("treasury_wsol_account"),
+                    1,
+                ),
+                fields(&[("treasury_share_bps", "u64")]),
+            ),
+            (
+                instruction_buy_stsol(
+                    1,
+                    1,
+                    &program_id,
+                    &stake_pool,
+                    &k("liq_pool"),
+                    &k("token_program"),
+                    &k("liq_pool_wsol_account"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("liq_pool_authority"),
+                    &k("user_wsol_account"),
+                    &k("user_st_sol_account"),
+                    &k("user_authority"),
+                ),
+                fields(&[("args", "BuyArgs")]),
+            ),
+            (
+                set_buy_fee(
+                    &program_id,
+                    &k("liq_pool"),
+                    &stake_pool,
+                    &k("fee_manager"),
+                    1,
+                ),
+                fields(&[("buy_fee_bps", "u64")]),
+            ),
+            (
+                instruction_liquidity_pool_unstake(
+                    1,
+                    1,
+                    &program_id,
+                    &k("liq_pool"),
+                    &stake_pool,
+                    &k("validator_stake_list"),
+                    &k("withdraw_authority"),
+                    &k("stake_to_split"),
+                    &k("ticket_stake_account"),
+                    &k("liq_pool_authority"),
+                    &k("liq_pool_st_sol_account"),
+                    &k("pool_mint"),
+                    &k("payer"),
+                    &k("token_program"),
+                    &stake::id(),
+                    &k("owner_fee_account"),
+                ),
+                fields(&[("args", "UnstakeArgs")]),
+            ),
+            (
+                instruction_liquidity_pool_claim(
+                    1,
+                    &program_id,
+                    &k("liq_pool"),
+                    &k("ticket_stake_account"),
+                    &k("liq_pool_authority"),
+                    &k("liq_pool_wsol_account"),
+                    &k("temp_wsol_account"),
+                    &k("wsol_mint"),
+                    &k("payer"),
+                    &k("ticket_rent_payer"),
+                    &k("token_program"),
+                    &stake::id(),
+                ),
+                fields(&[("ticket", "u64")]),
+            ),
+            (
+                deposit_sol(
+                    &program_id,
+                    &stake_pool,
+                    &k("withdraw_authority"),
+                    &k("reserve_stake"),
+                    &k("lamports_from"),
+                    &k("pool_tokens_to"),
+                    &k("owner_fee_account"),
+                    &k("pool_mint"),
+                    &k("token_program"),
+                    1,
+                )
+                .map(with_depositor),
+                fields(&[("lamports", "u64")]),
+            ),
+            (
+                set_fee(&program_id, &stake_pool, &k("fee_manager"), Fee::default()),
+                fields(&[("fee", "Fee")]),
+            ),
+            (
+                increase_validator_stake(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("withdraw_authority"),
+                    &k("validator_stake_list"),
+                    &k("reserve_stake"),
+                    &validator,
+                    1,
+                ),
+                fields(&[("lamports", "u64")]),
+            ),
+            (
+                decrease_validator_stake(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("withdraw_authority"),
+                    &k("validator_stake_list"),
+                    &k("reserve_stake"),
+                    &validator,
+                    1,
+                ),
+                fields(&[("lamports", "u64")]),
+            ),
+            (
+                set_validator_weights(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("validator_stake_list"),
+                    &[(validators, 1)],
+                ),
+                fields(&[("weights", "ValidatorWeights")]),
+            ),
+            (
+                rebalance(
+                    &program_id,
+                    &stake_pool,
+                    &k("withdraw_authority"),
+                    &k("validator_stake_list"),
+                    &k("reserve_stake"),
+                    &validator,
+                ),
+                vec![],
+            ),
+            (
+                add_validator_stake_list_page(
+                    &program_id,
+                    &stake_pool,
+                    &k("staker"),
+                    &k("validator_stake_list_page"),
+                ),
+                vec![],
+            ),
+            (
+                migrate_state(
+                    &program_id,
+                    &stake_pool,
+                    &k("owner"),
+                    &k("validator_stake_list"),
+                    Some(&k("validator_stake_list_page")),
+                ),
+                vec![],
+            ),
+            (
+                accept_owner(&program_id, &stake_pool, &k("pending_owner")),
+                vec![],
+            ),
+            (
+                set_staker(&program_id, &stake_pool, &k("owner"), &k("new_staker")),
+                vec![],
+            ),
+            (
+                set_fee_manager(
+                    &program_id,
+                    &stake_pool,
+                    &k("owner"),
+                    &k("new_fee_manager"),
+                    &k("new_owner_fee_account"),
+                ),
+                vec![],
+            ),
+            (
+                set_deposit_authority(
+                    &program_id,
+                    &stake_pool,
+                    &k("owner"),
+                    &k("new_deposit_authority"),
+                ),
+                vec![],
+            ),
+            (
+                set_depositor(&program_id, &stake_pool, &k("owner"), &depositor, true),
+                fields(&[("allowed", "bool")]),
+            ),
+            (
+                deactivate_delinquent_validator(
+                    &program_id,
+                    &stake_pool,
+                    &k("withdraw_authority"),
+                    &k("validator_stake_list"),
+                    &validator,
+                ),
+                vec![],
+            ),
+            (
+                set_delinquent_slots(&program_id, &stake_pool, &k("staker"), 1),
+                fields(&[("delinquent_slots", "u64")]),
+            ),
+            (
+                create_rate_history(&program_id, &stake_pool, &k("owner")),
+                vec![],
+            ),
+        ];
+
+        let types = types();
+        let instructions = instructions
+            .into_iter()
+            .map(|(instruction, data)| {
+                let instruction = instruction.unwrap();
+                let data_len: usize = data
+                    .iter()
+                    .map(|field| encoded_len(&types, field.type_name))
+                    .sum();
+                assert_eq!(instruction.data.len(), 1 + data_len);
+                keys.describe(instruction, data)
+            })
+            .collect::<Vec<_>>();
+        for (tag, instruction) in instructions.iter().enumerate() {
+            assert_eq!(instruction.tag as usize, tag);
+        }
+        Interface {
+            name: "spl_stake_pool",
+            encoding: "borsh",
+            types,
+            instructions,
+        }
+    }
+
+    #[test]
+    fn test_interface() {
+        let interface = serde_json::to_string_pretty(&interface()).unwrap() + "\n";
+        if std::env::var("UPDATE_INTERFACE").is_ok() {
+            std::fs::write(INTERFACE_PATH, &interface).unwrap();
+        }
+        let committed = std::fs::read_to_string(INTERFACE_PATH).unwrap();
+        assert!(
+            committed == interface,
+            "interface.json is out of date, regenerate it with UPDATE_INTERFACE=1 cargo test"
+        );
+    }
+
+    #[test]
+    fn test_update_pool_balance_with_all_pages() {
+        // max transaction size, `solana_sdk::packet::PACKET_DATA_SIZE`
+        const PACKET_DATA_SIZE: usize = 1232;
+        let program_id = Pubkey::new_unique();
+        let stake_pool = Pubkey::new_unique();
+        let pages: Vec<Pubkey> = (1..MAX_VALIDATOR_STAKE_LIST_PAGES)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        let instruction = update_pool_balance(
+            &program_id,
+            &stake_pool,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &pages,
+        )
+        .map(|instruction| with_rate_history(instruction, &stake_pool))
+        .unwrap();
+        let message = Message::new(&[instruction], Some(&Pubkey::new_unique()));
+        // signature count and the fee payer signature
+        assert!(1 + 64 + message.serialize().len() <= PACKET_DATA_SIZE);
+    }
+
+    #[test]
+    fn test_instruction_encoding() {
+        let instruction = StakePoolInstruction::Withdraw(0x0102);
+        assert_eq!(
+            instruction.serialize().unwrap(),
+            vec![7, 2, 1, 0, 0, 0, 0, 0, 0]
+        );
+        let instruction = StakePoolInstruction::SetFee(Fee {
+            denominator: 100,
+            numerator: 3,
+        });
+        let data = instruction.serialize().unwrap();
+        assert_eq!(data.len(), 17);
+        assert_eq!(StakePoolInstruction::deserialize(&data), Ok(instruction));
+        assert_eq!(
+            StakePoolInstruction::deserialize(&data[..16]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            StakePoolInstruction::deserialize(&[u8::MAX]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+}
@@ -37,6 +37,7 @@ pub mod instruction;
 pub mod processor;
 pub mod stake;
 pub mod state;
+pub mod vote;
 
 /// Current program version
 pub const PROGRAM_VERSION: u8 = 1;
@@ -1,14 +1,14 @@
 //! Program state processor
 
-//const CHECK_STAKE_ACCOUNT_IS_ACTIVE:bool=false; //while testing we allow not-yet-activated stake accounts7
-// commented, Self::check_stake_activation is already disabled to facilitate tests
-
 use crate::{
     error::StakePoolError,
-    instruction::{InitArgs, StakePoolInstruction},
+    instruction::{Fee, InitArgs, StakePoolInstruction, ValidatorWeights, MAX_VALIDATOR_WEIGHTS},
     stake,
-    state::{StakePool, ValidatorStakeInfo, ValidatorStakeList},
-    PROGRAM_VERSION,
+    state::{
+        DepositorList, LiquidityPool, RateHistory, RateHistoryEntry, RebalanceMove, Role, SellFeeCurve, StakePool, UnstakeTicket,
+        ValidatorStakeInfo, ValidatorStakeList, ValidatorStakeListView, MAX_DEPOSITORS, MAX_VALIDATOR_STAKE_LIST_PAGES,
+    },
+    vote,
 };
 use bincode::deserialize;
 use num_traits::FromPrimitive;
@@ -31,7 +31,7 @@ use solana_program::{
     sysvar::Sysvar,
 };
 use spl_token::state::Mint;
-use core::convert::{TryFrom,TryInto};
+use core::convert::TryFrom;
 
 /// Program state handler.
 pub struct Processor {}
@@ -42,6 +42,18 @@ impl Processor {
     pub const AUTHORITY_WITHDRAW: &'static [u8] = b"withdraw";
     /// Seed for general PDA authority
     pub const AUTHORITY: &'static [u8] = b"authority";
+    /// Seed for the stake pool reserve stake account
+    pub const RESERVE: &'static [u8] = b"reserve";
+    /// Seed for liq pool unstake ticket stake accounts
+    pub const UNSTAKE: &'static [u8] = b"unstake";
+    /// Seed for validator transient stake accounts
+    pub const TRANSIENT: &'static [u8] = b"transient";
+    /// Seed for the stake pool depositor list
+    pub const DEPOSITORS: &'static [u8] = b"depositors";
+    /// Seed for the stake pool rate history
+    pub const RATE_HISTORY: &'static [u8] = b"history";
+    /// Seed for the stake pool extension
+    pub const EXTENSION: &'static [u8] = b"extension";
 
     /// Calculates the authority id by generating a program address.
     /// from a base_account_pubkey as seed and a bump
@@ -79,6 +91,130 @@ impl Processor {
         )
     }
 
+    /// Generates the transient stake account address for the validator,
+    /// used by IncreaseValidatorStake and DecreaseValidatorStake
+    pub fn find_transient_stake_address(
+        program_id: &Pubkey,
+        validator: &Pubkey,
+        stake_pool: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                &validator.to_bytes()[..32],
+                &stake_pool.to_bytes()[..32],
+                Self::TRANSIENT,
+            ],
+            program_id,
+        )
+    }
+
+    /// Generates the stake account address for a liq pool unstake ticket
+    pub fn find_unstake_ticket_address(
+        program_id: &Pubkey,
+        liq_pool: &Pubkey,
+        ticket: u8,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&liq_pool.to_bytes()[..32], Self::UNSTAKE, &[ticket]],
+            program_id,
+        )
+    }
+
+    /// Generates the depositor list address of a stake pool
+    pub fn find_depositor_list_address(program_id: &Pubkey, stake_pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&stake_pool.to_bytes()[..32], Self::DEPOSITORS],
+            program_id,
+        )
+    }
+
+    /// Generates the rate history address of a stake pool
+    pub fn find_rate_history_address(program_id: &Pubkey, stake_pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&stake_pool.to_bytes()[..32], Self::RATE_HISTORY],
+            program_id,
+        )
+    }
+
+    /// Generates the extension address of a stake pool, holding the state fields added since v1
+    pub fn find_stake_pool_extension_address(
+        program_id: &Pubkey,
+        stake_pool: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[&stake_pool.to_bytes()[..32], Self::EXTENSION], program_id)
+    }
+
+    /// Deserializes the stake pool state from the stake pool account and its extension,
+    /// checking both are owned by the program and the extension address. Uninitialized
+    /// pools are read as version 0
+    pub fn load_stake_pool(
+        program_id: &Pubkey,
+        stake_pool_info: &AccountInfo,
+        extension_info: &AccountInfo,
+    ) -> Result<StakePool, ProgramError> {
+        // a stake pool account owned by another program could name any owner
+        if stake_pool_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut stake_pool = StakePool::deserialize_record(&stake_pool_info.data.borrow())?;
+        if stake_pool.is_initialized() {
+            stake_pool.check_extension(extension_info.key, program_id, stake_pool_info.key)?;
+            if extension_info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            stake_pool.deserialize_extension(&extension_info.data.borrow())?;
+        }
+        Ok(stake_pool)
+    }
+
+    /// Serializes the stake pool state into the stake pool account and its extension
+    pub fn save_stake_pool(
+        stake_pool: &StakePool,
+        stake_pool_info: &AccountInfo,
+        extension_info: &AccountInfo,
+    ) -> ProgramResult {
+        stake_pool.serialize(
+            &mut stake_pool_info.data.borrow_mut(),
+            &mut extension_info.data.borrow_mut(),
+        )
+    }
+
+    /// Checks the depositor accounts following the `Deposit` and `DepositSol` accounts.
+    /// Pools without a deposit authority take deposits from anyone and read none
+    pub fn check_depositor<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+        program_id: &Pubkey,
+        stake_pool_key: &Pubkey,
+        stake_pool: &StakePool,
+        account_info_iter: &mut I,
+    ) -> ProgramResult {
+        if !stake_pool.has_deposit_authority() {
+            return Ok(());
+        }
+        // Depositor, the deposit authority or a depositor of the list
+        let depositor_info = next_account_info(account_info_iter)?;
+        if !depositor_info.is_signer {
+            return Err(StakePoolError::SignatureMissing.into());
+        }
+        if *depositor_info.key == stake_pool.deposit_authority {
+            return Ok(());
+        }
+        // Depositor list
+        let depositor_list_info = next_account_info(account_info_iter)?;
+        let (depositor_list_address, _) =
+            Self::find_depositor_list_address(program_id, stake_pool_key);
+        if *depositor_list_info.key != depositor_list_address {
+            return Err(StakePoolError::InvalidDepositorList.into());
+        }
+        // the list does not exist until the first SetDepositor
+        if *depositor_list_info.owner != *program_id
+            || !DepositorList::deserialize(&depositor_list_info.data.borrow())?
+                .contains(depositor_info.key)
+        {
+            return Err(StakePoolError::DepositorNotAllowed.into());
+        }
+        Ok(())
+    }
+
     /// Checks withdraw or deposit authority
     pub fn check_authority(
         authority_to_check: &Pubkey,
@@ -108,6 +244,21 @@ impl Processor {
         }
     }
 
+    /// Returns the meta and the delegation of a stake account owned by the stake program
+    pub fn get_stake_state(
+        stake_account_info: &AccountInfo,
+    ) -> Result<(stake::Meta, stake::Stake), ProgramError> {
+        if *stake_account_info.owner != stake::id() {
+            return Err(StakePoolError::WrongStakeState.into());
+        }
+        let stake_state: stake::StakeState = deserialize(&stake_account_info.data.borrow())
+            .or(Err(ProgramError::InvalidAccountData))?;
+        match stake_state {
+            stake::StakeState::Stake(meta, stake) => Ok((meta, stake)),
+            _ => Err(StakePoolError::WrongStakeState.into()),
+        }
+    }
+
     /// Checks if validator stake account is a proper program address
     pub fn is_validator_stake_address(
         validator_account: &Pubkey,
@@ -236,6 +387,227 @@ impl Processor {
         )
     }
 
+    /// Issue a stake_delegate instruction.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stake_delegate<'a>(
+        stake_pool: &Pubkey,
+        stake_account: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        authority_type: &[u8],
+        bump_seed: u8,
+        vote_account: AccountInfo<'a>,
+        clock: AccountInfo<'a>,
+        stake_history: AccountInfo<'a>,
+        stake_config: AccountInfo<'a>,
+        stake_program_info: AccountInfo<'a>,
+    ) -> Result<(), ProgramError> {
+        let me_bytes = stake_pool.to_bytes();
+        let authority_signature_seeds = [&me_bytes[..32], authority_type, &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+
+        let ix = stake::delegate_stake(stake_account.key, authority.key, vote_account.key);
+
+        invoke_signed(
+            &ix,
+            &[
+                stake_account,
+                vote_account,
+                clock,
+                stake_history,
+                stake_config,
+                authority,
+                stake_program_info,
+            ],
+            signers,
+        )
+    }
+
+    /// Issue a stake_deactivate instruction.
+    pub fn stake_deactivate<'a>(
+        pda_base: &Pubkey,
+        stake_account: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        authority_type: &[u8],
+        bump_seed: u8,
+        clock: AccountInfo<'a>,
+        stake_program_info: AccountInfo<'a>,
+    ) -> Result<(), ProgramError> {
+        let me_bytes = pda_base.to_bytes();
+        let authority_signature_seeds = [&me_bytes[..32], authority_type, &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+
+        let ix = stake::deactivate_stake(stake_account.key, authority.key);
+
+        invoke_signed(
+            &ix,
+            &[stake_account, clock, authority, stake_program_info],
+            signers,
+        )
+    }
+
+    /// Issue a stake_withdraw instruction.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stake_withdraw<'a>(
+        pda_base: &Pubkey,
+        stake_account: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        authority_type: &[u8],
+        bump_seed: u8,
+        destination: AccountInfo<'a>,
+        amount: u64,
+        clock: AccountInfo<'a>,
+        stake_history: AccountInfo<'a>,
+        stake_program_info: AccountInfo<'a>,
+    ) -> Result<(), ProgramError> {
+        let me_bytes = pda_base.to_bytes();
+        let authority_signature_seeds = [&me_bytes[..32], authority_type, &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+
+        let ix = stake::withdraw(stake_account.key, authority.key, destination.key, amount);
+
+        invoke_signed(
+            &ix,
+            &[
+                stake_account,
+                destination,
+                clock,
+                stake_history,
+                authority,
+                stake_program_info,
+            ],
+            signers,
+        )
+    }
+
+    /// Creates a PDA account owned by `owner`, the payer funds the rent.
+    /// Lamports already sent to the address are kept, so funding it first does not block it
+    pub fn create_pda_account<'a>(
+        payer: AccountInfo<'a>,
+        new_account: AccountInfo<'a>,
+        system_program: AccountInfo<'a>,
+        rent: &Rent,
+        space: usize,
+        owner: &Pubkey,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let required_lamports = rent
+            .minimum_balance(space)
+            .saturating_sub(new_account.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, new_account.key, required_lamports),
+                &[payer, new_account.clone(), system_program.clone()],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(new_account.key, space as u64),
+            &[new_account.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(new_account.key, owner),
+            &[new_account, system_program],
+            &[signer_seeds],
+        )
+    }
+
+    /// Creates the stake pool reserve, an initialized (not delegated) stake account with
+    /// the withdraw authority as staker and withdrawer
+    #[allow(clippy::too_many_arguments)]
+    fn create_reserve_stake_account<'a>(
+        payer: AccountInfo<'a>,
+        reserve_stake_info: AccountInfo<'a>,
+        system_program_info: AccountInfo<'a>,
+        rent_info: AccountInfo<'a>,
+        stake_program_info: AccountInfo<'a>,
+        stake_pool: &Pubkey,
+        withdraw_authority: &Pubkey,
+        reserve_bump_seed: u8,
+    ) -> ProgramResult {
+        let rent = &Rent::from_account_info(&rent_info)?;
+        let stake_pool_bytes = stake_pool.to_bytes();
+        Self::create_pda_account(
+            payer,
+            reserve_stake_info.clone(),
+            system_program_info,
+            rent,
+            std::mem::size_of::<stake::StakeState>(),
+            &stake::id(),
+            &[&stake_pool_bytes[..32], Self::RESERVE, &[reserve_bump_seed]],
+        )?;
+        invoke(
+            &stake::initialize(
+                reserve_stake_info.key,
+                &stake::Authorized {
+                    staker: *withdraw_authority,
+                    withdrawer: *withdraw_authority,
+                },
+                &stake::Lockup::default(),
+            ),
+            &[reserve_stake_info, rent_info, stake_program_info],
+        )
+    }
+
+    /// Creates the validator transient stake account, allocated and owned by the stake
+    /// program so it can receive a split
+    #[allow(clippy::too_many_arguments)]
+    fn create_transient_stake_account<'a>(
+        payer: AccountInfo<'a>,
+        transient_stake_info: AccountInfo<'a>,
+        system_program_info: AccountInfo<'a>,
+        rent: &Rent,
+        validator: &Pubkey,
+        stake_pool: &Pubkey,
+        transient_bump_seed: u8,
+    ) -> ProgramResult {
+        Self::create_pda_account(
+            payer,
+            transient_stake_info,
+            system_program_info,
+            rent,
+            std::mem::size_of::<stake::StakeState>(),
+            &stake::id(),
+            &[
+                &validator.to_bytes()[..32],
+                &stake_pool.to_bytes()[..32],
+                Self::TRANSIENT,
+                &[transient_bump_seed],
+            ],
+        )
+    }
+
+    /// Withdraws the transient stake account rent from the reserve before the account is
+    /// created, so no lamports are missing and the reserve is not charged again as payer.
+    /// The rent stays pool stake, lamports from outside the pool would be counted as rewards
+    #[allow(clippy::too_many_arguments)]
+    fn fund_transient_stake_account<'a>(
+        stake_pool_key: &Pubkey,
+        stake_pool: &StakePool,
+        reserve_stake_info: AccountInfo<'a>,
+        withdraw_info: AccountInfo<'a>,
+        transient_stake_info: AccountInfo<'a>,
+        transient_rent: u64,
+        clock_info: AccountInfo<'a>,
+        stake_history_info: AccountInfo<'a>,
+        stake_program_info: AccountInfo<'a>,
+    ) -> ProgramResult {
+        if transient_rent == 0 {
+            return Ok(());
+        }
+        Self::stake_withdraw(
+            stake_pool_key,
+            reserve_stake_info,
+            withdraw_info,
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            transient_stake_info,
+            transient_rent,
+            clock_info,
+            stake_history_info,
+            stake_program_info,
+        )
+    }
+
     /// Issue a spl_token `Burn` instruction.
     #[allow(clippy::too_many_arguments)]
     pub fn token_burn<'a>(
@@ -268,30 +640,46 @@ impl Processor {
         )
     }
 
-    /// Issue a spl_token `MintTo` instruction.
+    /// Issue a spl_token `SetAuthority` instruction giving the mint authority to `new_authority`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn token_set_mint_authority<'a>(
+        stake_pool: &Pubkey,
+        token_program: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        authority_type: &[u8],
+        bump_seed: u8,
+        new_authority: &Pubkey,
+    ) -> Result<(), ProgramError> {
+        let me_bytes = stake_pool.to_bytes();
+        let authority_signature_seeds = [&me_bytes[..32], authority_type, &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+
+        let ix = spl_token::instruction::set_authority(
+            token_program.key,
+            mint.key,
+            Some(new_authority),
+            spl_token::instruction::AuthorityType::MintTokens,
+            authority.key,
+            &[],
+        )?;
+
+        invoke_signed(&ix, &[mint, authority, token_program], signers)
+    }
+
+    /// Issue a spl_token `MintTo` instruction signed by the liq pool authority.
     #[allow(clippy::too_many_arguments)]
     pub fn liq_pool_token_mint_to<'a>(
-        program_id: &Pubkey,
         liq_pool_account: &Pubkey,
         token_program: AccountInfo<'a>,
         mint: AccountInfo<'a>,
         destination: AccountInfo<'a>,
         liq_pool_authority: AccountInfo<'a>,
+        bump_seed: u8,
         amount: u64,
     ) -> Result<(), ProgramError> {
+        let signer_seeds: &[&[_]] = &[&liq_pool_account.to_bytes()[..32], Self::AUTHORITY, &[bump_seed]];
 
-        //msg!("&liq_pool_account.to_bytes()[..32] {:?}", &liq_pool_account.to_bytes()[..32]);
-        //perform the same computation used when calculating liq_pool_authority to obtain the same bump
-        let (_computed_liq_pool_authority,bump) = Self::find_authority_bump_seed(&program_id, &liq_pool_account, Self::AUTHORITY);
-        let signer_seeds: &[&[_]] =&[&liq_pool_account.to_bytes()[..32], Self::AUTHORITY,&[bump]]; 
-
-        //pub fn mint_to( 
-        // token_program_id: &Pubkey, 
-        // mint_pubkey: &Pubkey, 
-        // account_pubkey: &Pubkey, 
-        // owner_pubkey: &Pubkey, 
-        // signer_pubkeys: &[&Pubkey], 
-        // amount: u64, ) -> Result<Instruction, ProgramError>
         let ix = spl_token::instruction::mint_to(
             token_program.key,
             mint.key,
@@ -342,6 +730,8 @@ impl Processor {
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account, created here
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
         let owner_info = next_account_info(account_info_iter)?;
         let validator_stake_list_info = next_account_info(account_info_iter)?;
         let pool_mint_info = next_account_info(account_info_iter)?;
@@ -354,26 +744,38 @@ impl Processor {
         let rent = &Rent::from_account_info(rent_info)?;
         // Token program ID
         let token_program_info = next_account_info(account_info_iter)?;
+        // System program id
+        let system_program_info = next_account_info(account_info_iter)?;
+        // Reserve stake account, created here
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Stake program id
+        let stake_program_info = next_account_info(account_info_iter)?;
+
+        // Check program ids
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
         // Check if transaction was signed by owner
         if !owner_info.is_signer {
             return Err(StakePoolError::SignatureMissing.into());
         }
 
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool = StakePool::deserialize_record(&stake_pool_info.data.borrow())?;
         // Stake pool account should not be already initialized
         if stake_pool.is_initialized() {
             return Err(StakePoolError::AlreadyInUse.into());
         }
 
         // Check if validator stake list storage is unitialized
-        let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
-        if validator_stake_list.is_initialized() {
+        if validator_stake_list_info.data_len() < ValidatorStakeList::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if ValidatorStakeListView::new(&validator_stake_list_info.data.borrow()[..])?
+            .is_initialized()
+        {
             return Err(StakePoolError::AlreadyInUse.into());
         }
-        validator_stake_list.version = ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION;
-        validator_stake_list.validators.clear();
 
         // Check if stake pool account is rent-exempt
         if !rent.is_exempt(stake_pool_info.lamports(), stake_pool_info.data_len()) {
@@ -389,7 +791,10 @@ impl Processor {
         }
 
         // Numerator should be smaller than or equal to denominator (fee <= 1)
-        if init.fee.numerator > init.fee.denominator {
+        if init.fee.numerator > init.fee.denominator
+            || init.reward_fee.numerator > init.reward_fee.denominator
+            || init.withdrawal_fee.numerator > init.withdrawal_fee.denominator
+        {
             return Err(StakePoolError::FeeTooHigh.into());
         }
 
@@ -421,29 +826,70 @@ impl Processor {
             Self::AUTHORITY_WITHDRAW,
         );
 
+        let (reserve_stake_address, reserve_bump_seed) =
+            Self::find_authority_bump_seed(program_id, stake_pool_info.key, Self::RESERVE);
+        if *reserve_stake_info.key != reserve_stake_address {
+            return Err(StakePoolError::InvalidProgramAddress.into());
+        }
+
         let pool_mint = Mint::unpack_from_slice(&pool_mint_info.data.borrow())?;
 
         if !pool_mint.mint_authority.contains(&withdraw_authority_key) {
             return Err(StakePoolError::WrongMintingAuthority.into());
         }
 
-        validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
+        let (extension_address, extension_bump_seed) =
+            Self::find_stake_pool_extension_address(program_id, stake_pool_info.key);
+        if *stake_pool_extension_info.key != extension_address {
+            return Err(StakePoolError::InvalidStakePoolExtension.into());
+        }
+        let stake_pool_bytes = stake_pool_info.key.to_bytes();
+        Self::create_pda_account(
+            owner_info.clone(),
+            stake_pool_extension_info.clone(),
+            system_program_info.clone(),
+            rent,
+            StakePool::EXTENSION_LEN,
+            program_id,
+            &[&stake_pool_bytes[..32], Self::EXTENSION, &[extension_bump_seed]],
+        )?;
+        // the owner also pays the reserve rent
+        Self::create_reserve_stake_account(
+            owner_info.clone(),
+            reserve_stake_info.clone(),
+            system_program_info.clone(),
+            rent_info.clone(),
+            stake_program_info.clone(),
+            stake_pool_info.key,
+            &withdraw_authority_key,
+            reserve_bump_seed,
+        )?;
+
+        ValidatorStakeListView::new(&mut validator_stake_list_info.data.borrow_mut()[..])?
+            .initialize();
 
         msg!("Clock data: {:?}", clock_info.data.borrow());
         msg!("Epoch: {}", clock.epoch);
 
-        stake_pool.version = PROGRAM_VERSION;
+        stake_pool.version = StakePool::STAKE_POOL_VERSION;
         stake_pool.owner = *owner_info.key;
+        stake_pool.staker = *owner_info.key;
+        stake_pool.fee_manager = *owner_info.key;
         stake_pool.deposit_bump_seed = deposit_bump_seed;
         stake_pool.withdraw_bump_seed = withdraw_bump_seed;
         stake_pool.validator_stake_list = *validator_stake_list_info.key;
         stake_pool.pool_mint = *pool_mint_info.key;
         stake_pool.owner_fee_account = *owner_fee_info.key;
         stake_pool.token_program_id = *token_program_info.key;
+        stake_pool.extension_bump_seed = extension_bump_seed;
         stake_pool.last_update_epoch = clock.epoch;
         stake_pool.fee = init.fee;
+        stake_pool.reward_fee = init.reward_fee;
+        stake_pool.withdrawal_fee = init.withdrawal_fee;
+        stake_pool.withdrawal_fee_to_owner = init.withdrawal_fee_to_owner;
+        stake_pool.reserve_bump_seed = reserve_bump_seed;
 
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
     }
 
     /// Processes `CreateValidatorStakeAccount` instruction.
@@ -538,8 +984,10 @@ impl Processor {
         let account_info_iter = &mut accounts.iter();
         // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
-        // Pool owner account
-        let owner_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
         // Stake pool deposit authority
         let deposit_info = next_account_info(account_info_iter)?;
         // Stake pool withdraw authority
@@ -569,7 +1017,8 @@ impl Processor {
         }
 
         // Get stake pool stake (and check if it is initialized)
-        let mut stake_pool_data = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool_data =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool_data.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
@@ -578,8 +1027,8 @@ impl Processor {
         stake_pool_data.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
         stake_pool_data.check_authority_deposit(deposit_info.key, program_id, stake_pool_info.key)?;
 
-        // Check owner validity and signature
-        stake_pool_data.check_owner(owner_info)?;
+        // Check staker validity and signature
+        stake_pool_data.check_staker(staker_info)?;
 
         // Check stake pool last update epoch
         msg!("stake_pool_data.last_update_epoch {} vs. clock.epoch {}",stake_pool_data.last_update_epoch, clock.epoch);
@@ -595,13 +1044,12 @@ impl Processor {
         }
 
         // Check validator stake account list storage
-        if *validator_stake_list_info.key != stake_pool_data.validator_stake_list {
-            return Err(StakePoolError::InvalidValidatorStakeList.into());
-        }
+        stake_pool_data.check_validator_stake_list(validator_stake_list_info.key)?;
 
         // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
         let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
         if !validator_stake_list.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
@@ -612,6 +1060,9 @@ impl Processor {
         if validator_stake_list.contains(&validator_account) {
             return Err(StakePoolError::ValidatorAlreadyAdded.into());
         }
+        if validator_stake_list.len() >= validator_stake_list.capacity() {
+            return Err(StakePoolError::ValidatorStakeListFull.into());
+        }
 
         // Update Withdrawer and Staker authority to the program withdraw authority
         for authority in &[
@@ -654,18 +1105,20 @@ impl Processor {
         Self::check_stake_activation(stake_account_info, clock, stake_history)?;
 
         // Add validator to the list and save
-        validator_stake_list.validators.push(ValidatorStakeInfo {
+        validator_stake_list.push(ValidatorStakeInfo {
             validator_account,
             balance: stake_lamports,
             last_update_epoch: clock.epoch,
-        });
-        validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
+            transient_balance: 0,
+            target_weight: 0,
+            leaving: false,
+        })?;
 
         // Save amounts to the stake pool state
         stake_pool_data.pool_total += token_amount;
         // Only update stake total if the last state update epoch is current
         stake_pool_data.stake_total += stake_lamports;
-        stake_pool_data.serialize(&mut stake_pool_info.data.borrow_mut())?;
+        Self::save_stake_pool(&stake_pool_data, stake_pool_info, stake_pool_extension_info)?;
 
         msg!("--- end process_add_validator_stake_account");
 
@@ -680,8 +1133,10 @@ impl Processor {
         let account_info_iter = &mut accounts.iter();
         // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
-        // Pool owner account
-        let owner_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
         // Stake pool withdraw authority
         let withdraw_info = next_account_info(account_info_iter)?;
         // New stake authority
@@ -708,7 +1163,8 @@ impl Processor {
         }
 
         // Get stake pool stake (and check if it is initialized)
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
@@ -716,8 +1172,8 @@ impl Processor {
         // Check authority account
         stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
 
-        // Check owner validity and signature
-        stake_pool.check_owner(owner_info)?;
+        // Check staker validity and signature
+        stake_pool.check_staker(staker_info)?;
 
         // Check stake pool last update epoch
         if stake_pool.last_update_epoch < clock.epoch {
@@ -732,13 +1188,12 @@ impl Processor {
         }
 
         // Check validator stake account list storage
-        if *validator_stake_list_info.key != stake_pool.validator_stake_list {
-            return Err(StakePoolError::InvalidValidatorStakeList.into());
-        }
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
 
         // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
         let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
         if !validator_stake_list.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
@@ -746,8 +1201,13 @@ impl Processor {
         let validator_account =
             Self::get_validator_checked(program_id, stake_pool_info, stake_account_info)?;
 
-        if !validator_stake_list.contains(&validator_account) {
-            return Err(StakePoolError::ValidatorNotFound.into());
+        let validator_stake_record = validator_stake_list
+            .find(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+
+        // lamports still moving through the transient account would be lost
+        if validator_stake_record.transient_balance > 0 {
+            return Err(StakePoolError::TransientAccountInUse.into());
         }
 
         // Update Withdrawer and Staker authority to the provided authority
@@ -785,209 +1245,1065 @@ impl Processor {
         )?;
 
         // Remove validator from the list and save
-        validator_stake_list
-            .validators
-            .retain(|item| item.validator_account != validator_account);
-        validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
+        let removed = validator_stake_list
+            .remove(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+        stake_pool.total_target_weight = stake_pool
+            .total_target_weight
+            .saturating_sub(removed.target_weight);
 
         // Save amounts to the stake pool state
         stake_pool.pool_total -= token_amount;
         // Only update stake total if the last state update epoch is current
         stake_pool.stake_total -= stake_lamports;
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())?;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
 
         Ok(())
     }
 
-    /// Processes `UpdateListBalance` instruction.
-    pub fn process_update_list_balance(
-        _program_id: &Pubkey,
+    /// Processes `IncreaseValidatorStake` instruction.
+    /// Splits lamports from the reserve into the validator transient stake account
+    /// and delegates them, UpdateListBalance merges them once active.
+    /// The reserve funds the transient stake account rent
+    pub fn process_increase_validator_stake(
+        program_id: &Pubkey,
+        lamports: u64,
         accounts: &[AccountInfo],
-
     ) -> ProgramResult {
+        if lamports == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
 
         let account_info_iter = &mut accounts.iter();
-        // Account storing validator stake list
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
         let validator_stake_list_info = next_account_info(account_info_iter)?;
-        // Clock sysvar account
+        // Reserve stake account to split from
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Validator transient stake account to create
+        let transient_stake_info = next_account_info(account_info_iter)?;
+        // Validator vote account to delegate to
+        let validator_vote_info = next_account_info(account_info_iter)?;
+        // Sysvars and programs
         let clock_info = next_account_info(account_info_iter)?;
         let clock = &Clock::from_account_info(clock_info)?;
-        
-        // rest of the accounts are Validator stake accounts
-        let validator_stake_accounts_args = account_info_iter.as_slice();
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let stake_config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let stake_program_info = next_account_info(account_info_iter)?;
+
+        // Check program ids
+        if *system_program_info.key != solana_program::system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        stake_pool.check_staker(staker_info)?;
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+
+        // Check stake pool last update epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
 
         // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
         let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
         if !validator_stake_list.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        // //get validator account id state for each passed validator acc 
-        // let validator_accounts_args: Vec<Option<Pubkey>> = validator_stake_accounts_args
-        //     .iter()
-        //     .map(|stake| Self::get_validator(stake).ok())
-        //     .collect();
-
-        let mut changes = false;
-        
-        // Do a brute iteration through the list, optimize if necessary
-        for validator_stake_record in &mut validator_stake_list.validators {
+        let mut validator_stake_record = validator_stake_list
+            .find_mut(validator_vote_info.key)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+        if validator_stake_record.leaving {
+            return Err(StakePoolError::ValidatorLeaving.into());
+        }
 
-            if validator_stake_record.last_update_epoch >= clock.epoch {
-                continue;
-            }
-            msg!("validator_stake_record {}",validator_stake_record.validator_account);
-
-            //LMT debug force updated mark
-            //validator_stake_record.last_update_epoch = clock.epoch;
-            //changes = true;
-
-            //for (validator_stake_account, validator_account) in validator_stake_accounts
-            for item in validator_stake_accounts_args.iter() {
-                if *item.key==validator_stake_record.validator_account {
-                    validator_stake_record.last_update_epoch = clock.epoch;
-                    validator_stake_record.balance = **item.lamports.borrow();
-                    msg!("updated to {} {}",clock.epoch, validator_stake_record.balance);
-                    changes = true;
-                }
-            }
+        let (transient_stake_address, transient_bump_seed) = Self::find_transient_stake_address(
+            program_id,
+            validator_vote_info.key,
+            stake_pool_info.key,
+        );
+        if *transient_stake_info.key != transient_stake_address {
+            return Err(StakePoolError::InvalidStakeAccountAddress.into());
+        }
+        // one rebalance per validator at a time
+        if *transient_stake_info.owner == stake::id() || validator_stake_record.transient_balance > 0 {
+            return Err(StakePoolError::TransientAccountInUse.into());
         }
 
-        if changes {
-            validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
+        // the reserve also funds the transient stake account rent
+        let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+        let transient_rent = stake_rent.saturating_sub(transient_stake_info.lamports());
+        let required_lamports = lamports
+            .checked_add(transient_rent)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        if required_lamports > Self::reserve_stake_lamports(reserve_stake_info)? {
+            return Err(StakePoolError::ReserveStakeTooLow.into());
         }
 
+        Self::fund_transient_stake_account(
+            stake_pool_info.key,
+            &stake_pool,
+            reserve_stake_info.clone(),
+            withdraw_info.clone(),
+            transient_stake_info.clone(),
+            transient_rent,
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        Self::create_transient_stake_account(
+            reserve_stake_info.clone(),
+            transient_stake_info.clone(),
+            system_program_info.clone(),
+            rent,
+            validator_vote_info.key,
+            stake_pool_info.key,
+            transient_bump_seed,
+        )?;
+
+        Self::stake_split(
+            stake_pool_info.key,
+            reserve_stake_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            lamports,
+            transient_stake_info.clone(),
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        Self::stake_delegate(
+            stake_pool_info.key,
+            transient_stake_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            validator_vote_info.clone(),
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_config_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        msg!("increase {} stake by {}", validator_vote_info.key, lamports);
+
+        // the reserve paid the rent, it stays counted with the transient stake
+        validator_stake_record.transient_balance = transient_stake_info.lamports();
         Ok(())
     }
 
-    /// Processes `UpdatePoolBalance` instruction.
-    pub fn process_update_pool_balance(
-        _program_id: &Pubkey,
+    /// Processes `DecreaseValidatorStake` instruction.
+    /// Splits lamports from the validator stake account into its transient stake account
+    /// and deactivates them, UpdateListBalance moves them to the reserve once inactive.
+    /// The reserve funds the transient stake account rent
+    pub fn process_decrease_validator_stake(
+        program_id: &Pubkey,
+        lamports: u64,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
+        if lamports == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
         let account_info_iter = &mut accounts.iter();
         // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
         // Account storing validator stake list
         let validator_stake_list_info = next_account_info(account_info_iter)?;
-        // Clock sysvar account
+        // Reserve stake account, funds the transient stake account rent
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Validator stake account to split from
+        let stake_account_info = next_account_info(account_info_iter)?;
+        // Validator transient stake account to create
+        let transient_stake_info = next_account_info(account_info_iter)?;
+        // Sysvars and programs
         let clock_info = next_account_info(account_info_iter)?;
         let clock = &Clock::from_account_info(clock_info)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let stake_program_info = next_account_info(account_info_iter)?;
 
-        // Get stake pool stake (and check if it is initialized)
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        // Check program ids
+        if *system_program_info.key != solana_program::system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        // Check validator stake account list storage
-        if *validator_stake_list_info.key != stake_pool.validator_stake_list {
-            return Err(StakePoolError::InvalidValidatorStakeList.into());
+        stake_pool.check_staker(staker_info)?;
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+
+        // Check stake pool last update epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
         }
 
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
+
         // Read validator stake list account and check if it is valid
-        let validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
         if !validator_stake_list.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        msg!("Validators {} check last_update_epoch",validator_stake_list.validators.len());
-        let mut total_balance: u64 = 0;
-        for validator_stake_record in validator_stake_list.validators {
-            msg!("validator_stake_record.last_update_epoch:{} clock.epoch:{}",validator_stake_record.last_update_epoch , clock.epoch);
-            if validator_stake_record.last_update_epoch < clock.epoch {
-                return Err(StakePoolError::StakeListOutOfDate.into());
-            }
-            total_balance += validator_stake_record.balance;
+        let validator_account =
+            Self::get_validator_checked(program_id, stake_pool_info, stake_account_info)?;
+        let mut validator_stake_record = validator_stake_list
+            .find_mut(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+        if validator_stake_record.leaving {
+            return Err(StakePoolError::ValidatorLeaving.into());
         }
 
-        stake_pool.stake_total = total_balance;
-        stake_pool.last_update_epoch = clock.epoch;
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())?;
+        let (transient_stake_address, transient_bump_seed) = Self::find_transient_stake_address(
+            program_id,
+            &validator_account,
+            stake_pool_info.key,
+        );
+        if *transient_stake_info.key != transient_stake_address {
+            return Err(StakePoolError::InvalidStakeAccountAddress.into());
+        }
+        // one rebalance per validator at a time
+        if *transient_stake_info.owner == stake::id() || validator_stake_record.transient_balance > 0 {
+            return Err(StakePoolError::TransientAccountInUse.into());
+        }
+
+        let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+        let transient_rent = stake_rent.saturating_sub(transient_stake_info.lamports());
+        if transient_rent > Self::reserve_stake_lamports(reserve_stake_info)? {
+            return Err(StakePoolError::ReserveStakeTooLow.into());
+        }
+
+        Self::fund_transient_stake_account(
+            stake_pool_info.key,
+            &stake_pool,
+            reserve_stake_info.clone(),
+            withdraw_info.clone(),
+            transient_stake_info.clone(),
+            transient_rent,
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        Self::create_transient_stake_account(
+            reserve_stake_info.clone(),
+            transient_stake_info.clone(),
+            system_program_info.clone(),
+            rent,
+            &validator_account,
+            stake_pool_info.key,
+            transient_bump_seed,
+        )?;
+
+        // the stake program rejects splits that would leave the validator stake account
+        // below its rent exempt reserve
+        Self::stake_split(
+            stake_pool_info.key,
+            stake_account_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            lamports,
+            transient_stake_info.clone(),
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        Self::stake_deactivate(
+            stake_pool_info.key,
+            transient_stake_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        msg!("decrease {} stake by {}", validator_account, lamports);
 
+        validator_stake_record.balance = stake_account_info.lamports();
+        validator_stake_record.transient_balance = transient_stake_info.lamports();
         Ok(())
     }
 
-    /// Check stake activation status
-    pub fn check_stake_activation(
-        _stake_info: &AccountInfo,
-        _clock: &Clock,
-        _stake_history: &StakeHistory,
+    /// Processes `SetValidatorWeights` instruction.
+    pub fn process_set_validator_weights(
+        program_id: &Pubkey,
+        weights: ValidatorWeights,
+        accounts: &[AccountInfo],
     ) -> ProgramResult {
-        // TODO: remove conditional compilation when time travel in tests is possible
-        //#[cfg(not(feature = "test-bpf"))]
-        // This check is commented to make tests run without special command line arguments
-        /*{
-            let stake_acc_state: stake::StakeState =
-                deserialize(&stake_info.data.borrow()).unwrap();
-            let delegation = stake_acc_state.delegation();
-            if let Some(delegation) = delegation {
-                let target_epoch = clock.epoch;
-                let history = Some(stake_history);
-                let fix_stake_deactivate = true;
-                let (effective, activating, deactivating) = delegation
-                    .stake_activating_and_deactivating(target_epoch, history, fix_stake_deactivate);
-                if activating != 0 || deactivating != 0 || effective == 0 {
-                    return Err(StakePoolError::UserStakeNotActive.into());
-                }
-            } else {
-                return Err(StakePoolError::WrongStakeState.into());
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // rest of the accounts are validator vote accounts
+        let validator_infos = account_info_iter.as_slice();
+
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        stake_pool.check_staker(staker_info)?;
+
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
+
+        if validator_infos.len() != weights.weights.len()
+            || weights.weights.len() > MAX_VALIDATOR_WEIGHTS
+        {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
+        if !validator_stake_list.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        for (validator_info, weight) in validator_infos.iter().zip(weights.weights.iter()) {
+            let mut validator_stake_record = validator_stake_list
+                .find_mut(validator_info.key)
+                .ok_or(StakePoolError::ValidatorNotFound)?;
+            if validator_stake_record.leaving {
+                return Err(StakePoolError::ValidatorLeaving.into());
             }
-        }*/
-        Ok(())
+            stake_pool.total_target_weight = stake_pool
+                .total_target_weight
+                .saturating_sub(validator_stake_record.target_weight)
+                .checked_add(*weight)
+                .ok_or(StakePoolError::CalculationFailure)?;
+            validator_stake_record.target_weight = *weight;
+            msg!("validator {} target weight {}", validator_info.key, weight);
+        }
+
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
     }
 
-    /// Unpacks a spl_token `Account`.
-    pub fn unpack_token_account(
-        account_info: &AccountInfo,
-        token_program_id: &Pubkey,
+    /// Processes `Rebalance` instruction.
+    /// Moves the validator stake towards stake_total * target_weight / total_weight,
+    /// through the transient stake account. The reserve funds the transient account rent
+    pub fn process_rebalance(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Reserve stake account
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Validator stake account
+        let stake_account_info = next_account_info(account_info_iter)?;
+        // Validator transient stake account to create
+        let transient_stake_info = next_account_info(account_info_iter)?;
+        // Validator vote account
+        let validator_vote_info = next_account_info(account_info_iter)?;
+        // Sysvars and programs
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let stake_config_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let stake_program_info = next_account_info(account_info_iter)?;
 
-    ) -> Result<spl_token::state::Account, StakePoolError> {
+        // Check program ids
+        if *system_program_info.key != solana_program::system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        if account_info.owner != token_program_id {
-            Err(StakePoolError::IncorrectTokenProgramId)
-        } 
-        else {
-            spl_token::state::Account::unpack(&account_info.data.borrow())
-                .map_err(|_| StakePoolError::ExpectedAccount)
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
         }
-    }
 
-    /// Unpacks a spl_token `Mint`.
-    pub fn unpack_mint(
-        account_info: &AccountInfo,
-        token_program_id: &Pubkey,
-    ) -> Result<spl_token::state::Mint, StakePoolError> {
-        if account_info.owner != token_program_id {
-            Err(StakePoolError::IncorrectTokenProgramId)
-        } else {
-            spl_token::state::Mint::unpack(&account_info.data.borrow())
-                .map_err(|_| StakePoolError::ExpectedMint)
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+
+        // the targets are computed from stake_total, it must be current
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
         }
-    }
-    
-    // #[allow(clippy::too_many_arguments)]
-    // fn check_accounts(
-    //     program_id: &Pubkey,
-    //     authority_info: &AccountInfo,
-    //     source_account_info: &AccountInfo,
-    //     token_b_info: &AccountInfo,
-    //     pool_mint_info: &AccountInfo,
-    //     token_program_info: &AccountInfo,
-    //     user_token_a_info: Option<&AccountInfo>,
-    //     user_token_b_info: Option<&AccountInfo>,
-    //     pool_fee_account_info: Option<&AccountInfo>,
 
-    // ) -> ProgramResult {
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
 
-    //     if source_account_info.owner != program_id {
-    //         return Err(ProgramError::IncorrectProgramId);
-    //     }
-    //     if *source_account_info.key != *token_swap.token_a_account() {
-    //         return Err(StakePoolError::IncorrectSwapAccount.into());
-    //     }
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
+        if !validator_stake_list.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        let validator_account =
+            Self::get_validator_checked(program_id, stake_pool_info, stake_account_info)?;
+        if validator_account != *validator_vote_info.key {
+            return Err(StakePoolError::InvalidStakeAccountAddress.into());
+        }
+        let mut validator_stake_record = validator_stake_list
+            .find_mut(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+        if validator_stake_record.leaving {
+            return Err(StakePoolError::ValidatorLeaving.into());
+        }
+        if validator_stake_record.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListOutOfDate.into());
+        }
+
+        let (transient_stake_address, transient_bump_seed) = Self::find_transient_stake_address(
+            program_id,
+            &validator_account,
+            stake_pool_info.key,
+        );
+        if *transient_stake_info.key != transient_stake_address {
+            return Err(StakePoolError::InvalidStakeAccountAddress.into());
+        }
+        // one move per validator at a time
+        if *transient_stake_info.owner == stake::id() || validator_stake_record.transient_balance > 0 {
+            return Err(StakePoolError::TransientAccountInUse.into());
+        }
+
+        let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+        let reserve_lamports = Self::reserve_stake_lamports(reserve_stake_info)?;
+        let transient_rent = stake_rent.saturating_sub(transient_stake_info.lamports());
+        if reserve_lamports < transient_rent {
+            return Err(StakePoolError::ReserveStakeTooLow.into());
+        }
+
+        // the move is also bounded by what the accounts can give: the reserve keeps the
+        // transient rent, the validator stake account keeps what CreateValidatorStakeAccount funded
+        let rebalance_move = match stake_pool
+            .calc_rebalance_move(
+                &validator_stake_record,
+                stake_pool.total_target_weight,
+                clock.epoch,
+            )
+            .ok_or(StakePoolError::CalculationFailure)?
+        {
+            RebalanceMove::Increase(lamports) => {
+                RebalanceMove::Increase(lamports.min(reserve_lamports - transient_rent))
+            }
+            RebalanceMove::Decrease(lamports) => RebalanceMove::Decrease(
+                lamports.min(
+                    stake_account_info
+                        .lamports()
+                        .saturating_sub(sol_to_lamports(1.0) + stake_rent),
+                ),
+            ),
+            RebalanceMove::None => RebalanceMove::None,
+        };
+        let lamports = match rebalance_move {
+            RebalanceMove::Increase(lamports) | RebalanceMove::Decrease(lamports) => lamports,
+            RebalanceMove::None => 0,
+        };
+        if lamports == 0 {
+            return Err(StakePoolError::RebalanceNotNeeded.into());
+        }
+
+        Self::fund_transient_stake_account(
+            stake_pool_info.key,
+            &stake_pool,
+            reserve_stake_info.clone(),
+            withdraw_info.clone(),
+            transient_stake_info.clone(),
+            transient_rent,
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        Self::create_transient_stake_account(
+            reserve_stake_info.clone(),
+            transient_stake_info.clone(),
+            system_program_info.clone(),
+            rent,
+            &validator_account,
+            stake_pool_info.key,
+            transient_bump_seed,
+        )?;
+
+        if let RebalanceMove::Increase(_) = rebalance_move {
+            Self::stake_split(
+                stake_pool_info.key,
+                reserve_stake_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                lamports,
+                transient_stake_info.clone(),
+                clock_info.clone(),
+                stake_program_info.clone(),
+            )?;
+            Self::stake_delegate(
+                stake_pool_info.key,
+                transient_stake_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                validator_vote_info.clone(),
+                clock_info.clone(),
+                stake_history_info.clone(),
+                stake_config_info.clone(),
+                stake_program_info.clone(),
+            )?;
+            msg!("rebalance: increase {} stake by {}", validator_account, lamports);
+        } else {
+            Self::stake_split(
+                stake_pool_info.key,
+                stake_account_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                lamports,
+                transient_stake_info.clone(),
+                clock_info.clone(),
+                stake_program_info.clone(),
+            )?;
+            Self::stake_deactivate(
+                stake_pool_info.key,
+                transient_stake_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                clock_info.clone(),
+                stake_program_info.clone(),
+            )?;
+            msg!("rebalance: decrease {} stake by {}", validator_account, lamports);
+        }
+
+        validator_stake_record.balance = stake_account_info.lamports();
+        validator_stake_record.transient_balance = transient_stake_info.lamports();
+
+        // count the move against the epoch limit
+        if stake_pool.rebalance_epoch != clock.epoch {
+            stake_pool.rebalance_epoch = clock.epoch;
+            stake_pool.rebalanced_lamports = 0;
+        }
+        stake_pool.rebalanced_lamports = stake_pool
+            .rebalanced_lamports
+            .checked_add(lamports)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
+    }
+
+    /// Processes `UpdateListBalance` instruction.
+    /// Also settles the transient stake accounts: activated stake is merged into the
+    /// validator stake account, deactivated stake goes back to the reserve.
+    /// Every validator stake account must be the pool's program address for a listed validator
+    pub fn process_update_list_balance(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Reserve stake account, receives the deactivated transient stake
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        // Stake history sysvar account
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let stake_history = &StakeHistory::from_account_info(stake_history_info)?;
+        // Staking program id
+        let stake_program_info = next_account_info(account_info_iter)?;
+
+        // rest of the accounts are (validator stake account, transient stake account) pairs
+        let validator_stake_accounts_args = account_info_iter.as_slice();
+
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+
+        // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
+        if !validator_stake_list.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        for pair in validator_stake_accounts_args.chunks(2) {
+            let (stake_account_info, transient_stake_info) = match pair {
+                [stake_account_info, transient_stake_info] => (stake_account_info, transient_stake_info),
+                _ => return Err(ProgramError::NotEnoughAccountKeys),
+            };
+
+            // only the pool's own validator stake accounts are accepted
+            let (_, stake) = Self::get_stake_state(stake_account_info)?;
+            let validator_account = stake.delegation.voter_pubkey;
+            if !Self::is_validator_stake_address(
+                &validator_account,
+                program_id,
+                stake_pool_info,
+                stake_account_info,
+            ) {
+                return Err(StakePoolError::InvalidStakeAccountAddress.into());
+            }
+            let mut validator_stake_record = validator_stake_list
+                .find_mut(&validator_account)
+                .ok_or(StakePoolError::ValidatorNotFound)?;
+            if validator_stake_record.last_update_epoch >= clock.epoch {
+                continue;
+            }
+
+            let (transient_stake_address, _) = Self::find_transient_stake_address(
+                program_id,
+                &validator_account,
+                stake_pool_info.key,
+            );
+            if *transient_stake_info.key != transient_stake_address {
+                return Err(StakePoolError::InvalidStakeAccountAddress.into());
+            }
+
+            // settle the transient stake once it is done warming up or cooling down
+            if *transient_stake_info.owner == stake::id() {
+                let transient_stake_state: stake::StakeState =
+                    deserialize(&transient_stake_info.data.borrow())
+                        .or(Err(ProgramError::InvalidAccountData))?;
+                if let Some(delegation) = transient_stake_state.delegation() {
+                    let (effective, activating, _) = delegation
+                        .stake_activating_and_deactivating(clock.epoch, Some(stake_history), true);
+                    if delegation.deactivation_epoch != std::u64::MAX {
+                        if effective == 0 {
+                            Self::stake_withdraw(
+                                stake_pool_info.key,
+                                transient_stake_info.clone(),
+                                withdraw_info.clone(),
+                                Self::AUTHORITY_WITHDRAW,
+                                stake_pool.withdraw_bump_seed,
+                                reserve_stake_info.clone(),
+                                transient_stake_info.lamports(),
+                                clock_info.clone(),
+                                stake_history_info.clone(),
+                                stake_program_info.clone(),
+                            )?;
+                        }
+                    } else if activating == 0 && effective > 0 {
+                        Self::stake_merge(
+                            stake_pool_info.key,
+                            transient_stake_info.clone(),
+                            withdraw_info.clone(),
+                            Self::AUTHORITY_WITHDRAW,
+                            stake_pool.withdraw_bump_seed,
+                            stake_account_info.clone(),
+                            clock_info.clone(),
+                            stake_history_info.clone(),
+                            stake_program_info.clone(),
+                        )?;
+                    }
+                }
+            }
+
+            // a leaving validator stake goes to the reserve once inactive, the validator
+            // is then removed from the list. Lamports sent to the transient address
+            // are not a transient stake, they don't keep the validator
+            if validator_stake_record.leaving
+                && Self::transient_stake_lamports(transient_stake_info)? == 0
+            {
+                let (_, stake) = Self::get_stake_state(stake_account_info)?;
+                let (effective, _, _) = stake
+                    .delegation
+                    .stake_activating_and_deactivating(clock.epoch, Some(stake_history), true);
+                if effective == 0 {
+                    Self::stake_withdraw(
+                        stake_pool_info.key,
+                        stake_account_info.clone(),
+                        withdraw_info.clone(),
+                        Self::AUTHORITY_WITHDRAW,
+                        stake_pool.withdraw_bump_seed,
+                        reserve_stake_info.clone(),
+                        stake_account_info.lamports(),
+                        clock_info.clone(),
+                        stake_history_info.clone(),
+                        stake_program_info.clone(),
+                    )?;
+                    drop(validator_stake_record);
+                    validator_stake_list.remove(&validator_account);
+                    msg!("removed leaving validator {}", validator_account);
+                    continue;
+                }
+            }
+
+            // the balance is the delegated stake plus the rent reserve, lamports above that
+            // (the rent of a merged transient account, plain transfers) go to the reserve
+            let (meta, stake) = Self::get_stake_state(stake_account_info)?;
+            let balance = stake
+                .delegation
+                .stake
+                .checked_add(meta.rent_exempt_reserve)
+                .ok_or(StakePoolError::CalculationFailure)?;
+            let excess = stake_account_info.lamports().saturating_sub(balance);
+            if excess > 0 {
+                Self::stake_withdraw(
+                    stake_pool_info.key,
+                    stake_account_info.clone(),
+                    withdraw_info.clone(),
+                    Self::AUTHORITY_WITHDRAW,
+                    stake_pool.withdraw_bump_seed,
+                    reserve_stake_info.clone(),
+                    excess,
+                    clock_info.clone(),
+                    stake_history_info.clone(),
+                    stake_program_info.clone(),
+                )?;
+            }
+
+            validator_stake_record.last_update_epoch = clock.epoch;
+            validator_stake_record.balance = balance;
+            validator_stake_record.transient_balance =
+                Self::transient_stake_lamports(transient_stake_info)?;
+            msg!(
+                "updated {} to {} {} transient {}",
+                validator_account,
+                clock.epoch,
+                validator_stake_record.balance,
+                validator_stake_record.transient_balance
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Processes `UpdatePoolBalance` instruction.
+    pub fn process_update_pool_balance(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        // Reserve stake account
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority, mints the reward fee
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Account to receive the reward fee tokens
+        let owner_fee_info = next_account_info(account_info_iter)?;
+        // Pool token mint account
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Pool token program id
+        let token_program_info = next_account_info(account_info_iter)?;
+        // rest of the accounts are the validator stake list pages added to the pool,
+        // then the rate history account when the pool has one
+        let trailing_infos = account_info_iter.as_slice();
+
+        // Get stake pool stake (and check if it is initialized)
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        let (page_infos, rate_history_info) = if stake_pool.has_rate_history() {
+            let (rate_history_info, page_infos) = trailing_infos
+                .split_last()
+                .ok_or(StakePoolError::InvalidRateHistory)?;
+            (page_infos, Some(rate_history_info))
+        } else {
+            (trailing_infos, None)
+        };
+
+        // Check validator stake account list storage, all the pages are summed
+        let validator_stake_list_infos =
+            std::iter::once(validator_stake_list_info).chain(page_infos.iter());
+        if page_infos.len() != stake_pool.validator_stake_list_pages_len as usize
+            || !stake_pool
+                .validator_stake_list_pages()
+                .zip(validator_stake_list_infos.clone())
+                .all(|(page, page_info)| page == page_info.key)
+        {
+            return Err(StakePoolError::InvalidValidatorStakeList.into());
+        }
+
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+
+        if stake_pool.owner_fee_account != *owner_fee_info.key {
+            return Err(StakePoolError::InvalidFeeAccount.into());
+        }
+        if stake_pool.pool_mint != *pool_mint_info.key {
+            return Err(StakePoolError::WrongPoolMint.into());
+        }
+        if stake_pool.token_program_id != *token_program_info.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // lamports deposited with DepositSol, not delegated yet
+        let mut total_balance: u64 = Self::reserve_stake_lamports(reserve_stake_info)?;
+        for validator_stake_list_info in validator_stake_list_infos {
+            // Read validator stake list account and check if it is valid
+            let validator_stake_list_data = validator_stake_list_info.data.borrow();
+            let validator_stake_list =
+                ValidatorStakeListView::new(&validator_stake_list_data[..])?;
+            if !validator_stake_list.is_initialized() {
+                return Err(StakePoolError::InvalidState.into());
+            }
+
+            for validator_stake_record in validator_stake_list.iter() {
+                if validator_stake_record.last_update_epoch < clock.epoch {
+                    return Err(StakePoolError::StakeListOutOfDate.into());
+                }
+                total_balance = validator_stake_record
+                    .total_balance()
+                    .and_then(|balance| total_balance.checked_add(balance))
+                    .ok_or(StakePoolError::CalculationFailure)?;
+            }
+        }
+
+        let previous_stake_total = stake_pool.stake_total;
+        stake_pool.stake_total = total_balance;
+
+        // the owner takes reward_fee% of the rewards. Fee tokens are minted before
+        // the new pool_total is stored, so the published rate already accounts for them
+        if total_balance > previous_stake_total {
+            let fee_amount = stake_pool
+                .calc_reward_fee_amount(total_balance - previous_stake_total)
+                .ok_or(StakePoolError::CalculationFailure)?;
+            if fee_amount > 0 {
+                Self::token_mint_to(
+                    stake_pool_info.key,
+                    token_program_info.clone(),
+                    pool_mint_info.clone(),
+                    owner_fee_info.clone(),
+                    withdraw_info.clone(),
+                    Self::AUTHORITY_WITHDRAW,
+                    stake_pool.withdraw_bump_seed,
+                    fee_amount,
+                )?;
+                stake_pool.pool_total = stake_pool
+                    .pool_total
+                    .checked_add(fee_amount)
+                    .ok_or(StakePoolError::CalculationFailure)?;
+            }
+        }
+
+        // promote the fee scheduled by SetFee once its epoch arrives
+        if stake_pool.has_pending_fee() && clock.epoch >= stake_pool.next_fee_epoch {
+            stake_pool.fee = stake_pool.next_fee;
+            stake_pool.next_fee = Fee::default();
+            stake_pool.next_fee_epoch = 0;
+        }
+
+        if let Some(rate_history_info) = rate_history_info {
+            let stake_pool_bytes = stake_pool_info.key.to_bytes();
+            let rate_history_address = Pubkey::create_program_address(
+                &[
+                    &stake_pool_bytes[..32],
+                    Self::RATE_HISTORY,
+                    &[stake_pool.rate_history_bump_seed],
+                ],
+                program_id,
+            )?;
+            if *rate_history_info.key != rate_history_address
+                || *rate_history_info.owner != *program_id
+            {
+                return Err(StakePoolError::InvalidRateHistory.into());
+            }
+            let mut rate_history = RateHistory::deserialize(&rate_history_info.data.borrow())?;
+            if !rate_history.is_initialized() {
+                return Err(StakePoolError::InvalidState.into());
+            }
+            rate_history.push(RateHistoryEntry {
+                epoch: clock.epoch,
+                stake_total: stake_pool.stake_total,
+                pool_total: stake_pool.pool_total,
+            });
+            rate_history.serialize(&mut rate_history_info.data.borrow_mut())?;
+        }
+
+        stake_pool.last_update_epoch = clock.epoch;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+
+        Ok(())
+    }
+
+    /// Check stake activation status, only fully active stake is accepted
+    pub fn check_stake_activation(
+        stake_info: &AccountInfo,
+        clock: &Clock,
+        stake_history: &StakeHistory,
+    ) -> ProgramResult {
+        let stake_acc_state: stake::StakeState = deserialize(&stake_info.data.borrow())
+            .or(Err(ProgramError::InvalidAccountData))?;
+        let delegation = stake_acc_state.delegation();
+        if let Some(delegation) = delegation {
+            let target_epoch = clock.epoch;
+            let history = Some(stake_history);
+            let fix_stake_deactivate = true;
+            let (effective, activating, deactivating) = delegation
+                .stake_activating_and_deactivating(target_epoch, history, fix_stake_deactivate);
+            if activating != 0 || deactivating != 0 || effective == 0 {
+                return Err(StakePoolError::UserStakeNotActive.into());
+            }
+        } else {
+            return Err(StakePoolError::WrongStakeState.into());
+        }
+        Ok(())
+    }
+
+    /// Lamports in the reserve stake account above its rent
+    pub fn reserve_stake_lamports(reserve_stake_info: &AccountInfo) -> Result<u64, ProgramError> {
+        if *reserve_stake_info.owner != stake::id() {
+            return Err(StakePoolError::WrongStakeState.into());
+        }
+        let rent_exempt_reserve = match deserialize::<stake::StakeState>(&reserve_stake_info.data.borrow()) {
+            Ok(stake::StakeState::Initialized(meta)) => meta.rent_exempt_reserve,
+            _ => return Err(StakePoolError::WrongStakeState.into()),
+        };
+        reserve_stake_info
+            .lamports()
+            .checked_sub(rent_exempt_reserve)
+            .ok_or_else(|| StakePoolError::CalculationFailure.into())
+    }
+
+    /// Lamports in a validator transient stake account, 0 while the address holds no
+    /// delegated stake: lamports sent to the address before the pool creates the account
+    /// are not pool stake
+    pub fn transient_stake_lamports(
+        transient_stake_info: &AccountInfo,
+    ) -> Result<u64, ProgramError> {
+        if *transient_stake_info.owner != stake::id() {
+            return Ok(0);
+        }
+        let transient_stake_state: stake::StakeState =
+            deserialize(&transient_stake_info.data.borrow())
+                .or(Err(ProgramError::InvalidAccountData))?;
+        match transient_stake_state.delegation() {
+            Some(_) => Ok(transient_stake_info.lamports()),
+            None => Ok(0),
+        }
+    }
+
+    /// Unpacks a spl_token `Account`.
+    pub fn unpack_token_account(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+
+    ) -> Result<spl_token::state::Account, StakePoolError> {
+
+        if account_info.owner != token_program_id {
+            Err(StakePoolError::IncorrectTokenProgramId)
+        } 
+        else {
+            spl_token::state::Account::unpack(&account_info.data.borrow())
+                .map_err(|_| StakePoolError::ExpectedAccount)
+        }
+    }
+
+    /// Unpacks a spl_token `Mint`.
+    pub fn unpack_mint(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> Result<spl_token::state::Mint, StakePoolError> {
+        if account_info.owner != token_program_id {
+            Err(StakePoolError::IncorrectTokenProgramId)
+        } else {
+            spl_token::state::Mint::unpack(&account_info.data.borrow())
+                .map_err(|_| StakePoolError::ExpectedMint)
+        }
+    }
+    
+    // #[allow(clippy::too_many_arguments)]
+    // fn check_accounts(
+    //     program_id: &Pubkey,
+    //     authority_info: &AccountInfo,
+    //     source_account_info: &AccountInfo,
+    //     token_b_info: &AccountInfo,
+    //     pool_mint_info: &AccountInfo,
+    //     token_program_info: &AccountInfo,
+    //     user_token_a_info: Option<&AccountInfo>,
+    //     user_token_b_info: Option<&AccountInfo>,
+    //     pool_fee_account_info: Option<&AccountInfo>,
+
+    // ) -> ProgramResult {
+
+    //     if source_account_info.owner != program_id {
+    //         return Err(ProgramError::IncorrectProgramId);
+    //     }
+    //     if *source_account_info.key != *token_swap.token_a_account() {
+    //         return Err(StakePoolError::IncorrectSwapAccount.into());
+    //     }
     //     if *token_b_info.key != *token_swap.token_b_account() {
     //         return Err(StakePoolError::IncorrectSwapAccount.into());
     //     }
@@ -1015,720 +2331,2342 @@ impl Processor {
     //     Ok(())
     // }
 
-    /// Issue a spl_token `Transfer` instruction.
-    pub fn token_transfer_from_signer<'a>(
-//        owner: &Pubkey,
-        token_program: AccountInfo<'a>,
-        source: AccountInfo<'a>,
-        destination: AccountInfo<'a>,
-        authority: AccountInfo<'a>,
-        amount: u64) 
-        
-        -> Result<(), ProgramError> 
+    /// Issue a spl_token `Transfer` instruction.
+    pub fn token_transfer_from_signer<'a>(
+//        owner: &Pubkey,
+        token_program: AccountInfo<'a>,
+        source: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        amount: u64) 
+        
+        -> Result<(), ProgramError> 
+
+    {
+
+        let ix = spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+        )?;
+        invoke(
+            &ix,
+            &[source, destination, authority, token_program]
+        )
+    }
+
+    /// Issue a spl_token `Burn` instruction signed by the token account owner.
+    pub fn token_burn_from_signer<'a>(
+        token_program: AccountInfo<'a>,
+        burn_account: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let ix = spl_token::instruction::burn(
+            token_program.key,
+            burn_account.key,
+            mint.key,
+            authority.key,
+            &[],
+            amount,
+        )?;
+        invoke(&ix, &[burn_account, mint, authority, token_program])
+    }
+
+    /// Issue a spl_token `Transfer` instruction.
+    pub fn token_transfer<'a>(
+        //        owner: &Pubkey,
+        token_program: AccountInfo<'a>,
+        source: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        pda_base: &Pubkey,
+        authority_type: &[u8],
+        nonce: u8,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let pda_bytes = pda_base.to_bytes();
+        let authority_signature_seeds = [&pda_bytes[..32], authority_type, &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+        )?;
+        invoke_signed(
+            &ix,
+            &[source, destination, authority, token_program],
+            signers,
+        )
+    }
+
+
+    /// Deserializes the liquidity pool state, checking it belongs to this program and is initialized
+    pub fn load_liquidity_pool(
+        program_id: &Pubkey,
+        liq_pool_info: &AccountInfo,
+    ) -> Result<LiquidityPool, ProgramError> {
+        if liq_pool_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let liq_pool = LiquidityPool::deserialize(&liq_pool_info.data.borrow())?;
+        if !liq_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+        Ok(liq_pool)
+    }
+
+    /// Processes [InitializeLiquidityPool](enum.Instruction.html).
+    pub fn process_initialize_liquidity_pool(
+        program_id: &Pubkey,
+        sell_fee: SellFeeCurve,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool owner
+        let owner_info = next_account_info(account_info_iter)?;
+        // Liq pool legs
+        let wsol_account_info = next_account_info(account_info_iter)?;
+        let stsol_account_info = next_account_info(account_info_iter)?;
+        // $METALP mint
+        let metalp_mint_info = next_account_info(account_info_iter)?;
+        // Liq pool authority
+        let liq_pool_authority_info = next_account_info(account_info_iter)?;
+        // Rent sysvar account
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        // Token program ID
+        let token_program_info = next_account_info(account_info_iter)?;
+        // Treasury wSOL account
+        let treasury_info = next_account_info(account_info_iter)?;
+
+        if liq_pool_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut liq_pool = LiquidityPool::deserialize(&liq_pool_info.data.borrow())?;
+        // Liq pool account should not be already initialized
+        if liq_pool.is_initialized() {
+            return Err(StakePoolError::AlreadyInUse.into());
+        }
+
+        // Check if liq pool account is rent-exempt
+        if !rent.is_exempt(liq_pool_info.lamports(), liq_pool_info.data_len()) {
+            return Err(StakePoolError::AccountNotRentExempt.into());
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Only the stake pool owner can create its liquidity pool
+        stake_pool.check_owner(owner_info)?;
+
+        if stake_pool.token_program_id != *token_program_info.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !sell_fee.is_valid() {
+            return Err(StakePoolError::InvalidSellFeeCurve.into());
+        }
+
+        let (liq_pool_authority, authority_bump_seed) =
+            Self::find_authority_bump_seed(program_id, liq_pool_info.key, Self::AUTHORITY);
+        if *liq_pool_authority_info.key != liq_pool_authority {
+            return Err(StakePoolError::InvalidProgramAddress.into());
+        }
+
+        // Both legs must be owned by the liq pool authority and hold the right mint
+        let wsol_account = Self::unpack_token_account(wsol_account_info, token_program_info.key)?;
+        if wsol_account.mint != spl_token::native_mint::id() {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+        let stsol_account = Self::unpack_token_account(stsol_account_info, token_program_info.key)?;
+        if stsol_account.mint != stake_pool.pool_mint {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+        if wsol_account.owner != liq_pool_authority || stsol_account.owner != liq_pool_authority {
+            return Err(StakePoolError::WrongOwner.into());
+        }
+
+        let metalp_mint = Self::unpack_mint(metalp_mint_info, token_program_info.key)?;
+        if !metalp_mint.mint_authority.contains(&liq_pool_authority) {
+            return Err(StakePoolError::WrongMintingAuthority.into());
+        }
+
+        Self::check_treasury_account(treasury_info, token_program_info.key)?;
+
+        liq_pool.version = LiquidityPool::LIQUIDITY_POOL_VERSION;
+        liq_pool.authority_bump_seed = authority_bump_seed;
+        liq_pool.stake_pool = *stake_pool_info.key;
+        liq_pool.wsol_account = *wsol_account_info.key;
+        liq_pool.stsol_account = *stsol_account_info.key;
+        liq_pool.metalp_mint = *metalp_mint_info.key;
+        liq_pool.sell_fee = sell_fee;
+        liq_pool.treasury_wsol_account = *treasury_info.key;
+        // no treasury share until the owner sets one
+        liq_pool.treasury_share_bps = 0;
+        liq_pool.buy_fee_bps = LiquidityPool::DEFAULT_BUY_FEE_BPS;
+
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())
+    }
+
+    /// Checks the treasury is a wSOL account
+    fn check_treasury_account(treasury_info: &AccountInfo, token_program_id: &Pubkey) -> ProgramResult {
+        let treasury = Self::unpack_token_account(treasury_info, token_program_id)?;
+        if treasury.mint != spl_token::native_mint::id() {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+        Ok(())
+    }
+
+    /// Processes [SetSellFee](enum.Instruction.html).
+    pub fn process_set_sell_fee(
+        program_id: &Pubkey,
+        sell_fee: SellFeeCurve,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool fee manager
+        let fee_manager_info = next_account_info(account_info_iter)?;
+
+        let mut liq_pool = Self::load_liquidity_pool(program_id, liq_pool_info)?;
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Only the stake pool fee manager can change the fee
+        stake_pool.check_fee_manager(fee_manager_info)?;
+
+        if !sell_fee.is_valid() {
+            return Err(StakePoolError::InvalidSellFeeCurve.into());
+        }
+
+        liq_pool.sell_fee = sell_fee;
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes [SetBuyFee](enum.Instruction.html).
+    pub fn process_set_buy_fee(
+        program_id: &Pubkey,
+        buy_fee_bps: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool fee manager
+        let fee_manager_info = next_account_info(account_info_iter)?;
+
+        let mut liq_pool = Self::load_liquidity_pool(program_id, liq_pool_info)?;
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Only the stake pool fee manager can change the fee
+        stake_pool.check_fee_manager(fee_manager_info)?;
+
+        // a 100% fee can not be priced
+        if buy_fee_bps >= SellFeeCurve::BPS_DENOMINATOR {
+            return Err(StakePoolError::FeeTooHigh.into());
+        }
+
+        liq_pool.buy_fee_bps = buy_fee_bps;
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes [SetTreasury](enum.Instruction.html).
+    pub fn process_set_treasury(
+        program_id: &Pubkey,
+        treasury_share_bps: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool owner
+        let owner_info = next_account_info(account_info_iter)?;
+        // Treasury wSOL account
+        let treasury_info = next_account_info(account_info_iter)?;
+
+        let mut liq_pool = Self::load_liquidity_pool(program_id, liq_pool_info)?;
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Only the stake pool owner can change the treasury
+        stake_pool.check_owner(owner_info)?;
+
+        if treasury_share_bps > SellFeeCurve::BPS_DENOMINATOR {
+            return Err(StakePoolError::FeeTooHigh.into());
+        }
+        Self::check_treasury_account(treasury_info, &stake_pool.token_program_id)?;
+
+        liq_pool.treasury_wsol_account = *treasury_info.key;
+        liq_pool.treasury_share_bps = treasury_share_bps;
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes [AddLiquidity](enum.Instruction.html).
+    pub fn process_add_liquidity(
+        program_id: &Pubkey,
+        wsol_amount: u64,
+        min_metalp_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("--- add_liquidity {}", wsol_amount);
+
+        if wsol_amount == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Liq pool account
+        let liq_pool_state_account = next_account_info(account_info_iter)?;
+        // SPL token program
+        let token_program = next_account_info(account_info_iter)?;
+        // $METALP mint and its authority (the liq pool authority)
+        let metalp_token_mint_account = next_account_info(account_info_iter)?;
+        let metalp_mint_authority = next_account_info(account_info_iter)?;
+        // User wSOL account and its owner (signer)
+        let user_wsol_source_account = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        // Liq pool legs
+        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
+        // User account to receive $METALP
+        let user_metalp_account_destination = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let liq_pool = Self::load_liquidity_pool(program_id, liq_pool_state_account)?;
+
+        // Check accounts against the liq pool state
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+        if *metalp_token_mint_account.key != liq_pool.metalp_mint {
+            return Err(StakePoolError::WrongPoolMint.into());
+        }
+        if *liq_pool_wsol_account.key != liq_pool.wsol_account
+            || *liq_pool_stsol_account.key != liq_pool.stsol_account
+        {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(metalp_mint_authority.key, program_id, liq_pool_state_account.key)?;
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+        // the stSOL leg is valued at the stake pool rate, updated this epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        let metalp_supply = Self::unpack_mint(metalp_token_mint_account, token_program.key)?.supply;
+
+        // value the pool: wSOL at face value + stSOL at the stake pool rate
+        let liq_pool_wsol_account_info =
+            Self::unpack_token_account(liq_pool_wsol_account, token_program.key)?;
+        let liq_pool_stsol_total =
+            Self::unpack_token_account(liq_pool_stsol_account, token_program.key)?.amount;
+        // pending unstake tickets will come back as wSOL
+        let total_value = LiquidityPool::calc_total_value(
+            liq_pool_wsol_account_info.amount,
+            liq_pool_stsol_total,
+            &stake_pool,
+        )
+        .and_then(|value| value.checked_add(liq_pool.calc_pending_unstake_lamports()?))
+        .ok_or(StakePoolError::CalculationFailure)?;
+
+        //make sure user provided acc is wSOL
+        let user_account_info =
+            Self::unpack_token_account(user_wsol_source_account, token_program.key)?;
+        if user_account_info.mint != liq_pool_wsol_account_info.mint {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+
+        // Calculate metalp amount, before the user's wSOL changes the pool value
+        let metalp_amount =
+            LiquidityPool::calc_metalp_amount(wsol_amount, total_value, metalp_supply)
+                .ok_or(StakePoolError::CalculationFailure)?;
+        msg!(
+            "pool value {} $METALP supply {} minting {}",
+            total_value,
+            metalp_supply,
+            metalp_amount
+        );
+        if metalp_amount < min_metalp_out {
+            return Err(StakePoolError::SlippageExceeded.into());
+        }
+
+        //transfer user wsol to our wsol account
+        Self::token_transfer_from_signer(
+            token_program.clone(),
+            user_wsol_source_account.clone(),
+            liq_pool_wsol_account.clone(),
+            user_transfer_authority_info.clone(),
+            wsol_amount,
+        )?;
+
+        // mint metalp tokens for the user
+        Self::liq_pool_token_mint_to(
+            liq_pool_state_account.key,
+            token_program.clone(),
+            metalp_token_mint_account.clone(),
+            user_metalp_account_destination.clone(),
+            metalp_mint_authority.clone(),
+            liq_pool.authority_bump_seed,
+            metalp_amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Processes [SellstSOL](enum.Instruction.html).
+    pub fn process_sell_stsol(
+        program_id: &Pubkey,
+        stsol_amount: u64,
+        min_wsol_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if stsol_amount == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        msg!("--- sell_stsol {}", stsol_amount);
+
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Liq pool account
+        let liq_pool_account = next_account_info(account_info_iter)?;
+        // SPL token program
+        let token_program = next_account_info(account_info_iter)?;
+        // Liq pool legs and their authority
+        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_authority = next_account_info(account_info_iter)?;
+        // User accounts: wSOL to receive, stSOL to sell, and the stSOL owner (signer)
+        let user_wsol_account = next_account_info(account_info_iter)?;
+        let user_stsol_account = next_account_info(account_info_iter)?;
+        let user_authority = next_account_info(account_info_iter)?;
+        // Treasury wSOL account
+        let treasury_wsol_account = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let liq_pool = Self::load_liquidity_pool(program_id, liq_pool_account)?;
+
+        // Check accounts against the liq pool state
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+        if *liq_pool_wsol_account.key != liq_pool.wsol_account
+            || *liq_pool_stsol_account.key != liq_pool.stsol_account
+        {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(liq_pool_authority.key, program_id, liq_pool_account.key)?;
+        if *treasury_wsol_account.key != liq_pool.treasury_wsol_account {
+            return Err(StakePoolError::WrongTreasuryAccount.into());
+        }
+
+        // Get stake pool stake (and check if it is initialized)
+        let stake_pool_data =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool_data.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+        // the stSOL is valued at the stake pool rate, updated this epoch
+        if stake_pool_data.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        //get how much wSOL there's in the pool
+        let our_wsol_token_info =
+            Self::unpack_token_account(liq_pool_wsol_account, token_program.key)?;
+        let our_wsol_total = our_wsol_token_info.amount;
+        msg!("our_wsol_total {}", our_wsol_total);
+
+        //make sure user provided accs are stSOL & wSOL
+        let user_st_sol_token_info =
+            Self::unpack_token_account(user_stsol_account, token_program.key)?;
+        if user_st_sol_token_info.mint != stake_pool_data.pool_mint {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+        let user_wsol_token_info =
+            Self::unpack_token_account(user_wsol_account, token_program.key)?;
+        if user_wsol_token_info.mint != our_wsol_token_info.mint {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+
+        // compute SOL value of stSOL shares being sold
+        let total_stsol_minted = stake_pool_data.pool_total;
+        let sol_value = proportional(
+            stsol_amount,
+            stake_pool_data.stake_total as u128,
+            total_stsol_minted as u128,
+        )
+        .ok_or(StakePoolError::CalculationFailure)?;
+        // Calculate fee, higher when the trade leaves less wSOL in the pool
+        let fee_amount = liq_pool
+            .sell_fee
+            .calc_sell_fee(sol_value, our_wsol_total)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        let wsol_amount_to_user = sol_value - fee_amount;
+        msg!(
+            "stSol {} sol_value {} fee {}",
+            stsol_amount,
+            sol_value,
+            fee_amount
+        );
+        if wsol_amount_to_user < min_wsol_out {
+            return Err(StakePoolError::SlippageExceeded.into());
+        }
+        // the treasury share of the fee goes to the treasury,
+        // the rest stays in the liq-pool, increasing share-value for all liquidity providers.
+        let treasury_cut = liq_pool
+            .calc_treasury_cut(fee_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        // is there enough wSOL to pay the user and the treasury?
+        let wsol_out = wsol_amount_to_user
+            .checked_add(treasury_cut)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        if wsol_out > our_wsol_total {
+            return Err(StakePoolError::NotEnoughTokensInThePool.into());
+        }
+
+        //transfer user's stSOL to our LP stSOL account
+        Self::token_transfer_from_signer(
+            token_program.clone(),
+            user_stsol_account.clone(),
+            liq_pool_stsol_account.clone(),
+            user_authority.clone(),
+            stsol_amount,
+        )?;
+
+        // transfer equivalent wSOL minus fee to user
+        Self::token_transfer(
+            token_program.clone(),
+            liq_pool_wsol_account.clone(),
+            user_wsol_account.clone(),
+            liq_pool_authority.clone(),
+            liq_pool_account.key,
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            wsol_amount_to_user,
+        )?;
+
+        // the treasury cut of the fee goes to the treasury
+        if treasury_cut > 0 {
+            Self::token_transfer(
+                token_program.clone(),
+                liq_pool_wsol_account.clone(),
+                treasury_wsol_account.clone(),
+                liq_pool_authority.clone(),
+                liq_pool_account.key,
+                Self::AUTHORITY,
+                liq_pool.authority_bump_seed,
+                treasury_cut,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Processes [BuyStSol](enum.Instruction.html).
+    pub fn process_buy_stsol(
+        program_id: &Pubkey,
+        wsol_amount: u64,
+        min_stsol_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if wsol_amount == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        msg!("--- buy_stsol {}", wsol_amount);
+
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Liq pool account
+        let liq_pool_account = next_account_info(account_info_iter)?;
+        // SPL token program
+        let token_program = next_account_info(account_info_iter)?;
+        // Liq pool legs and their authority
+        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_authority = next_account_info(account_info_iter)?;
+        // User accounts: wSOL to pay, stSOL to receive, and the wSOL owner (signer)
+        let user_wsol_account = next_account_info(account_info_iter)?;
+        let user_stsol_account = next_account_info(account_info_iter)?;
+        let user_authority = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let liq_pool = Self::load_liquidity_pool(program_id, liq_pool_account)?;
+
+        // Check accounts against the liq pool state
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+        if *liq_pool_wsol_account.key != liq_pool.wsol_account
+            || *liq_pool_stsol_account.key != liq_pool.stsol_account
+        {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(liq_pool_authority.key, program_id, liq_pool_account.key)?;
+
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+        // the stSOL is priced at the stake pool rate, updated this epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        //make sure user provided accs are wSOL & stSOL
+        let user_wsol_token_info =
+            Self::unpack_token_account(user_wsol_account, token_program.key)?;
+        if user_wsol_token_info.mint != spl_token::native_mint::id() {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+        let user_st_sol_token_info =
+            Self::unpack_token_account(user_stsol_account, token_program.key)?;
+        if user_st_sol_token_info.mint != stake_pool.pool_mint {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+
+        // price the buy, capped at the stSOL the pool holds
+        let our_stsol_total =
+            Self::unpack_token_account(liq_pool_stsol_account, token_program.key)?.amount;
+        let (wsol_in, stsol_out) = liq_pool
+            .calc_buy(wsol_amount, our_stsol_total, &stake_pool)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        msg!(
+            "wSOL {} in {} stSOL out {} (pool stSOL {})",
+            wsol_amount,
+            wsol_in,
+            stsol_out,
+            our_stsol_total
+        );
+        if stsol_out == 0 {
+            return Err(StakePoolError::NotEnoughTokensInThePool.into());
+        }
+        if stsol_out < min_stsol_out {
+            return Err(StakePoolError::SlippageExceeded.into());
+        }
+
+        //transfer user's wSOL to our LP wSOL account, the fee stays in the pool
+        Self::token_transfer_from_signer(
+            token_program.clone(),
+            user_wsol_account.clone(),
+            liq_pool_wsol_account.clone(),
+            user_authority.clone(),
+            wsol_in,
+        )?;
+
+        // transfer stSOL to user
+        Self::token_transfer(
+            token_program.clone(),
+            liq_pool_stsol_account.clone(),
+            user_stsol_account.clone(),
+            liq_pool_authority.clone(),
+            liq_pool_account.key,
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            stsol_out,
+        )?;
+
+        Ok(())
+    }
+
+    /// Processes [RemoveLiquidity](enum.Instruction.html).
+    pub fn process_remove_liquidity(
+        program_id: &Pubkey,
+        metalp_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if metalp_amount == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_account = next_account_info(account_info_iter)?;
+        // SPL token program
+        let token_program = next_account_info(account_info_iter)?;
+        // $METALP mint
+        let metalp_mint_account = next_account_info(account_info_iter)?;
+        // Liq pool legs and their authority
+        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
+        let liq_pool_authority = next_account_info(account_info_iter)?;
+        // User $METALP account to burn from, and its owner (signer)
+        let user_metalp_account = next_account_info(account_info_iter)?;
+        let user_authority = next_account_info(account_info_iter)?;
+        // User accounts to receive both legs
+        let user_wsol_account = next_account_info(account_info_iter)?;
+        let user_stsol_account = next_account_info(account_info_iter)?;
+
+        if !user_authority.is_signer {
+            return Err(StakePoolError::SignatureMissing.into());
+        }
+
+        let liq_pool = Self::load_liquidity_pool(program_id, liq_pool_account)?;
+
+        // Check accounts against the liq pool state
+        if *metalp_mint_account.key != liq_pool.metalp_mint {
+            return Err(StakePoolError::WrongPoolMint.into());
+        }
+        if *liq_pool_wsol_account.key != liq_pool.wsol_account
+            || *liq_pool_stsol_account.key != liq_pool.stsol_account
+        {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(liq_pool_authority.key, program_id, liq_pool_account.key)?;
+
+        let metalp_supply = Self::unpack_mint(metalp_mint_account, token_program.key)?.supply;
+        if metalp_supply == 0 {
+            return Err(StakePoolError::NotEnoughTokensInThePool.into());
+        }
+        let liq_pool_wsol_total = Self::unpack_token_account(liq_pool_wsol_account, token_program.key)?.amount;
+        let liq_pool_stsol_total = Self::unpack_token_account(liq_pool_stsol_account, token_program.key)?.amount;
+
+        // pending unstake tickets count in the wSOL leg
+        let pending_unstake = liq_pool
+            .calc_pending_unstake_lamports()
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // compute the share of each leg the burned $METALP represents, using the supply before burning
+        let (wsol_amount, stsol_amount) = LiquidityPool::calc_remove_amounts(
+            metalp_amount,
+            liq_pool_wsol_total
+                .checked_add(pending_unstake)
+                .ok_or(StakePoolError::CalculationFailure)?,
+            liq_pool_stsol_total,
+            metalp_supply,
+        )
+        .ok_or(StakePoolError::NotEnoughTokensInThePool)?;
+        // the wSOL still unstaking can not be paid until claimed
+        if wsol_amount > liq_pool_wsol_total {
+            return Err(StakePoolError::NotEnoughTokensInThePool.into());
+        }
+        msg!("burn $METALP {} for wSOL {} stSOL {}", metalp_amount, wsol_amount, stsol_amount);
+
+        // burn user's $METALP (fails if the user does not have enough)
+        Self::token_burn_from_signer(
+            token_program.clone(),
+            user_metalp_account.clone(),
+            metalp_mint_account.clone(),
+            user_authority.clone(),
+            metalp_amount,
+        )?;
+
+        if wsol_amount > 0 {
+            Self::token_transfer(
+                token_program.clone(),
+                liq_pool_wsol_account.clone(),
+                user_wsol_account.clone(),
+                liq_pool_authority.clone(),
+                liq_pool_account.key,
+                Self::AUTHORITY,
+                liq_pool.authority_bump_seed,
+                wsol_amount,
+            )?;
+        }
+        if stsol_amount > 0 {
+            Self::token_transfer(
+                token_program.clone(),
+                liq_pool_stsol_account.clone(),
+                user_stsol_account.clone(),
+                liq_pool_authority.clone(),
+                liq_pool_account.key,
+                Self::AUTHORITY,
+                liq_pool.authority_bump_seed,
+                stsol_amount,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Processes [LiquidityPoolUnstake](enum.Instruction.html).
+    pub fn process_liquidity_pool_unstake(
+        program_id: &Pubkey,
+        stsol_amount: u64,
+        ticket: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if stsol_amount == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool, its extension, validator stake list and withdraw authority
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Validator stake account to split
+        let stake_split_from = next_account_info(account_info_iter)?;
+        // Ticket stake account (PDA) to receive the split
+        let ticket_stake_info = next_account_info(account_info_iter)?;
+        // Liq pool authority and stSOL leg
+        let liq_pool_authority = next_account_info(account_info_iter)?;
+        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
+        // Pool token mint account
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Payer of the ticket stake account rent
+        let payer_info = next_account_info(account_info_iter)?;
+        // Sysvars and programs
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let stake_program_info = next_account_info(account_info_iter)?;
+        // Account to receive the withdrawal fee tokens
+        let owner_fee_info = next_account_info(account_info_iter)?;
+
+        if !payer_info.is_signer {
+            return Err(StakePoolError::SignatureMissing.into());
+        }
+
+        let mut liq_pool = Self::load_liquidity_pool(program_id, liq_pool_info)?;
+
+        // Check accounts against the liq pool state
+        if *stake_pool_info.key != liq_pool.stake_pool {
+            return Err(StakePoolError::WrongStakePool.into());
+        }
+        if *liq_pool_stsol_account.key != liq_pool.stsol_account {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(liq_pool_authority.key, program_id, liq_pool_info.key)?;
+
+        // each ticket takes at least its share of the LP stSOL, so dust unstakes can not hold all the tickets
+        let liq_pool_stsol_total =
+            Self::unpack_token_account(liq_pool_stsol_account, token_program_info.key)?.amount;
+        if stsol_amount < liq_pool_stsol_total / LiquidityPool::MAX_UNSTAKE_TICKETS as u64 {
+            return Err(StakePoolError::UnstakeAmountTooLow.into());
+        }
+
+        // the ticket must be free
+        let ticket_index = usize::try_from(ticket)
+            .ok()
+            .filter(|index| *index < LiquidityPool::MAX_UNSTAKE_TICKETS)
+            .ok_or(StakePoolError::InvalidUnstakeTicket)?;
+        if liq_pool.unstake_tickets[ticket_index].is_pending() {
+            return Err(StakePoolError::UnstakeTicketInUse.into());
+        }
+        let (ticket_stake_address, stake_bump_seed) =
+            Self::find_unstake_ticket_address(program_id, liq_pool_info.key, ticket_index as u8);
+        if *ticket_stake_info.key != ticket_stake_address {
+            return Err(StakePoolError::InvalidUnstakeTicket.into());
+        }
+
+        // create the ticket stake account, the split needs it allocated and owned by the stake program
+        let liq_pool_bytes = liq_pool_info.key.to_bytes();
+        Self::create_pda_account(
+            payer_info.clone(),
+            ticket_stake_info.clone(),
+            system_program_info.clone(),
+            rent,
+            std::mem::size_of::<stake::StakeState>(),
+            &stake::id(),
+            &[&liq_pool_bytes[..32], Self::UNSTAKE, &[ticket_index as u8], &[stake_bump_seed]],
+        )?;
+        let rent_lamports = ticket_stake_info.lamports();
+
+        // same path as Withdraw, the liq pool authority gets the split stake
+        // and the liq pool pays the withdrawal fee
+        let (stake_pool, stake_amount, fee_amount) = Self::split_stake_for_pool_tokens(
+            program_id,
+            stake_pool_info,
+            stake_pool_extension_info,
+            validator_stake_list_info,
+            withdraw_info,
+            stake_split_from,
+            ticket_stake_info,
+            liq_pool_authority.key,
+            clock_info,
+            token_program_info,
+            stake_program_info,
+            stsol_amount,
+            true,
+        )?;
+
+        if stake_pool.owner_fee_account != *owner_fee_info.key {
+            return Err(StakePoolError::InvalidFeeAccount.into());
+        }
+
+        let mut burn_amount = stsol_amount;
+        if fee_amount > 0 && stake_pool.pays_withdrawal_fee_to_owner() {
+            Self::token_transfer(
+                token_program_info.clone(),
+                liq_pool_stsol_account.clone(),
+                owner_fee_info.clone(),
+                liq_pool_authority.clone(),
+                liq_pool_info.key,
+                Self::AUTHORITY,
+                liq_pool.authority_bump_seed,
+                fee_amount,
+            )?;
+            burn_amount -= fee_amount;
+        }
+
+        // burn the LP stSOL (fails if the LP does not have enough)
+        Self::token_burn(
+            liq_pool_info.key,
+            token_program_info.clone(),
+            liq_pool_stsol_account.clone(),
+            pool_mint_info.clone(),
+            liq_pool_authority.clone(),
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            burn_amount,
+        )?;
+
+        Self::stake_deactivate(
+            liq_pool_info.key,
+            ticket_stake_info.clone(),
+            liq_pool_authority.clone(),
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        msg!("unstake stSOL {} for {} lamports, ticket {}", stsol_amount, stake_amount, ticket_index);
+
+        liq_pool.unstake_tickets[ticket_index] = UnstakeTicket {
+            lamports: stake_amount,
+            rent_lamports,
+            rent_payer: *payer_info.key,
+            epoch: clock.epoch,
+            stake_bump_seed,
+        };
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())
+    }
+
+    /// Processes [LiquidityPoolClaim](enum.Instruction.html).
+    pub fn process_liquidity_pool_claim(
+        program_id: &Pubkey,
+        ticket: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Liq pool account
+        let liq_pool_info = next_account_info(account_info_iter)?;
+        // Ticket stake account
+        let ticket_stake_info = next_account_info(account_info_iter)?;
+        // Liq pool authority and wSOL leg
+        let liq_pool_authority = next_account_info(account_info_iter)?;
+        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
+        // Temporary wSOL account, wraps the withdrawn lamports
+        let temp_wsol_info = next_account_info(account_info_iter)?;
+        let wsol_mint_info = next_account_info(account_info_iter)?;
+        // Payer of the temporary account rent
+        let payer_info = next_account_info(account_info_iter)?;
+        // Receives the ticket stake account rent
+        let rent_payer_info = next_account_info(account_info_iter)?;
+        // Sysvars and programs
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let stake_program_info = next_account_info(account_info_iter)?;
+
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *wsol_mint_info.key != spl_token::native_mint::id() {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
+
+        let mut liq_pool = Self::load_liquidity_pool(program_id, liq_pool_info)?;
+
+        if *liq_pool_wsol_account.key != liq_pool.wsol_account {
+            return Err(StakePoolError::WrongLiquidityPoolAccount.into());
+        }
+        liq_pool.check_authority(liq_pool_authority.key, program_id, liq_pool_info.key)?;
+
+        // the ticket must be pending, and the accounts must be its own
+        let ticket_index = usize::try_from(ticket)
+            .ok()
+            .filter(|index| *index < LiquidityPool::MAX_UNSTAKE_TICKETS)
+            .ok_or(StakePoolError::InvalidUnstakeTicket)?;
+        let unstake_ticket = liq_pool.unstake_tickets[ticket_index];
+        if !unstake_ticket.is_pending() || *rent_payer_info.key != unstake_ticket.rent_payer {
+            return Err(StakePoolError::InvalidUnstakeTicket.into());
+        }
+        let ticket_stake_address = Pubkey::create_program_address(
+            &[
+                &liq_pool_info.key.to_bytes()[..32],
+                Self::UNSTAKE,
+                &[ticket_index as u8],
+                &[unstake_ticket.stake_bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| StakePoolError::InvalidProgramAddress)?;
+        if *ticket_stake_info.key != ticket_stake_address {
+            return Err(StakePoolError::InvalidUnstakeTicket.into());
+        }
+        if clock.epoch <= unstake_ticket.epoch {
+            return Err(StakePoolError::UnstakeTicketNotReady.into());
+        }
+
+        // everything above the rent goes to the LP, rewards earned while cooling down included
+        let wsol_amount = ticket_stake_info
+            .lamports()
+            .checked_sub(unstake_ticket.rent_lamports)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // there is no way to add lamports to an existing wSOL account,
+        // so wrap them in a new one owned by the liq pool authority and transfer
+        invoke(
+            &system_instruction::create_account(
+                payer_info.key,
+                temp_wsol_info.key,
+                rent.minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                token_program_info.key,
+            ),
+            &[
+                payer_info.clone(),
+                temp_wsol_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        Self::stake_withdraw(
+            liq_pool_info.key,
+            ticket_stake_info.clone(),
+            liq_pool_authority.clone(),
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            temp_wsol_info.clone(),
+            wsol_amount,
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        invoke(
+            &spl_token::instruction::initialize_account2(
+                token_program_info.key,
+                temp_wsol_info.key,
+                wsol_mint_info.key,
+                liq_pool_authority.key,
+            )?,
+            &[
+                temp_wsol_info.clone(),
+                wsol_mint_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::token_transfer(
+            token_program_info.clone(),
+            temp_wsol_info.clone(),
+            liq_pool_wsol_account.clone(),
+            liq_pool_authority.clone(),
+            liq_pool_info.key,
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            wsol_amount,
+        )?;
+        // close the temporary account, its rent goes back to the payer
+        let liq_pool_bytes = liq_pool_info.key.to_bytes();
+        invoke_signed(
+            &spl_token::instruction::close_account(
+                token_program_info.key,
+                temp_wsol_info.key,
+                payer_info.key,
+                liq_pool_authority.key,
+                &[],
+            )?,
+            &[
+                temp_wsol_info.clone(),
+                payer_info.clone(),
+                liq_pool_authority.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[&liq_pool_bytes[..32], Self::AUTHORITY, &[liq_pool.authority_bump_seed]]],
+        )?;
+
+        // drain the ticket stake account, the rent goes back to whoever paid it
+        Self::stake_withdraw(
+            liq_pool_info.key,
+            ticket_stake_info.clone(),
+            liq_pool_authority.clone(),
+            Self::AUTHORITY,
+            liq_pool.authority_bump_seed,
+            rent_payer_info.clone(),
+            unstake_ticket.rent_lamports,
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+        msg!("claim ticket {} for {} wSOL", ticket_index, wsol_amount);
+
+        liq_pool.unstake_tickets[ticket_index] = UnstakeTicket::default();
+        liq_pool.serialize(&mut liq_pool_info.data.borrow_mut())
+    }
+
+    /// Processes [Deposit](enum.Instruction.html).
+    pub fn process_deposit(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Stake pool deposit authority
+        let deposit_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Stake account to join the pool (withdraw should be set to stake pool deposit)
+        let stake_info = next_account_info(account_info_iter)?;
+        // Validator stake account to merge with
+        let validator_stake_account_info = next_account_info(account_info_iter)?;
+        // User account to receive pool tokens
+        let dest_user_info = next_account_info(account_info_iter)?;
+        // Account to receive pool fee tokens
+        let owner_fee_info = next_account_info(account_info_iter)?;
+        // Pool token mint account
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        // Stake history sysvar account
+        let stake_history_info = next_account_info(account_info_iter)?;
+        let stake_history = &StakeHistory::from_account_info(stake_history_info)?;
+        // Pool token program id
+        let token_program_info = next_account_info(account_info_iter)?;
+        // Stake program id
+        let stake_program_info = next_account_info(account_info_iter)?;
+
+        // Check program ids
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Check the depositor of a pool with a deposit authority
+        Self::check_depositor(program_id, stake_pool_info.key, &stake_pool, account_info_iter)?;
+
+        // Check if stake is active
+        Self::check_stake_activation(stake_info, clock, stake_history)?;
+
+        // Check authority accounts
+        msg!("Check authority accounts");
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_authority_deposit(deposit_info.key, program_id, stake_pool_info.key)?;
+
+        if stake_pool.owner_fee_account != *owner_fee_info.key {
+            return Err(StakePoolError::InvalidFeeAccount.into());
+        }
+
+        if stake_pool.token_program_id != *token_program_info.key {
+            msg!("stake_pool.token_program_id {} != accs[11] (token_program_info) {}", stake_pool.token_program_id, *token_program_info.key);
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
+
+        // Check stake pool last update epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
+        if !validator_stake_list.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        let validator_account =
+            Self::get_validator_checked(program_id, stake_pool_info, validator_stake_account_info)?;
+
+        //find the validator account in the validator list
+        let mut validator_list_item = validator_stake_list
+            .find_mut(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+        if validator_list_item.leaving {
+            return Err(StakePoolError::ValidatorLeaving.into());
+        }
+
+        // take the amount form the account created by the user to stake
+        let stake_lamports = **stake_info.lamports.borrow();
+        // computes how many shares/tokens of the pool the amount being staked represents
+        // ("pool" here means "tokens")
+        let pool_amount = stake_pool
+            .calc_pool_deposit_amount(stake_lamports)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // apply fee% to token amount
+        let fee_amount = stake_pool
+            .calc_fee_amount(pool_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // discount fee% from token amount
+        let user_amount = pool_amount
+            .checked_sub(fee_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // //DEBUG - revert
+        // msg!("REVERT sets stake_pool_info as 'Withdrawer' for stake_info");
+        // Self::stake_authorize(
+        //     stake_pool_info.key,
+        //     stake_info.clone(),
+        //     deposit_info.clone(),
+        //     Self::AUTHORITY_DEPOSIT,
+        //     stake_pool.deposit_bump_seed,
+        //     owner_fee_info.key,
+        //     stake::StakeAuthorize::Withdrawer,
+        //     clock_info.clone(),
+        //     stake_program_info.clone(),
+        // )?;
+
+        // // sets stake_pool_info as "Staker" for stake_info
+        // msg!("REVERT sets stake_pool_info as 'Staker' for stake_info");
+        // Self::stake_authorize(
+        //     stake_pool_info.key,
+        //     stake_info.clone(),
+        //     deposit_info.clone(),
+        //     Self::AUTHORITY_DEPOSIT,
+        //     stake_pool.deposit_bump_seed,
+        //     owner_fee_info.key,
+        //     stake::StakeAuthorize::Staker,
+        //     clock_info.clone(),
+        //     stake_program_info.clone(),
+        // )?;
+        // //DEBUG
+        // return Ok(());
+
+
+        // sets stake_pool_info as "Withdrawer" for stake_info
+        msg!("sets withdraw_info_auth as 'Withdrawer' for user_stake_acc");
+        Self::stake_authorize(
+            stake_pool_info.key,
+            stake_info.clone(),
+            deposit_info.clone(),
+            Self::AUTHORITY_DEPOSIT,
+            stake_pool.deposit_bump_seed,
+            withdraw_info.key,
+            stake::StakeAuthorize::Withdrawer,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        // sets stake_pool_info as "Staker" for stake_info
+        msg!("sets withdraw_info_auth as 'Staker' for user_stake_acc");
+        Self::stake_authorize(
+            stake_pool_info.key,
+            stake_info.clone(),
+            deposit_info.clone(),
+            Self::AUTHORITY_DEPOSIT,
+            stake_pool.deposit_bump_seed,
+            withdraw_info.key,
+            stake::StakeAuthorize::Staker,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        // merges stake_info into stake_pool_info
+        msg!("merges stake_info into stake_pool_info");
+        Self::stake_merge(
+            stake_pool_info.key,
+            stake_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            validator_stake_account_info.clone(),
+            clock_info.clone(),
+            stake_history_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        // mints tokens/shares for the user (tokens minus fee)
+        msg!("mints tokens/shares for the user");
+        Self::token_mint_to(
+            stake_pool_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_user_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            user_amount,
+        )?;
+
+        // mints *fee* tokens/shares for owner_fee_info
+        if fee_amount>0 {
+            Self::token_mint_to(
+                stake_pool_info.key,
+                token_program_info.clone(),
+                pool_mint_info.clone(),
+                owner_fee_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                fee_amount,
+            )?;
+        }
+        //update total tokens/shares 
+        stake_pool.pool_total += pool_amount;
+        //update total staked
+        stake_pool.stake_total += stake_lamports;
+        //save contract state into stake_pool_info account
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+
+        //update validator balance in out internal list
+        validator_list_item.balance = **validator_stake_account_info.lamports.borrow();
+
+        Ok(())
+    }
+
+    /// Splits the lamports `pool_amount` pool tokens are worth from a validator stake account
+    /// into `stake_split_to`, hands it to `new_authority` (staker and withdrawer) and updates
+    /// the stake pool totals and the validator stake list. The caller burns the pool tokens.
+    /// With `apply_withdrawal_fee` only `pool_amount` minus the withdrawal fee is paid out.
+    /// Returns the updated stake pool, the lamports split and the withdrawal fee in pool tokens
+    #[allow(clippy::too_many_arguments)]
+    fn split_stake_for_pool_tokens<'a>(
+        program_id: &Pubkey,
+        stake_pool_info: &AccountInfo<'a>,
+        stake_pool_extension_info: &AccountInfo<'a>,
+        validator_stake_list_info: &AccountInfo<'a>,
+        withdraw_info: &AccountInfo<'a>,
+        stake_split_from: &AccountInfo<'a>,
+        stake_split_to: &AccountInfo<'a>,
+        new_authority: &Pubkey,
+        clock_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        stake_program_info: &AccountInfo<'a>,
+        pool_amount: u64,
+        apply_withdrawal_fee: bool,
+    ) -> Result<(StakePool, u64, u64), ProgramError> {
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        // Check program ids
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // load contract state
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Check authority account
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+
+        if stake_pool.token_program_id != *token_program_info.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Check validator stake account list storage
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
+
+        // Check stake pool last update epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
+        let mut validator_stake_list =
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
+        if !validator_stake_list.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        //get the validator pub key from the state stored at stake_pool_info arg
+        let validator_account =
+            Self::get_validator_checked(program_id, stake_pool_info, stake_split_from)?;
+
+        //finds validator in our internal list
+        let mut validator_list_item = validator_stake_list
+            .find_mut(&validator_account)
+            .ok_or(StakePoolError::ValidatorNotFound)?;
+
+        let fee_amount = if apply_withdrawal_fee {
+            stake_pool
+                .calc_withdrawal_fee_amount(pool_amount)
+                .ok_or(StakePoolError::CalculationFailure)?
+        } else {
+            0
+        };
+        // the fee tokens are not paid out
+        let paid_pool_amount = pool_amount
+            .checked_sub(fee_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // computes how many lamports represent the token/shares being sold|burned
+        let stake_amount = stake_pool
+            .calc_lamports_amount(paid_pool_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // split the amount from this contract stake acc into stake_split_to
+        Self::stake_split(
+            stake_pool_info.key,
+            stake_split_from.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            stake_amount,
+            stake_split_to.clone(),
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        // "transfer" the mount to the new authority in a convoluted solana-requeried way:
+        // 1. splits the staked amount *into a new account* the caller created just for this call and sent as parameter
+        // 2. moves ownership of the new account (who can withdraw, who can stake, to the user)
+        // at the end, the user "new account" has the lamports, and the user can operate it.- 
+        //
+        //assigns Withdrawer to user on the split account
+        Self::stake_authorize(
+            stake_pool_info.key,
+            stake_split_to.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            new_authority,
+            stake::StakeAuthorize::Withdrawer,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        //assings Staker to user on the split account
+        Self::stake_authorize(
+            stake_pool_info.key,
+            stake_split_to.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            new_authority,
+            stake::StakeAuthorize::Staker,
+            clock_info.clone(),
+            stake_program_info.clone(),
+        )?;
+
+        //update token supply, fee tokens sent to the owner stay in circulation
+        stake_pool.pool_total -= if stake_pool.pays_withdrawal_fee_to_owner() {
+            paid_pool_amount
+        } else {
+            pool_amount
+        };
+        //update total staked
+        stake_pool.stake_total -= stake_amount;
+        //save into contract state
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+
+        //updte our internal validator list
+        validator_list_item.balance = **stake_split_from.lamports.borrow();
+
+        Ok((stake_pool, stake_amount, fee_amount))
+    }
+
+    /// Processes [DepositSol](enum.Instruction.html).
+    pub fn process_deposit_sol(
+        program_id: &Pubkey,
+        lamports: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if lamports == 0 {
+            return Err(StakePoolError::ZeroAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Reserve stake account
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // User account to take the lamports from
+        let user_info = next_account_info(account_info_iter)?;
+        // User account to receive pool tokens
+        let dest_user_info = next_account_info(account_info_iter)?;
+        // Account to receive pool fee tokens
+        let owner_fee_info = next_account_info(account_info_iter)?;
+        // Pool token mint account
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        // System program id
+        let system_program_info = next_account_info(account_info_iter)?;
+        // Pool token program id
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if !user_info.is_signer {
+            return Err(StakePoolError::SignatureMissing.into());
+        }
+
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // Check the depositor of a pool with a deposit authority
+        Self::check_depositor(program_id, stake_pool_info.key, &stake_pool, account_info_iter)?;
+
+        // Check authority and reserve accounts
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+        stake_pool.check_reserve_stake(reserve_stake_info.key, program_id, stake_pool_info.key)?;
+
+        if stake_pool.owner_fee_account != *owner_fee_info.key {
+            return Err(StakePoolError::InvalidFeeAccount.into());
+        }
+
+        if stake_pool.token_program_id != *token_program_info.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Check stake pool last update epoch
+        if stake_pool.last_update_epoch < clock.epoch {
+            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
+        }
+
+        // computes how many shares/tokens of the pool the lamports represent
+        let pool_amount = stake_pool
+            .calc_pool_deposit_amount(lamports)
+            .ok_or(StakePoolError::CalculationFailure)?;
 
-    {
+        // apply fee% to token amount
+        let fee_amount = stake_pool
+            .calc_fee_amount(pool_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
 
-        let ix = spl_token::instruction::transfer(
-            token_program.key,
-            source.key,
-            destination.key,
-            authority.key,
-            &[],
-            amount,
-        )?;
+        // discount fee% from token amount
+        let user_amount = pool_amount
+            .checked_sub(fee_amount)
+            .ok_or(StakePoolError::CalculationFailure)?;
+
+        // move the lamports into the reserve
         invoke(
-            &ix,
-            &[source, destination, authority, token_program]
-        )
-    }
+            &system_instruction::transfer(user_info.key, reserve_stake_info.key, lamports),
+            &[
+                user_info.clone(),
+                reserve_stake_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
 
-    /// Issue a spl_token `Transfer` instruction.
-    pub fn token_transfer<'a>(
-        //        owner: &Pubkey,
-        token_program: AccountInfo<'a>,
-        source: AccountInfo<'a>,
-        destination: AccountInfo<'a>,
-        authority: AccountInfo<'a>,
-        pda_base: &Pubkey,
-        authority_type: &[u8],
-        nonce: u8,
-        amount: u64,
-    ) -> Result<(), ProgramError> {
-        let pda_bytes = pda_base.to_bytes();
-        let authority_signature_seeds = [&pda_bytes[..32], authority_type, &[nonce]];
-        let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::transfer(
-            token_program.key,
-            source.key,
-            destination.key,
-            authority.key,
-            &[],
-            amount,
+        // mints tokens/shares for the user (tokens minus fee)
+        Self::token_mint_to(
+            stake_pool_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_user_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            user_amount,
         )?;
-        invoke_signed(
-            &ix,
-            &[source, destination, authority, token_program],
-            signers,
-        )
-    }
 
+        // mints *fee* tokens/shares for owner_fee_info
+        if fee_amount > 0 {
+            Self::token_mint_to(
+                stake_pool_info.key,
+                token_program_info.clone(),
+                pool_mint_info.clone(),
+                owner_fee_info.clone(),
+                withdraw_info.clone(),
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                fee_amount,
+            )?;
+        }
 
-    /// Processes [AddLiquidity](enum.Instruction.html).
-    pub fn process_add_liquidity(
+        //update total tokens/shares
+        stake_pool.pool_total += pool_amount;
+        //update total staked, the reserve counts until it gets delegated
+        stake_pool.stake_total += lamports;
+        //save contract state into stake_pool_info account
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
+    }
 
+    /// Processes [Withdraw](enum.Instruction.html).
+    /// split contract's staking acc into stake_split_to and assigns authority to user_stake_authority
+    pub fn process_withdraw(
         program_id: &Pubkey,
-        wsol_amount: u64,
+        pool_amount: u64,
         accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Stake pool withdraw authority
+        let withdraw_info = next_account_info(account_info_iter)?;
+        // Stake account to split
+        let stake_split_from = next_account_info(account_info_iter)?;
+        // Unitialized stake account to receive withdrawal
+        let stake_split_to = next_account_info(account_info_iter)?;
+        // User account to set as a new withdraw authority
+        let user_stake_authority = next_account_info(account_info_iter)?;
+        // User account with pool tokens to burn from
+        let burn_from_info = next_account_info(account_info_iter)?;
+        // Pool token mint account
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Clock sysvar account
+        let clock_info = next_account_info(account_info_iter)?;
+        // Pool token program id
+        let token_program_info = next_account_info(account_info_iter)?;
+        // Stake program id
+        let stake_program_info = next_account_info(account_info_iter)?;
+        // Account to receive the withdrawal fee tokens
+        let owner_fee_info = next_account_info(account_info_iter)?;
+
+        let (stake_pool, _, fee_amount) = Self::split_stake_for_pool_tokens(
+            program_id,
+            stake_pool_info,
+            stake_pool_extension_info,
+            validator_stake_list_info,
+            withdraw_info,
+            stake_split_from,
+            stake_split_to,
+            user_stake_authority.key,
+            clock_info,
+            token_program_info,
+            stake_program_info,
+            pool_amount,
+            true,
+        )?;
+
+        if stake_pool.owner_fee_account != *owner_fee_info.key {
+            return Err(StakePoolError::InvalidFeeAccount.into());
+        }
+
+        let mut burn_amount = pool_amount;
+        if fee_amount > 0 && stake_pool.pays_withdrawal_fee_to_owner() {
+            // the withdraw authority is the delegate for pool_amount
+            Self::token_transfer(
+                token_program_info.clone(),
+                burn_from_info.clone(),
+                owner_fee_info.clone(),
+                withdraw_info.clone(),
+                stake_pool_info.key,
+                Self::AUTHORITY_WITHDRAW,
+                stake_pool.withdraw_bump_seed,
+                fee_amount,
+            )?;
+            burn_amount -= fee_amount;
+        }
+
+        //burns the tokens
+        Self::token_burn(
+            stake_pool_info.key,
+            token_program_info.clone(),
+            burn_from_info.clone(),
+            pool_mint_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            burn_amount,
+        )?;
 
+        Ok(())
+    }
+    /// Processes [SetStakeAuthority](enum.Instruction.html).
+    pub fn process_set_staking_auth(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
     ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let withdraw_info = next_account_info(account_info_iter)?;
+        let stake_info = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+        // (Reserved)
+        let reserved = next_account_info(account_info_iter)?;
+        // Stake program id
+        let stake_program_info = next_account_info(account_info_iter)?;
 
-        msg!("--------------------------------------------------");
-        msg!("enter, amount {}",wsol_amount);
+        // Check program ids
+        if *stake_program_info.key != stake::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        if wsol_amount == 0 {
-            return Err(StakePoolError::ZeroAmount.into());
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
         }
 
+        // Check authority account
+        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
+
+        // Check owner validity and signature
+        stake_pool.check_owner(owner_info)?;
+
+        Self::stake_authorize(
+            stake_pool_info.key,
+            stake_info.clone(),
+            withdraw_info.clone(),
+            Self::AUTHORITY_WITHDRAW,
+            stake_pool.withdraw_bump_seed,
+            staker_info.key,
+            stake::StakeAuthorize::Staker,
+            reserved.clone(),
+            stake_program_info.clone(),
+        )?;
+        Ok(())
+    }
+
+    /// Processes [SetOwner](enum.Instruction.html).
+    /// The new owner takes over once it signs `AcceptOwner`
+    pub fn process_set_owner(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let new_owner_info = next_account_info(account_info_iter)?;
 
-        // Stake pool account
-        let liq_pool_state_account = next_account_info(account_info_iter)?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
 
-        let token_program = next_account_info(account_info_iter)?;
-        let metalp_token_mint_account = next_account_info(account_info_iter)?;
-        let metalp_mint_authority = next_account_info(account_info_iter)?;
+        // Check owner validity and signature
+        stake_pool.check_owner(owner_info)?;
 
-        let user_wsol_source_account = next_account_info(account_info_iter)?;
-        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        stake_pool.pending_owner = *new_owner_info.key;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+        Ok(())
+    }
 
-        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
-        let user_metalp_account_destination = next_account_info(account_info_iter)?;
+    /// Processes [AcceptOwner](enum.Instruction.html).
+    pub fn process_accept_owner(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let pending_owner_info = next_account_info(account_info_iter)?;
 
-        // Get stake pool stake (and check if it is initialized)
-        // msg!("stake_pool_info {:?}",liq_pool_state_account);
-        // let stake_pool_data = StakePool::deserialize(&liq_pool_state_account.data.borrow())?;
-        // if !stake_pool_data.is_initialized() {
-        //     return Err(StakePoolError::InvalidState.into());
-        // }
-
-        //get data from spl-token accounts
-        //let source_account_info: spl_token::state::Account = Self::unpack_token_account(user_wsol_source_account, &program_id)?;
-        //let dest_user_account_info: spl_token::state::Account = Self::unpack_token_account(user_metalp_account_destination, &program_id)?;
-
-        // TODO
-        // Self::check_accounts(
-        //     program_id,
-        //     authority_info,
-        //     user_wsol_source_account,
-        //     liq_pool_wsol_dest_account,
-        //     metalp_token_mint_account,
-        //     liq_pool_wsol_dest_account,
-        // )?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
 
-        //msg!("Self::unpack_mint {:?}",metalp_token_mint_account);
-        let metalp_mint_info = Self::unpack_mint(metalp_token_mint_account, &token_program.key)?;
-        let metalp_supply = to_u128(metalp_mint_info.supply)?;
+        // Check pending owner validity and signature
+        stake_pool.check_role(Role::PendingOwner, pending_owner_info)?;
 
-        //msg!("Self::unpack_token_account liq_pool_wsol_dest_account {:?}",liq_pool_wsol_dest_account);
-        let liq_pool_wsol_account_info = Self::unpack_token_account(liq_pool_wsol_account, &token_program.key)?;
-        msg!("liq_pool_wsol_dest_account {} {} {:?}",liq_pool_wsol_account.key, liq_pool_wsol_account_info.amount, liq_pool_wsol_account_info);
-        let our_wsol_total = to_u128(liq_pool_wsol_account_info.amount)?;
+        stake_pool.owner = stake_pool.pending_owner;
+        stake_pool.pending_owner = Pubkey::default();
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+        Ok(())
+    }
 
-        let user_account_info = Self::unpack_token_account(user_wsol_source_account, &token_program.key)?;
-        msg!("user_account_info {} {} {:?}",user_wsol_source_account.key, user_account_info.amount, user_account_info);
-        msg!("user_transfer_authority {} {:?}",user_transfer_authority_info.key, user_transfer_authority_info);
+    /// Processes [SetStaker](enum.Instruction.html).
+    pub fn process_set_staker(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let signer_info = next_account_info(account_info_iter)?;
+        let new_staker_info = next_account_info(account_info_iter)?;
 
-        assert!(user_account_info.mint==liq_pool_wsol_account_info.mint,"user acc mint must be wSOL {}",liq_pool_wsol_account_info.mint);
-        //let user_wsol_source_account_total = to_u128(user_wsol_source_account_info.amount)?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
 
-        //transfer user wsol to our wsol account
-        msg!("before token_transfer_from_signer");
-        Self::token_transfer_from_signer(
-            token_program.clone(),
-            user_wsol_source_account.clone(),
-            liq_pool_wsol_account.clone(),
-            user_transfer_authority_info.clone(),
-            wsol_amount,
-        )?;
+        // The owner or the current staker
+        stake_pool.check_role_or_owner(Role::Staker, signer_info)?;
 
-        // Calculate metalp amount and mint metalp tokens for the user
-        let metalp_amount = shares_from_value(wsol_amount, our_wsol_total, metalp_supply).ok_or(StakePoolError::CalculationFailure)?;
-        msg!("before token_mint_to");
-        Self::liq_pool_token_mint_to(
-            program_id,
-            liq_pool_state_account.key,
-            token_program.clone(),
-            metalp_token_mint_account.clone(),
-            user_metalp_account_destination.clone(),
-            metalp_mint_authority.clone(),
-            metalp_amount,
-        )?;
+        stake_pool.staker = *new_staker_info.key;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+        Ok(())
+    }
+
+    /// Processes [SetFeeManager](enum.Instruction.html).
+    pub fn process_set_fee_manager(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let signer_info = next_account_info(account_info_iter)?;
+        let new_fee_manager_info = next_account_info(account_info_iter)?;
+        let new_owner_fee_info = next_account_info(account_info_iter)?;
+
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
+        }
+
+        // The owner or the current fee manager
+        stake_pool.check_role_or_owner(Role::FeeManager, signer_info)?;
+
+        // Check for owner fee account to have proper mint assigned
+        if stake_pool.pool_mint
+            != spl_token::state::Account::unpack_from_slice(&new_owner_fee_info.data.borrow())?.mint
+        {
+            return Err(StakePoolError::WrongAccountMint.into());
+        }
 
+        stake_pool.fee_manager = *new_fee_manager_info.key;
+        stake_pool.owner_fee_account = *new_owner_fee_info.key;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
         Ok(())
     }
 
-    
-    /// Processes [SellstSOL](enum.Instruction.html).
-    pub fn process_sell_stsol(
-
+    /// Processes `SetDepositAuthority` instruction.
+    pub fn process_set_deposit_authority(
         program_id: &Pubkey,
-        stsol_amount: u64,
         accounts: &[AccountInfo],
-
     ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let new_deposit_authority_info = next_account_info(account_info_iter)?;
 
-        if stsol_amount == 0 {
-            return Err(StakePoolError::ZeroAmount.into());
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
+            return Err(StakePoolError::InvalidState.into());
         }
 
-        msg!("--- sell_stsol {}",stsol_amount);
+        // Check owner validity and signature
+        stake_pool.check_owner(owner_info)?;
+
+        stake_pool.deposit_authority = *new_deposit_authority_info.key;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+        Ok(())
+    }
 
+    /// Processes `SetDepositor` instruction.
+    /// Adds or removes a depositor of the depositor list, creating the list first if needed
+    pub fn process_set_depositor(
+        program_id: &Pubkey,
+        allowed: bool,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-
         // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
-        // Liq pool account
-        let liq_pool_account = next_account_info(account_info_iter)?;
-        // SPL token program
-        let token_program = next_account_info(account_info_iter)?;
-
-        let liq_pool_wsol_account = next_account_info(account_info_iter)?;
-        let liq_pool_stsol_account = next_account_info(account_info_iter)?;
-        let liq_pool_authority = next_account_info(account_info_iter)?;
-
-        let user_wsol_account = next_account_info(account_info_iter)?;
-        let user_stsol_account = next_account_info(account_info_iter)?;
-        let user_authority = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool owner account, pays the depositor list rent
+        let owner_info = next_account_info(account_info_iter)?;
+        // Depositor list account
+        let depositor_list_info = next_account_info(account_info_iter)?;
+        // Depositor to add or remove
+        let depositor_info = next_account_info(account_info_iter)?;
+        // Rent sysvar account
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        // System program id
+        let system_program_info = next_account_info(account_info_iter)?;
 
-        // Get stake pool stake (and check if it is initialized)
-        let stake_pool_data = StakePool::deserialize(&stake_pool_info.data.borrow())?;
-        if !stake_pool_data.is_initialized() {
+        let stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
-        msg!("stSOL mint {}",stake_pool_data.pool_mint);
-        let st_sol_mint = stake_pool_data.pool_mint;
-
-        //get data from spl-token accounts
-        //let source_account_info: spl_token::state::Account = Self::unpack_token_account(user_stsol_source_account, &program_id)?;
-        //letwsoluser_account_info: spl_token::state::Account = Self::unpack_token_account(user_metalp_account_destination, &program_id)?;
-
-        // TODO
-        // Self::check_accounts(
-        //     program_id,
-        //     authority_info,
-        //     user_stsol_source_account,
-        //     liq_pool_wsol_source_account,
-        //stsol metalp_token_mint_account,
-        //     liq_pool_wsol_source_account,
-        // )?;
 
-        //let user_stsol_source_account_info = Self::unpack_token_account(user_stsol_account, &token_program.key)?;
+        // Check owner validity and signature
+        stake_pool.check_owner(owner_info)?;
 
-        //get how much wSOL there's in the pool
-        let our_wsol_token_info = Self::unpack_token_account(liq_pool_wsol_account, &token_program.key)?;
-        let our_wsol_total = our_wsol_token_info.amount;
-        msg!("our_wsol_total {}",our_wsol_total);
-        // is it enough?
-        if stsol_amount > our_wsol_total {
-            return Err(StakePoolError::NotEnoughTokensInThePool.into());
+        let (depositor_list_address, bump_seed) =
+            Self::find_depositor_list_address(program_id, stake_pool_info.key);
+        if *depositor_list_info.key != depositor_list_address {
+            return Err(StakePoolError::InvalidDepositorList.into());
         }
 
-        let liq_pool_wsol_token_info = Self::unpack_token_account(liq_pool_wsol_account, &token_program.key)?;
-        let liq_pool_stsol_token_info = Self::unpack_token_account(liq_pool_stsol_account, &token_program.key)?;
-
-        let user_st_sol_token_info = Self::unpack_token_account(user_stsol_account, &token_program.key)?;
-        let user_st_sol_total = user_st_sol_token_info.amount;
-        msg!("user_st_sol_token_info.mint {} liq_pool_stsol_token_info.mint {}",user_st_sol_token_info.mint,liq_pool_stsol_token_info.mint);
-        msg!("user_st_sol_total {}",user_st_sol_total);
-        //make sure user provided acc is stSOL
-        assert!(st_sol_mint==user_st_sol_token_info.mint,"user acc must be of stSOL mint {}",st_sol_mint);
-        assert!(user_st_sol_token_info.mint==liq_pool_stsol_token_info.mint);
-        
-        let user_wsol_token_info = Self::unpack_token_account(user_wsol_account, &token_program.key)?;
-        let user_wsol_total = user_wsol_token_info.amount;
-        assert!(user_wsol_token_info.mint==liq_pool_wsol_token_info.mint);
-        msg!("user_wsol_token_info.mint {} liq_pool_wsol_token_info.mint {}",user_wsol_token_info.mint,liq_pool_wsol_token_info.mint);
-        msg!("user_wsol__total {}",user_wsol_total);
-
-        //let stsol_mint_info = Self::unpack_mint(stsol_token_mint_account, &program_id)?;
-        //let stsol_supply = to_u128(stsol_mint_info.supply)?;
-
-        //transfer user's stSOL to our LP stSOL account
-        Self::token_transfer_from_signer(
-            token_program.clone(),
-            user_stsol_account.clone(),
-            liq_pool_stsol_account.clone(),
-            user_authority.clone(),
-            stsol_amount,
-        )?;
-
-        // compute SOL value of stSOL shares being sold
-        let total_stsol_minted = stake_pool_data.pool_total;
-        let sol_value = proportional(stsol_amount, stake_pool_data.stake_total as u128, total_stsol_minted as u128).ok_or(StakePoolError::CalculationFailure)?;
-        // Calculate fee & transfer equivalent wSOL minus fee to user
-        let fee_amount = proportional(sol_value, stake_pool_data.fee.numerator as u128, stake_pool_data.fee.denominator as u128).ok_or(StakePoolError::CalculationFailure)?;
-        let wsol_amount_to_user = sol_value - fee_amount;
-        msg!("stSol {} sol_value {} fee {}",stsol_amount,sol_value,fee_amount);
-        msg!("Fee:{}/{}",stake_pool_data.fee.numerator, stake_pool_data.fee.denominator);
-
-        //perform the same computation used when calculating liq_pool_authority to obtain the same bump
-        let (_computed_liq_pool_authority,bump) = Pubkey::find_program_address(&[&liq_pool_account.key.to_bytes()[..32] ,Self::AUTHORITY], &program_id);
-        //let signer_seeds: &[&[_]] =&[&liq_pool_account.key.to_bytes()[..32] ,b"authority",&[bump]];
-
-        // transfer equivalent wSOL minus fee to user
-        msg!("transfer equivalent wSOL minus fee to user {}",wsol_amount_to_user);
-        Self::token_transfer(
-            token_program.clone(),
-            liq_pool_wsol_account.clone(),
-            user_wsol_account.clone(),
-            liq_pool_authority.clone(),
-            liq_pool_account.key,
-            Self::AUTHORITY,
-            bump,
-            wsol_amount_to_user,
-        )?;
-
-        //TODO
-        // //1/3 fee goes to meta-pool fees controlled by owner
-        // Self::token_transfer(
-        //     liq_pool_wsol_transfer_authority_info.key,
-        //     token_program.clone(),
-        //     liq_pool_wsol_source_account.clone(),
-        //     user_wsol_account_destination.clone(),
-        //     liq_pool_wsol_transfer_authority_info.clone(),
-        //     0,
-        //     fee_amount/3,
-        // )?;
-
-        //the fee stays in the liq-pool, increasing share-value for all liquidity providers.
-        //TODO send 1/3 of the fee to the metapool owner
+        if *depositor_list_info.owner != *program_id {
+            let stake_pool_bytes = stake_pool_info.key.to_bytes();
+            Self::create_pda_account(
+                owner_info.clone(),
+                depositor_list_info.clone(),
+                system_program_info.clone(),
+                rent,
+                DepositorList::LEN,
+                program_id,
+                &[&stake_pool_bytes[..32], Self::DEPOSITORS, &[bump_seed]],
+            )?;
+        }
 
-        Ok(())
+        let mut depositor_list = DepositorList::deserialize(&depositor_list_info.data.borrow())?;
+        depositor_list.version = DepositorList::DEPOSITOR_LIST_VERSION;
+        if allowed {
+            if depositor_list.contains(depositor_info.key) {
+                return Err(StakePoolError::AlreadyInUse.into());
+            }
+            if depositor_list.depositors.len() >= MAX_DEPOSITORS {
+                return Err(StakePoolError::DepositorListFull.into());
+            }
+            depositor_list.depositors.push(*depositor_info.key);
+        } else {
+            if !depositor_list.contains(depositor_info.key) {
+                return Err(StakePoolError::DepositorNotAllowed.into());
+            }
+            depositor_list
+                .depositors
+                .retain(|depositor| depositor != depositor_info.key);
+        }
+        depositor_list.serialize(&mut depositor_list_info.data.borrow_mut())
     }
-    
-    /// Processes [Deposit](enum.Instruction.html).
-    pub fn process_deposit(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
 
+    /// Processes [SetFee](enum.Instruction.html).
+    pub fn process_set_fee(program_id: &Pubkey, fee: Fee, accounts: &[AccountInfo]) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        // Stake pool
         let stake_pool_info = next_account_info(account_info_iter)?;
-        // Account storing validator stake list
-        let validator_stake_list_info = next_account_info(account_info_iter)?;
-        // Stake pool deposit authority
-        let deposit_info = next_account_info(account_info_iter)?;
-        // Stake pool withdraw authority
-        let withdraw_info = next_account_info(account_info_iter)?;
-        // Stake account to join the pool (withdraw should be set to stake pool deposit)
-        let stake_info = next_account_info(account_info_iter)?;
-        // Validator stake account to merge with
-        let validator_stake_account_info = next_account_info(account_info_iter)?;
-        // User account to receive pool tokens
-        let dest_user_info = next_account_info(account_info_iter)?;
-        // Account to receive pool fee tokens
-        let owner_fee_info = next_account_info(account_info_iter)?;
-        // Pool token mint account
-        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        let fee_manager_info = next_account_info(account_info_iter)?;
         // Clock sysvar account
         let clock_info = next_account_info(account_info_iter)?;
         let clock = &Clock::from_account_info(clock_info)?;
-        // Stake history sysvar account
-        let stake_history_info = next_account_info(account_info_iter)?;
-        let stake_history = &StakeHistory::from_account_info(stake_history_info)?;
-        // Pool token program id
-        let token_program_info = next_account_info(account_info_iter)?;
-        // Stake program id
-        let stake_program_info = next_account_info(account_info_iter)?;
-
-        // Check program ids
-        if *stake_program_info.key != stake::id() {
-            return Err(ProgramError::IncorrectProgramId);
-        }
 
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        // Check if stake is active
-        Self::check_stake_activation(stake_info, clock, stake_history)?;
-
-        // Check authority accounts
-        msg!("Check authority accounts");
-        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
-        stake_pool.check_authority_deposit(deposit_info.key, program_id, stake_pool_info.key)?;
-
-        // if stake_pool.owner_fee_account != *owner_fee_info.key {
-        //     return Err(StakePoolError::InvalidFeeAccount.into());
-        // }
+        // Check fee manager validity and signature
+        stake_pool.check_fee_manager(fee_manager_info)?;
 
-        if stake_pool.token_program_id != *token_program_info.key {
-            msg!("stake_pool.token_program_id {} != accs[11] (token_program_info) {}", stake_pool.token_program_id, *token_program_info.key);
-            return Err(ProgramError::IncorrectProgramId);
+        // Numerator should be smaller than or equal to denominator (fee <= 1)
+        if fee.numerator > fee.denominator {
+            return Err(StakePoolError::FeeTooHigh.into());
         }
 
-        // Check validator stake account list storage
-        if *validator_stake_list_info.key != stake_pool.validator_stake_list {
-            return Err(StakePoolError::InvalidValidatorStakeList.into());
-        }
+        // depositors get notice: the fee is promoted by UpdatePoolBalance
+        stake_pool.next_fee = fee;
+        stake_pool.next_fee_epoch = clock
+            .epoch
+            .checked_add(StakePool::FEE_CHANGE_EPOCHS)
+            .ok_or(StakePoolError::CalculationFailure)?;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
+        Ok(())
+    }
 
-        // Check stake pool last update epoch
-        if stake_pool.last_update_epoch < clock.epoch {
-            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
-        }
+    /// Processes `AddValidatorStakeListPage` instruction.
+    /// Initializes an empty validator stake list account and links it to the pool
+    pub fn process_add_validator_stake_list_page(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // Stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool staker account
+        let staker_info = next_account_info(account_info_iter)?;
+        // New validator stake list page
+        let page_info = next_account_info(account_info_iter)?;
+        // Rent sysvar account
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
 
-        // Read validator stake list account and check if it is valid
-        let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
-        if !validator_stake_list.is_initialized() {
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
+        if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        let validator_account =
-            Self::get_validator_checked(program_id, stake_pool_info, validator_stake_account_info)?;
-
-        //find the validator account in the validator list
-        let validator_list_item = validator_stake_list
-            .find_mut(&validator_account)
-            .ok_or(StakePoolError::ValidatorNotFound)?;
-
-        // take the amount form the account created by the user to stake
-        let stake_lamports = **stake_info.lamports.borrow();
-        // computes how many shares/tokens of the pool the amount being staked represents
-        // ("pool" here means "tokens")
-        let pool_amount = stake_pool
-            .calc_pool_deposit_amount(stake_lamports)
-            .ok_or(StakePoolError::CalculationFailure)?;
-
-        // apply fee% to token amount
-        let fee_amount = 0; //stake_pool
-            // .calc_fee_amount(pool_amount)
-            // .ok_or(StakePoolError::CalculationFailure)?;
+        stake_pool.check_staker(staker_info)?;
 
-        // discount fee% from token amount
-        let user_amount = pool_amount
-            .checked_sub(fee_amount)
-            .ok_or(StakePoolError::CalculationFailure)?;
+        let pages_len = stake_pool.validator_stake_list_pages_len as usize;
+        if pages_len >= MAX_VALIDATOR_STAKE_LIST_PAGES - 1 {
+            return Err(StakePoolError::TooManyValidatorStakeListPages.into());
+        }
+        if stake_pool.check_validator_stake_list(page_info.key).is_ok() {
+            return Err(StakePoolError::AlreadyInUse.into());
+        }
 
-        // //DEBUG - revert
-        // msg!("REVERT sets stake_pool_info as 'Withdrawer' for stake_info");
-        // Self::stake_authorize(
-        //     stake_pool_info.key,
-        //     stake_info.clone(),
-        //     deposit_info.clone(),
-        //     Self::AUTHORITY_DEPOSIT,
-        //     stake_pool.deposit_bump_seed,
-        //     owner_fee_info.key,
-        //     stake::StakeAuthorize::Withdrawer,
-        //     clock_info.clone(),
-        //     stake_program_info.clone(),
-        // )?;
+        if *page_info.owner != *program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if page_info.data_len() < ValidatorStakeList::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if !rent.is_exempt(page_info.lamports(), page_info.data_len()) {
+            return Err(StakePoolError::AccountNotRentExempt.into());
+        }
 
-        // // sets stake_pool_info as "Staker" for stake_info
-        // msg!("REVERT sets stake_pool_info as 'Staker' for stake_info");
-        // Self::stake_authorize(
-        //     stake_pool_info.key,
-        //     stake_info.clone(),
-        //     deposit_info.clone(),
-        //     Self::AUTHORITY_DEPOSIT,
-        //     stake_pool.deposit_bump_seed,
-        //     owner_fee_info.key,
-        //     stake::StakeAuthorize::Staker,
-        //     clock_info.clone(),
-        //     stake_program_info.clone(),
-        // )?;
-        // //DEBUG
-        // return Ok(());
+        let mut page_data = page_info.data.borrow_mut();
+        let mut page = ValidatorStakeListView::new(&mut page_data[..])?;
+        if page.is_initialized() {
+            return Err(StakePoolError::AlreadyInUse.into());
+        }
+        page.initialize();
 
+        stake_pool.validator_stake_list_pages[pages_len] = *page_info.key;
+        stake_pool.validator_stake_list_pages_len += 1;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
+    }
 
-        // sets stake_pool_info as "Withdrawer" for stake_info
-        msg!("sets withdraw_info_auth as 'Withdrawer' for user_stake_acc");
-        Self::stake_authorize(
-            stake_pool_info.key,
-            stake_info.clone(),
-            deposit_info.clone(),
-            Self::AUTHORITY_DEPOSIT,
-            stake_pool.deposit_bump_seed,
-            withdraw_info.key,
-            stake::StakeAuthorize::Withdrawer,
-            clock_info.clone(),
-            stake_program_info.clone(),
-        )?;
+    /// Processes `MigrateState` instruction.
+    /// Upgrades a v1 stake pool and its validator stake list in place, the fields added
+    /// since v1 go to the stake pool extension created here
+    pub fn process_migrate_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // v1 stake pool account
+        let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account, created here
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool owner account, pays the extension rent
+        let owner_info = next_account_info(account_info_iter)?;
+        // v1 validator stake list storage account
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Rent sysvar account
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        // System program id
+        let system_program_info = next_account_info(account_info_iter)?;
+        // Reserve stake account, created here
+        let reserve_stake_info = next_account_info(account_info_iter)?;
+        // Stake program id
+        let stake_program_info = next_account_info(account_info_iter)?;
+        // Validator stake list page for the validators the upgraded list can't hold
+        let page_info = next_account_info(account_info_iter).ok();
 
-        // sets stake_pool_info as "Staker" for stake_info
-        msg!("sets withdraw_info_auth as 'Staker' for user_stake_acc");
-        Self::stake_authorize(
-            stake_pool_info.key,
-            stake_info.clone(),
-            deposit_info.clone(),
-            Self::AUTHORITY_DEPOSIT,
-            stake_pool.deposit_bump_seed,
-            withdraw_info.key,
-            stake::StakeAuthorize::Staker,
-            clock_info.clone(),
-            stake_program_info.clone(),
+        if *stake_pool_info.owner != *program_id
+            || *validator_stake_list_info.owner != *program_id
+            || *stake_program_info.key != stake::id()
+        {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut stake_pool = StakePool::deserialize_v1(&stake_pool_info.data.borrow())?;
+        stake_pool.check_owner(owner_info)?;
+        if *validator_stake_list_info.key != stake_pool.validator_stake_list {
+            return Err(StakePoolError::InvalidValidatorStakeList.into());
+        }
+
+        let (extension_address, extension_bump_seed) =
+            Self::find_stake_pool_extension_address(program_id, stake_pool_info.key);
+        if *stake_pool_extension_info.key != extension_address {
+            return Err(StakePoolError::InvalidStakePoolExtension.into());
+        }
+        let stake_pool_bytes = stake_pool_info.key.to_bytes();
+        Self::create_pda_account(
+            owner_info.clone(),
+            stake_pool_extension_info.clone(),
+            system_program_info.clone(),
+            rent,
+            StakePool::EXTENSION_LEN,
+            program_id,
+            &[&stake_pool_bytes[..32], Self::EXTENSION, &[extension_bump_seed]],
         )?;
 
-        // merges stake_info into stake_pool_info
-        msg!("merges stake_info into stake_pool_info");
-        Self::stake_merge(
+        {
+            // the page is checked like by AddValidatorStakeListPage
+            let mut page_data;
+            let mut page = match page_info {
+                Some(page_info) => {
+                    if *page_info.owner != *program_id {
+                        return Err(ProgramError::IncorrectProgramId);
+                    }
+                    if page_info.key == validator_stake_list_info.key {
+                        return Err(StakePoolError::AlreadyInUse.into());
+                    }
+                    if page_info.data_len() < ValidatorStakeList::LEN {
+                        return Err(ProgramError::InvalidAccountData);
+                    }
+                    if !rent.is_exempt(page_info.lamports(), page_info.data_len()) {
+                        return Err(StakePoolError::AccountNotRentExempt.into());
+                    }
+                    page_data = page_info.data.borrow_mut();
+                    let mut page = ValidatorStakeListView::new(&mut page_data[..])?;
+                    if page.is_initialized() {
+                        return Err(StakePoolError::AlreadyInUse.into());
+                    }
+                    page.initialize();
+                    stake_pool.validator_stake_list_pages[0] = *page_info.key;
+                    stake_pool.validator_stake_list_pages_len = 1;
+                    Some(page)
+                }
+                None => None,
+            };
+            ValidatorStakeList::migrate_v1(
+                &mut validator_stake_list_info.data.borrow_mut(),
+                page.as_mut(),
+            )?;
+        }
+
+        // the address and the authorities of the pool are kept, the validator stake
+        // accounts and the pool mint authority don't change. v1 had no reserve, the
+        // owner pays its rent
+        let (reserve_stake_address, reserve_bump_seed) =
+            Self::find_authority_bump_seed(program_id, stake_pool_info.key, Self::RESERVE);
+        if *reserve_stake_info.key != reserve_stake_address {
+            return Err(StakePoolError::InvalidProgramAddress.into());
+        }
+        let withdraw_authority = Self::authority_id(
+            program_id,
             stake_pool_info.key,
-            stake_info.clone(),
-            withdraw_info.clone(),
             Self::AUTHORITY_WITHDRAW,
             stake_pool.withdraw_bump_seed,
-            validator_stake_account_info.clone(),
-            clock_info.clone(),
-            stake_history_info.clone(),
-            stake_program_info.clone(),
         )?;
-
-        // mints tokens/shares for the user (tokens minus fee)
-        msg!("mints tokens/shares for the user");
-        Self::token_mint_to(
+        Self::create_reserve_stake_account(
+            owner_info.clone(),
+            reserve_stake_info.clone(),
+            system_program_info.clone(),
+            rent_info.clone(),
+            stake_program_info.clone(),
             stake_pool_info.key,
-            token_program_info.clone(),
-            pool_mint_info.clone(),
-            dest_user_info.clone(),
-            withdraw_info.clone(),
-            Self::AUTHORITY_WITHDRAW,
-            stake_pool.withdraw_bump_seed,
-            user_amount,
+            &withdraw_authority,
+            reserve_bump_seed,
         )?;
-
-        // mints *fee* tokens/shares for owner_fee_info
-        if fee_amount>0 {
-            Self::token_mint_to(
-                stake_pool_info.key,
-                token_program_info.clone(),
-                pool_mint_info.clone(),
-                owner_fee_info.clone(),
-                withdraw_info.clone(),
-                Self::AUTHORITY_WITHDRAW,
-                stake_pool.withdraw_bump_seed,
-                fee_amount,
-            )?;
-        }
-        //update total tokens/shares 
-        stake_pool.pool_total += pool_amount;
-        //update total staked
-        stake_pool.stake_total += stake_lamports;
-        //save contract state into stake_pool_info account
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())?;
-
-        //update validator balance in out internal list
-        validator_list_item.balance = **validator_stake_account_info.lamports.borrow();
-        //save updated validator list into validator_stake_list_info account
-        validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
-
-        Ok(())
+        stake_pool.version = StakePool::STAKE_POOL_VERSION;
+        stake_pool.extension_bump_seed = extension_bump_seed;
+        stake_pool.reserve_bump_seed = reserve_bump_seed;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
     }
 
-    /// Processes [Withdraw](enum.Instruction.html).
-    /// split contract's staking acc into stake_split_to and assigns authority to user_stake_authority
-    pub fn process_withdraw(
+    /// Processes `DeactivateDelinquentValidator` instruction.
+    /// Anyone can call it, the vote account decides if the validator is delinquent
+    pub fn process_deactivate_delinquent_validator(
         program_id: &Pubkey,
-        pool_amount: u64,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        // Stake pool
+        // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
-        // Account storing validator stake list
-        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
         // Stake pool withdraw authority
         let withdraw_info = next_account_info(account_info_iter)?;
-        // Stake account to split
-        let stake_split_from = next_account_info(account_info_iter)?;
-        // Unitialized stake account to receive withdrawal
-        let stake_split_to = next_account_info(account_info_iter)?;
-        // User account to set as a new withdraw authority
-        let user_stake_authority = next_account_info(account_info_iter)?;
-        // User account with pool tokens to burn from
-        let burn_from_info = next_account_info(account_info_iter)?;
-        // Pool token mint account
-        let pool_mint_info = next_account_info(account_info_iter)?;
+        // Account storing validator stake list
+        let validator_stake_list_info = next_account_info(account_info_iter)?;
+        // Validator stake account to deactivate
+        let stake_account_info = next_account_info(account_info_iter)?;
+        // Validator vote account
+        let validator_vote_info = next_account_info(account_info_iter)?;
         // Clock sysvar account
         let clock_info = next_account_info(account_info_iter)?;
         let clock = &Clock::from_account_info(clock_info)?;
-        // Pool token program id
-        let token_program_info = next_account_info(account_info_iter)?;
-        // Stake program id
+        // Staking program id
         let stake_program_info = next_account_info(account_info_iter)?;
 
-        // Check program ids
         if *stake_program_info.key != stake::id() {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        // load contract state
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        // Check authority account
         stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
 
-        if stake_pool.token_program_id != *token_program_info.key {
-            return Err(ProgramError::IncorrectProgramId);
-        }
-
         // Check validator stake account list storage
-        if *validator_stake_list_info.key != stake_pool.validator_stake_list {
-            return Err(StakePoolError::InvalidValidatorStakeList.into());
-        }
-
-        // Check stake pool last update epoch
-        if stake_pool.last_update_epoch < clock.epoch {
-            return Err(StakePoolError::StakeListAndPoolOutOfDate.into());
-        }
+        stake_pool.check_validator_stake_list(validator_stake_list_info.key)?;
 
-        // Read validator stake list account and check if it is valid
+        let mut validator_stake_list_data = validator_stake_list_info.data.borrow_mut();
         let mut validator_stake_list =
-            ValidatorStakeList::deserialize(&validator_stake_list_info.data.borrow())?;
+            ValidatorStakeListView::new(&mut validator_stake_list_data[..])?;
         if !validator_stake_list.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        //get the validator pub key from the state stored at stake_pool_info arg
         let validator_account =
-            Self::get_validator_checked(program_id, stake_pool_info, stake_split_from)?;
-
-        //finds validator in our internal list
-        let validator_list_item = validator_stake_list
+            Self::get_validator_checked(program_id, stake_pool_info, stake_account_info)?;
+        if validator_account != *validator_vote_info.key {
+            return Err(StakePoolError::InvalidStakeAccountAddress.into());
+        }
+        let mut validator_stake_record = validator_stake_list
             .find_mut(&validator_account)
             .ok_or(StakePoolError::ValidatorNotFound)?;
+        if validator_stake_record.leaving {
+            return Err(StakePoolError::ValidatorLeaving.into());
+        }
+        // the transient stake is settled by UpdateListBalance first
+        if validator_stake_record.transient_balance > 0 {
+            return Err(StakePoolError::TransientAccountInUse.into());
+        }
 
-        // computes how many lamports represent the token/shares being sold|burned
-        let stake_amount = stake_pool
-            .calc_lamports_amount(pool_amount)
-            .ok_or(StakePoolError::CalculationFailure)?;
-
-        // split the amount from this contract stake acc into stake_split_to
-        Self::stake_split(
-            stake_pool_info.key,
-            stake_split_from.clone(),
-            withdraw_info.clone(),
-            Self::AUTHORITY_WITHDRAW,
-            stake_pool.withdraw_bump_seed,
-            stake_amount,
-            stake_split_to.clone(),
-            clock_info.clone(),
-            stake_program_info.clone(),
-        )?;
-
-        // "transfer" the mount to the user in a convoluted solana-requeried way:
-        // 1. splits the staked amount *into a new account* the caller created just for this call and sent as parameter
-        // 2. moves ownership of the new account (who can withdraw, who can stake, to the user)
-        // at the end, the user "new account" has the lamports, and the user can operate it.- 
-        //
-        //assigns Withdrawer to user on the split account
-        Self::stake_authorize(
-            stake_pool_info.key,
-            stake_split_to.clone(),
-            withdraw_info.clone(),
-            Self::AUTHORITY_WITHDRAW,
-            stake_pool.withdraw_bump_seed,
-            user_stake_authority.key,
-            stake::StakeAuthorize::Withdrawer,
-            clock_info.clone(),
-            stake_program_info.clone(),
-        )?;
+        let last_voted_slot = vote::validator_last_voted_slot(validator_vote_info)?;
+        if !stake_pool.is_delinquent(last_voted_slot, clock.slot) {
+            return Err(StakePoolError::ValidatorNotDelinquent.into());
+        }
 
-        //assings Staker to user on the split account
-        Self::stake_authorize(
+        Self::stake_deactivate(
             stake_pool_info.key,
-            stake_split_to.clone(),
+            stake_account_info.clone(),
             withdraw_info.clone(),
             Self::AUTHORITY_WITHDRAW,
             stake_pool.withdraw_bump_seed,
-            user_stake_authority.key,
-            stake::StakeAuthorize::Staker,
             clock_info.clone(),
             stake_program_info.clone(),
         )?;
+        msg!(
+            "deactivate delinquent validator {}, last vote {:?}",
+            validator_account,
+            last_voted_slot
+        );
 
-        //burns the tokens
-        Self::token_burn(
-            stake_pool_info.key,
-            token_program_info.clone(),
-            burn_from_info.clone(),
-            pool_mint_info.clone(),
-            withdraw_info.clone(),
-            Self::AUTHORITY_WITHDRAW,
-            stake_pool.withdraw_bump_seed,
-            pool_amount,
-        )?;
-
-        //update token supply
-        stake_pool.pool_total -= pool_amount;
-        //update total staked
-        stake_pool.stake_total -= stake_amount;
-        //save into contract state
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())?;
-
-        //updte our internal validator list
-        validator_list_item.balance = **stake_split_from.lamports.borrow();
-        //save into validator_stake_list state
-        validator_stake_list.serialize(&mut validator_stake_list_info.data.borrow_mut())?;
+        // Rebalance no longer targets the validator
+        stake_pool.total_target_weight = stake_pool
+            .total_target_weight
+            .saturating_sub(validator_stake_record.target_weight);
+        validator_stake_record.target_weight = 0;
+        validator_stake_record.leaving = true;
+        drop(validator_stake_record);
 
-        Ok(())
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)
     }
-    /// Processes [SetStakeAuthority](enum.Instruction.html).
-    pub fn process_set_staking_auth(
+
+    /// Processes `SetDelinquentSlots` instruction.
+    pub fn process_set_delinquent_slots(
         program_id: &Pubkey,
+        delinquent_slots: u64,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let stake_pool_info = next_account_info(account_info_iter)?;
-        let owner_info = next_account_info(account_info_iter)?;
-        let withdraw_info = next_account_info(account_info_iter)?;
-        let stake_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
         let staker_info = next_account_info(account_info_iter)?;
-        // (Reserved)
-        let reserved = next_account_info(account_info_iter)?;
-        // Stake program id
-        let stake_program_info = next_account_info(account_info_iter)?;
-
-        // Check program ids
-        if *stake_program_info.key != stake::id() {
-            return Err(ProgramError::IncorrectProgramId);
-        }
 
-        let stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
 
-        // Check authority account
-        stake_pool.check_authority_withdraw(withdraw_info.key, program_id, stake_pool_info.key)?;
-
-        // Check owner validity and signature
-        stake_pool.check_owner(owner_info)?;
+        // Check staker validity and signature
+        stake_pool.check_staker(staker_info)?;
 
-        Self::stake_authorize(
-            stake_pool_info.key,
-            stake_info.clone(),
-            withdraw_info.clone(),
-            Self::AUTHORITY_WITHDRAW,
-            stake_pool.withdraw_bump_seed,
-            staker_info.key,
-            stake::StakeAuthorize::Staker,
-            reserved.clone(),
-            stake_program_info.clone(),
-        )?;
+        stake_pool.delinquent_slots = delinquent_slots;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
         Ok(())
     }
 
-    /// Processes [SetOwner](enum.Instruction.html).
-    pub fn process_set_owner(_program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    /// Processes `CreateRateHistory` instruction.
+    pub fn process_create_rate_history(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
+        // Stake pool account
         let stake_pool_info = next_account_info(account_info_iter)?;
+        // Stake pool extension account
+        let stake_pool_extension_info = next_account_info(account_info_iter)?;
+        // Pool owner account, pays the rate history rent
         let owner_info = next_account_info(account_info_iter)?;
-        let new_owner_info = next_account_info(account_info_iter)?;
-        let new_owner_fee_info = next_account_info(account_info_iter)?;
+        // Rate history account
+        let rate_history_info = next_account_info(account_info_iter)?;
+        // Rent sysvar account
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+        // System program id
+        let system_program_info = next_account_info(account_info_iter)?;
 
-        let mut stake_pool = StakePool::deserialize(&stake_pool_info.data.borrow())?;
+        let mut stake_pool =
+            Self::load_stake_pool(program_id, stake_pool_info, stake_pool_extension_info)?;
         if !stake_pool.is_initialized() {
             return Err(StakePoolError::InvalidState.into());
         }
@@ -1736,19 +4674,36 @@ impl Processor {
         // Check owner validity and signature
         stake_pool.check_owner(owner_info)?;
 
-        // Check for owner fee account to have proper mint assigned
-        if stake_pool.pool_mint
-            != spl_token::state::Account::unpack_from_slice(&new_owner_fee_info.data.borrow())?.mint
-        {
-            return Err(StakePoolError::WrongAccountMint.into());
+        if stake_pool.has_rate_history() {
+            return Err(StakePoolError::AlreadyInUse.into());
+        }
+        let (rate_history_address, bump_seed) =
+            Self::find_rate_history_address(program_id, stake_pool_info.key);
+        if *rate_history_info.key != rate_history_address {
+            return Err(StakePoolError::InvalidRateHistory.into());
         }
 
-        stake_pool.owner = *new_owner_info.key;
-        stake_pool.owner_fee_account = *new_owner_fee_info.key;
-        stake_pool.serialize(&mut stake_pool_info.data.borrow_mut())?;
+        let stake_pool_bytes = stake_pool_info.key.to_bytes();
+        Self::create_pda_account(
+            owner_info.clone(),
+            rate_history_info.clone(),
+            system_program_info.clone(),
+            rent,
+            RateHistory::LEN,
+            program_id,
+            &[&stake_pool_bytes[..32], Self::RATE_HISTORY, &[bump_seed]],
+        )?;
+        let rate_history = RateHistory {
+            version: RateHistory::RATE_HISTORY_VERSION,
+            ..RateHistory::default()
+        };
+        rate_history.serialize(&mut rate_history_info.data.borrow_mut())?;
+
+        stake_pool.rate_history_bump_seed = bump_seed;
+        Self::save_stake_pool(&stake_pool, stake_pool_info, stake_pool_extension_info)?;
         Ok(())
     }
-    
+
     /// Processes [Instruction](enum.Instruction.html).
     pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
         msg!("-----enter process");
@@ -1795,13 +4750,109 @@ impl Processor {
                 msg!("Instruction: SetOwner");
                 Self::process_set_owner(program_id, accounts)
             }
-            StakePoolInstruction::AddLiquidity(wsol_amount) => {
+            StakePoolInstruction::AddLiquidity(args) => {
                 msg!("Instruction: AddLiquidity");
-                Self::process_add_liquidity(program_id, wsol_amount, accounts)
+                Self::process_add_liquidity(program_id, args.amount, args.min_metalp_out, accounts)
             }
-            StakePoolInstruction::SellstSOL(stsol_amount) => {
+            StakePoolInstruction::SellstSOL(args) => {
                 msg!("Instruction: sell stSOL");
-                Self::process_sell_stsol(program_id, stsol_amount, accounts)
+                Self::process_sell_stsol(program_id, args.amount, args.min_wsol_out, accounts)
+            }
+            StakePoolInstruction::RemoveLiquidity(metalp_amount) => {
+                msg!("Instruction: RemoveLiquidity");
+                Self::process_remove_liquidity(program_id, metalp_amount, accounts)
+            }
+            StakePoolInstruction::InitializeLiquidityPool(sell_fee) => {
+                msg!("Instruction: InitializeLiquidityPool");
+                Self::process_initialize_liquidity_pool(program_id, sell_fee, accounts)
+            }
+            StakePoolInstruction::SetSellFee(sell_fee) => {
+                msg!("Instruction: SetSellFee");
+                Self::process_set_sell_fee(program_id, sell_fee, accounts)
+            }
+            StakePoolInstruction::SetTreasury(treasury_share_bps) => {
+                msg!("Instruction: SetTreasury");
+                Self::process_set_treasury(program_id, treasury_share_bps, accounts)
+            }
+            StakePoolInstruction::BuyStSol(args) => {
+                msg!("Instruction: buy stSOL");
+                Self::process_buy_stsol(program_id, args.amount, args.min_stsol_out, accounts)
+            }
+            StakePoolInstruction::SetBuyFee(buy_fee_bps) => {
+                msg!("Instruction: SetBuyFee");
+                Self::process_set_buy_fee(program_id, buy_fee_bps, accounts)
+            }
+            StakePoolInstruction::LiquidityPoolUnstake(args) => {
+                msg!("Instruction: LiquidityPoolUnstake");
+                Self::process_liquidity_pool_unstake(program_id, args.amount, args.ticket, accounts)
+            }
+            StakePoolInstruction::LiquidityPoolClaim(ticket) => {
+                msg!("Instruction: LiquidityPoolClaim");
+                Self::process_liquidity_pool_claim(program_id, ticket, accounts)
+            }
+            StakePoolInstruction::DepositSol(lamports) => {
+                msg!("Instruction: DepositSol");
+                Self::process_deposit_sol(program_id, lamports, accounts)
+            }
+            StakePoolInstruction::SetFee(fee) => {
+                msg!("Instruction: SetFee");
+                Self::process_set_fee(program_id, fee, accounts)
+            }
+            StakePoolInstruction::IncreaseValidatorStake(lamports) => {
+                msg!("Instruction: IncreaseValidatorStake");
+                Self::process_increase_validator_stake(program_id, lamports, accounts)
+            }
+            StakePoolInstruction::DecreaseValidatorStake(lamports) => {
+                msg!("Instruction: DecreaseValidatorStake");
+                Self::process_decrease_validator_stake(program_id, lamports, accounts)
+            }
+            StakePoolInstruction::SetValidatorWeights(weights) => {
+                msg!("Instruction: SetValidatorWeights");
+                Self::process_set_validator_weights(program_id, weights, accounts)
+            }
+            StakePoolInstruction::Rebalance => {
+                msg!("Instruction: Rebalance");
+                Self::process_rebalance(program_id, accounts)
+            }
+            StakePoolInstruction::AddValidatorStakeListPage => {
+                msg!("Instruction: AddValidatorStakeListPage");
+                Self::process_add_validator_stake_list_page(program_id, accounts)
+            }
+            StakePoolInstruction::MigrateState => {
+                msg!("Instruction: MigrateState");
+                Self::process_migrate_state(program_id, accounts)
+            }
+            StakePoolInstruction::AcceptOwner => {
+                msg!("Instruction: AcceptOwner");
+                Self::process_accept_owner(program_id, accounts)
+            }
+            StakePoolInstruction::SetStaker => {
+                msg!("Instruction: SetStaker");
+                Self::process_set_staker(program_id, accounts)
+            }
+            StakePoolInstruction::SetFeeManager => {
+                msg!("Instruction: SetFeeManager");
+                Self::process_set_fee_manager(program_id, accounts)
+            }
+            StakePoolInstruction::SetDepositAuthority => {
+                msg!("Instruction: SetDepositAuthority");
+                Self::process_set_deposit_authority(program_id, accounts)
+            }
+            StakePoolInstruction::SetDepositor(allowed) => {
+                msg!("Instruction: SetDepositor");
+                Self::process_set_depositor(program_id, allowed, accounts)
+            }
+            StakePoolInstruction::DeactivateDelinquentValidator => {
+                msg!("Instruction: DeactivateDelinquentValidator");
+                Self::process_deactivate_delinquent_validator(program_id, accounts)
+            }
+            StakePoolInstruction::SetDelinquentSlots(delinquent_slots) => {
+                msg!("Instruction: SetDelinquentSlots");
+                Self::process_set_delinquent_slots(program_id, delinquent_slots, accounts)
+            }
+            StakePoolInstruction::CreateRateHistory => {
+                msg!("Instruction: CreateRateHistory");
+                Self::process_create_rate_history(program_id, accounts)
             }
         }
     }
@@ -1841,22 +4892,36 @@ impl PrintProgramError for StakePoolError {
             StakePoolError::ZeroAmount=> msg!("Error: Amount must be greater than zero"),
             StakePoolError::ConversionFailure=> msg!("Error: Data Conversion Failure"),
             StakePoolError::NotEnoughTokensInThePool=> msg!("Error: Not Enough Tokens In The Pool"),
+            StakePoolError::WrongStakePool=> msg!("Error: Stake pool account does not match the liquidity pool state"),
+            StakePoolError::WrongLiquidityPoolAccount=> msg!("Error: Liquidity pool account does not match the liquidity pool state"),
+            StakePoolError::InvalidSellFeeCurve=> msg!("Error: Sell fee curve is invalid, min fee must be <= max fee <= 100%"),
+            StakePoolError::SlippageExceeded=> msg!("Error: Output amount is below the requested minimum"),
+            StakePoolError::WrongTreasuryAccount=> msg!("Error: Treasury account does not match the liquidity pool state"),
+            StakePoolError::InvalidUnstakeTicket=> msg!("Error: Unstake ticket index is out of range, the ticket is not pending, or an account does not match it"),
+            StakePoolError::UnstakeTicketInUse=> msg!("Error: Unstake ticket is already pending"),
+            StakePoolError::UnstakeTicketNotReady=> msg!("Error: Unstake ticket stake is still cooling down"),
+            StakePoolError::TransientAccountInUse=> msg!("Error: Validator transient stake account is still being activated or deactivated"),
+            StakePoolError::ReserveStakeTooLow=> msg!("Error: Reserve stake account does not have enough lamports above its rent"),
+            StakePoolError::RebalanceNotNeeded=> msg!("Error: Validator stake is at its target, or the epoch rebalance limit is reached"),
+            StakePoolError::ValidatorStakeListFull=> msg!("Error: Validator stake list page is full, add a new page to the pool"),
+            StakePoolError::TooManyValidatorStakeListPages=> msg!("Error: Stake pool already links the maximum number of validator stake list pages"),
+            StakePoolError::StateNeedsMigration=> msg!("Error: Stake pool state uses an older layout, run MigrateState"),
+            StakePoolError::InvalidStateVersion=> msg!("Error: State version is unknown, or not the one expected by the instruction"),
+            StakePoolError::WrongStaker=> msg!("Error: Wrong staker"),
+            StakePoolError::WrongFeeManager=> msg!("Error: Wrong fee manager"),
+            StakePoolError::WrongPendingOwner=> msg!("Error: Signer is not the pending owner"),
+            StakePoolError::DepositorNotAllowed=> msg!("Error: Depositor is not allowed to deposit in the stake pool"),
+            StakePoolError::InvalidDepositorList=> msg!("Error: Depositor list account does not match the stake pool"),
+            StakePoolError::DepositorListFull=> msg!("Error: Depositor list is full"),
+            StakePoolError::ValidatorLeaving=> msg!("Error: Validator is leaving the pool"),
+            StakePoolError::ValidatorNotDelinquent=> msg!("Error: Validator is not delinquent"),
+            StakePoolError::InvalidRateHistory=> msg!("Error: Rate history account does not match the stake pool"),
+            StakePoolError::InvalidStakePoolExtension=> msg!("Error: Stake pool extension account does not match the stake pool"),
+            StakePoolError::UnstakeAmountTooLow=> msg!("Error: Unstake amount is below the liquidity pool stSOL balance share of one ticket"),
         }
     }
 }
 
-fn to_u128(val: u64) -> Result<u128, StakePoolError> {
-    val.try_into().map_err(|_| StakePoolError::ConversionFailure)
-}
-
-#[allow(dead_code)]
-fn value_from_shares(shares: u64, total_value:u128, total_shares:u128) -> Option<u64> {
-    return proportional(shares, total_value,total_shares);
-}
-fn shares_from_value(value: u64, total_value:u128, total_shares:u128) -> Option<u64> {
-    return proportional(value, total_shares,total_value);
-}
-
 /// calculate amount*numerator/denominator
 /// as value  = shares * share_price where share_price=total_value/total_shares
 /// or shares = amount_value / share_price where share_price=total_value/total_shares 
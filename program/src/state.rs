@@ -2,23 +2,28 @@
 
 use crate::error::StakePoolError;
 use crate::instruction::Fee;
-use crate::processor::Processor;
+use crate::processor::{proportional, Processor};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use borsh::{BorshDeserialize, BorshSerialize};
 use core::convert::TryInto;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    account_info::AccountInfo, clock::Slot, entrypoint::ProgramResult, program_error::ProgramError,
     pubkey::Pubkey,
 };
 use std::convert::TryFrom;
 use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
 
 /// Initialized program details.
-#[repr(C)]
+/// Serialized as little-endian fields in declaration order, see `StakePool::serialize`:
+/// the fields up to `fee` in the stake pool account, sized like a v1 account, the
+/// fields added since v1 in the stake pool extension account
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct StakePool {
     /// Pool version
     pub version: u8,
     /// Owner authority
-    /// allows for updating the staking authority
+    /// allows for updating the staking authority and assigns the other roles
     pub owner: Pubkey,
     /// Deposit authority bump seed
     /// for `create_program_address(&[state::StakePool account, "deposit"])`
@@ -34,6 +39,9 @@ pub struct StakePool {
     pub owner_fee_account: Pubkey,
     /// Pool token program id
     pub token_program_id: Pubkey,
+    /// Extension account bump seed
+    /// for `create_program_address(&[state::StakePool account, "extension"])`
+    pub extension_bump_seed: u8,
     /// total stake under management (lamports)
     pub stake_total: u64,
     /// total pool (in tokens)
@@ -42,11 +50,101 @@ pub struct StakePool {
     pub last_update_epoch: u64,
     /// Fee applied to deposits
     pub fee: Fee,
+    /// Reserve stake account bump seed
+    /// for `create_program_address(&[state::StakePool account, "reserve"])`
+    pub reserve_bump_seed: u8,
+    /// Fee taken from epoch staking rewards, minted to the owner fee account
+    pub reward_fee: Fee,
+    /// Deposit fee set by `SetFee`, replaces `fee` at `next_fee_epoch`
+    pub next_fee: Fee,
+    /// Epoch `next_fee` takes effect, 0 when no fee change is pending
+    pub next_fee_epoch: u64,
+    /// Fee applied to withdrawals, in pool tokens
+    pub withdrawal_fee: Fee,
+    /// 0: the withdrawal fee tokens are burned without taking stake, raising the value
+    /// of the remaining pool tokens. Otherwise they are transferred to the owner fee account
+    pub withdrawal_fee_to_owner: u8,
+    /// Epoch of the last `Rebalance`
+    pub rebalance_epoch: u64,
+    /// Lamports moved by `Rebalance` during `rebalance_epoch`
+    pub rebalanced_lamports: u64,
+    /// Sum of the validators target weights, over all validator stake list pages
+    pub total_target_weight: u64,
+    /// Number of pages used in `validator_stake_list_pages`
+    pub validator_stake_list_pages_len: u8,
+    /// Validator stake list pages added by `AddValidatorStakeListPage`,
+    /// following `validator_stake_list`
+    pub validator_stake_list_pages: [Pubkey; MAX_VALIDATOR_STAKE_LIST_PAGES - 1],
+    /// Staker authority, manages the validators and their stake
+    pub staker: Pubkey,
+    /// Fee manager authority, sets the fees and the owner fee account
+    pub fee_manager: Pubkey,
+    /// Owner set by `SetOwner`, becomes the owner once it signs `AcceptOwner`.
+    /// Default when no transfer is pending
+    pub pending_owner: Pubkey,
+    /// Deposits must be signed by this authority, or by a depositor of the pool
+    /// `DepositorList`. Default when anyone can deposit
+    pub deposit_authority: Pubkey,
+    /// Slots without a vote after which anyone can deactivate a validator with
+    /// `DeactivateDelinquentValidator`, 0 for `DEFAULT_DELINQUENT_SLOTS`
+    pub delinquent_slots: u64,
+    /// Rate history account bump seed
+    /// for `create_program_address(&[state::StakePool account, "history"])`,
+    /// 0 until `CreateRateHistory`
+    pub rate_history_bump_seed: u8,
+}
+
+/// Length of the encoded StakePool extension fields, version included
+const STAKE_POOL_EXTENSION_PACKED_LEN: usize = 253;
+
+/// Authorities of a stake pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// `StakePool::owner`
+    Owner,
+    /// `StakePool::pending_owner`
+    PendingOwner,
+    /// `StakePool::staker`
+    Staker,
+    /// `StakePool::fee_manager`
+    FeeManager,
+}
+
+/// Stake move computed for one validator by `Rebalance`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RebalanceMove {
+    /// The validator is at its target, or the epoch limit is reached
+    None,
+    /// Move lamports from the reserve to the validator
+    Increase(u64),
+    /// Move lamports from the validator to the reserve
+    Decrease(u64),
 }
 
 impl StakePool {
-    /// Length of state data when serialized
-    pub const LEN: usize = size_of::<StakePool>();
+    /// Length of the stake pool account, the length of a v1 account: the v1 `#[repr(C)]`
+    /// struct was copied as is into the account
+    pub const LEN: usize = 208;
+    /// Length of the stake pool extension account: the encoded fields added since v1,
+    /// then space reserved for new fields
+    pub const EXTENSION_LEN: usize = 720;
+    /// v1 layout, without extension. `MigrateState` upgrades it in place
+    pub const STAKE_POOL_VERSION_V1: u8 = 1;
+    /// Current layout, written by `serialize`
+    pub const STAKE_POOL_VERSION: u8 = 2;
+    /// Current extension layout
+    pub const STAKE_POOL_EXTENSION_VERSION: u8 = 1;
+    /// Epochs between `SetFee` and the new fee taking effect
+    pub const FEE_CHANGE_EPOCHS: u64 = 2;
+    /// Max stake `Rebalance` can move per epoch, in basis points of `stake_total`
+    pub const MAX_REBALANCE_BPS_PER_EPOCH: u64 = 500;
+    /// Delinquency threshold of the pools not setting `delinquent_slots`, about one epoch
+    pub const DEFAULT_DELINQUENT_SLOTS: u64 = 432_000;
+
+    /// true if `SetFee` stored a fee not yet in effect
+    pub fn has_pending_fee(&self) -> bool {
+        self.next_fee_epoch != 0
+    }
 
     /// calculate the pool tokens that should be minted
     /// based on lamports deposited
@@ -91,6 +189,110 @@ impl StakePool {
         )
         .ok()
     }
+    /// calculate the withdrawal fee in pool tokens, withdrawal_fee% of pool_amount
+    pub fn calc_withdrawal_fee_amount(&self, pool_amount: u64) -> Option<u64> {
+        if self.withdrawal_fee.denominator == 0 {
+            return Some(0);
+        }
+        u64::try_from(
+            (pool_amount as u128)
+                .checked_mul(self.withdrawal_fee.numerator as u128)?
+                .checked_div(self.withdrawal_fee.denominator as u128)?,
+        )
+        .ok()
+    }
+    /// true if the withdrawal fee goes to the owner fee account instead of staying in the pool
+    pub fn pays_withdrawal_fee_to_owner(&self) -> bool {
+        self.withdrawal_fee_to_owner != 0
+    }
+    /// calculate the pool tokens to mint to the owner for `reward_lamports` of rewards.
+    /// fee_lamports = reward*numerator/denominator and the tokens are priced at the rate
+    /// that results after minting them: fee_lamports * pool_total / (stake_total - fee_lamports).
+    /// `stake_total` must already include the rewards
+    pub fn calc_reward_fee_amount(&self, reward_lamports: u64) -> Option<u64> {
+        if self.reward_fee.denominator == 0 || self.pool_total == 0 {
+            return Some(0);
+        }
+        let fee_lamports = (reward_lamports as u128)
+            .checked_mul(self.reward_fee.numerator as u128)?
+            .checked_div(self.reward_fee.denominator as u128)?;
+        if fee_lamports == 0 {
+            return Some(0);
+        }
+        u64::try_from(
+            fee_lamports
+                .checked_mul(self.pool_total as u128)?
+                .checked_div((self.stake_total as u128).checked_sub(fee_lamports)?)?,
+        )
+        .ok()
+    }
+    /// lamports `Rebalance` can still move during `epoch`
+    pub fn calc_rebalance_lamports_left(&self, epoch: u64) -> Option<u64> {
+        let epoch_limit = u64::try_from(
+            (self.stake_total as u128)
+                .checked_mul(Self::MAX_REBALANCE_BPS_PER_EPOCH as u128)?
+                .checked_div(10_000)?,
+        )
+        .ok()?;
+        let used = if self.rebalance_epoch == epoch {
+            self.rebalanced_lamports
+        } else {
+            0
+        };
+        Some(epoch_limit.saturating_sub(used))
+    }
+    /// calculate the move that brings `validator` towards its share of `stake_total`:
+    /// stake_total * target_weight / total_weight, bounded by the epoch limit
+    pub fn calc_rebalance_move(
+        &self,
+        validator: &ValidatorStakeInfo,
+        total_weight: u64,
+        epoch: u64,
+    ) -> Option<RebalanceMove> {
+        if total_weight == 0 {
+            return Some(RebalanceMove::None);
+        }
+        let target = u64::try_from(
+            (self.stake_total as u128)
+                .checked_mul(validator.target_weight as u128)?
+                .checked_div(total_weight as u128)?,
+        )
+        .ok()?;
+        let lamports_left = self.calc_rebalance_lamports_left(epoch)?;
+        let current = validator.total_balance()?;
+        Some(if current < target {
+            match (target - current).min(lamports_left) {
+                0 => RebalanceMove::None,
+                lamports => RebalanceMove::Increase(lamports),
+            }
+        } else {
+            match (current - target).min(lamports_left) {
+                0 => RebalanceMove::None,
+                lamports => RebalanceMove::Decrease(lamports),
+            }
+        })
+    }
+
+    /// Validator stake list accounts of the pool, `validator_stake_list` first
+    pub fn validator_stake_list_pages(&self) -> impl Iterator<Item = &Pubkey> {
+        std::iter::once(&self.validator_stake_list).chain(
+            self.validator_stake_list_pages
+                .iter()
+                .take(self.validator_stake_list_pages_len as usize),
+        )
+    }
+
+    /// Checks the account is one of the pool validator stake list pages
+    pub fn check_validator_stake_list(&self, validator_stake_list: &Pubkey) -> ProgramResult {
+        if self
+            .validator_stake_list_pages()
+            .any(|page| page == validator_stake_list)
+        {
+            Ok(())
+        } else {
+            Err(StakePoolError::InvalidValidatorStakeList.into())
+        }
+    }
 
     /// Checks withdraw authority
     pub fn check_authority_withdraw(
@@ -123,51 +325,697 @@ impl StakePool {
         )
     }
 
-    /// Check owner validity and signature
-    pub fn check_owner(&self, owner_info: &AccountInfo) -> Result<(), ProgramError> {
-        if *owner_info.key != self.owner {
-            return Err(StakePoolError::WrongOwner.into());
+    /// Checks the extension account address
+    pub fn check_extension(
+        &self,
+        extension_to_check: &Pubkey,
+        program_id: &Pubkey,
+        stake_pool_key: &Pubkey,
+    ) -> Result<(), ProgramError> {
+        Processor::check_authority(
+            extension_to_check,
+            program_id,
+            stake_pool_key,
+            Processor::EXTENSION,
+            self.extension_bump_seed,
+        )
+        .map_err(|_| StakePoolError::InvalidStakePoolExtension.into())
+    }
+
+    /// Checks the reserve stake account address
+    pub fn check_reserve_stake(
+        &self,
+        reserve_to_check: &Pubkey,
+        program_id: &Pubkey,
+        stake_pool_key: &Pubkey,
+    ) -> Result<(), ProgramError> {
+        Processor::check_authority(
+            reserve_to_check,
+            program_id,
+            stake_pool_key,
+            Processor::RESERVE,
+            self.reserve_bump_seed,
+        )
+    }
+
+    /// Authority holding `role`
+    pub fn role_key(&self, role: Role) -> &Pubkey {
+        match role {
+            Role::Owner => &self.owner,
+            Role::PendingOwner => &self.pending_owner,
+            Role::Staker => &self.staker,
+            Role::FeeManager => &self.fee_manager,
         }
-        if !owner_info.is_signer {
+    }
+
+    /// Check the account holds `role` and signed
+    pub fn check_role(&self, role: Role, authority_info: &AccountInfo) -> ProgramResult {
+        if authority_info.key != self.role_key(role) {
+            return Err(match role {
+                Role::Owner => StakePoolError::WrongOwner,
+                Role::PendingOwner => StakePoolError::WrongPendingOwner,
+                Role::Staker => StakePoolError::WrongStaker,
+                Role::FeeManager => StakePoolError::WrongFeeManager,
+            }
+            .into());
+        }
+        if !authority_info.is_signer {
             return Err(StakePoolError::SignatureMissing.into());
         }
         Ok(())
     }
 
+    /// Check the account holds `role` or is the owner, and signed
+    pub fn check_role_or_owner(&self, role: Role, authority_info: &AccountInfo) -> ProgramResult {
+        if *authority_info.key == self.owner {
+            self.check_role(Role::Owner, authority_info)
+        } else {
+            self.check_role(role, authority_info)
+        }
+    }
+
+    /// Check owner validity and signature
+    pub fn check_owner(&self, owner_info: &AccountInfo) -> ProgramResult {
+        self.check_role(Role::Owner, owner_info)
+    }
+
+    /// Check staker validity and signature
+    pub fn check_staker(&self, staker_info: &AccountInfo) -> ProgramResult {
+        self.check_role(Role::Staker, staker_info)
+    }
+
+    /// Check fee manager validity and signature
+    pub fn check_fee_manager(&self, fee_manager_info: &AccountInfo) -> ProgramResult {
+        self.check_role(Role::FeeManager, fee_manager_info)
+    }
+
+    /// true if deposits are restricted to the deposit authority and the depositor list
+    pub fn has_deposit_authority(&self) -> bool {
+        self.deposit_authority != Pubkey::default()
+    }
+
+    /// Slots without a vote after which a validator is delinquent
+    pub fn delinquent_slots_threshold(&self) -> u64 {
+        if self.delinquent_slots == 0 {
+            Self::DEFAULT_DELINQUENT_SLOTS
+        } else {
+            self.delinquent_slots
+        }
+    }
+
+    /// true if a validator last voting at `last_voted_slot` is delinquent at `slot`.
+    /// A validator that never voted is delinquent
+    pub fn is_delinquent(&self, last_voted_slot: Option<Slot>, slot: Slot) -> bool {
+        match last_voted_slot {
+            Some(last_voted_slot) => {
+                slot.saturating_sub(last_voted_slot) > self.delinquent_slots_threshold()
+            }
+            None => true,
+        }
+    }
+
+    /// true once `CreateRateHistory` linked a rate history account to the pool
+    pub fn has_rate_history(&self) -> bool {
+        self.rate_history_bump_seed != 0
+    }
+
     /// Check if StakePool is initialized
     pub fn is_initialized(&self) -> bool {
         self.version > 0
     }
 
-    /// Deserializes a byte buffer into a [StakePool](struct.StakePool.html).
-    pub fn deserialize(input: &[u8]) -> Result<StakePool, ProgramError> {
-        if input.len() < size_of::<StakePool>() {
+    /// Deserializes the stake pool account and its extension into a
+    /// [StakePool](struct.StakePool.html). Uninitialized accounts are read as version 0
+    /// without reading the extension, v1 accounts need `MigrateState`
+    pub fn deserialize(input: &[u8], extension: &[u8]) -> Result<StakePool, ProgramError> {
+        let mut stake_pool = Self::deserialize_record(input)?;
+        if stake_pool.is_initialized() {
+            stake_pool.deserialize_extension(extension)?;
+        }
+        Ok(stake_pool)
+    }
+
+    /// Deserializes the stake pool account alone, the fields added since v1 are left
+    /// cleared until `deserialize_extension` reads them
+    pub fn deserialize_record(input: &[u8]) -> Result<StakePool, ProgramError> {
+        if input.first() == Some(&Self::STAKE_POOL_VERSION_V1) {
+            return Err(StakePoolError::StateNeedsMigration.into());
+        }
+        if input.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        match input[0] {
+            0 => Ok(StakePool::default()),
+            Self::STAKE_POOL_VERSION => Ok(Self::unpack(array_ref![input, 0, StakePool::LEN])),
+            _ => Err(StakePoolError::InvalidStateVersion.into()),
+        }
+    }
+
+    /// Deserializes a v1 account, used by `MigrateState`. The version is kept.
+    /// The fields added since v1 are cleared and the roles added since v1 are given to the owner
+    pub fn deserialize_v1(input: &[u8]) -> Result<StakePool, ProgramError> {
+        if input.first() != Some(&Self::STAKE_POOL_VERSION_V1) {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+        if input.len() != Self::LEN {
             return Err(ProgramError::InvalidAccountData);
         }
+        let stake_pool = Self::unpack(array_ref![input, 0, StakePool::LEN]);
+        // the extension bump seed is in the v1 struct padding
+        Ok(StakePool {
+            extension_bump_seed: 0,
+            staker: stake_pool.owner,
+            fee_manager: stake_pool.owner,
+            ..stake_pool
+        })
+    }
 
-        let stake_pool: &StakePool = unsafe { &*(&input[0] as *const u8 as *const StakePool) };
+    /// Reads the fields added since v1 from the extension account
+    pub fn deserialize_extension(&mut self, input: &[u8]) -> ProgramResult {
+        if input.len() < Self::EXTENSION_LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let input = array_ref![input, 0, STAKE_POOL_EXTENSION_PACKED_LEN];
+        #[rustfmt::skip]
+        let (
+            version, reserve_bump_seed, reward_fee, next_fee, next_fee_epoch, withdrawal_fee,
+            withdrawal_fee_to_owner, rebalance_epoch, rebalanced_lamports, total_target_weight,
+            validator_stake_list_pages_len, validator_stake_list_pages, staker, fee_manager,
+            pending_owner, deposit_authority, delinquent_slots, rate_history_bump_seed,
+        ) = array_refs![
+            input, 1, 1, 16, 16, 8, 16, 1, 8, 8, 8, 1,
+            32 * (MAX_VALIDATOR_STAKE_LIST_PAGES - 1), 32, 32, 32, 32, 8, 1
+        ];
+        if version[0] != Self::STAKE_POOL_EXTENSION_VERSION {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+        let mut pages = [Pubkey::default(); MAX_VALIDATOR_STAKE_LIST_PAGES - 1];
+        for (index, page) in pages.iter_mut().enumerate() {
+            *page = Pubkey::new_from_array(*array_ref![validator_stake_list_pages, 32 * index, 32]);
+        }
+        self.reserve_bump_seed = reserve_bump_seed[0];
+        self.reward_fee = Fee::unpack(reward_fee);
+        self.next_fee = Fee::unpack(next_fee);
+        self.next_fee_epoch = u64::from_le_bytes(*next_fee_epoch);
+        self.withdrawal_fee = Fee::unpack(withdrawal_fee);
+        self.withdrawal_fee_to_owner = withdrawal_fee_to_owner[0];
+        self.rebalance_epoch = u64::from_le_bytes(*rebalance_epoch);
+        self.rebalanced_lamports = u64::from_le_bytes(*rebalanced_lamports);
+        self.total_target_weight = u64::from_le_bytes(*total_target_weight);
+        self.validator_stake_list_pages_len = validator_stake_list_pages_len[0];
+        self.validator_stake_list_pages = pages;
+        self.staker = Pubkey::new_from_array(*staker);
+        self.fee_manager = Pubkey::new_from_array(*fee_manager);
+        self.pending_owner = Pubkey::new_from_array(*pending_owner);
+        self.deposit_authority = Pubkey::new_from_array(*deposit_authority);
+        self.delinquent_slots = u64::from_le_bytes(*delinquent_slots);
+        self.rate_history_bump_seed = rate_history_bump_seed[0];
+        Ok(())
+    }
 
-        Ok(*stake_pool)
+    /// The fields of the stake pool account, at the offsets of the v1 `#[repr(C)]` struct:
+    /// the u64 fields are aligned after the pubkeys
+    fn unpack(input: &[u8; StakePool::LEN]) -> StakePool {
+        #[rustfmt::skip]
+        let (
+            version, owner, deposit_bump_seed, withdraw_bump_seed, validator_stake_list,
+            pool_mint, owner_fee_account, token_program_id, extension_bump_seed, _reserved,
+            stake_total, pool_total, last_update_epoch, fee,
+        ) = array_refs![input, 1, 32, 1, 1, 32, 32, 32, 32, 1, 4, 8, 8, 8, 16];
+        StakePool {
+            version: version[0],
+            owner: Pubkey::new_from_array(*owner),
+            deposit_bump_seed: deposit_bump_seed[0],
+            withdraw_bump_seed: withdraw_bump_seed[0],
+            validator_stake_list: Pubkey::new_from_array(*validator_stake_list),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            owner_fee_account: Pubkey::new_from_array(*owner_fee_account),
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            extension_bump_seed: extension_bump_seed[0],
+            stake_total: u64::from_le_bytes(*stake_total),
+            pool_total: u64::from_le_bytes(*pool_total),
+            last_update_epoch: u64::from_le_bytes(*last_update_epoch),
+            fee: Fee::unpack(fee),
+            ..StakePool::default()
+        }
     }
 
-    /// Serializes [StakePool](struct.StakePool.html) into a byte buffer.
-    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
-        if output.len() < size_of::<StakePool>() {
+    /// Serializes [StakePool](struct.StakePool.html) into the stake pool account and its
+    /// extension. The reserved space is zeroed
+    pub fn serialize(&self, output: &mut [u8], extension: &mut [u8]) -> ProgramResult {
+        if output.len() < Self::LEN || extension.len() < Self::EXTENSION_LEN {
             return Err(ProgramError::InvalidAccountData);
         }
-        #[allow(clippy::cast_ptr_alignment)]
-        //creates a pointer to the start of the buffer with the "shape" of the StakePool
-        let value = unsafe { &mut *(&mut output[0] as *mut u8 as *mut StakePool) };
-        //copies this struct flat memory into the buffer
-        *value = *self;
+        let output = array_mut_ref![output, 0, StakePool::LEN];
+        #[rustfmt::skip]
+        let (
+            version, owner, deposit_bump_seed, withdraw_bump_seed, validator_stake_list,
+            pool_mint, owner_fee_account, token_program_id, extension_bump_seed, reserved,
+            stake_total, pool_total, last_update_epoch, fee,
+        ) = mut_array_refs![output, 1, 32, 1, 1, 32, 32, 32, 32, 1, 4, 8, 8, 8, 16];
+        version[0] = self.version;
+        owner.copy_from_slice(self.owner.as_ref());
+        deposit_bump_seed[0] = self.deposit_bump_seed;
+        withdraw_bump_seed[0] = self.withdraw_bump_seed;
+        validator_stake_list.copy_from_slice(self.validator_stake_list.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        owner_fee_account.copy_from_slice(self.owner_fee_account.as_ref());
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        extension_bump_seed[0] = self.extension_bump_seed;
+        *reserved = [0; 4];
+        *stake_total = self.stake_total.to_le_bytes();
+        *pool_total = self.pool_total.to_le_bytes();
+        *last_update_epoch = self.last_update_epoch.to_le_bytes();
+        self.fee.pack(fee);
+
+        let extension = array_mut_ref![extension, 0, StakePool::EXTENSION_LEN];
+        #[rustfmt::skip]
+        let (
+            version, reserve_bump_seed, reward_fee, next_fee, next_fee_epoch, withdrawal_fee,
+            withdrawal_fee_to_owner, rebalance_epoch, rebalanced_lamports, total_target_weight,
+            validator_stake_list_pages_len, validator_stake_list_pages, staker, fee_manager,
+            pending_owner, deposit_authority, delinquent_slots, rate_history_bump_seed, reserved,
+        ) = mut_array_refs![
+            extension, 1, 1, 16, 16, 8, 16, 1, 8, 8, 8, 1,
+            32 * (MAX_VALIDATOR_STAKE_LIST_PAGES - 1), 32, 32, 32, 32, 8, 1,
+            StakePool::EXTENSION_LEN - STAKE_POOL_EXTENSION_PACKED_LEN
+        ];
+        version[0] = Self::STAKE_POOL_EXTENSION_VERSION;
+        reserve_bump_seed[0] = self.reserve_bump_seed;
+        self.reward_fee.pack(reward_fee);
+        self.next_fee.pack(next_fee);
+        *next_fee_epoch = self.next_fee_epoch.to_le_bytes();
+        self.withdrawal_fee.pack(withdrawal_fee);
+        withdrawal_fee_to_owner[0] = self.withdrawal_fee_to_owner;
+        *rebalance_epoch = self.rebalance_epoch.to_le_bytes();
+        *rebalanced_lamports = self.rebalanced_lamports.to_le_bytes();
+        *total_target_weight = self.total_target_weight.to_le_bytes();
+        validator_stake_list_pages_len[0] = self.validator_stake_list_pages_len;
+        for (output, page) in validator_stake_list_pages
+            .chunks_mut(32)
+            .zip(self.validator_stake_list_pages.iter())
+        {
+            output.copy_from_slice(page.as_ref());
+        }
+        staker.copy_from_slice(self.staker.as_ref());
+        fee_manager.copy_from_slice(self.fee_manager.as_ref());
+        pending_owner.copy_from_slice(self.pending_owner.as_ref());
+        deposit_authority.copy_from_slice(self.deposit_authority.as_ref());
+        *delinquent_slots = self.delinquent_slots.to_le_bytes();
+        rate_history_bump_seed[0] = self.rate_history_bump_seed;
+        *reserved = [0; StakePool::EXTENSION_LEN - STAKE_POOL_EXTENSION_PACKED_LEN];
+        Ok(())
+    }
+}
+
+impl Fee {
+    fn unpack(input: &[u8; 16]) -> Fee {
+        let (denominator, numerator) = array_refs![input, 8, 8];
+        Fee {
+            denominator: u64::from_le_bytes(*denominator),
+            numerator: u64::from_le_bytes(*numerator),
+        }
+    }
+
+    fn pack(&self, output: &mut [u8; 16]) {
+        let (denominator, numerator) = mut_array_refs![output, 8, 8];
+        *denominator = self.denominator.to_le_bytes();
+        *numerator = self.numerator.to_le_bytes();
+    }
+}
+
+/// Liquidity-dependent fee charged when selling stSOL into the liquidity pool.
+/// The fee goes from `min_fee_bps` (wSOL leg at or above `target_liquidity`)
+/// up to `max_fee_bps` (wSOL leg empty), linearly on the post-trade wSOL balance
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct SellFeeCurve {
+    /// fee in basis points when liquidity is at or above the target
+    pub min_fee_bps: u64,
+    /// fee in basis points when the wSOL leg is empty
+    pub max_fee_bps: u64,
+    /// wSOL lamports in the pool above which the min fee applies
+    pub target_liquidity: u64,
+}
+
+impl SellFeeCurve {
+    /// Basis points in 100%
+    pub const BPS_DENOMINATOR: u64 = 10_000;
+
+    /// Check min_fee <= max_fee <= 100%
+    pub fn is_valid(&self) -> bool {
+        self.min_fee_bps <= self.max_fee_bps && self.max_fee_bps <= Self::BPS_DENOMINATOR
+    }
+
+    /// calculate the fee in basis points for a wSOL balance left in the pool after the trade
+    /// as fee = max - (max - min) * wsol_after / target, or min if wsol_after >= target
+    pub fn calc_sell_fee_bps(&self, wsol_balance_after: u64) -> Option<u64> {
+        if self.target_liquidity == 0 || wsol_balance_after >= self.target_liquidity {
+            return Some(self.min_fee_bps);
+        }
+        let delta = self.max_fee_bps.checked_sub(self.min_fee_bps)?;
+        let discount = proportional(
+            delta,
+            wsol_balance_after as u128,
+            self.target_liquidity as u128,
+        )?;
+        self.max_fee_bps.checked_sub(discount)
+    }
+
+    /// calculate the fee in lamports for selling stSOL worth `sol_value` to a pool holding `wsol_balance`.
+    /// The post-trade balance is taken before the fee (wsol_balance - sol_value), so the quote never undercharges
+    pub fn calc_sell_fee(&self, sol_value: u64, wsol_balance: u64) -> Option<u64> {
+        let fee_bps = self.calc_sell_fee_bps(wsol_balance.saturating_sub(sol_value))?;
+        proportional(sol_value, fee_bps as u128, Self::BPS_DENOMINATOR as u128)
+    }
+
+    fn unpack(input: &[u8; 24]) -> Self {
+        let (min_fee_bps, max_fee_bps, target_liquidity) = array_refs![input, 8, 8, 8];
+        SellFeeCurve {
+            min_fee_bps: u64::from_le_bytes(*min_fee_bps),
+            max_fee_bps: u64::from_le_bytes(*max_fee_bps),
+            target_liquidity: u64::from_le_bytes(*target_liquidity),
+        }
+    }
+
+    fn pack(&self, output: &mut [u8; 24]) {
+        let (min_fee_bps, max_fee_bps, target_liquidity) = mut_array_refs![output, 8, 8, 8];
+        *min_fee_bps = self.min_fee_bps.to_le_bytes();
+        *max_fee_bps = self.max_fee_bps.to_le_bytes();
+        *target_liquidity = self.target_liquidity.to_le_bytes();
+    }
+}
+
+/// Stake being unstaked from the liquidity pool stSOL leg, claimed back as wSOL after cooldown
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UnstakeTicket {
+    /// lamports the burned stSOL was worth, 0 if the ticket is free
+    pub lamports: u64,
+    /// lamports paid for the ticket stake account rent, refunded on claim
+    pub rent_lamports: u64,
+    /// account that paid the rent
+    pub rent_payer: Pubkey,
+    /// epoch the stake was deactivated in
+    pub epoch: u64,
+    /// bump seed for `create_program_address(&[state::LiquidityPool account, "unstake", ticket index])`
+    pub stake_bump_seed: u8,
+}
+
+/// Length of an encoded UnstakeTicket
+const UNSTAKE_TICKET_LEN: usize = 57;
+
+impl UnstakeTicket {
+    /// Check if the ticket holds an unstake waiting to be claimed
+    pub fn is_pending(&self) -> bool {
+        self.lamports > 0
+    }
+
+    fn unpack(input: &[u8; UNSTAKE_TICKET_LEN]) -> Self {
+        let (lamports, rent_lamports, rent_payer, epoch, stake_bump_seed) =
+            array_refs![input, 8, 8, 32, 8, 1];
+        UnstakeTicket {
+            lamports: u64::from_le_bytes(*lamports),
+            rent_lamports: u64::from_le_bytes(*rent_lamports),
+            rent_payer: Pubkey::new_from_array(*rent_payer),
+            epoch: u64::from_le_bytes(*epoch),
+            stake_bump_seed: stake_bump_seed[0],
+        }
+    }
+
+    fn pack(&self, output: &mut [u8; UNSTAKE_TICKET_LEN]) {
+        let (lamports, rent_lamports, rent_payer, epoch, stake_bump_seed) =
+            mut_array_refs![output, 8, 8, 32, 8, 1];
+        *lamports = self.lamports.to_le_bytes();
+        *rent_lamports = self.rent_lamports.to_le_bytes();
+        rent_payer.copy_from_slice(self.rent_payer.as_ref());
+        *epoch = self.epoch.to_le_bytes();
+        stake_bump_seed[0] = self.stake_bump_seed;
+    }
+}
+
+/// Length of the encoded LiquidityPool fields
+const LIQUIDITY_POOL_PACKED_LEN: usize =
+    202 + UNSTAKE_TICKET_LEN * LiquidityPool::MAX_UNSTAKE_TICKETS;
+
+/// wSOL/stSOL liquidity pool details.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LiquidityPool {
+    /// Liquidity pool version
+    pub version: u8,
+    /// Liq pool authority bump seed
+    /// for `create_program_address(&[state::LiquidityPool account, "authority"])`
+    pub authority_bump_seed: u8,
+    /// Stake pool whose stSOL this liquidity pool trades
+    pub stake_pool: Pubkey,
+    /// wSOL leg, spl-token account owned by the liq pool authority
+    pub wsol_account: Pubkey,
+    /// stSOL leg, spl-token account owned by the liq pool authority
+    pub stsol_account: Pubkey,
+    /// $METALP mint, minted to liquidity providers
+    pub metalp_mint: Pubkey,
+    /// Fee curve applied when selling stSOL, the fee stays in the pool
+    /// except for the treasury share
+    pub sell_fee: SellFeeCurve,
+    /// wSOL account receiving the treasury share of the sell fee
+    pub treasury_wsol_account: Pubkey,
+    /// share of the sell fee sent to the treasury, in basis points
+    pub treasury_share_bps: u64,
+    /// Fee applied when buying stSOL, in basis points, stays in the pool
+    pub buy_fee_bps: u64,
+    /// stSOL unstaked by the crank and not claimed yet, counted as wSOL in the pool value
+    pub unstake_tickets: [UnstakeTicket; LiquidityPool::MAX_UNSTAKE_TICKETS],
+}
+
+impl LiquidityPool {
+    /// Length of state data when serialized: the encoded fields, then space reserved
+    /// for new fields
+    pub const LEN: usize = 720;
+
+    /// Current layout, written by `serialize`. Bumped on each layout change
+    pub const LIQUIDITY_POOL_VERSION: u8 = 1;
+
+    /// Buy fee set on initialization, 0.3%
+    pub const DEFAULT_BUY_FEE_BPS: u64 = 30;
+
+    /// Unstake tickets that can be pending at the same time
+    pub const MAX_UNSTAKE_TICKETS: usize = 8;
+
+    /// Checks liq pool authority
+    pub fn check_authority(
+        &self,
+        authority_to_check: &Pubkey,
+        program_id: &Pubkey,
+        liq_pool_key: &Pubkey,
+    ) -> Result<(), ProgramError> {
+        Processor::check_authority(
+            authority_to_check,
+            program_id,
+            liq_pool_key,
+            Processor::AUTHORITY,
+            self.authority_bump_seed,
+        )
+    }
+
+    /// Check if LiquidityPool is initialized
+    pub fn is_initialized(&self) -> bool {
+        self.version > 0
+    }
+
+    /// calculate the value in lamports of both legs,
+    /// wSOL at face value and stSOL at the stake pool rate (`calc_lamports_amount`)
+    pub fn calc_total_value(
+        wsol_balance: u64,
+        stsol_balance: u64,
+        stake_pool: &StakePool,
+    ) -> Option<u64> {
+        let stsol_value = if stsol_balance == 0 {
+            0
+        } else {
+            stake_pool.calc_lamports_amount(stsol_balance)?
+        };
+        wsol_balance.checked_add(stsol_value)
+    }
+
+    /// sum of the lamports of pending unstake tickets
+    pub fn calc_pending_unstake_lamports(&self) -> Option<u64> {
+        self.unstake_tickets
+            .iter()
+            .try_fold(0u64, |total, ticket| total.checked_add(ticket.lamports))
+    }
+
+    /// calculate the $METALP to mint for `wsol_amount` added to a pool worth `total_value` lamports
+    /// as shares = amount * metalp_supply / total_value.
+    /// The first provider gets 1:1 shares (anything already in the legs goes with them).
+    /// Shares of a pool worth nothing can not be priced
+    pub fn calc_metalp_amount(
+        wsol_amount: u64,
+        total_value: u64,
+        metalp_supply: u64,
+    ) -> Option<u64> {
+        if metalp_supply == 0 {
+            return Some(wsol_amount);
+        }
+        if total_value == 0 {
+            return None;
+        }
+        proportional(wsol_amount, metalp_supply as u128, total_value as u128)
+    }
+
+    /// calculate the (wSOL, stSOL) pro-rata share of both legs for `metalp_amount`,
+    /// using the $METALP supply before burning
+    pub fn calc_remove_amounts(
+        metalp_amount: u64,
+        wsol_balance: u64,
+        stsol_balance: u64,
+        metalp_supply: u64,
+    ) -> Option<(u64, u64)> {
+        if metalp_supply == 0 || metalp_amount > metalp_supply {
+            return None;
+        }
+        Some((
+            proportional(metalp_amount, wsol_balance as u128, metalp_supply as u128)?,
+            proportional(metalp_amount, stsol_balance as u128, metalp_supply as u128)?,
+        ))
+    }
+
+    /// calculate the part of a sell fee that goes to the treasury
+    /// as cut = fee * treasury_share_bps / 10_000
+    pub fn calc_treasury_cut(&self, fee_amount: u64) -> Option<u64> {
+        proportional(
+            fee_amount,
+            self.treasury_share_bps as u128,
+            SellFeeCurve::BPS_DENOMINATOR as u128,
+        )
+    }
+
+    /// calculate the (wSOL in, stSOL out) when buying stSOL with `wsol_amount`.
+    /// stSOL is priced at the stake pool rate after the buy fee, as out = (amount - fee) * pool_total / stake_total.
+    /// If the pool holds less stSOL than that, the output is capped at `stsol_balance`
+    /// and only the wSOL needed for it (rounded up) is taken
+    pub fn calc_buy(
+        &self,
+        wsol_amount: u64,
+        stsol_balance: u64,
+        stake_pool: &StakePool,
+    ) -> Option<(u64, u64)> {
+        let bps = SellFeeCurve::BPS_DENOMINATOR as u128;
+        let net_bps = bps.checked_sub(self.buy_fee_bps as u128)?;
+        if net_bps == 0 {
+            return None;
+        }
+        let fee = proportional(wsol_amount, self.buy_fee_bps as u128, bps)?;
+        let stsol_out = stake_pool.calc_pool_withdraw_amount(wsol_amount.checked_sub(fee)?)?;
+        if stsol_out <= stsol_balance {
+            return Some((wsol_amount, stsol_out));
+        }
+        // capped: wSOL in = ceil(ceil(value) * 10_000 / (10_000 - fee_bps))
+        let value = (stsol_balance as u128)
+            .checked_mul(stake_pool.stake_total as u128)?
+            .checked_add((stake_pool.pool_total as u128).checked_sub(1)?)?
+            .checked_div(stake_pool.pool_total as u128)?;
+        let wsol_in = value
+            .checked_mul(bps)?
+            .checked_add(net_bps - 1)?
+            .checked_div(net_bps)?;
+        Some((u64::try_from(wsol_in).ok()?.min(wsol_amount), stsol_balance))
+    }
+
+    /// Deserializes a byte buffer into a [LiquidityPool](struct.LiquidityPool.html).
+    /// Uninitialized accounts are read as version 0
+    pub fn deserialize(input: &[u8]) -> Result<LiquidityPool, ProgramError> {
+        match *input.first().ok_or(ProgramError::InvalidAccountData)? {
+            0 | Self::LIQUIDITY_POOL_VERSION => {
+                if input.len() < Self::LEN {
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                let packed = array_ref![input, 0, LIQUIDITY_POOL_PACKED_LEN];
+                Ok(Self::unpack(packed))
+            }
+            _ => Err(StakePoolError::InvalidStateVersion.into()),
+        }
+    }
+
+    fn unpack(input: &[u8; LIQUIDITY_POOL_PACKED_LEN]) -> LiquidityPool {
+        #[rustfmt::skip]
+        let (
+            version, authority_bump_seed, stake_pool, wsol_account, stsol_account, metalp_mint,
+            sell_fee, treasury_wsol_account, treasury_share_bps, buy_fee_bps, unstake_tickets,
+        ) = array_refs![
+            input, 1, 1, 32, 32, 32, 32, 24, 32, 8, 8,
+            UNSTAKE_TICKET_LEN * LiquidityPool::MAX_UNSTAKE_TICKETS
+        ];
+        let mut liq_pool = LiquidityPool {
+            version: version[0],
+            authority_bump_seed: authority_bump_seed[0],
+            stake_pool: Pubkey::new_from_array(*stake_pool),
+            wsol_account: Pubkey::new_from_array(*wsol_account),
+            stsol_account: Pubkey::new_from_array(*stsol_account),
+            metalp_mint: Pubkey::new_from_array(*metalp_mint),
+            sell_fee: SellFeeCurve::unpack(sell_fee),
+            treasury_wsol_account: Pubkey::new_from_array(*treasury_wsol_account),
+            treasury_share_bps: u64::from_le_bytes(*treasury_share_bps),
+            buy_fee_bps: u64::from_le_bytes(*buy_fee_bps),
+            ..LiquidityPool::default()
+        };
+        for (ticket, input) in liq_pool
+            .unstake_tickets
+            .iter_mut()
+            .zip(unstake_tickets.chunks(UNSTAKE_TICKET_LEN))
+        {
+            *ticket = UnstakeTicket::unpack(array_ref![input, 0, UNSTAKE_TICKET_LEN]);
+        }
+        liq_pool
+    }
 
+    /// Serializes [LiquidityPool](struct.LiquidityPool.html) into a byte buffer.
+    /// The reserved space is zeroed
+    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
+        if output.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let output = array_mut_ref![output, 0, LiquidityPool::LEN];
+        #[rustfmt::skip]
+        let (
+            version, authority_bump_seed, stake_pool, wsol_account, stsol_account, metalp_mint,
+            sell_fee, treasury_wsol_account, treasury_share_bps, buy_fee_bps, unstake_tickets,
+            reserved,
+        ) = mut_array_refs![
+            output, 1, 1, 32, 32, 32, 32, 24, 32, 8, 8,
+            UNSTAKE_TICKET_LEN * LiquidityPool::MAX_UNSTAKE_TICKETS,
+            LiquidityPool::LEN - LIQUIDITY_POOL_PACKED_LEN
+        ];
+        version[0] = self.version;
+        authority_bump_seed[0] = self.authority_bump_seed;
+        stake_pool.copy_from_slice(self.stake_pool.as_ref());
+        wsol_account.copy_from_slice(self.wsol_account.as_ref());
+        stsol_account.copy_from_slice(self.stsol_account.as_ref());
+        metalp_mint.copy_from_slice(self.metalp_mint.as_ref());
+        self.sell_fee.pack(sell_fee);
+        treasury_wsol_account.copy_from_slice(self.treasury_wsol_account.as_ref());
+        *treasury_share_bps = self.treasury_share_bps.to_le_bytes();
+        *buy_fee_bps = self.buy_fee_bps.to_le_bytes();
+        for (output, ticket) in unstake_tickets
+            .chunks_mut(UNSTAKE_TICKET_LEN)
+            .zip(self.unstake_tickets.iter())
+        {
+            ticket.pack(array_mut_ref![output, 0, UNSTAKE_TICKET_LEN]);
+        }
+        *reserved = [0; LiquidityPool::LEN - LIQUIDITY_POOL_PACKED_LEN];
         Ok(())
     }
 }
 
-const MAX_VALIDATOR_STAKE_ACCOUNTS: usize = 1000;
+/// Max validators in one validator stake list page. A v1 list upgraded in place by
+/// `MigrateState` holds fewer, see `ValidatorStakeList::capacity`
+pub const MAX_VALIDATOR_STAKE_ACCOUNTS: usize = 1000;
+/// Max validator stake list pages of a pool, `StakePool::validator_stake_list` included,
+/// so a pool holds up to 2000 validators. `UpdatePoolBalance` reads every entry of every
+/// page in one instruction, the limit keeps it under the compute budget, see `huge_pool` tests.
+/// The page keys are stored in the stake pool extension
+pub const MAX_VALIDATOR_STAKE_LIST_PAGES: usize = 2;
 
-/// Storage list for all validator stake accounts in the pool.
+/// Storage list for validator stake accounts in the pool, one page of them.
+/// A pool links up to `MAX_VALIDATOR_STAKE_LIST_PAGES` of these accounts
 #[repr(C)]
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct ValidatorStakeList {
@@ -177,8 +1025,8 @@ pub struct ValidatorStakeList {
     pub validators: Vec<ValidatorStakeInfo>,
 }
 
-/// Information about the singe validator stake account
-#[repr(C)]
+/// Information about the singe validator stake account.
+/// Serialized as little-endian fields in declaration order
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct ValidatorStakeInfo {
     /// Validator account pubkey
@@ -189,6 +1037,18 @@ pub struct ValidatorStakeInfo {
 
     /// Last epoch balance field was updated
     pub last_update_epoch: u64,
+
+    /// Lamports in the validator transient stake account, being activated by
+    /// `IncreaseValidatorStake` or deactivated by `DecreaseValidatorStake`
+    pub transient_balance: u64,
+
+    /// Share of the pool stake `Rebalance` targets for this validator,
+    /// relative to the sum of all weights. Set by `SetValidatorWeights`
+    pub target_weight: u64,
+
+    /// Set by `DeactivateDelinquentValidator`: the validator stake is deactivating,
+    /// `UpdateListBalance` then moves it to the reserve and removes the validator
+    pub leaving: bool,
 }
 
 impl ValidatorStakeList {
@@ -199,8 +1059,27 @@ impl ValidatorStakeList {
     /// Header length
     pub const HEADER_LEN: usize = size_of::<u8>() + size_of::<u16>();
 
+    /// v1 layout, 1000 entries of 48 bytes: the validator account, balance and
+    /// last update epoch. The `#[repr(C)]` entries had no padding
+    pub const VALIDATOR_STAKE_LIST_VERSION_V1: u8 = 1;
+
     /// Version of validator stake list
-    pub const VALIDATOR_STAKE_LIST_VERSION: u8 = 1;
+    pub const VALIDATOR_STAKE_LIST_VERSION: u8 = 2;
+
+    /// Length of a v1 account
+    pub const V1_LEN: usize = Self::HEADER_LEN + Self::V1_ENTRY_LEN * Self::V1_MAX_VALIDATORS;
+
+    /// Length of a v1 entry
+    const V1_ENTRY_LEN: usize = 48;
+
+    /// Max validators in a v1 list
+    const V1_MAX_VALIDATORS: usize = 1000;
+
+    /// Validators an account of `data_len` bytes holds
+    pub fn capacity(data_len: usize) -> usize {
+        (data_len.saturating_sub(Self::HEADER_LEN) / ValidatorStakeInfo::LEN)
+            .min(MAX_VALIDATOR_STAKE_ACCOUNTS)
+    }
 
     /// Check if contains validator with particular pubkey
     pub fn contains(&self, validator: &Pubkey) -> bool {
@@ -222,6 +1101,13 @@ impl ValidatorStakeList {
             .find(|x| x.validator_account == *validator)
     }
 
+    /// Sum of the validators target weights
+    pub fn total_weight(&self) -> u64 {
+        self.validators
+            .iter()
+            .fold(0u64, |total, x| total.saturating_add(x.target_weight))
+    }
+
     /// Check if validator stake list is initialized
     pub fn is_initialized(&self) -> bool {
         self.version > 0
@@ -229,7 +1115,10 @@ impl ValidatorStakeList {
 
     /// Deserializes a byte buffer into a ValidatorStakeList.
     pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() < Self::LEN {
+        if input.first() == Some(&Self::VALIDATOR_STAKE_LIST_VERSION_V1) {
+            return Err(StakePoolError::StateNeedsMigration.into());
+        }
+        if input.len() < Self::HEADER_LEN {
             return Err(ProgramError::InvalidAccountData);
         }
 
@@ -239,13 +1128,16 @@ impl ValidatorStakeList {
                 validators: vec![],
             });
         }
+        if input[0] != Self::VALIDATOR_STAKE_LIST_VERSION {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
 
         let number_of_validators: usize = u16::from_le_bytes(
             input[1..3]
                 .try_into()
                 .or(Err(ProgramError::InvalidAccountData))?,
         ) as usize;
-        if number_of_validators > MAX_VALIDATOR_STAKE_ACCOUNTS {
+        if number_of_validators > Self::capacity(input.len()) {
             return Err(ProgramError::InvalidAccountData);
         }
         let mut validators: Vec<ValidatorStakeInfo> = Vec::with_capacity(number_of_validators);
@@ -265,10 +1157,9 @@ impl ValidatorStakeList {
 
     /// Serializes ValidatorStakeList into a byte buffer.
     pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
-        if output.len() < Self::LEN {
-            return Err(ProgramError::InvalidAccountData);
-        }
-        if self.validators.len() > MAX_VALIDATOR_STAKE_ACCOUNTS {
+        if output.len() < Self::HEADER_LEN
+            || self.validators.len() > Self::capacity(output.len())
+        {
             return Err(ProgramError::InvalidAccountData);
         }
         output[0] = self.version;
@@ -282,54 +1173,837 @@ impl ValidatorStakeList {
         }
         Ok(())
     }
-}
-
-impl ValidatorStakeInfo {
-    /// Length of ValidatorStakeInfo data when serialized
-    pub const LEN: usize = size_of::<ValidatorStakeInfo>();
 
-    /// Deserializes a byte buffer into a ValidatorStakeInfo.
-    pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() < Self::LEN {
+    /// Validators of a v1 list, read by `MigrateState`. The fields added since v1 are cleared
+    pub fn iter_v1(
+        input: &[u8],
+    ) -> Result<impl Iterator<Item = ValidatorStakeInfo> + '_, ProgramError> {
+        if input.len() != Self::V1_LEN {
             return Err(ProgramError::InvalidAccountData);
         }
-        #[allow(clippy::cast_ptr_alignment)]
-        let stake_info: &ValidatorStakeInfo =
-            unsafe { &*(&input[0] as *const u8 as *const ValidatorStakeInfo) };
-        Ok(*stake_info)
-    }
-
-    /// Serializes ValidatorStakeInfo into a byte buffer.
-    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
-        if output.len() < Self::LEN {
+        if input[0] != Self::VALIDATOR_STAKE_LIST_VERSION_V1 {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+        let len = u16::from_le_bytes([input[1], input[2]]) as usize;
+        if len > Self::V1_MAX_VALIDATORS {
             return Err(ProgramError::InvalidAccountData);
         }
+        Ok((0..len).map(move |index| {
+            let from = ValidatorStakeList::HEADER_LEN + index * ValidatorStakeList::V1_ENTRY_LEN;
+            ValidatorStakeInfo::unpack_v1(array_ref![input, from, ValidatorStakeList::V1_ENTRY_LEN])
+        }))
+    }
 
-        #[allow(clippy::cast_ptr_alignment)]
-        let value = unsafe { &mut *(&mut output[0] as *mut u8 as *mut ValidatorStakeInfo) };
-        *value = *self;
+    /// Upgrades a v1 list in place, used by `MigrateState`. The fields added since v1 are
+    /// cleared. The account keeps the first `capacity(V1_LEN)` validators, the others are
+    /// appended to `overflow`
+    pub fn migrate_v1<T: AsRef<[u8]> + AsMut<[u8]>>(
+        input: &mut [u8],
+        overflow: Option<&mut ValidatorStakeListView<T>>,
+    ) -> ProgramResult {
+        let len = Self::iter_v1(input)?.count();
+        let kept = len.min(Self::capacity(input.len()));
+        if len > kept {
+            let overflow = overflow.ok_or(StakePoolError::ValidatorStakeListFull)?;
+            for validator in Self::iter_v1(input)?.skip(kept) {
+                overflow.push(validator)?;
+            }
+        }
+        // the entries got longer: from the last one, an entry only overwrites
+        // the v1 entries already upgraded
+        for index in (0..kept).rev() {
+            let from = Self::HEADER_LEN + index * Self::V1_ENTRY_LEN;
+            let validator = ValidatorStakeInfo::unpack_v1(array_ref![
+                input,
+                from,
+                ValidatorStakeList::V1_ENTRY_LEN
+            ]);
+            let to = Self::HEADER_LEN + index * ValidatorStakeInfo::LEN;
+            validator.pack(array_mut_ref![input, to, VALIDATOR_STAKE_INFO_LEN]);
+        }
+        for byte in input[Self::HEADER_LEN + kept * ValidatorStakeInfo::LEN..].iter_mut() {
+            *byte = 0;
+        }
+        input[0] = Self::VALIDATOR_STAKE_LIST_VERSION;
+        input[1..3].copy_from_slice(&(kept as u16).to_le_bytes());
         Ok(())
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_state_packing() {
-        // Not initialized
-        let stake_list = ValidatorStakeList {
-            version: 0,
-            validators: vec![],
-        };
-        let mut bytes: [u8; ValidatorStakeList::LEN] = [0; ValidatorStakeList::LEN];
-        stake_list.serialize(&mut bytes).unwrap();
-        let stake_list_unpacked = ValidatorStakeList::deserialize(&bytes).unwrap();
-        assert_eq!(stake_list_unpacked, stake_list);
+/// Borrowed view over the ValidatorStakeList account data, entries are
+/// read and updated in place without copying the whole list
+pub struct ValidatorStakeListView<T> {
+    data: T,
+}
 
-        // Empty
-        let stake_list = ValidatorStakeList {
+impl<T: AsRef<[u8]>> ValidatorStakeListView<T> {
+    /// Checks the account data size and the stored number of entries
+    pub fn new(data: T) -> Result<Self, ProgramError> {
+        if data.as_ref().first() == Some(&ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION_V1) {
+            return Err(StakePoolError::StateNeedsMigration.into());
+        }
+        if data.as_ref().len() < ValidatorStakeList::HEADER_LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let view = Self { data };
+        if view.is_initialized()
+            && view.version() != ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION
+        {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+        if view.len() > view.capacity() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(view)
+    }
+
+    /// Validator stake list version
+    pub fn version(&self) -> u8 {
+        self.data.as_ref()[0]
+    }
+
+    /// Check if validator stake list is initialized
+    pub fn is_initialized(&self) -> bool {
+        self.version() > 0
+    }
+
+    /// Number of validators in the list
+    pub fn len(&self) -> usize {
+        let input = self.data.as_ref();
+        u16::from_le_bytes([input[1], input[2]]) as usize
+    }
+
+    /// Check if the list has no validators
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Validators the account holds
+    pub fn capacity(&self) -> usize {
+        ValidatorStakeList::capacity(self.data.as_ref().len())
+    }
+
+    fn offset(index: usize) -> usize {
+        ValidatorStakeList::HEADER_LEN + index * ValidatorStakeInfo::LEN
+    }
+
+    /// Validator at `index`
+    fn get(&self, index: usize) -> ValidatorStakeInfo {
+        ValidatorStakeInfo::unpack(array_ref![
+            self.data.as_ref(),
+            Self::offset(index),
+            VALIDATOR_STAKE_INFO_LEN
+        ])
+    }
+
+    /// Index of the validator, compares the pubkey bytes without reading the entries
+    fn position(&self, validator: &Pubkey) -> Option<usize> {
+        let input = self.data.as_ref();
+        (0..self.len()).find(|index| {
+            let from = Self::offset(*index);
+            input[from..from + size_of::<Pubkey>()] == validator.as_ref()[..]
+        })
+    }
+
+    /// Copies of the validators in the list
+    pub fn iter(&self) -> impl Iterator<Item = ValidatorStakeInfo> + '_ {
+        (0..self.len()).map(move |index| self.get(index))
+    }
+
+    /// Check if contains validator with particular pubkey
+    pub fn contains(&self, validator: &Pubkey) -> bool {
+        self.position(validator).is_some()
+    }
+
+    /// Check if contains validator with particular pubkey (immutable)
+    pub fn find(&self, validator: &Pubkey) -> Option<ValidatorStakeInfo> {
+        self.position(validator).map(|index| self.get(index))
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> ValidatorStakeListView<T> {
+    /// Check if contains validator with particular pubkey (mutable),
+    /// changes are written back to the account data when the entry is dropped
+    pub fn find_mut(&mut self, validator: &Pubkey) -> Option<ValidatorStakeInfoMut<'_>> {
+        let index = self.position(validator)?;
+        let info = self.get(index);
+        let from = Self::offset(index);
+        Some(ValidatorStakeInfoMut {
+            info,
+            output: &mut self.data.as_mut()[from..from + ValidatorStakeInfo::LEN],
+        })
+    }
+
+    /// Marks the list initialized and empty
+    pub fn initialize(&mut self) {
+        let output = self.data.as_mut();
+        output[0] = ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION;
+        output[1..3].copy_from_slice(&0u16.to_le_bytes());
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.data.as_mut()[1..3].copy_from_slice(&(len as u16).to_le_bytes());
+    }
+
+    /// Appends a validator at the end of the list
+    pub fn push(&mut self, validator: ValidatorStakeInfo) -> ProgramResult {
+        let len = self.len();
+        if len >= self.capacity() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let from = Self::offset(len);
+        validator.serialize(&mut self.data.as_mut()[from..from + ValidatorStakeInfo::LEN])?;
+        self.set_len(len + 1);
+        Ok(())
+    }
+
+    /// Removes a validator, the following entries are shifted to keep the order
+    pub fn remove(&mut self, validator: &Pubkey) -> Option<ValidatorStakeInfo> {
+        let len = self.len();
+        let index = self.position(validator)?;
+        let removed = self.get(index);
+        let from = Self::offset(index);
+        self.data
+            .as_mut()
+            .copy_within(from + ValidatorStakeInfo::LEN..Self::offset(len), from);
+        self.set_len(len - 1);
+        Some(removed)
+    }
+}
+
+/// Validator found with `ValidatorStakeListView::find_mut`
+pub struct ValidatorStakeInfoMut<'a> {
+    info: ValidatorStakeInfo,
+    output: &'a mut [u8],
+}
+
+impl Deref for ValidatorStakeInfoMut<'_> {
+    type Target = ValidatorStakeInfo;
+
+    fn deref(&self) -> &ValidatorStakeInfo {
+        &self.info
+    }
+}
+
+impl DerefMut for ValidatorStakeInfoMut<'_> {
+    fn deref_mut(&mut self) -> &mut ValidatorStakeInfo {
+        &mut self.info
+    }
+}
+
+impl Drop for ValidatorStakeInfoMut<'_> {
+    fn drop(&mut self) {
+        self.info
+            .pack(array_mut_ref![self.output, 0, VALIDATOR_STAKE_INFO_LEN]);
+    }
+}
+
+/// Length of ValidatorStakeInfo data when serialized
+const VALIDATOR_STAKE_INFO_LEN: usize = 65;
+
+impl ValidatorStakeInfo {
+    /// Length of ValidatorStakeInfo data when serialized
+    pub const LEN: usize = VALIDATOR_STAKE_INFO_LEN;
+
+    /// lamports under management for this validator, transient stake included
+    pub fn total_balance(&self) -> Option<u64> {
+        self.balance.checked_add(self.transient_balance)
+    }
+
+    /// Deserializes a byte buffer into a ValidatorStakeInfo.
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self::unpack(array_ref![input, 0, VALIDATOR_STAKE_INFO_LEN]))
+    }
+
+    /// Serializes ValidatorStakeInfo into a byte buffer.
+    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
+        if output.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        self.pack(array_mut_ref![output, 0, VALIDATOR_STAKE_INFO_LEN]);
+        Ok(())
+    }
+
+    fn unpack(input: &[u8; VALIDATOR_STAKE_INFO_LEN]) -> Self {
+        #[rustfmt::skip]
+        let (
+            validator_account, balance, last_update_epoch, transient_balance, target_weight,
+            leaving,
+        ) = array_refs![input, 32, 8, 8, 8, 8, 1];
+        ValidatorStakeInfo {
+            validator_account: Pubkey::new_from_array(*validator_account),
+            balance: u64::from_le_bytes(*balance),
+            last_update_epoch: u64::from_le_bytes(*last_update_epoch),
+            transient_balance: u64::from_le_bytes(*transient_balance),
+            target_weight: u64::from_le_bytes(*target_weight),
+            leaving: leaving[0] != 0,
+        }
+    }
+
+    fn unpack_v1(input: &[u8; ValidatorStakeList::V1_ENTRY_LEN]) -> Self {
+        let (validator_account, balance, last_update_epoch) = array_refs![input, 32, 8, 8];
+        ValidatorStakeInfo {
+            validator_account: Pubkey::new_from_array(*validator_account),
+            balance: u64::from_le_bytes(*balance),
+            last_update_epoch: u64::from_le_bytes(*last_update_epoch),
+            ..ValidatorStakeInfo::default()
+        }
+    }
+
+    fn pack(&self, output: &mut [u8; VALIDATOR_STAKE_INFO_LEN]) {
+        #[rustfmt::skip]
+        let (
+            validator_account, balance, last_update_epoch, transient_balance, target_weight,
+            leaving,
+        ) = mut_array_refs![output, 32, 8, 8, 8, 8, 1];
+        validator_account.copy_from_slice(self.validator_account.as_ref());
+        *balance = self.balance.to_le_bytes();
+        *last_update_epoch = self.last_update_epoch.to_le_bytes();
+        *transient_balance = self.transient_balance.to_le_bytes();
+        *target_weight = self.target_weight.to_le_bytes();
+        leaving[0] = self.leaving as u8;
+    }
+}
+
+/// Max depositors in the depositor list of a pool
+pub const MAX_DEPOSITORS: usize = 256;
+
+/// Depositors allowed to deposit in a pool with a deposit authority, besides the
+/// authority itself. PDA of (stake pool, "depositors"), created by the first `SetDepositor`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DepositorList {
+    /// Depositor list version
+    pub version: u8,
+    /// Allowed depositors
+    pub depositors: Vec<Pubkey>,
+}
+
+impl DepositorList {
+    /// Length of DepositorList data when serialized
+    pub const LEN: usize = Self::HEADER_LEN + 32 * MAX_DEPOSITORS;
+
+    /// Header length
+    pub const HEADER_LEN: usize = size_of::<u8>() + size_of::<u16>();
+
+    /// Version of depositor list
+    pub const DEPOSITOR_LIST_VERSION: u8 = 1;
+
+    /// Check if the depositor is in the list
+    pub fn contains(&self, depositor: &Pubkey) -> bool {
+        self.depositors.contains(depositor)
+    }
+
+    /// Check if depositor list is initialized
+    pub fn is_initialized(&self) -> bool {
+        self.version > 0
+    }
+
+    /// Deserializes a byte buffer into a DepositorList.
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if input[0] == 0 {
+            return Ok(DepositorList::default());
+        }
+        if input[0] != Self::DEPOSITOR_LIST_VERSION {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+
+        let number_of_depositors = u16::from_le_bytes([input[1], input[2]]) as usize;
+        if number_of_depositors > MAX_DEPOSITORS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let depositors = input[Self::HEADER_LEN..]
+            .chunks(32)
+            .take(number_of_depositors)
+            .map(|input| Pubkey::new_from_array(*array_ref![input, 0, 32]))
+            .collect();
+        Ok(DepositorList {
+            version: input[0],
+            depositors,
+        })
+    }
+
+    /// Serializes DepositorList into a byte buffer.
+    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
+        if output.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if self.depositors.len() > MAX_DEPOSITORS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        output[0] = self.version;
+        output[1..3].copy_from_slice(&u16::to_le_bytes(self.depositors.len() as u16));
+        for (output, depositor) in output[Self::HEADER_LEN..]
+            .chunks_mut(32)
+            .zip(self.depositors.iter())
+        {
+            output.copy_from_slice(depositor.as_ref());
+        }
+        Ok(())
+    }
+}
+
+/// Epochs kept in the rate history, over a year of epochs
+pub const MAX_RATE_HISTORY_ENTRIES: usize = 256;
+
+/// Length of a RateHistoryEntry when serialized
+const RATE_HISTORY_ENTRY_LEN: usize = 24;
+
+/// Pool balances recorded by `UpdatePoolBalance` in an epoch
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateHistoryEntry {
+    /// Epoch of the update
+    pub epoch: u64,
+    /// `StakePool::stake_total` after the update
+    pub stake_total: u64,
+    /// `StakePool::pool_total` after the update, reward fee tokens included
+    pub pool_total: u64,
+}
+
+impl RateHistoryEntry {
+    /// Lamports per pool token, None while the pool has no tokens
+    pub fn rate(&self) -> Option<f64> {
+        if self.pool_total == 0 {
+            return None;
+        }
+        Some(self.stake_total as f64 / self.pool_total as f64)
+    }
+
+    fn unpack(input: &[u8; RATE_HISTORY_ENTRY_LEN]) -> Self {
+        let (epoch, stake_total, pool_total) = array_refs![input, 8, 8, 8];
+        Self {
+            epoch: u64::from_le_bytes(*epoch),
+            stake_total: u64::from_le_bytes(*stake_total),
+            pool_total: u64::from_le_bytes(*pool_total),
+        }
+    }
+
+    fn pack(&self, output: &mut [u8; RATE_HISTORY_ENTRY_LEN]) {
+        let (epoch, stake_total, pool_total) = mut_array_refs![output, 8, 8, 8];
+        *epoch = self.epoch.to_le_bytes();
+        *stake_total = self.stake_total.to_le_bytes();
+        *pool_total = self.pool_total.to_le_bytes();
+    }
+}
+
+/// Ring buffer of the pool balances, one entry per updated epoch. Once full the
+/// oldest entry is overwritten. PDA of (stake pool, "history"), created by `CreateRateHistory`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RateHistory {
+    /// Rate history version
+    pub version: u8,
+    /// Index of the entry written by the next epoch, the oldest entry once full
+    pub next: usize,
+    /// Entries in ring buffer order, see `iter` for the epoch order
+    pub entries: Vec<RateHistoryEntry>,
+}
+
+impl RateHistory {
+    /// Length of RateHistory data when serialized
+    pub const LEN: usize = Self::HEADER_LEN + RATE_HISTORY_ENTRY_LEN * MAX_RATE_HISTORY_ENTRIES;
+
+    /// Header length: version, number of entries, next index
+    pub const HEADER_LEN: usize = size_of::<u8>() + 2 * size_of::<u16>();
+
+    /// Version of rate history
+    pub const RATE_HISTORY_VERSION: u8 = 1;
+
+    /// Check if rate history is initialized
+    pub fn is_initialized(&self) -> bool {
+        self.version > 0
+    }
+
+    /// Entries from the oldest epoch to the newest
+    pub fn iter(&self) -> impl Iterator<Item = &RateHistoryEntry> {
+        self.entries[self.next..]
+            .iter()
+            .chain(self.entries[..self.next].iter())
+    }
+
+    /// Entry of the last updated epoch
+    pub fn newest(&self) -> Option<&RateHistoryEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.entries
+            .get((self.next + self.entries.len() - 1) % self.entries.len())
+    }
+
+    /// Records the balances of an epoch. Later updates in the same epoch replace its entry
+    pub fn push(&mut self, entry: RateHistoryEntry) {
+        if let Some(newest) = self.newest() {
+            if newest.epoch == entry.epoch {
+                let index = (self.next + self.entries.len() - 1) % self.entries.len();
+                self.entries[index] = entry;
+                return;
+            }
+        }
+        if self.entries.len() < MAX_RATE_HISTORY_ENTRIES {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+        }
+        self.next = (self.next + 1) % MAX_RATE_HISTORY_ENTRIES;
+    }
+
+    /// Annualized yield of the pool tokens over the last `epochs` epochs, 0.07 for 7%,
+    /// with `epochs_per_year` compounding periods. The span starts at the newest entry at
+    /// least `epochs` epochs old, None if the history does not go back that far
+    pub fn annualized_yield(&self, epochs: u64, epochs_per_year: f64) -> Option<f64> {
+        let newest = self.newest()?;
+        let since = newest.epoch.checked_sub(epochs)?;
+        let oldest = self.iter().filter(|entry| entry.epoch <= since).last()?;
+        if oldest.epoch == newest.epoch {
+            return None;
+        }
+        let growth = newest.rate()? / oldest.rate()?;
+        let elapsed_epochs = (newest.epoch - oldest.epoch) as f64;
+        Some(growth.powf(epochs_per_year / elapsed_epochs) - 1.0)
+    }
+
+    /// Deserializes a byte buffer into a RateHistory.
+    pub fn deserialize(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if input[0] == 0 {
+            return Ok(RateHistory::default());
+        }
+        if input[0] != Self::RATE_HISTORY_VERSION {
+            return Err(StakePoolError::InvalidStateVersion.into());
+        }
+
+        let number_of_entries = u16::from_le_bytes([input[1], input[2]]) as usize;
+        let next = u16::from_le_bytes([input[3], input[4]]) as usize;
+        // entries are appended until the buffer is full, then overwritten from the oldest
+        let valid_next = if number_of_entries < MAX_RATE_HISTORY_ENTRIES {
+            next == number_of_entries
+        } else {
+            next < MAX_RATE_HISTORY_ENTRIES
+        };
+        if number_of_entries > MAX_RATE_HISTORY_ENTRIES || !valid_next {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let entries = input[Self::HEADER_LEN..]
+            .chunks(RATE_HISTORY_ENTRY_LEN)
+            .take(number_of_entries)
+            .map(|input| RateHistoryEntry::unpack(array_ref![input, 0, RATE_HISTORY_ENTRY_LEN]))
+            .collect();
+        Ok(RateHistory {
+            version: input[0],
+            next,
+            entries,
+        })
+    }
+
+    /// Serializes RateHistory into a byte buffer.
+    pub fn serialize(&self, output: &mut [u8]) -> ProgramResult {
+        if output.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if self.entries.len() > MAX_RATE_HISTORY_ENTRIES || self.next > self.entries.len() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        output[0] = self.version;
+        output[1..3].copy_from_slice(&u16::to_le_bytes(self.entries.len() as u16));
+        output[3..5].copy_from_slice(&u16::to_le_bytes(self.next as u16));
+        for (output, entry) in output[Self::HEADER_LEN..]
+            .chunks_mut(RATE_HISTORY_ENTRY_LEN)
+            .zip(self.entries.iter())
+        {
+            entry.pack(array_mut_ref![output, 0, RATE_HISTORY_ENTRY_LEN]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sell_fee_curve() {
+        let curve = SellFeeCurve {
+            min_fee_bps: 30,
+            max_fee_bps: 300,
+            target_liquidity: 1_000_000,
+        };
+        assert!(curve.is_valid());
+        // at or above the target: min fee
+        assert_eq!(curve.calc_sell_fee_bps(1_000_000), Some(30));
+        assert_eq!(curve.calc_sell_fee_bps(5_000_000), Some(30));
+        // empty pool: max fee
+        assert_eq!(curve.calc_sell_fee_bps(0), Some(300));
+        // linear in between
+        assert_eq!(curve.calc_sell_fee_bps(500_000), Some(165));
+        assert_eq!(curve.calc_sell_fee_bps(250_000), Some(233));
+        // fee uses the post-trade balance
+        assert_eq!(curve.calc_sell_fee(100_000, 2_000_000), Some(300));
+        assert_eq!(curve.calc_sell_fee(100_000, 600_000), Some(1_650));
+        assert_eq!(curve.calc_sell_fee(100_000, 50_000), Some(3_000));
+        // no target: flat min fee
+        let flat = SellFeeCurve {
+            target_liquidity: 0,
+            ..curve
+        };
+        assert_eq!(flat.calc_sell_fee(100_000, 0), Some(300));
+
+        assert!(!SellFeeCurve { min_fee_bps: 301, ..curve }.is_valid());
+        assert!(!SellFeeCurve { max_fee_bps: 10_001, ..curve }.is_valid());
+    }
+
+    #[test]
+    fn test_calc_reward_fee_amount() {
+        // 100_000 lamports of rewards already counted in stake_total, 10% reward fee
+        let stake_pool = StakePool {
+            stake_total: 1_100_000,
+            pool_total: 1_000_000,
+            reward_fee: Fee {
+                numerator: 1,
+                denominator: 10,
+            },
+            ..StakePool::default()
+        };
+        let fee_tokens = stake_pool.calc_reward_fee_amount(100_000).unwrap();
+        assert_eq!(fee_tokens, 9_174);
+        // after minting, the fee tokens are worth the 10_000 lamports fee (rounded down)
+        let stake_pool_after = StakePool {
+            pool_total: stake_pool.pool_total + fee_tokens,
+            ..stake_pool
+        };
+        assert_eq!(stake_pool_after.calc_lamports_amount(fee_tokens), Some(9_999));
+        // no fee configured, or no holders yet
+        let no_fee = StakePool {
+            reward_fee: Fee::default(),
+            ..stake_pool
+        };
+        assert_eq!(no_fee.calc_reward_fee_amount(100_000), Some(0));
+        let empty = StakePool {
+            pool_total: 0,
+            ..stake_pool
+        };
+        assert_eq!(empty.calc_reward_fee_amount(100_000), Some(0));
+    }
+
+    #[test]
+    fn test_is_delinquent() {
+        // 0 uses the default threshold
+        let stake_pool = StakePool::default();
+        let slot = 1_000_000;
+        assert!(!stake_pool.is_delinquent(Some(slot - StakePool::DEFAULT_DELINQUENT_SLOTS), slot));
+        assert!(
+            stake_pool.is_delinquent(Some(slot - StakePool::DEFAULT_DELINQUENT_SLOTS - 1), slot)
+        );
+        assert!(stake_pool.is_delinquent(None, slot));
+
+        let stake_pool = StakePool {
+            delinquent_slots: 100,
+            ..StakePool::default()
+        };
+        assert!(!stake_pool.is_delinquent(Some(slot), slot));
+        assert!(!stake_pool.is_delinquent(Some(slot - 100), slot));
+        assert!(stake_pool.is_delinquent(Some(slot - 101), slot));
+        // votes for slots ahead of the clock are not delinquent
+        assert!(!stake_pool.is_delinquent(Some(slot + 1), slot));
+    }
+
+    #[test]
+    fn test_calc_rebalance_move() {
+        let stake_pool = StakePool {
+            stake_total: 10_000_000,
+            rebalance_epoch: 3,
+            ..StakePool::default()
+        };
+        let validator = ValidatorStakeInfo {
+            balance: 2_000_000,
+            target_weight: 1,
+            ..ValidatorStakeInfo::default()
+        };
+        // 5% of the stake per epoch
+        assert_eq!(stake_pool.calc_rebalance_lamports_left(4), Some(500_000));
+        // target 1/4 of the stake: 2_500_000
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&validator, 4, 4),
+            Some(RebalanceMove::Increase(500_000))
+        );
+        // target 1/2: 5_000_000, bounded by the epoch limit
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&validator, 2, 4),
+            Some(RebalanceMove::Increase(500_000))
+        );
+        // target 1/10: 1_000_000, transient stake counts
+        let moving = ValidatorStakeInfo {
+            transient_balance: 100_000,
+            ..validator
+        };
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&moving, 10, 4),
+            Some(RebalanceMove::Decrease(500_000))
+        );
+        let almost = ValidatorStakeInfo {
+            balance: 1_200_000,
+            ..validator
+        };
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&almost, 10, 4),
+            Some(RebalanceMove::Decrease(200_000))
+        );
+        // limit shared by the epoch moves
+        let used = StakePool {
+            rebalanced_lamports: 400_000,
+            ..stake_pool
+        };
+        assert_eq!(
+            used.calc_rebalance_move(&validator, 2, 3),
+            Some(RebalanceMove::Increase(100_000))
+        );
+        assert_eq!(
+            used.calc_rebalance_move(&validator, 2, 4),
+            Some(RebalanceMove::Increase(500_000))
+        );
+        let exhausted = StakePool {
+            rebalanced_lamports: 500_000,
+            ..stake_pool
+        };
+        assert_eq!(
+            exhausted.calc_rebalance_move(&validator, 2, 3),
+            Some(RebalanceMove::None)
+        );
+        // at target, or no weights set
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&validator, 5, 4),
+            Some(RebalanceMove::None)
+        );
+        assert_eq!(
+            stake_pool.calc_rebalance_move(&validator, 0, 4),
+            Some(RebalanceMove::None)
+        );
+        // balances overflowing u64
+        let overflowing = ValidatorStakeInfo {
+            balance: u64::MAX,
+            transient_balance: 1,
+            ..validator
+        };
+        assert_eq!(overflowing.total_balance(), None);
+        assert_eq!(stake_pool.calc_rebalance_move(&overflowing, 4, 4), None);
+    }
+
+    #[test]
+    fn test_calc_buy() {
+        let stake_pool = StakePool {
+            stake_total: 1_100_000,
+            pool_total: 1_000_000,
+            ..StakePool::default()
+        };
+        let liq_pool = LiquidityPool {
+            buy_fee_bps: 100,
+            ..LiquidityPool::default()
+        };
+        // fee 1%, 99_000 lamports buy 90_000 stSOL
+        assert_eq!(liq_pool.calc_buy(100_000, 1_000_000, &stake_pool), Some((100_000, 90_000)));
+        // capped at the pool stSOL, 45_000 stSOL are worth 49_500, plus fee 50_000
+        assert_eq!(liq_pool.calc_buy(100_000, 45_000, &stake_pool), Some((50_000, 45_000)));
+        assert_eq!(liq_pool.calc_buy(100_000, 0, &stake_pool), Some((0, 0)));
+        // the pool never sells stSOL below its value
+        for stsol_balance in [1, 7, 33_333, 89_999].iter() {
+            let (wsol_in, stsol_out) = liq_pool.calc_buy(100_000, *stsol_balance, &stake_pool).unwrap();
+            assert_eq!(stsol_out, *stsol_balance);
+            assert!(wsol_in <= 100_000);
+            assert!(wsol_in >= stake_pool.calc_lamports_amount(stsol_out).unwrap());
+        }
+        // a 100% buy fee can not be priced
+        let no_buy = LiquidityPool {
+            buy_fee_bps: 10_000,
+            ..LiquidityPool::default()
+        };
+        assert_eq!(no_buy.calc_buy(100_000, 1_000_000, &stake_pool), None);
+    }
+
+    #[test]
+    fn test_calc_metalp_amount() {
+        // the first provider gets 1:1 shares
+        assert_eq!(LiquidityPool::calc_metalp_amount(1_000, 0, 0), Some(1_000));
+        assert_eq!(
+            LiquidityPool::calc_metalp_amount(1_000, 5_000, 0),
+            Some(1_000)
+        );
+        // then shares are proportional to the pool value
+        assert_eq!(
+            LiquidityPool::calc_metalp_amount(1_000, 4_000, 2_000),
+            Some(500)
+        );
+        assert_eq!(
+            LiquidityPool::calc_metalp_amount(1_000, 3_000, 1_000),
+            Some(333)
+        );
+        // a drained pool with outstanding shares would mint 1:1 and dilute the holders
+        assert_eq!(LiquidityPool::calc_metalp_amount(1_000, 0, 2_000), None);
+    }
+
+    #[test]
+    fn test_add_then_remove_liquidity_never_gains_value() {
+        let stake_pools = [(1_000_000_000, 1_000_000_000), (1_234_567_891, 1_000_000_007), (999_999_999, 1_000_000_000)];
+        let legs = [(0, 1), (1, 0), (3_000_000_001, 0), (100_000, 7_777_777), (987_654_321, 123_456_789)];
+        let supplies = [1, 3, 1_000_000, 4_000_000_003];
+        let amounts = [1, 2, 999, 1_000_000_000, 50_000_000_017];
+        for (stake_total, pool_total) in stake_pools.iter() {
+            let stake_pool = StakePool {
+                stake_total: *stake_total,
+                pool_total: *pool_total,
+                ..StakePool::default()
+            };
+            for (wsol_balance, stsol_balance) in legs.iter() {
+                for metalp_supply in supplies.iter() {
+                    for wsol_amount in amounts.iter() {
+                        let total_value =
+                            LiquidityPool::calc_total_value(*wsol_balance, *stsol_balance, &stake_pool)
+                                .unwrap();
+                        // a near-empty pool with a big supply can overflow: the instruction fails
+                        let metalp_amount =
+                            match LiquidityPool::calc_metalp_amount(*wsol_amount, total_value, *metalp_supply) {
+                                Some(amount) => amount,
+                                None => continue,
+                            };
+                        let (wsol_out, stsol_out) = LiquidityPool::calc_remove_amounts(
+                            metalp_amount,
+                            wsol_balance + wsol_amount,
+                            *stsol_balance,
+                            metalp_supply + metalp_amount,
+                        )
+                        .unwrap();
+                        let value_out =
+                            LiquidityPool::calc_total_value(wsol_out, stsol_out, &stake_pool).unwrap();
+                        assert!(
+                            value_out <= *wsol_amount,
+                            "gained value: in {} out {} (legs {}/{}, supply {}, rate {}/{})",
+                            wsol_amount, value_out, wsol_balance, stsol_balance, metalp_supply,
+                            stake_total, pool_total,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_packing() {
+        // Not initialized
+        let stake_list = ValidatorStakeList {
+            version: 0,
+            validators: vec![],
+        };
+        let mut bytes: [u8; ValidatorStakeList::LEN] = [0; ValidatorStakeList::LEN];
+        stake_list.serialize(&mut bytes).unwrap();
+        let stake_list_unpacked = ValidatorStakeList::deserialize(&bytes).unwrap();
+        assert_eq!(stake_list_unpacked, stake_list);
+
+        // Empty
+        let stake_list = ValidatorStakeList {
             version: ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
             validators: vec![],
         };
@@ -346,16 +2020,25 @@ mod test {
                     validator_account: Pubkey::new_from_array([1; 32]),
                     balance: 123456789,
                     last_update_epoch: 987654321,
+                    transient_balance: 0,
+                    target_weight: 0,
+                    leaving: false,
                 },
                 ValidatorStakeInfo {
                     validator_account: Pubkey::new_from_array([2; 32]),
                     balance: 998877665544,
                     last_update_epoch: 11223445566,
+                    transient_balance: 5_000_000,
+                    target_weight: 7,
+                    leaving: false,
                 },
                 ValidatorStakeInfo {
                     validator_account: Pubkey::new_from_array([3; 32]),
                     balance: 0,
                     last_update_epoch: 999999999999999,
+                    transient_balance: 0,
+                    target_weight: 0,
+                    leaving: true,
                 },
             ],
         };
@@ -363,5 +2046,565 @@ mod test {
         stake_list.serialize(&mut bytes).unwrap();
         let stake_list_unpacked = ValidatorStakeList::deserialize(&bytes).unwrap();
         assert_eq!(stake_list_unpacked, stake_list);
+
+        // Unknown version
+        bytes[0] = ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION + 1;
+        assert_eq!(
+            ValidatorStakeList::deserialize(&bytes),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+        assert!(ValidatorStakeListView::new(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_validator_stake_list_migration() {
+        let v1_list = |len: u16| {
+            // v1 list: 1000 entries of 48 bytes
+            let mut bytes = vec![0xeeu8; ValidatorStakeList::V1_LEN];
+            bytes[0] = ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION_V1;
+            bytes[1..3].copy_from_slice(&len.to_le_bytes());
+            for n in 1..=len as usize {
+                let from = ValidatorStakeList::HEADER_LEN + (n - 1) * 48;
+                bytes[from..from + 32].copy_from_slice(&[n as u8; 32]);
+                bytes[from + 32..from + 40].copy_from_slice(&(n as u64 * 1_000).to_le_bytes());
+                bytes[from + 40..from + 48].copy_from_slice(&(40 + n as u64).to_le_bytes());
+            }
+            bytes
+        };
+        let validator = |n: usize| ValidatorStakeInfo {
+            validator_account: Pubkey::new_from_array([n as u8; 32]),
+            balance: n as u64 * 1_000,
+            last_update_epoch: 40 + n as u64,
+            ..ValidatorStakeInfo::default()
+        };
+
+        let mut bytes = v1_list(3);
+        assert_eq!(bytes.len(), 48_003);
+        assert_eq!(
+            ValidatorStakeList::deserialize(&bytes),
+            Err(StakePoolError::StateNeedsMigration.into())
+        );
+        assert_eq!(
+            ValidatorStakeListView::new(&bytes[..]).err(),
+            Some(StakePoolError::StateNeedsMigration.into())
+        );
+        assert_eq!(
+            ValidatorStakeList::iter_v1(&bytes)
+                .unwrap()
+                .collect::<Vec<_>>(),
+            (1..=3).map(validator).collect::<Vec<_>>()
+        );
+
+        // upgraded in place, the rest of the account is cleared
+        ValidatorStakeList::migrate_v1::<&mut [u8]>(&mut bytes, None).unwrap();
+        assert_eq!(
+            ValidatorStakeList::deserialize(&bytes).unwrap(),
+            ValidatorStakeList {
+                version: ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
+                validators: (1..=3).map(validator).collect(),
+            }
+        );
+        assert!(bytes[ValidatorStakeList::HEADER_LEN + 3 * ValidatorStakeInfo::LEN..]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(
+            ValidatorStakeList::migrate_v1::<&mut [u8]>(&mut bytes, None),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+
+        // a full v1 list holds more validators than the upgraded account can
+        let capacity = ValidatorStakeList::capacity(ValidatorStakeList::V1_LEN);
+        assert_eq!(capacity, 738);
+        let mut bytes = v1_list(1000);
+        assert_eq!(ValidatorStakeList::iter_v1(&bytes).unwrap().count(), 1000);
+        assert_eq!(
+            ValidatorStakeList::migrate_v1::<&mut [u8]>(&mut bytes, None),
+            Err(StakePoolError::ValidatorStakeListFull.into())
+        );
+        let mut page = vec![0u8; ValidatorStakeList::LEN];
+        let mut overflow = ValidatorStakeListView::new(&mut page[..]).unwrap();
+        overflow.initialize();
+        ValidatorStakeList::migrate_v1(&mut bytes, Some(&mut overflow)).unwrap();
+        let validators: Vec<_> = (1..=1000).map(validator).collect();
+        assert_eq!(
+            ValidatorStakeList::deserialize(&bytes).unwrap().validators,
+            validators[..capacity]
+        );
+        assert_eq!(
+            ValidatorStakeList::deserialize(&page).unwrap().validators,
+            validators[capacity..]
+        );
+
+        let mut bytes = v1_list(1000);
+        bytes[1..3].copy_from_slice(&1001u16.to_le_bytes());
+        assert!(ValidatorStakeList::iter_v1(&bytes).is_err());
+        assert!(ValidatorStakeList::migrate_v1::<&mut [u8]>(&mut bytes, None).is_err());
+
+        // current lists are not v1 lists
+        let mut bytes = vec![0u8; ValidatorStakeList::LEN];
+        ValidatorStakeListView::new(&mut bytes[..])
+            .unwrap()
+            .initialize();
+        assert!(ValidatorStakeList::iter_v1(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_depositor_list_packing() {
+        // Not initialized
+        let mut bytes = vec![0u8; DepositorList::LEN];
+        assert_eq!(
+            DepositorList::deserialize(&bytes).unwrap(),
+            DepositorList::default()
+        );
+
+        let depositor_list = DepositorList {
+            version: DepositorList::DEPOSITOR_LIST_VERSION,
+            depositors: vec![
+                Pubkey::new_from_array([1; 32]),
+                Pubkey::new_from_array([2; 32]),
+            ],
+        };
+        depositor_list.serialize(&mut bytes).unwrap();
+        let depositor_list_unpacked = DepositorList::deserialize(&bytes).unwrap();
+        assert_eq!(depositor_list_unpacked, depositor_list);
+        assert!(depositor_list_unpacked.contains(&Pubkey::new_from_array([2; 32])));
+        assert!(!depositor_list_unpacked.contains(&Pubkey::new_from_array([3; 32])));
+
+        // A removed depositor is not read back
+        let depositor_list = DepositorList {
+            version: DepositorList::DEPOSITOR_LIST_VERSION,
+            depositors: vec![Pubkey::new_from_array([2; 32])],
+        };
+        depositor_list.serialize(&mut bytes).unwrap();
+        assert_eq!(DepositorList::deserialize(&bytes).unwrap(), depositor_list);
+
+        assert!(DepositorList::deserialize(&bytes[..DepositorList::LEN - 1]).is_err());
+        bytes[1..3].copy_from_slice(&(MAX_DEPOSITORS as u16 + 1).to_le_bytes());
+        assert!(DepositorList::deserialize(&bytes).is_err());
+        bytes[0] = DepositorList::DEPOSITOR_LIST_VERSION + 1;
+        assert_eq!(
+            DepositorList::deserialize(&bytes),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+    }
+
+    fn rate_history_entry(epoch: u64) -> RateHistoryEntry {
+        RateHistoryEntry {
+            epoch,
+            stake_total: 1_000_000 + epoch * 100,
+            pool_total: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn test_rate_history_packing() {
+        // Not initialized
+        let mut bytes = vec![0u8; RateHistory::LEN];
+        assert_eq!(
+            RateHistory::deserialize(&bytes).unwrap(),
+            RateHistory::default()
+        );
+
+        let mut rate_history = RateHistory {
+            version: RateHistory::RATE_HISTORY_VERSION,
+            ..RateHistory::default()
+        };
+        rate_history.push(rate_history_entry(10));
+        rate_history.push(rate_history_entry(11));
+        // a later update in the same epoch replaces its entry
+        rate_history.push(RateHistoryEntry {
+            pool_total: 999_000,
+            ..rate_history_entry(11)
+        });
+        assert_eq!(rate_history.entries.len(), 2);
+        assert_eq!(rate_history.newest().unwrap().pool_total, 999_000);
+        rate_history.serialize(&mut bytes).unwrap();
+        assert_eq!(RateHistory::deserialize(&bytes).unwrap(), rate_history);
+
+        // once full the oldest entries are overwritten
+        for epoch in 12..12 + MAX_RATE_HISTORY_ENTRIES as u64 {
+            rate_history.push(rate_history_entry(epoch));
+        }
+        assert_eq!(rate_history.entries.len(), MAX_RATE_HISTORY_ENTRIES);
+        assert_eq!(rate_history.next, 2);
+        let epochs: Vec<u64> = rate_history.iter().map(|entry| entry.epoch).collect();
+        let expected: Vec<u64> = (12..12 + MAX_RATE_HISTORY_ENTRIES as u64).collect();
+        assert_eq!(epochs, expected);
+        assert_eq!(
+            rate_history.newest().unwrap().epoch,
+            11 + MAX_RATE_HISTORY_ENTRIES as u64
+        );
+        rate_history.serialize(&mut bytes).unwrap();
+        assert_eq!(RateHistory::deserialize(&bytes).unwrap(), rate_history);
+
+        assert!(RateHistory::deserialize(&bytes[..RateHistory::LEN - 1]).is_err());
+        bytes[3..5].copy_from_slice(&(MAX_RATE_HISTORY_ENTRIES as u16).to_le_bytes());
+        assert!(RateHistory::deserialize(&bytes).is_err());
+        bytes[0] = RateHistory::RATE_HISTORY_VERSION + 1;
+        assert_eq!(
+            RateHistory::deserialize(&bytes),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_annualized_yield() {
+        let mut rate_history = RateHistory::default();
+        assert_eq!(rate_history.annualized_yield(1, 100.0), None);
+
+        // the rate grows 1% per epoch
+        let mut stake_total = 1_000_000_000f64;
+        for epoch in 10..20 {
+            rate_history.push(RateHistoryEntry {
+                epoch,
+                stake_total: stake_total as u64,
+                pool_total: 1_000_000_000,
+            });
+            stake_total *= 1.01;
+        }
+        let expected = 1.01f64.powf(100.0) - 1.0;
+        for epochs in &[1, 5, 9] {
+            let apy = rate_history.annualized_yield(*epochs, 100.0).unwrap();
+            assert!((apy - expected).abs() < 1e-6);
+        }
+        // the history does not go back far enough
+        assert_eq!(rate_history.annualized_yield(10, 100.0), None);
+        assert_eq!(rate_history.annualized_yield(0, 100.0), None);
+
+        // skipped epochs: the span starts at the newest entry old enough
+        let mut rate_history = RateHistory::default();
+        rate_history.push(RateHistoryEntry {
+            epoch: 10,
+            stake_total: 1_000,
+            pool_total: 1_000,
+        });
+        rate_history.push(RateHistoryEntry {
+            epoch: 14,
+            stake_total: 1_100,
+            pool_total: 1_000,
+        });
+        let apy = rate_history.annualized_yield(2, 4.0).unwrap();
+        assert!((apy - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stake_pool_packing() {
+        // Not initialized, the extension is not read
+        let bytes = [0u8; StakePool::LEN];
+        let stake_pool = StakePool::deserialize(&bytes, &[]).unwrap();
+        assert_eq!(stake_pool, StakePool::default());
+
+        let mut validator_stake_list_pages =
+            [Pubkey::default(); MAX_VALIDATOR_STAKE_LIST_PAGES - 1];
+        validator_stake_list_pages[0] = Pubkey::new_from_array([7; 32]);
+        let stake_pool = StakePool {
+            version: StakePool::STAKE_POOL_VERSION,
+            owner: Pubkey::new_from_array([1; 32]),
+            deposit_bump_seed: 254,
+            withdraw_bump_seed: 253,
+            validator_stake_list: Pubkey::new_from_array([2; 32]),
+            pool_mint: Pubkey::new_from_array([3; 32]),
+            owner_fee_account: Pubkey::new_from_array([4; 32]),
+            token_program_id: Pubkey::new_from_array([5; 32]),
+            extension_bump_seed: 251,
+            stake_total: 123456789,
+            pool_total: 987654321,
+            last_update_epoch: 42,
+            fee: Fee {
+                denominator: 100,
+                numerator: 3,
+            },
+            reserve_bump_seed: 252,
+            reward_fee: Fee {
+                denominator: 1000,
+                numerator: 25,
+            },
+            next_fee: Fee {
+                denominator: 100,
+                numerator: 5,
+            },
+            next_fee_epoch: 44,
+            withdrawal_fee: Fee {
+                denominator: 10_000,
+                numerator: 30,
+            },
+            withdrawal_fee_to_owner: 1,
+            rebalance_epoch: 41,
+            rebalanced_lamports: 5_000_000,
+            total_target_weight: 17,
+            validator_stake_list_pages_len: 1,
+            validator_stake_list_pages,
+            staker: Pubkey::new_from_array([9; 32]),
+            fee_manager: Pubkey::new_from_array([10; 32]),
+            pending_owner: Pubkey::new_from_array([8; 32]),
+            deposit_authority: Pubkey::new_from_array([11; 32]),
+            delinquent_slots: 1_000,
+            rate_history_bump_seed: 250,
+        };
+        let mut bytes = [0xffu8; StakePool::LEN];
+        let mut extension = [0xffu8; StakePool::EXTENSION_LEN];
+        stake_pool.serialize(&mut bytes, &mut extension).unwrap();
+        assert_eq!(
+            StakePool::deserialize(&bytes, &extension).unwrap(),
+            stake_pool
+        );
+        // the stake pool account keeps the v1 offsets, the extension bump seed
+        // and the reserved space in the v1 struct padding
+        assert_eq!(bytes[0], StakePool::STAKE_POOL_VERSION);
+        assert_eq!(bytes[163], 251);
+        assert_eq!(bytes[164..168], [0; 4]);
+        assert_eq!(bytes[168..176], 123456789u64.to_le_bytes());
+        assert_eq!(bytes[200..208], 3u64.to_le_bytes());
+        // little-endian fields in declaration order, then the zeroed reserved space
+        assert_eq!(extension[0], StakePool::STAKE_POOL_EXTENSION_VERSION);
+        assert_eq!(extension[1], 252);
+        assert_eq!(
+            extension[STAKE_POOL_EXTENSION_PACKED_LEN - 73..STAKE_POOL_EXTENSION_PACKED_LEN - 41],
+            [8; 32]
+        );
+        assert_eq!(
+            extension[STAKE_POOL_EXTENSION_PACKED_LEN - 41..STAKE_POOL_EXTENSION_PACKED_LEN - 9],
+            [11; 32]
+        );
+        assert_eq!(
+            extension[STAKE_POOL_EXTENSION_PACKED_LEN - 9..STAKE_POOL_EXTENSION_PACKED_LEN - 1],
+            1_000u64.to_le_bytes()
+        );
+        assert_eq!(extension[STAKE_POOL_EXTENSION_PACKED_LEN - 1], 250);
+        assert!(extension[STAKE_POOL_EXTENSION_PACKED_LEN..]
+            .iter()
+            .all(|byte| *byte == 0));
+
+        // the stake pool account alone
+        assert_eq!(
+            StakePool::deserialize_record(&bytes).unwrap(),
+            StakePool {
+                version: StakePool::STAKE_POOL_VERSION,
+                owner: stake_pool.owner,
+                deposit_bump_seed: 254,
+                withdraw_bump_seed: 253,
+                validator_stake_list: stake_pool.validator_stake_list,
+                pool_mint: stake_pool.pool_mint,
+                owner_fee_account: stake_pool.owner_fee_account,
+                token_program_id: stake_pool.token_program_id,
+                extension_bump_seed: 251,
+                stake_total: 123456789,
+                pool_total: 987654321,
+                last_update_epoch: 42,
+                fee: stake_pool.fee,
+                ..StakePool::default()
+            }
+        );
+
+        assert!(StakePool::deserialize(&bytes[..StakePool::LEN - 1], &extension).is_err());
+        assert!(StakePool::deserialize(&bytes, &extension[..StakePool::EXTENSION_LEN - 1]).is_err());
+        assert!(StakePool::deserialize(&bytes, &[]).is_err());
+        extension[0] = StakePool::STAKE_POOL_EXTENSION_VERSION + 1;
+        assert_eq!(
+            StakePool::deserialize(&bytes, &extension),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+        bytes[0] = StakePool::STAKE_POOL_VERSION + 1;
+        assert_eq!(
+            StakePool::deserialize(&bytes, &extension),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_stake_pool_migration() {
+        // v1 account: the `#[repr(C)]` struct, the u64 fields aligned after the pubkeys
+        let mut bytes = [0xeeu8; StakePool::LEN];
+        let mut put = |offset: usize, value: &[u8]| {
+            bytes[offset..offset + value.len()].copy_from_slice(value)
+        };
+        put(0, &[StakePool::STAKE_POOL_VERSION_V1]);
+        put(1, &[1; 32]);
+        put(33, &[254, 253]);
+        put(35, &[2; 32]);
+        put(67, &[3; 32]);
+        put(99, &[4; 32]);
+        put(131, &[5; 32]);
+        put(168, &123456789u64.to_le_bytes());
+        put(176, &987654321u64.to_le_bytes());
+        put(184, &42u64.to_le_bytes());
+        put(192, &100u64.to_le_bytes());
+        put(200, &3u64.to_le_bytes());
+
+        assert_eq!(
+            StakePool::deserialize(&bytes, &[]),
+            Err(StakePoolError::StateNeedsMigration.into())
+        );
+        assert_eq!(
+            StakePool::deserialize_record(&bytes),
+            Err(StakePoolError::StateNeedsMigration.into())
+        );
+        let stake_pool = StakePool::deserialize_v1(&bytes).unwrap();
+        assert_eq!(
+            stake_pool,
+            StakePool {
+                version: StakePool::STAKE_POOL_VERSION_V1,
+                owner: Pubkey::new_from_array([1; 32]),
+                deposit_bump_seed: 254,
+                withdraw_bump_seed: 253,
+                validator_stake_list: Pubkey::new_from_array([2; 32]),
+                pool_mint: Pubkey::new_from_array([3; 32]),
+                owner_fee_account: Pubkey::new_from_array([4; 32]),
+                token_program_id: Pubkey::new_from_array([5; 32]),
+                stake_total: 123456789,
+                pool_total: 987654321,
+                last_update_epoch: 42,
+                fee: Fee {
+                    denominator: 100,
+                    numerator: 3,
+                },
+                staker: Pubkey::new_from_array([1; 32]),
+                fee_manager: Pubkey::new_from_array([1; 32]),
+                ..StakePool::default()
+            }
+        );
+        assert!(StakePool::deserialize_v1(&bytes[..StakePool::LEN - 1]).is_err());
+
+        // MigrateState rewrites the account in place: the v1 fields keep their offsets
+        let stake_pool = StakePool {
+            version: StakePool::STAKE_POOL_VERSION,
+            extension_bump_seed: 251,
+            ..stake_pool
+        };
+        let v1_bytes = bytes;
+        let mut extension = [0u8; StakePool::EXTENSION_LEN];
+        stake_pool.serialize(&mut bytes, &mut extension).unwrap();
+        assert_eq!(bytes[1..163], v1_bytes[1..163]);
+        assert_eq!(bytes[168..], v1_bytes[168..]);
+        assert_eq!(
+            StakePool::deserialize(&bytes, &extension).unwrap(),
+            stake_pool
+        );
+
+        // current accounts are not v1 accounts
+        assert_eq!(
+            StakePool::deserialize_v1(&bytes),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_liquidity_pool_packing() {
+        // Not initialized
+        let bytes = [0u8; LiquidityPool::LEN];
+        let liq_pool = LiquidityPool::deserialize(&bytes).unwrap();
+        assert_eq!(liq_pool, LiquidityPool::default());
+
+        let mut unstake_tickets = [UnstakeTicket::default(); LiquidityPool::MAX_UNSTAKE_TICKETS];
+        unstake_tickets[1] = UnstakeTicket {
+            lamports: 5_000_000,
+            rent_lamports: 2_282_880,
+            rent_payer: Pubkey::new_from_array([7; 32]),
+            epoch: 42,
+            stake_bump_seed: 251,
+        };
+        let liq_pool = LiquidityPool {
+            version: LiquidityPool::LIQUIDITY_POOL_VERSION,
+            authority_bump_seed: 254,
+            stake_pool: Pubkey::new_from_array([1; 32]),
+            wsol_account: Pubkey::new_from_array([2; 32]),
+            stsol_account: Pubkey::new_from_array([3; 32]),
+            metalp_mint: Pubkey::new_from_array([4; 32]),
+            sell_fee: SellFeeCurve {
+                min_fee_bps: 10,
+                max_fee_bps: 300,
+                target_liquidity: 1_000_000,
+            },
+            treasury_wsol_account: Pubkey::new_from_array([5; 32]),
+            treasury_share_bps: 2_000,
+            buy_fee_bps: 30,
+            unstake_tickets,
+        };
+        let mut bytes = [0xffu8; LiquidityPool::LEN];
+        liq_pool.serialize(&mut bytes).unwrap();
+        assert_eq!(LiquidityPool::deserialize(&bytes).unwrap(), liq_pool);
+        // little-endian fields in declaration order, then the zeroed reserved space
+        assert_eq!(bytes[0], LiquidityPool::LIQUIDITY_POOL_VERSION);
+        assert_eq!(bytes[130..138], 10u64.to_le_bytes());
+        assert_eq!(
+            bytes[202 + UNSTAKE_TICKET_LEN..210 + UNSTAKE_TICKET_LEN],
+            5_000_000u64.to_le_bytes()
+        );
+        assert!(bytes[LIQUIDITY_POOL_PACKED_LEN..]
+            .iter()
+            .all(|byte| *byte == 0));
+
+        assert!(LiquidityPool::deserialize(&bytes[..LiquidityPool::LEN - 1]).is_err());
+        bytes[0] = 3;
+        assert_eq!(
+            LiquidityPool::deserialize(&bytes),
+            Err(StakePoolError::InvalidStateVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_validator_stake_list_view() {
+        let validator = |n: u8| ValidatorStakeInfo {
+            validator_account: Pubkey::new_from_array([n; 32]),
+            balance: n as u64 * 1_000,
+            last_update_epoch: n as u64,
+            transient_balance: 0,
+            target_weight: n as u64,
+            leaving: n == 3,
+        };
+        let mut bytes = vec![0; ValidatorStakeList::LEN];
+        let mut view = ValidatorStakeListView::new(&mut bytes[..]).unwrap();
+        assert!(!view.is_initialized());
+
+        view.initialize();
+        assert!(view.is_initialized());
+        assert!(view.is_empty());
+        for n in 1..=3 {
+            view.push(validator(n)).unwrap();
+        }
+        assert_eq!(view.len(), 3);
+        assert!(view.contains(&Pubkey::new_from_array([2; 32])));
+        assert_eq!(view.find(&Pubkey::new_from_array([4; 32])), None);
+
+        // updated in place
+        view.find_mut(&Pubkey::new_from_array([2; 32]))
+            .unwrap()
+            .balance = 5_000;
+        // the order of the remaining validators is kept
+        assert_eq!(
+            view.remove(&Pubkey::new_from_array([1; 32])),
+            Some(validator(1))
+        );
+        assert_eq!(view.remove(&Pubkey::new_from_array([1; 32])), None);
+
+        // same encoding as ValidatorStakeList
+        let stake_list = ValidatorStakeList::deserialize(&bytes).unwrap();
+        assert_eq!(
+            stake_list,
+            ValidatorStakeList {
+                version: ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
+                validators: vec![
+                    ValidatorStakeInfo {
+                        balance: 5_000,
+                        ..validator(2)
+                    },
+                    validator(3),
+                ],
+            }
+        );
+
+        // full list
+        let mut view = ValidatorStakeListView::new(&mut bytes[..]).unwrap();
+        for n in view.len()..MAX_VALIDATOR_STAKE_ACCOUNTS {
+            view.push(validator(n as u8)).unwrap();
+        }
+        assert_eq!(
+            view.push(validator(0)),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(
+            ValidatorStakeListView::new(&bytes[..ValidatorStakeList::LEN - 1]).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
     }
 }
@@ -0,0 +1,103 @@
+//! Vote account reading, only what the pool needs to detect delinquent validators
+
+use solana_program::{account_info::AccountInfo, clock::Slot, program_error::ProgramError};
+use std::convert::TryInto;
+
+solana_program::declare_id!("Vote111111111111111111111111111111111111111");
+
+/// `VoteStateVersions::V0_23_5`, converted to the current layout by the next vote
+const VOTE_STATE_V0_23_5: u32 = 0;
+/// `VoteStateVersions::Current`, votes are `Lockout { slot: u64, confirmation_count: u32 }`
+const VOTE_STATE_CURRENT: u32 = 1;
+/// Layout of the clusters after 1.14, each vote is prefixed by its latency byte
+const VOTE_STATE_LANDED_VOTES: u32 = 2;
+/// Offset of the votes length: version, node pubkey, withdrawer, commission
+const VOTES_OFFSET: usize = 4 + 32 + 32 + 1;
+
+/// Last slot voted by the vote account, None if it never voted.
+/// Accounts still in the v0.23.5 layout, uninitialized ones included, have not voted since
+pub fn last_voted_slot(data: &[u8]) -> Result<Option<Slot>, ProgramError> {
+    let read_u64 = |offset: usize| -> Result<u64, ProgramError> {
+        data.get(offset..offset + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidAccountData)
+    };
+    let version = data
+        .get(0..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    // offset of the slot in a vote, and length of a vote
+    let (slot_offset, vote_len) = match version {
+        VOTE_STATE_CURRENT => (0, 12),
+        VOTE_STATE_LANDED_VOTES => (1, 13),
+        VOTE_STATE_V0_23_5 => return Ok(None),
+        _ => return Err(ProgramError::InvalidAccountData),
+    };
+    let votes_len = read_u64(VOTES_OFFSET)? as usize;
+    if votes_len == 0 {
+        return Ok(None);
+    }
+    let last_vote = (votes_len - 1)
+        .checked_mul(vote_len)
+        .and_then(|offset| offset.checked_add(VOTES_OFFSET + 8 + slot_offset))
+        .ok_or(ProgramError::InvalidAccountData)?;
+    read_u64(last_vote).map(Some)
+}
+
+/// Last slot voted by the validator, None if it never voted or closed its vote account
+pub fn validator_last_voted_slot(
+    vote_account_info: &AccountInfo,
+) -> Result<Option<Slot>, ProgramError> {
+    if *vote_account_info.owner != id() || vote_account_info.lamports() == 0 {
+        return Ok(None);
+    }
+    last_voted_slot(&vote_account_info.data.borrow())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vote_state(version: u32, slots: &[Slot]) -> Vec<u8> {
+        let vote_len = if version == VOTE_STATE_CURRENT {
+            12
+        } else {
+            13
+        };
+        let mut data = vec![0u8; VOTES_OFFSET + 8 + slots.len() * vote_len + 64];
+        data[0..4].copy_from_slice(&version.to_le_bytes());
+        data[VOTES_OFFSET..VOTES_OFFSET + 8].copy_from_slice(&(slots.len() as u64).to_le_bytes());
+        for (index, slot) in slots.iter().enumerate() {
+            let from = VOTES_OFFSET + 8 + index * vote_len + vote_len - 12;
+            data[from..from + 8].copy_from_slice(&slot.to_le_bytes());
+            data[from + 8..from + 12].copy_from_slice(&1u32.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_last_voted_slot() {
+        for version in &[VOTE_STATE_CURRENT, VOTE_STATE_LANDED_VOTES] {
+            assert_eq!(last_voted_slot(&vote_state(*version, &[])), Ok(None));
+            assert_eq!(
+                last_voted_slot(&vote_state(*version, &[10, 11, 15])),
+                Ok(Some(15))
+            );
+        }
+        // uninitialized accounts never voted
+        assert_eq!(last_voted_slot(&[0; 3731]), Ok(None));
+        // unknown layouts and truncated data are rejected
+        assert_eq!(
+            last_voted_slot(&vote_state(3, &[10])),
+            Err(ProgramError::InvalidAccountData)
+        );
+        let mut data = vote_state(VOTE_STATE_CURRENT, &[10]);
+        data[VOTES_OFFSET..VOTES_OFFSET + 8].copy_from_slice(&100u64.to_le_bytes());
+        assert_eq!(
+            last_voted_slot(&data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+}
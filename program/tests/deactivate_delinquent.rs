@@ -0,0 +1,412 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use bincode::deserialize;
+use helpers::*;
+use solana_program::{
+    clock::{Clock, Slot},
+    epoch_schedule::EpochSchedule,
+    pubkey::Pubkey,
+};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::Account, instruction::InstructionError, signature::Keypair, signature::Signer,
+    transaction::Transaction, transaction::TransactionError, transport::TransportError,
+};
+use solana_vote_program::vote_state::{VoteInit, VoteState, VoteStateVersions};
+use spl_stake_pool::*;
+
+const RESERVE_LAMPORTS: u64 = 1_000_000_000;
+
+/// Vote account that last voted for `slot`
+fn vote_account(node: &Pubkey, slot: Slot) -> Account {
+    let mut vote_state = VoteState::new(
+        &VoteInit {
+            node_pubkey: *node,
+            authorized_voter: *node,
+            authorized_withdrawer: *node,
+            commission: 0,
+        },
+        &Clock::default(),
+    );
+    vote_state.process_slot_vote_unchecked(slot);
+    let mut data = vec![0; VoteState::size_of()];
+    VoteState::serialize(&VoteStateVersions::Current(Box::new(vote_state)), &mut data).unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: solana_vote_program::id(),
+        ..Account::default()
+    }
+}
+
+async fn deactivate_delinquent_validator(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    validator: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::deactivate_delinquent_validator(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.withdraw_authority,
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            validator,
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await?;
+    Ok(())
+}
+
+async fn set_delinquent_slots(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    staker: &Keypair,
+    delinquent_slots: u64,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_delinquent_slots(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &staker.pubkey(),
+            delinquent_slots,
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, staker], context.last_blockhash);
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await?;
+    Ok(())
+}
+
+/// Pool with one validator and a funded reserve, updated in the first normal epoch.
+/// The validator vote account last voted for `voted_slot`, or never voted
+async fn setup(
+    voted_slot: Option<Slot>,
+) -> (
+    ProgramTestContext,
+    EpochSchedule,
+    StakePoolAccounts,
+    ValidatorStakeAccount,
+) {
+    let stake_pool_accounts = StakePoolAccounts::new();
+    let validator_stake = ValidatorStakeAccount::new_with_target_authority(
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+    let mut program_test = program_test();
+    if let Some(slot) = voted_slot {
+        program_test.add_account(
+            validator_stake.vote.pubkey(),
+            vote_account(&validator_stake.vote.pubkey(), slot),
+        );
+    }
+    let mut context = program_test.start_with_context().await;
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    let validator_stake =
+        add_validator_stake_account_for(&mut context, &stake_pool_accounts, validator_stake).await;
+
+    // fund the reserve
+    let user = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user.pubkey(),
+        RESERVE_LAMPORTS + 1_000_000_000,
+    )
+    .await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+    stake_pool_accounts
+        .deposit_sol(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            RESERVE_LAMPORTS,
+        )
+        .await
+        .unwrap();
+
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + 1)
+        .unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    (
+        context,
+        epoch_schedule,
+        stake_pool_accounts,
+        validator_stake,
+    )
+}
+
+#[tokio::test]
+async fn test_deactivate_delinquent_validator() {
+    // the vote account of the test validator never votes
+    let (mut context, epoch_schedule, stake_pool_accounts, validator_stake) = setup(None).await;
+    let vote = validator_stake.vote.pubkey();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_validator_weights(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &[(vote, 3)],
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    deactivate_delinquent_validator(&mut context, &stake_pool_accounts, &vote)
+        .await
+        .unwrap();
+
+    // the validator is leaving and no longer targeted
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let info = *validator_stake_list.find(&vote).unwrap();
+    assert!(info.leaving);
+    assert_eq!(info.target_weight, 0);
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.total_target_weight, 0);
+    let stake_account =
+        get_account(&mut context.banks_client, &validator_stake.stake_account).await;
+    let stake_state = deserialize::<stake::StakeState>(&stake_account.data).unwrap();
+    assert_ne!(
+        stake_state.delegation().unwrap().deactivation_epoch,
+        std::u64::MAX
+    );
+
+    // leaving validators get no more stake
+    let transaction_error = stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &vote,
+            100_000_000,
+        )
+        .await
+        .err()
+        .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ValidatorLeaving as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to increase the stake of a leaving validator"),
+    }
+
+    // next epoch the stake is inactive, it goes to the reserve and the validator is removed
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + epoch_schedule.slots_per_epoch + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let reserve_before = get_account(
+        &mut context.banks_client,
+        &stake_pool_accounts.reserve_stake,
+    )
+    .await
+    .lamports;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let reserve = get_account(
+        &mut context.banks_client,
+        &stake_pool_accounts.reserve_stake,
+    )
+    .await;
+    assert_eq!(reserve.lamports, reserve_before + stake_account.lamports);
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    assert!(!validator_stake_list.contains(&vote));
+    assert!(context
+        .banks_client
+        .get_account(validator_stake.stake_account)
+        .await
+        .unwrap()
+        .is_none());
+    let stake_pool_after = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool_after.stake_total, stake_pool.stake_total);
+}
+
+#[tokio::test]
+async fn test_remove_delinquent_validator_after_transient_donation() {
+    let (mut context, epoch_schedule, stake_pool_accounts, validator_stake) = setup(None).await;
+    let vote = validator_stake.vote.pubkey();
+    let (transient_stake, _) = processor::Processor::find_transient_stake_address(
+        &id(),
+        &vote,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+
+    deactivate_delinquent_validator(&mut context, &stake_pool_accounts, &vote)
+        .await
+        .unwrap();
+
+    // lamports sent to the transient address don't keep the validator in the list
+    let rent = context.banks_client.get_rent().await.unwrap();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &transient_stake,
+        rent.minimum_balance(0),
+    )
+    .await;
+
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + epoch_schedule.slots_per_epoch + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    assert!(!validator_stake_list.contains(&vote));
+}
+
+#[tokio::test]
+async fn test_deactivate_validator_not_delinquent() {
+    let (mut context, epoch_schedule, stake_pool_accounts, validator_stake) = setup(Some(1)).await;
+    let vote = validator_stake.vote.pubkey();
+
+    // the default threshold is about an epoch
+    let transaction_error =
+        deactivate_delinquent_validator(&mut context, &stake_pool_accounts, &vote)
+            .await
+            .err()
+            .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ValidatorNotDelinquent as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deactivate a validator voting recently"),
+    }
+
+    set_delinquent_slots(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        1,
+    )
+    .await
+    .unwrap();
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.delinquent_slots, 1);
+
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + 2)
+        .unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    deactivate_delinquent_validator(&mut context, &stake_pool_accounts, &vote)
+        .await
+        .unwrap();
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    assert!(validator_stake_list.find(&vote).unwrap().leaving);
+}
+
+#[tokio::test]
+async fn test_set_delinquent_slots_with_wrong_staker() {
+    let (mut context, _, stake_pool_accounts, _) = setup(None).await;
+
+    let wrong_staker = Keypair::new();
+    let transaction_error =
+        set_delinquent_slots(&mut context, &stake_pool_accounts, &wrong_staker, 1)
+            .await
+            .err()
+            .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!(
+            "Wrong error occurs while try to set the delinquency threshold with wrong staker"
+        ),
+    }
+}
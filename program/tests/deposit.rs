@@ -5,7 +5,7 @@ mod helpers;
 use helpers::*;
 
 use solana_program::hash::Hash;
-use solana_program_test::BanksClient;
+use solana_program_test::{BanksClient, ProgramTestContext};
 use solana_sdk::{
     instruction::InstructionError,
     signature::{Keypair, Signer},
@@ -16,42 +16,28 @@ use solana_sdk::{
 use spl_stake_pool::*;
 use spl_token::error as token_error;
 
-async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
-    StakePoolAccounts,
-    ValidatorStakeAccount,
-) {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+async fn setup() -> (ProgramTestContext, StakePoolAccounts, ValidatorStakeAccount) {
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
-    (
-        banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        validator_stake_account,
-    )
+    (context, stake_pool_accounts, validator_stake_account)
 }
 
 #[tokio::main]
 #[test]
 async fn test_stake_pool_deposit() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, validator_stake_account) =
-        setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
@@ -65,26 +51,19 @@ async fn test_stake_pool_deposit() {
     };
 
     let stake_lamports = create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
     )
     .await;
 
-    create_vote(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &validator_stake_account.vote,
-    )
-    .await;
     delegate_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake.pubkey(),
         &stake_authority,
         &validator_stake_account.vote.pubkey(),
@@ -93,9 +72,9 @@ async fn test_stake_pool_deposit() {
 
     // Change authority to the stake pool's deposit
     authorize_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake.pubkey(),
         &stake_authority,
         &stake_pool_accounts.deposit_authority,
@@ -103,9 +82,9 @@ async fn test_stake_pool_deposit() {
     )
     .await;
     authorize_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake.pubkey(),
         &stake_authority,
         &stake_pool_accounts.deposit_authority,
@@ -113,12 +92,23 @@ async fn test_stake_pool_deposit() {
     )
     .await;
 
+    // Only fully active stake is accepted
+    warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -127,14 +117,11 @@ async fn test_stake_pool_deposit() {
     .unwrap();
 
     // Save stake pool state before depositing
-    let stake_pool_account =
-        get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool_before =
-        state::StakePool::deserialize(&stake_pool_account.data.as_slice()).unwrap();
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
 
     // Save validator stake account record before depositing
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -146,9 +133,9 @@ async fn test_stake_pool_deposit() {
 
     stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -157,7 +144,8 @@ async fn test_stake_pool_deposit() {
         .unwrap();
 
     // Original stake account should be drained
-    assert!(banks_client
+    assert!(context
+        .banks_client
         .get_account(user_stake.pubkey())
         .await
         .expect("get_account")
@@ -167,8 +155,7 @@ async fn test_stake_pool_deposit() {
     let fee = stake_pool_accounts.calculate_fee(tokens_issued);
 
     // Stake pool should add its balance to the pool balance
-    let stake_pool = get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool = state::StakePool::deserialize(&stake_pool.data.as_slice()).unwrap();
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
     assert_eq!(
         stake_pool.stake_total,
         stake_pool_before.stake_total + stake_lamports
@@ -180,19 +167,19 @@ async fn test_stake_pool_deposit() {
 
     // Check minted tokens
     let user_token_balance =
-        get_token_balance(&mut banks_client, &user_pool_account.pubkey()).await;
-    assert_eq!(user_token_balance, tokens_issued);// - fee);
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
+    assert_eq!(user_token_balance, tokens_issued - fee);
 
-    // let pool_fee_token_balance = get_token_balance(
-    //     &mut banks_client,
-    //     &stake_pool_accounts.pool_fee_account.pubkey(),
-    // )
-    // .await;
-    // assert_eq!(pool_fee_token_balance, fee);
+    let pool_fee_token_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    assert_eq!(pool_fee_token_balance, fee);
 
     // Check balances in validator stake account list storage
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -207,8 +194,11 @@ async fn test_stake_pool_deposit() {
     );
 
     // Check validator stake account actual SOL balance
-    let validator_stake_account =
-        get_account(&mut banks_client, &validator_stake_account.stake_account).await;
+    let validator_stake_account = get_account(
+        &mut context.banks_client,
+        &validator_stake_account.stake_account,
+    )
+    .await;
     assert_eq!(
         validator_stake_account.lamports,
         validator_stake_item.balance
@@ -217,8 +207,7 @@ async fn test_stake_pool_deposit() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_stake_program_id() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, validator_stake_account) =
-        setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
@@ -227,9 +216,9 @@ async fn test_stake_pool_deposit_with_wrong_stake_program_id() {
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -255,10 +244,11 @@ async fn test_stake_pool_deposit_with_wrong_stake_program_id() {
             &wrong_stake_program.pubkey(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -274,38 +264,25 @@ async fn test_stake_pool_deposit_with_wrong_stake_program_id() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_pool_fee_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        mut stake_pool_accounts,
-        validator_stake_account,
-    ) = setup().await;
+    let (mut context, mut stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -318,9 +295,9 @@ async fn test_stake_pool_deposit_with_wrong_pool_fee_account() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -341,35 +318,74 @@ async fn test_stake_pool_deposit_with_wrong_pool_fee_account() {
     }
 }
 
+#[tokio::test]
+async fn test_stake_pool_deposit_with_zero_fee_denominator() {
+    let mut context = program_test().start_with_context().await;
+    let mut stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts.fee = instruction::Fee {
+        numerator: 0,
+        denominator: 0,
+    };
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+
+    let deposit_info = simple_deposit(
+        &mut context,
+        false,
+        &stake_pool_accounts,
+        &validator_stake_account,
+    )
+    .await;
+
+    // No fee is charged: the user receives every minted token
+    assert_eq!(deposit_info.pool_tokens, deposit_info.stake_lamports);
+
+    let pool_fee_token_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    assert_eq!(pool_fee_token_balance, 0);
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    assert_eq!(
+        stake_pool.pool_total,
+        stake_pool_before.pool_total + deposit_info.pool_tokens
+    );
+}
+
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_token_program_id() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, validator_stake_account) =
-        setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -395,10 +411,11 @@ async fn test_stake_pool_deposit_with_wrong_token_program_id() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -414,38 +431,25 @@ async fn test_stake_pool_deposit_with_wrong_token_program_id() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_validator_stake_list_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        mut stake_pool_accounts,
-        validator_stake_account,
-    ) = setup().await;
+    let (mut context, mut stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -458,9 +462,9 @@ async fn test_stake_pool_deposit_with_wrong_validator_stake_list_account() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -483,10 +487,14 @@ async fn test_stake_pool_deposit_with_wrong_validator_stake_list_account() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -497,9 +505,9 @@ async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
 
     let user_stake_authority = Keypair::new();
     create_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &validator_stake_account.stake_pool,
         &validator_stake_account.stake_account,
         &validator_stake_account.vote.pubkey(),
@@ -511,9 +519,9 @@ async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
     let user_pool_account = Keypair::new();
     let user = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -528,9 +536,9 @@ async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
         withdrawer: stake_pool_accounts.deposit_authority,
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_acc,
         &authorized,
         &lockup,
@@ -538,9 +546,9 @@ async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
     .await;
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_acc.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -565,10 +573,14 @@ async fn test_stake_pool_deposit_where_stake_acc_not_in_stake_state() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_to_unknown_validator() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -577,15 +589,19 @@ async fn test_stake_pool_deposit_to_unknown_validator() {
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     validator_stake_account
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
     let user_pool_account = Keypair::new();
     let user = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -595,26 +611,19 @@ async fn test_stake_pool_deposit_to_unknown_validator() {
 
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -639,38 +648,25 @@ async fn test_stake_pool_deposit_to_unknown_validator() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_deposit_authority() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        mut stake_pool_accounts,
-        validator_stake_account,
-    ) = setup().await;
+    let (mut context, mut stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -682,9 +678,9 @@ async fn test_stake_pool_deposit_with_wrong_deposit_authority() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -707,38 +703,25 @@ async fn test_stake_pool_deposit_with_wrong_deposit_authority() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_withdraw_authority() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        mut stake_pool_accounts,
-        validator_stake_account,
-    ) = setup().await;
+    let (mut context, mut stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -750,9 +733,9 @@ async fn test_stake_pool_deposit_with_wrong_withdraw_authority() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -775,32 +758,61 @@ async fn test_stake_pool_deposit_with_wrong_withdraw_authority() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_set_deposit_authority() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, validator_stake_account) =
-        setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
 
     let user = Keypair::new();
     // make stake account
     let user_stake = Keypair::new();
     let lockup = stake::Lockup::default();
+    let stake_authority = Keypair::new();
     let authorized = stake::Authorized {
-        staker: Keypair::new().pubkey(),
-        withdrawer: stake_pool_accounts.deposit_authority,
+        staker: stake_authority.pubkey(),
+        withdrawer: stake_authority.pubkey(),
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
     )
     .await;
+    delegate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+        &validator_stake_account.vote.pubkey(),
+    )
+    .await;
+    // the staker is not set to the deposit authority
+    authorize_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+        &stake_pool_accounts.deposit_authority,
+        stake::StakeAuthorize::Withdrawer,
+    )
+    .await;
+    warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -810,9 +822,9 @@ async fn test_stake_pool_deposit_with_wrong_set_deposit_authority() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -833,23 +845,15 @@ async fn test_stake_pool_deposit_with_wrong_set_deposit_authority() {
 
 #[tokio::test]
 async fn test_stake_pool_deposit_with_wrong_mint_for_receiver_acc() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, validator_stake_account) =
-        setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
 
     // make stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
 
@@ -859,9 +863,9 @@ async fn test_stake_pool_deposit_with_wrong_mint_for_receiver_acc() {
     let outside_pool_fee_acc = Keypair::new();
 
     create_mint(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &outside_mint,
         &outside_withdraw_auth.pubkey(),
     )
@@ -869,9 +873,9 @@ async fn test_stake_pool_deposit_with_wrong_mint_for_receiver_acc() {
     .unwrap();
 
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &outside_pool_fee_acc,
         &outside_mint.pubkey(),
         &outside_owner.pubkey(),
@@ -881,9 +885,9 @@ async fn test_stake_pool_deposit_with_wrong_mint_for_receiver_acc() {
 
     let transaction_error = stake_pool_accounts
         .deposit_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &outside_pool_fee_acc.pubkey(),
             &validator_stake_account.stake_account,
@@ -904,6 +908,355 @@ async fn test_stake_pool_deposit_with_wrong_mint_for_receiver_acc() {
     }
 }
 
+#[tokio::test]
+async fn test_stake_pool_deposit_with_activating_stake() {
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
+
+    let user = Keypair::new();
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // Delegate in the current epoch, the stake is still activating
+    let user_stake = Keypair::new();
+    let stake_authority = Keypair::new();
+    let authorized = stake::Authorized {
+        staker: stake_authority.pubkey(),
+        withdrawer: stake_authority.pubkey(),
+    };
+    create_independent_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake,
+        &authorized,
+        &stake::Lockup::default(),
+    )
+    .await;
+    delegate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+        &validator_stake_account.vote.pubkey(),
+    )
+    .await;
+    for stake_authorize in &[
+        stake::StakeAuthorize::Staker,
+        stake::StakeAuthorize::Withdrawer,
+    ] {
+        authorize_stake_account(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.pubkey(),
+            &stake_authority,
+            &stake_pool_accounts.deposit_authority,
+            *stake_authorize,
+        )
+        .await;
+    }
+
+    let transaction_error = stake_pool_accounts
+        .deposit_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.pubkey(),
+            &user_pool_account.pubkey(),
+            &validator_stake_account.stake_account,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::UserStakeNotActive as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit activating stake"),
+    }
+}
+
+#[tokio::test]
+async fn test_stake_pool_deposit_with_deactivating_stake() {
+    let (mut context, stake_pool_accounts, validator_stake_account) = setup().await;
+
+    let user = Keypair::new();
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let user_stake = Keypair::new();
+    let stake_authority = Keypair::new();
+    let authorized = stake::Authorized {
+        staker: stake_authority.pubkey(),
+        withdrawer: stake_authority.pubkey(),
+    };
+    create_independent_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake,
+        &authorized,
+        &stake::Lockup::default(),
+    )
+    .await;
+    delegate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+        &validator_stake_account.vote.pubkey(),
+    )
+    .await;
+
+    // Fully activate, then deactivate in the same epoch as the deposit
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+    deactivate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+    )
+    .await;
+    for stake_authorize in &[
+        stake::StakeAuthorize::Staker,
+        stake::StakeAuthorize::Withdrawer,
+    ] {
+        authorize_stake_account(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.pubkey(),
+            &stake_authority,
+            &stake_pool_accounts.deposit_authority,
+            *stake_authorize,
+        )
+        .await;
+    }
+
+    let transaction_error = stake_pool_accounts
+        .deposit_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.pubkey(),
+            &user_pool_account.pubkey(),
+            &validator_stake_account.stake_account,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::UserStakeNotActive as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit deactivating stake"),
+    }
+}
+
+async fn setup_deposit_sol_user(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    lamports: u64,
+) -> (Keypair, Keypair) {
+    let user = Keypair::new();
+    transfer(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &user.pubkey(),
+        lamports,
+    )
+    .await;
+
+    // make pool token account
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    (user, user_pool_account)
+}
+
+#[tokio::test]
+async fn test_stake_pool_deposit_sol() {
+    let (mut context, stake_pool_accounts, _) = setup().await;
+
+    let deposit_lamports = 1_000_000_000;
+    let (user, user_pool_account) = setup_deposit_sol_user(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        3 * deposit_lamports,
+    )
+    .await;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let reserve_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+
+    // the reserve is created with the pool, each deposit adds to it
+    for deposit_number in 1..=2u64 {
+        let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+        let user_token_balance_before =
+            get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
+        let fee_token_balance_before = get_token_balance(
+            &mut context.banks_client,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+        )
+        .await;
+
+        stake_pool_accounts
+            .deposit_sol(
+                &mut context.banks_client,
+                &context.payer,
+                &context.last_blockhash,
+                &user,
+                &user_pool_account.pubkey(),
+                deposit_lamports,
+            )
+            .await
+            .unwrap();
+
+        let tokens_issued = stake_pool_before
+            .calc_pool_deposit_amount(deposit_lamports)
+            .unwrap();
+        let fee = stake_pool_accounts.calculate_fee(tokens_issued);
+
+        // Check minted tokens, the fee goes to the owner
+        assert_eq!(
+            get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await,
+            user_token_balance_before + tokens_issued - fee
+        );
+        assert_eq!(
+            get_token_balance(
+                &mut context.banks_client,
+                &stake_pool_accounts.pool_fee_account.pubkey()
+            )
+            .await,
+            fee_token_balance_before + fee
+        );
+
+        // Check pool stats
+        let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+        assert_eq!(
+            stake_pool.stake_total,
+            stake_pool_before.stake_total + deposit_lamports
+        );
+        assert_eq!(
+            stake_pool.pool_total,
+            stake_pool_before.pool_total + tokens_issued
+        );
+
+        // Check the reserve holds the lamports, in a not delegated stake account
+        let reserve = get_account(
+            &mut context.banks_client,
+            &stake_pool_accounts.reserve_stake,
+        )
+        .await;
+        assert_eq!(reserve.owner, stake::id());
+        assert_eq!(
+            reserve.lamports,
+            reserve_rent + deposit_number * deposit_lamports
+        );
+        match bincode::deserialize::<stake::StakeState>(&reserve.data).unwrap() {
+            stake::StakeState::Initialized(meta) => {
+                assert_eq!(
+                    meta.authorized.staker,
+                    stake_pool_accounts.withdraw_authority
+                );
+                assert_eq!(
+                    meta.authorized.withdrawer,
+                    stake_pool_accounts.withdraw_authority
+                );
+            }
+            _ => panic!("Reserve stake account is not initialized"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_stake_pool_deposit_sol_with_wrong_pool_fee_account() {
+    let (mut context, mut stake_pool_accounts, _) = setup().await;
+
+    let (user, user_pool_account) = setup_deposit_sol_user(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        1_000_000_000,
+    )
+    .await;
+
+    stake_pool_accounts.pool_fee_account = Keypair::new();
+
+    let transaction_error = stake_pool_accounts
+        .deposit_sol(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            100_000_000,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidFeeAccount as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit SOL with wrong pool fee account"),
+    }
+}
+
 #[tokio::test]
 async fn test_deposit_with_uninitialized_validator_stake_list() {} // TODO
 
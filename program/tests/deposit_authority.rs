@@ -0,0 +1,574 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+async fn setup() -> (ProgramTestContext, StakePoolAccounts, Keypair, Keypair) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    // the owner pays the depositor list rent
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts.owner.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+
+    // user with SOL and a pool token account
+    let user = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user.pubkey(),
+        10_000_000_000,
+    )
+    .await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    (context, stake_pool_accounts, user, user_pool_account)
+}
+
+async fn set_deposit_authority(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    deposit_authority: &Pubkey,
+) -> Result<(), TransportError> {
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction::set_deposit_authority(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            deposit_authority,
+        )
+        .unwrap(),
+        &[owner],
+    )
+    .await
+}
+
+async fn set_depositor(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    depositor: &Pubkey,
+    allowed: bool,
+) -> Result<(), TransportError> {
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction::set_depositor(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            depositor,
+            allowed,
+        )
+        .unwrap(),
+        &[owner],
+    )
+    .await
+}
+
+/// Deposits SOL from `user`, with the depositor accounts when `depositor` is set
+async fn deposit_sol(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    user: &Keypair,
+    user_pool_account: &Pubkey,
+    depositor: Option<&Keypair>,
+    lamports: u64,
+) -> Result<(), TransportError> {
+    let mut instruction = instruction::deposit_sol(
+        &id(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &stake_pool_accounts.withdraw_authority,
+        &stake_pool_accounts.reserve_stake,
+        &user.pubkey(),
+        user_pool_account,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &spl_token::id(),
+        lamports,
+    )
+    .unwrap();
+    let mut signers = vec![user];
+    if let Some(depositor) = depositor {
+        let stake_pool = stake_pool_accounts
+            .get_stake_pool(&mut context.banks_client)
+            .await;
+        instruction = instruction::with_depositor(
+            instruction,
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &depositor.pubkey(),
+            depositor.pubkey() == stake_pool.deposit_authority,
+        );
+        if depositor.pubkey() != user.pubkey() {
+            signers.push(depositor);
+        }
+    }
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction,
+        &signers,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_set_deposit_authority() {
+    let (mut context, stake_pool_accounts, _, _) = setup().await;
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert!(!stake_pool.has_deposit_authority());
+
+    let deposit_authority = Keypair::new();
+    set_deposit_authority(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &deposit_authority.pubkey(),
+    )
+    .await
+    .unwrap();
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert!(stake_pool.has_deposit_authority());
+    assert_eq!(stake_pool.deposit_authority, deposit_authority.pubkey());
+
+    // The default pubkey opens the pool again
+    set_deposit_authority(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &Pubkey::default(),
+    )
+    .await
+    .unwrap();
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert!(!stake_pool.has_deposit_authority());
+}
+
+#[tokio::test]
+async fn test_set_deposit_authority_with_wrong_owner() {
+    let (mut context, stake_pool_accounts, _, _) = setup().await;
+
+    let wrong_owner = Keypair::new();
+    let transaction_error = set_deposit_authority(
+        &mut context,
+        &stake_pool_accounts,
+        &wrong_owner,
+        &wrong_owner.pubkey(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set deposit authority with wrong owner"),
+    }
+}
+
+#[tokio::test]
+async fn test_permissionless_deposit_sol() {
+    let (mut context, stake_pool_accounts, user, user_pool_account) = setup().await;
+
+    // Without a deposit authority no depositor accounts are needed
+    deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        None,
+        1_000_000_000,
+    )
+    .await
+    .unwrap();
+    assert!(get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await > 0);
+}
+
+#[tokio::test]
+async fn test_permissioned_deposit_sol() {
+    let (mut context, stake_pool_accounts, user, user_pool_account) = setup().await;
+
+    let deposit_authority = Keypair::new();
+    set_deposit_authority(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &deposit_authority.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // Depositor accounts are required
+    let transaction_error = deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        None,
+        1_000_000_000,
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::NotEnoughAccountKeys,
+        )) => {}
+        _ => panic!("Wrong error occurs while try to deposit SOL without depositor"),
+    }
+
+    // The deposit authority signs
+    deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        Some(&deposit_authority),
+        1_000_000_000,
+    )
+    .await
+    .unwrap();
+    let balance = get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
+    assert!(balance > 0);
+
+    // The user is not in the depositor list yet
+    let transaction_error = deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        Some(&user),
+        2_000_000_000,
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::DepositorNotAllowed as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit SOL with unlisted depositor"),
+    }
+
+    set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &user.pubkey(),
+        true,
+    )
+    .await
+    .unwrap();
+    deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        Some(&user),
+        3_000_000_000,
+    )
+    .await
+    .unwrap();
+    assert!(
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await > balance
+    );
+
+    // Removed depositors can't deposit anymore
+    set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &user.pubkey(),
+        false,
+    )
+    .await
+    .unwrap();
+    let transaction_error = deposit_sol(
+        &mut context,
+        &stake_pool_accounts,
+        &user,
+        &user_pool_account.pubkey(),
+        Some(&user),
+        4_000_000_000,
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::DepositorNotAllowed as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit SOL with removed depositor"),
+    }
+}
+
+#[tokio::test]
+async fn test_permissioned_deposit() {
+    let (mut context, stake_pool_accounts, user, user_pool_account) = setup().await;
+    let validator_stake_account =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+
+    let deposit_authority = Keypair::new();
+    set_deposit_authority(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &deposit_authority.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let user_stake = Keypair::new();
+    create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
+        &user_stake,
+    )
+    .await;
+    let deposit = instruction::deposit(
+        &id(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.withdraw_authority,
+        &user_stake.pubkey(),
+        &validator_stake_account.stake_account,
+        &user_pool_account.pubkey(),
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &spl_token::id(),
+        &stake::id(),
+    )
+    .unwrap();
+
+    // A depositor not in the list
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction::with_depositor(
+            deposit.clone(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &user.pubkey(),
+            false,
+        ),
+        &[&user],
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::DepositorNotAllowed as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to deposit with unlisted depositor"),
+    }
+
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction::with_depositor(
+            deposit,
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &deposit_authority.pubkey(),
+            true,
+        ),
+        &[&deposit_authority],
+    )
+    .await
+    .unwrap();
+    assert!(get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await > 0);
+}
+
+#[tokio::test]
+async fn test_set_depositor() {
+    let (mut context, stake_pool_accounts, _, _) = setup().await;
+    let (depositor_list, _) = processor::Processor::find_depositor_list_address(
+        &id(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+
+    let depositors = [Pubkey::new_unique(), Pubkey::new_unique()];
+    for depositor in depositors.iter() {
+        set_depositor(
+            &mut context,
+            &stake_pool_accounts,
+            &stake_pool_accounts.owner,
+            depositor,
+            true,
+        )
+        .await
+        .unwrap();
+    }
+    let account = get_account(&mut context.banks_client, &depositor_list).await;
+    assert_eq!(account.owner, id());
+    assert_eq!(
+        state::DepositorList::deserialize(account.data.as_slice())
+            .unwrap()
+            .depositors,
+        depositors.to_vec()
+    );
+
+    set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &depositors[0],
+        false,
+    )
+    .await
+    .unwrap();
+    let account = get_account(&mut context.banks_client, &depositor_list).await;
+    assert_eq!(
+        state::DepositorList::deserialize(account.data.as_slice())
+            .unwrap()
+            .depositors,
+        vec![depositors[1]]
+    );
+
+    // Already in the list
+    let transaction_error = set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &depositors[1],
+        true,
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::AlreadyInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to add a listed depositor"),
+    }
+
+    // Not in the list
+    let transaction_error = set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &depositors[0],
+        false,
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::DepositorNotAllowed as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to remove an unlisted depositor"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_depositor_with_wrong_owner() {
+    let (mut context, stake_pool_accounts, _, _) = setup().await;
+
+    let wrong_owner = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wrong_owner.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+    let transaction_error = set_depositor(
+        &mut context,
+        &stake_pool_accounts,
+        &wrong_owner,
+        &wrong_owner.pubkey(),
+        true,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set a depositor with wrong owner"),
+    }
+}
@@ -7,7 +7,9 @@ const LIQ_POOL_ACCOUNT:&str="rxTBFFRfwcgx5YedbwLcKntCwMs9tJoQvzYmRnbpLKS";
 const LIQ_POOL_WSOL_ACCOUNT:&str="7efxCKtTp5DDScftcEFSsxHPgqAk8WxuRAi786cg3qBZ"; //TODO
 const LIQ_POOL_ST_SOL_ACCOUNT:&str="DWB8abtU8B2A3EQpY2dWxaHnPiVwXgHRQSaGBGAgdBj7"; //TODO
 
-use solana_program::{hash::Hash, program_pack::Pack, pubkey::Pubkey, system_instruction};
+use solana_program::{
+    hash::Hash, instruction::Instruction, program_pack::Pack, pubkey::Pubkey, system_instruction,
+};
 use solana_program_test::*;
 use solana_sdk::{
     account::Account,
@@ -28,6 +30,18 @@ pub fn program_test() -> ProgramTest {
     )
 }
 
+/// Warps to the first slot of the next epoch, stake delegated before is active there
+pub async fn warp_to_next_epoch(context: &mut ProgramTestContext) -> Hash {
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    let slot = context.banks_client.get_root_slot().await.unwrap();
+    let next_epoch = epoch_schedule.get_epoch(slot) + 1;
+    context
+        .warp_to_slot(epoch_schedule.get_first_slot_in_epoch(next_epoch))
+        .unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    context.last_blockhash
+}
+
 pub async fn get_account(banks_client: &mut BanksClient, pubkey: &Pubkey) -> Account {
     banks_client
         .get_account(*pubkey)
@@ -36,6 +50,29 @@ pub async fn get_account(banks_client: &mut BanksClient, pubkey: &Pubkey) -> Acc
         .expect("account empty")
 }
 
+pub async fn process_instruction(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    transaction.sign(&all_signers, *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+pub async fn get_validator_stake_list(
+    banks_client: &mut BanksClient,
+    validator_stake_list: &Pubkey,
+) -> state::ValidatorStakeList {
+    let validator_stake_list = get_account(banks_client, validator_stake_list).await;
+    state::ValidatorStakeList::deserialize(validator_stake_list.data.as_slice()).unwrap()
+}
+
 pub async fn create_mint(
     banks_client: &mut BanksClient,
     payer: &Keypair,
@@ -206,15 +243,22 @@ pub async fn create_stake_pool(
     pool_mint: &Pubkey,
     pool_token_account: &Pubkey,
     owner: &Keypair,
-    fee: &instruction::Fee,
+    init_args: instruction::InitArgs,
 ) -> Result<(), TransportError> {
     let rent = banks_client.get_rent().await.unwrap();
     let rent_stake_pool = rent.minimum_balance(state::StakePool::LEN);
+    let rent_stake_pool_extension = rent.minimum_balance(state::StakePool::EXTENSION_LEN);
+    let rent_reserve_stake = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
     let rent_validator_stake_list = rent.minimum_balance(state::ValidatorStakeList::LEN);
-    let init_args = instruction::InitArgs { fee: *fee };
 
     let mut transaction = Transaction::new_with_payer(
         &[
+            // the owner pays the stake pool extension and reserve rent
+            system_instruction::transfer(
+                &payer.pubkey(),
+                &owner.pubkey(),
+                rent_stake_pool_extension + rent_reserve_stake,
+            ),
             system_instruction::create_account(
                 &payer.pubkey(),
                 &stake_pool.pubkey(),
@@ -310,7 +354,7 @@ pub async fn create_account(
 ) -> Result<(), TransportError> { 
     
     let rent = banks_client.get_rent().await.unwrap();
-    let lamports = rent.minimum_balance(state::StakePool::LEN);
+    let lamports = rent.minimum_balance(state::LiquidityPool::LEN);
 
     let mut transaction = Transaction::new_with_payer(
         &[
@@ -318,7 +362,7 @@ pub async fn create_account(
                 &payer.pubkey(),
                 &account.pubkey(),
                 lamports,
-                spl_token::state::Account::LEN as u64,
+                state::LiquidityPool::LEN as u64,
                 &id(),
             ),
         ],
@@ -330,6 +374,43 @@ pub async fn create_account(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+pub async fn create_liquidity_pool(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    liq_pool: &Pubkey,
+    stake_pool: &Pubkey,
+    owner: &Keypair,
+    liq_pool_wsol_account: &Pubkey,
+    liq_pool_st_sol_account: &Pubkey,
+    meta_lp_mint: &Pubkey,
+    liq_pool_authority: &Pubkey,
+    treasury_wsol_account: &Pubkey,
+    sell_fee: &state::SellFeeCurve,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::initialize_liquidity_pool(
+            &id(),
+            liq_pool,
+            stake_pool,
+            &owner.pubkey(),
+            liq_pool_wsol_account,
+            liq_pool_st_sol_account,
+            meta_lp_mint,
+            liq_pool_authority,
+            &spl_token::id(),
+            treasury_wsol_account,
+            *sell_fee,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
 pub async fn create_blank_stake_account(
     banks_client: &mut BanksClient,
     payer: &Keypair,
@@ -403,6 +484,21 @@ pub async fn delegate_stake_account(
     banks_client.process_transaction(transaction).await.unwrap();
 }
 
+pub async fn deactivate_stake_account(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake: &Pubkey,
+    authorized: &Keypair,
+) {
+    let mut transaction = Transaction::new_with_payer(
+        &[stake::deactivate_stake(&stake, &authorized.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, authorized], *recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+}
+
 pub async fn authorize_stake_account(
     banks_client: &mut BanksClient,
     payer: &Keypair,
@@ -468,7 +564,15 @@ impl ValidatorStakeAccount {
         )
         .await;
 
-        create_vote(&mut banks_client, &payer, &recent_blockhash, &self.vote).await;
+        // tests needing votes add the vote account at genesis
+        if banks_client
+            .get_account(self.vote.pubkey())
+            .await
+            .unwrap()
+            .is_none()
+        {
+            create_vote(&mut banks_client, &payer, &recent_blockhash, &self.vote).await;
+        }
         delegate_stake_account(
             &mut banks_client,
             &payer,
@@ -500,13 +604,20 @@ pub struct StakePoolAccounts {
     pub owner: Keypair,
     pub withdraw_authority: Pubkey,
     pub deposit_authority: Pubkey,
+    pub reserve_stake: Pubkey,
+    pub stake_pool_extension: Pubkey,
     pub fee: instruction::Fee,
+    pub reward_fee: instruction::Fee,
+    pub withdrawal_fee: instruction::Fee,
+    pub withdrawal_fee_to_owner: u8,
 
     pub liq_pool_state_acc: Keypair,
     pub liq_pool_authority: Pubkey,
     pub meta_lp_mint_acc: Keypair,
     pub liq_pool_wsol_acc: Keypair,
     pub liq_pool_st_sol_acc: Keypair,
+    pub liq_pool_sell_fee: state::SellFeeCurve,
+    pub liq_pool_treasury_acc: Keypair,
 }
 
 impl StakePoolAccounts {
@@ -525,6 +636,14 @@ impl StakePoolAccounts {
             &[&stake_pool_address.to_bytes()[..32], b"deposit"],
             &id(),
         );
+        let (reserve_stake, _) = Pubkey::find_program_address(
+            &[&stake_pool_address.to_bytes()[..32], b"reserve"],
+            &id(),
+        );
+        let (stake_pool_extension, _) = Pubkey::find_program_address(
+            &[&stake_pool_address.to_bytes()[..32], b"extension"],
+            &id(),
+        );
 
         let pool_mint = Keypair::new();
         let pool_fee_account = Keypair::new();
@@ -557,19 +676,52 @@ impl StakePoolAccounts {
             owner,
             withdraw_authority,
             deposit_authority,
+            reserve_stake,
+            stake_pool_extension,
             fee: instruction::Fee {
                 numerator: 1,
                 denominator: 100,
             },
+            reward_fee: instruction::Fee {
+                numerator: 5,
+                denominator: 100,
+            },
+            withdrawal_fee: instruction::Fee::default(),
+            withdrawal_fee_to_owner: 0,
             liq_pool_state_acc,
             liq_pool_authority,
             meta_lp_mint_acc,
             liq_pool_wsol_acc,
             liq_pool_st_sol_acc,
-            }
+            liq_pool_sell_fee: state::SellFeeCurve {
+                min_fee_bps: 30,
+                max_fee_bps: 300,
+                target_liquidity: 1_000_000,
+            },
+            liq_pool_treasury_acc: Keypair::new(),
+        }
+    }
+
+    pub fn init_args(&self) -> instruction::InitArgs {
+        instruction::InitArgs {
+            fee: self.fee,
+            reward_fee: self.reward_fee,
+            withdrawal_fee: self.withdrawal_fee,
+            withdrawal_fee_to_owner: self.withdrawal_fee_to_owner,
+        }
+    }
+
+    pub fn calculate_withdrawal_fee(&self, amount: u64) -> u64 {
+        if self.withdrawal_fee.denominator == 0 {
+            return 0;
+        }
+        amount * self.withdrawal_fee.numerator / self.withdrawal_fee.denominator
     }
 
     pub fn calculate_fee(&self, amount: u64) -> u64 {
+        if self.fee.denominator == 0 {
+            return 0;
+        }
         amount * self.fee.numerator / self.fee.denominator
     }
 
@@ -607,11 +759,11 @@ impl StakePoolAccounts {
             &self.pool_mint.pubkey(),
             &self.pool_fee_account.pubkey(),
             &self.owner,
-            &self.fee,
+            self.init_args(),
         )
         .await?;
 
-        //liq pool state account (not used yet)
+        //liq pool state account
         create_account(
             &mut banks_client,
             &payer,
@@ -654,6 +806,33 @@ impl StakePoolAccounts {
         )
         .await?;
 
+        // treasury wsol acc, receives the treasury share of the sell fee
+        create_token_account(
+            &mut banks_client,
+            &payer,
+            &recent_blockhash,
+            &self.liq_pool_treasury_acc,
+            &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+            &self.owner.pubkey(),
+        )
+        .await?;
+
+        create_liquidity_pool(
+            &mut banks_client,
+            &payer,
+            &recent_blockhash,
+            &self.liq_pool_state_acc.pubkey(),
+            &self.stake_pool.pubkey(),
+            &self.owner,
+            &self.liq_pool_wsol_acc.pubkey(),
+            &self.liq_pool_st_sol_acc.pubkey(),
+            &self.meta_lp_mint_acc.pubkey(),
+            &self.liq_pool_authority,
+            &self.liq_pool_treasury_acc.pubkey(),
+            &self.liq_pool_sell_fee,
+        )
+        .await?;
+
         Ok(())
     }
 
@@ -690,6 +869,235 @@ impl StakePoolAccounts {
         Ok(())
     }
 
+    pub async fn deposit_sol(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+        user: &Keypair,
+        pool_account: &Pubkey,
+        lamports: u64,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::deposit_sol(
+                &id(),
+                &self.stake_pool.pubkey(),
+                &self.withdraw_authority,
+                &self.reserve_stake,
+                &user.pubkey(),
+                pool_account,
+                &self.pool_fee_account.pubkey(),
+                &self.pool_mint.pubkey(),
+                &spl_token::id(),
+                lamports,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, user], *recent_blockhash);
+        banks_client.process_transaction(transaction).await?;
+        Ok(())
+    }
+
+    pub async fn get_stake_pool(&self, banks_client: &mut BanksClient) -> state::StakePool {
+        let stake_pool = get_account(banks_client, &self.stake_pool.pubkey()).await;
+        let extension = get_account(banks_client, &self.stake_pool_extension).await;
+        assert_eq!(extension.owner, id());
+        state::StakePool::deserialize(stake_pool.data.as_slice(), extension.data.as_slice())
+            .unwrap()
+    }
+
+    pub async fn get_rate_history(&self, banks_client: &mut BanksClient) -> state::RateHistory {
+        let (rate_history, _) =
+            processor::Processor::find_rate_history_address(&id(), &self.stake_pool.pubkey());
+        let rate_history = get_account(banks_client, &rate_history).await;
+        assert_eq!(rate_history.owner, id());
+        state::RateHistory::deserialize(rate_history.data.as_slice()).unwrap()
+    }
+
+    /// Validator stake list pages linked to the pool, `validator_stake_list` first
+    pub async fn get_validator_stake_list_pages(
+        &self,
+        banks_client: &mut BanksClient,
+    ) -> Vec<Pubkey> {
+        self.get_stake_pool(banks_client)
+            .await
+            .validator_stake_list_pages()
+            .cloned()
+            .collect()
+    }
+
+    /// Info of a validator, looked up in every page of the pool
+    pub async fn get_validator_stake_info(
+        &self,
+        banks_client: &mut BanksClient,
+        validator: &Pubkey,
+    ) -> state::ValidatorStakeInfo {
+        for page in self.get_validator_stake_list_pages(banks_client).await {
+            let validator_stake_list = get_validator_stake_list(banks_client, &page).await;
+            if let Some(info) = validator_stake_list.find(validator) {
+                return *info;
+            }
+        }
+        panic!("validator not found in the pool");
+    }
+
+    pub async fn update_pool_balance(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+    ) -> Result<(), TransportError> {
+        let pages = self.get_validator_stake_list_pages(banks_client).await;
+        let stake_pool = self.get_stake_pool(banks_client).await;
+        let mut update_pool_balance = instruction::update_pool_balance(
+            &id(),
+            &self.stake_pool.pubkey(),
+            &self.validator_stake_list.pubkey(),
+            &self.reserve_stake,
+            &self.withdraw_authority,
+            &self.pool_fee_account.pubkey(),
+            &self.pool_mint.pubkey(),
+            &spl_token::id(),
+            &pages[1..],
+        )
+        .unwrap();
+        if stake_pool.has_rate_history() {
+            update_pool_balance =
+                instruction::with_rate_history(update_pool_balance, &self.stake_pool.pubkey());
+        }
+        let mut transaction =
+            Transaction::new_with_payer(&[update_pool_balance], Some(&payer.pubkey()));
+        transaction.sign(&[payer], *recent_blockhash);
+        banks_client.process_transaction(transaction).await?;
+        Ok(())
+    }
+
+    pub async fn update_list_balance(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+        validators: &[Pubkey],
+    ) -> Result<(), TransportError> {
+        self.update_list_balance_page(
+            banks_client,
+            payer,
+            recent_blockhash,
+            &self.validator_stake_list.pubkey(),
+            validators,
+        )
+        .await
+    }
+
+    pub async fn update_list_balance_page(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+        validator_stake_list_page: &Pubkey,
+        validators: &[Pubkey],
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::update_list_balance(
+                &id(),
+                &self.stake_pool.pubkey(),
+                validator_stake_list_page,
+                &self.withdraw_authority,
+                &self.reserve_stake,
+                validators,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer], *recent_blockhash);
+        banks_client.process_transaction(transaction).await?;
+        Ok(())
+    }
+
+    /// Updates every validator in the list pages, then the pool balance
+    pub async fn update_all(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+    ) -> Result<(), TransportError> {
+        for page in self.get_validator_stake_list_pages(banks_client).await {
+            let validator_stake_list = get_account(banks_client, &page).await;
+            let validators: Vec<Pubkey> =
+                state::ValidatorStakeList::deserialize(validator_stake_list.data.as_slice())
+                    .unwrap()
+                    .validators
+                    .iter()
+                    .map(|info| info.validator_account)
+                    .collect();
+            self.update_list_balance_page(
+                banks_client,
+                payer,
+                recent_blockhash,
+                &page,
+                &validators,
+            )
+            .await?;
+        }
+        self.update_pool_balance(banks_client, payer, recent_blockhash)
+            .await
+    }
+
+    pub async fn increase_validator_stake(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+        validator: &Pubkey,
+        lamports: u64,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::increase_validator_stake(
+                &id(),
+                &self.stake_pool.pubkey(),
+                &self.owner.pubkey(),
+                &self.withdraw_authority,
+                &self.validator_stake_list.pubkey(),
+                &self.reserve_stake,
+                validator,
+                lamports,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &self.owner], *recent_blockhash);
+        banks_client.process_transaction(transaction).await?;
+        Ok(())
+    }
+
+    pub async fn decrease_validator_stake(
+        &self,
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        recent_blockhash: &Hash,
+        validator: &Pubkey,
+        lamports: u64,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::decrease_validator_stake(
+                &id(),
+                &self.stake_pool.pubkey(),
+                &self.owner.pubkey(),
+                &self.withdraw_authority,
+                &self.validator_stake_list.pubkey(),
+                &self.reserve_stake,
+                validator,
+                lamports,
+            )
+            .unwrap()],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[payer, &self.owner], *recent_blockhash);
+        banks_client.process_transaction(transaction).await?;
+        Ok(())
+    }
+
     pub async fn withdraw_stake(
         &self,
         banks_client: &mut BanksClient,
@@ -711,6 +1119,7 @@ impl StakePoolAccounts {
                 stake_recipient,
                 recipient_new_authority,
                 pool_account,
+                &self.pool_fee_account.pubkey(),
                 &self.pool_mint.pubkey(),
                 &spl_token::id(),
                 &stake::id(),
@@ -784,28 +1193,39 @@ impl StakePoolAccounts {
     }
 }
 
+/// Creates a validator stake account and adds it to the pool once its stake is active
 pub async fn simple_add_validator_stake_account(
-
-    banks_client: &mut BanksClient,
-    payer: &Keypair,
-    recent_blockhash: &Hash,
+    context: &mut ProgramTestContext,
     stake_pool_accounts: &StakePoolAccounts,
-
 ) -> ValidatorStakeAccount {
-
     let user_stake = ValidatorStakeAccount::new_with_target_authority(
         &stake_pool_accounts.deposit_authority,
         &stake_pool_accounts.stake_pool.pubkey(),
     );
+    add_validator_stake_account_for(context, stake_pool_accounts, user_stake).await
+}
+
+/// Same as `simple_add_validator_stake_account`, for a validator chosen by the test
+pub async fn add_validator_stake_account_for(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    user_stake: ValidatorStakeAccount,
+) -> ValidatorStakeAccount {
     user_stake
-        .create_and_delegate(banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await;
 
+    let recent_blockhash = warp_to_next_epoch(context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
     let user_pool_account = Keypair::new();
     let user = Keypair::new();
     create_token_account(
-        banks_client,
-        &payer,
+        &mut context.banks_client,
+        &context.payer,
         &recent_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
@@ -816,8 +1236,8 @@ pub async fn simple_add_validator_stake_account(
 
     let error = stake_pool_accounts
         .add_validator_stake_account(
-            banks_client,
-            &payer,
+            &mut context.banks_client,
+            &context.payer,
             &recent_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
@@ -825,11 +1245,62 @@ pub async fn simple_add_validator_stake_account(
         .await;
     assert!(error.is_none());
 
-    println!("-- end simple_add_validator_stake_account");
-
     user_stake
 }
 
+/// Creates a stake account delegated to `vote` with the pool deposit authority as staker and
+/// withdrawer, then warps until it is active and updates the pool
+pub async fn create_active_deposit_stake(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: &StakePoolAccounts,
+    vote: &Pubkey,
+    user_stake: &Keypair,
+) -> u64 {
+    let stake_authority = Keypair::new();
+    let authorized = stake::Authorized {
+        staker: stake_authority.pubkey(),
+        withdrawer: stake_authority.pubkey(),
+    };
+    let stake_lamports = create_independent_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        user_stake,
+        &authorized,
+        &stake::Lockup::default(),
+    )
+    .await;
+    delegate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake.pubkey(),
+        &stake_authority,
+        vote,
+    )
+    .await;
+    for stake_authorize in &[stake::StakeAuthorize::Staker, stake::StakeAuthorize::Withdrawer] {
+        authorize_stake_account(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.pubkey(),
+            &stake_authority,
+            &stake_pool_accounts.deposit_authority,
+            *stake_authorize,
+        )
+        .await;
+    }
+
+    let recent_blockhash = warp_to_next_epoch(context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    stake_lamports
+}
+
 pub struct DepositInfo {
     pub user: Keypair,
     pub user_pool_account: Pubkey,
@@ -838,36 +1309,28 @@ pub struct DepositInfo {
 }
 
 pub async fn simple_deposit(
-    banks_client: &mut BanksClient,
-    payer: &Keypair, payer_is_user: bool,
-    recent_blockhash: &Hash,
+    context: &mut ProgramTestContext,
+    payer_is_user: bool,
     stake_pool_accounts: &StakePoolAccounts,
     validator_stake_account: &ValidatorStakeAccount,
 ) -> DepositInfo {
     let user = Keypair::new();
-    // make stake account
+    // make an active stake account
     let user_stake = Keypair::new();
-    let lockup = stake::Lockup::default();
-    let authorized = stake::Authorized {
-        staker: stake_pool_accounts.deposit_authority,
-        withdrawer: stake_pool_accounts.deposit_authority,
-    };
-    let stake_lamports = create_independent_stake_account(
-        banks_client,
-        payer,
-        recent_blockhash,
+    let stake_lamports = create_active_deposit_stake(
+        context,
+        stake_pool_accounts,
+        &validator_stake_account.vote.pubkey(),
         &user_stake,
-        &authorized,
-        &lockup,
     )
     .await;
     // make pool token account
     let user_pool_account = Keypair::new();
-    let owner = &if payer_is_user {payer.pubkey()} else {user.pubkey()};
+    let owner = &if payer_is_user {context.payer.pubkey()} else {user.pubkey()};
     create_token_account(
-        banks_client,
-        payer,
-        recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         owner,
@@ -877,9 +1340,9 @@ pub async fn simple_deposit(
 
     stake_pool_accounts
         .deposit_stake(
-            banks_client,
-            payer,
-            recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.pubkey(),
             &user_pool_account.pubkey(),
             &validator_stake_account.stake_account,
@@ -888,7 +1351,7 @@ pub async fn simple_deposit(
         .unwrap();
 
     let user_pool_account = user_pool_account.pubkey();
-    let pool_tokens = get_token_balance(banks_client, &user_pool_account).await;
+    let pool_tokens = get_token_balance(&mut context.banks_client, &user_pool_account).await;
 
     return DepositInfo {
         user,
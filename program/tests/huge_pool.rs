@@ -0,0 +1,362 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+
+use bincode::serialize;
+use solana_program::{program_option::COption, program_pack::Pack, pubkey::Pubkey, rent::Rent};
+use solana_program_test::*;
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_stake_pool::{
+    processor::Processor,
+    state::{
+        StakePool, ValidatorStakeInfo, ValidatorStakeList, MAX_VALIDATOR_STAKE_ACCOUNTS,
+        MAX_VALIDATOR_STAKE_LIST_PAGES,
+    },
+    *,
+};
+
+const HUGE_POOL_SIZE: usize = 500;
+const DEPOSIT_COMPUTE_UNITS: u64 = 100_000;
+/// Default compute budget of an instruction
+const UPDATE_POOL_BALANCE_COMPUTE_UNITS: u64 = 200_000;
+const STAKE_AMOUNT: u64 = 200_000_000_000;
+
+/// Fully active stake account delegated since genesis
+fn bootstrap_stake_account(authority: &Pubkey, vote: &Pubkey, stake_amount: u64) -> Account {
+    let stake_rent = Rent::default().minimum_balance(std::mem::size_of::<stake::StakeState>());
+    let stake_state = stake::StakeState::Stake(
+        stake::Meta {
+            rent_exempt_reserve: stake_rent,
+            authorized: stake::Authorized {
+                staker: *authority,
+                withdrawer: *authority,
+            },
+            lockup: stake::Lockup::default(),
+        },
+        stake::Stake {
+            delegation: stake::Delegation::new(vote, stake_amount, std::u64::MAX, 0.25),
+            credits_observed: 0,
+        },
+    );
+    let mut data = serialize(&stake_state).unwrap();
+    data.resize(std::mem::size_of::<stake::StakeState>(), 0);
+    Account {
+        lamports: stake_rent + stake_amount,
+        data,
+        owner: stake::id(),
+        ..Account::default()
+    }
+}
+
+fn token_account(mint: &Pubkey, owner: &Pubkey) -> Account {
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        state: spl_token::state::AccountState::Initialized,
+        ..spl_token::state::Account::default()
+    }
+    .pack_into_slice(&mut data);
+    Account {
+        lamports: 10_000_000,
+        data,
+        owner: spl_token::id(),
+        ..Account::default()
+    }
+}
+
+/// Validator stake list account holding `validators`
+fn add_validator_stake_list(
+    program_test: &mut ProgramTest,
+    address: Pubkey,
+    validators: Vec<ValidatorStakeInfo>,
+) {
+    let validator_stake_list = ValidatorStakeList {
+        version: ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
+        validators,
+    };
+    let mut data = vec![0; ValidatorStakeList::LEN];
+    validator_stake_list.serialize(&mut data).unwrap();
+    program_test.add_account(
+        address,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+}
+
+/// Stake pool, extension, pool mint and fee account, `pages` are linked after
+/// `validator_stake_list`
+fn add_stake_pool(
+    program_test: &mut ProgramTest,
+    stake_pool_accounts: &StakePoolAccounts,
+    stake_total: u64,
+    pages: &[Pubkey],
+) {
+    let stake_pool_pubkey = stake_pool_accounts.stake_pool.pubkey();
+    let (_, deposit_bump_seed) = Processor::find_authority_bump_seed(
+        &id(),
+        &stake_pool_pubkey,
+        Processor::AUTHORITY_DEPOSIT,
+    );
+    let (_, withdraw_bump_seed) = Processor::find_authority_bump_seed(
+        &id(),
+        &stake_pool_pubkey,
+        Processor::AUTHORITY_WITHDRAW,
+    );
+    let (_, reserve_bump_seed) =
+        Processor::find_authority_bump_seed(&id(), &stake_pool_pubkey, Processor::RESERVE);
+    let (extension, extension_bump_seed) =
+        Processor::find_stake_pool_extension_address(&id(), &stake_pool_pubkey);
+
+    let mut validator_stake_list_pages = [Pubkey::default(); MAX_VALIDATOR_STAKE_LIST_PAGES - 1];
+    validator_stake_list_pages[..pages.len()].copy_from_slice(pages);
+    let stake_pool = StakePool {
+        version: StakePool::STAKE_POOL_VERSION,
+        owner: stake_pool_accounts.owner.pubkey(),
+        deposit_bump_seed,
+        withdraw_bump_seed,
+        validator_stake_list: stake_pool_accounts.validator_stake_list.pubkey(),
+        pool_mint: stake_pool_accounts.pool_mint.pubkey(),
+        owner_fee_account: stake_pool_accounts.pool_fee_account.pubkey(),
+        token_program_id: spl_token::id(),
+        extension_bump_seed,
+        stake_total,
+        pool_total: stake_total,
+        last_update_epoch: 0,
+        fee: stake_pool_accounts.fee,
+        reserve_bump_seed,
+        validator_stake_list_pages_len: pages.len() as u8,
+        validator_stake_list_pages,
+        staker: stake_pool_accounts.owner.pubkey(),
+        fee_manager: stake_pool_accounts.owner.pubkey(),
+        ..StakePool::default()
+    };
+    let mut data = vec![0; StakePool::LEN];
+    let mut extension_data = vec![0; StakePool::EXTENSION_LEN];
+    stake_pool
+        .serialize(&mut data, &mut extension_data)
+        .unwrap();
+    program_test.add_account(
+        stake_pool_pubkey,
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        extension,
+        Account {
+            lamports: 10_000_000,
+            data: extension_data,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint {
+        mint_authority: COption::Some(stake_pool_accounts.withdraw_authority),
+        supply: stake_total,
+        decimals: 9,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+    program_test.add_account(
+        stake_pool_accounts.pool_mint.pubkey(),
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        stake_pool_accounts.pool_fee_account.pubkey(),
+        token_account(
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+        ),
+    );
+}
+
+/// Stake pool with `HUGE_POOL_SIZE` validators, only the last one has a real stake account
+fn setup() -> (ProgramTest, StakePoolAccounts, Pubkey, Keypair, Keypair) {
+    let mut program_test = ProgramTest::new("spl_stake_pool", id(), None);
+    program_test.set_bpf_compute_max_units(DEPOSIT_COMPUTE_UNITS);
+
+    let stake_pool_accounts = StakePoolAccounts::new();
+    let stake_pool_pubkey = stake_pool_accounts.stake_pool.pubkey();
+
+    let mut validators = vec![];
+    for _ in 0..HUGE_POOL_SIZE - 1 {
+        validators.push(ValidatorStakeInfo {
+            validator_account: Pubkey::new_unique(),
+            balance: STAKE_AMOUNT,
+            ..ValidatorStakeInfo::default()
+        });
+    }
+    // the deposit looks up the last validator of the list
+    let vote = Pubkey::new_unique();
+    validators.push(ValidatorStakeInfo {
+        validator_account: vote,
+        balance: STAKE_AMOUNT,
+        ..ValidatorStakeInfo::default()
+    });
+    add_validator_stake_list(
+        &mut program_test,
+        stake_pool_accounts.validator_stake_list.pubkey(),
+        validators,
+    );
+    add_stake_pool(
+        &mut program_test,
+        &stake_pool_accounts,
+        STAKE_AMOUNT * HUGE_POOL_SIZE as u64,
+        &[],
+    );
+
+    let (validator_stake_account, _) =
+        Processor::find_stake_address_for_validator(&id(), &vote, &stake_pool_pubkey);
+    program_test.add_account(
+        validator_stake_account,
+        bootstrap_stake_account(&stake_pool_accounts.withdraw_authority, &vote, STAKE_AMOUNT),
+    );
+
+    let user_stake = Keypair::new();
+    program_test.add_account(
+        user_stake.pubkey(),
+        bootstrap_stake_account(
+            &stake_pool_accounts.deposit_authority,
+            &vote,
+            TEST_STAKE_AMOUNT,
+        ),
+    );
+    let user_pool_account = Keypair::new();
+    program_test.add_account(
+        user_pool_account.pubkey(),
+        token_account(
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &Pubkey::new_unique(),
+        ),
+    );
+
+    (
+        program_test,
+        stake_pool_accounts,
+        validator_stake_account,
+        user_stake,
+        user_pool_account,
+    )
+}
+
+#[tokio::test]
+async fn test_deposit_compute_units_with_huge_pool() {
+    let (program_test, stake_pool_accounts, validator_stake_account, user_stake, user_pool_account) =
+        setup();
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::deposit(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.deposit_authority,
+            &stake_pool_accounts.withdraw_authority,
+            &user_stake.pubkey(),
+            &validator_stake_account,
+            &user_pool_account.pubkey(),
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &stake::id(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    // fails with ComputationalBudgetExceeded above DEPOSIT_COMPUTE_UNITS
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    let validator_stake_list = get_account(
+        &mut banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let validator_stake_list =
+        ValidatorStakeList::deserialize(validator_stake_list.data.as_slice()).unwrap();
+    assert_eq!(validator_stake_list.validators.len(), HUGE_POOL_SIZE);
+    let validator = validator_stake_list.validators.last().unwrap();
+    let stake_account = get_account(&mut banks_client, &validator_stake_account).await;
+    assert_eq!(validator.balance, stake_account.lamports);
+}
+
+#[tokio::test]
+async fn test_update_pool_balance_compute_units_with_all_pages() {
+    let mut program_test = ProgramTest::new("spl_stake_pool", id(), None);
+    program_test.set_bpf_compute_max_units(UPDATE_POOL_BALANCE_COMPUTE_UNITS);
+
+    let stake_pool_accounts = StakePoolAccounts::new();
+    // every page of the pool is full, the pool list included
+    let pages: Vec<Pubkey> = (1..MAX_VALIDATOR_STAKE_LIST_PAGES)
+        .map(|_| Pubkey::new_unique())
+        .collect();
+    for page in std::iter::once(stake_pool_accounts.validator_stake_list.pubkey())
+        .chain(pages.iter().cloned())
+    {
+        let validators = (0..MAX_VALIDATOR_STAKE_ACCOUNTS)
+            .map(|_| ValidatorStakeInfo {
+                validator_account: Pubkey::new_unique(),
+                balance: STAKE_AMOUNT,
+                ..ValidatorStakeInfo::default()
+            })
+            .collect();
+        add_validator_stake_list(&mut program_test, page, validators);
+    }
+    let stake_total =
+        STAKE_AMOUNT * (MAX_VALIDATOR_STAKE_ACCOUNTS * MAX_VALIDATOR_STAKE_LIST_PAGES) as u64;
+    add_stake_pool(&mut program_test, &stake_pool_accounts, stake_total, &pages);
+
+    let stake_rent = Rent::default().minimum_balance(std::mem::size_of::<stake::StakeState>());
+    let mut data = serialize(&stake::StakeState::Initialized(stake::Meta {
+        rent_exempt_reserve: stake_rent,
+        authorized: stake::Authorized {
+            staker: stake_pool_accounts.withdraw_authority,
+            withdrawer: stake_pool_accounts.withdraw_authority,
+        },
+        lockup: stake::Lockup::default(),
+    }))
+    .unwrap();
+    data.resize(std::mem::size_of::<stake::StakeState>(), 0);
+    program_test.add_account(
+        stake_pool_accounts.reserve_stake,
+        Account {
+            lamports: stake_rent,
+            data,
+            owner: stake::id(),
+            ..Account::default()
+        },
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // fails with ComputationalBudgetExceeded above UPDATE_POOL_BALANCE_COMPUTE_UNITS
+    stake_pool_accounts
+        .update_pool_balance(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.stake_total, stake_total);
+}
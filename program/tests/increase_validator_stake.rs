@@ -0,0 +1,358 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::epoch_schedule::EpochSchedule;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+const RESERVE_LAMPORTS: u64 = 10_000_000_000;
+
+/// Pool with one validator and `RESERVE_LAMPORTS` in the reserve, updated in the first normal epoch
+async fn setup() -> (
+    ProgramTestContext,
+    EpochSchedule,
+    StakePoolAccounts,
+    ValidatorStakeAccount,
+) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+
+    let validator_stake =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+
+    let user = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user.pubkey(),
+        RESERVE_LAMPORTS + 1_000_000_000,
+    )
+    .await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+    stake_pool_accounts
+        .deposit_sol(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            RESERVE_LAMPORTS,
+        )
+        .await
+        .unwrap();
+
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_list_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &[validator_stake.vote.pubkey()],
+        )
+        .await
+        .unwrap();
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    (context, epoch_schedule, stake_pool_accounts, validator_stake)
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake() {
+    let (mut context, epoch_schedule, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let vote = validator_stake.vote.pubkey();
+    let (transient_stake, _) = processor::Processor::find_transient_stake_address(
+        &id(),
+        &vote,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+    let reserve_before = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake)
+        .await
+        .lamports;
+    let info_before = stake_pool_accounts
+        .get_validator_stake_info(&mut context.banks_client, &vote)
+        .await;
+
+    let lamports = 2_000_000_000;
+    stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &vote,
+            lamports,
+        )
+        .await
+        .unwrap();
+
+    // the lamports and the transient account rent left the reserve, they are activating
+    // in the transient stake account
+    let reserve = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake).await;
+    assert_eq!(reserve.lamports, reserve_before - lamports - stake_rent);
+    let transient = get_account(&mut context.banks_client, &transient_stake).await;
+    assert_eq!(transient.lamports, lamports + stake_rent);
+    let info = stake_pool_accounts
+        .get_validator_stake_info(&mut context.banks_client, &vote)
+        .await;
+    assert_eq!(info.balance, info_before.balance);
+    assert_eq!(info.transient_balance, lamports + stake_rent);
+
+    // next epoch the transient stake is active and gets merged
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + epoch_schedule.slots_per_epoch + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_list_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &[vote],
+        )
+        .await
+        .unwrap();
+
+    let info = stake_pool_accounts
+        .get_validator_stake_info(&mut context.banks_client, &vote)
+        .await;
+    assert_eq!(info.balance, info_before.balance + lamports);
+    assert_eq!(info.transient_balance, 0);
+    assert!(context
+        .banks_client
+        .get_account(transient_stake)
+        .await
+        .unwrap()
+        .is_none());
+    // the transient account rent goes back to the reserve after the merge
+    let reserve = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake).await;
+    assert_eq!(reserve.lamports, reserve_before - lamports);
+
+    // the move does not change the pool stake, no reward fee is charged
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    assert_eq!(stake_pool.stake_total, stake_pool_before.stake_total);
+    assert_eq!(stake_pool.pool_total, stake_pool_before.pool_total);
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake_twice() {
+    let (mut context, _, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let vote = validator_stake.vote.pubkey();
+
+    stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &vote,
+            1_000_000_000,
+        )
+        .await
+        .unwrap();
+
+    let transaction_error = stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &vote,
+            1_000_000_001,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::TransientAccountInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to increase stake with a busy transient account"),
+    }
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake_after_transient_donation() {
+    let (mut context, _, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let vote = validator_stake.vote.pubkey();
+    let (transient_stake, _) = processor::Processor::find_transient_stake_address(
+        &id(),
+        &vote,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+
+    // anyone can send lamports to the transient address before the pool creates the account
+    let rent = context.banks_client.get_rent().await.unwrap();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &transient_stake,
+        rent.minimum_balance(0),
+    )
+    .await;
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+    let info = stake_pool_accounts
+        .get_validator_stake_info(&mut context.banks_client, &vote)
+        .await;
+    assert_eq!(info.transient_balance, 0);
+
+    stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &vote,
+            1_000_000_000,
+        )
+        .await
+        .unwrap();
+    let transient = get_account(&mut context.banks_client, &transient_stake).await;
+    assert_eq!(transient.owner, stake::id());
+    let info = stake_pool_accounts
+        .get_validator_stake_info(&mut context.banks_client, &vote)
+        .await;
+    assert_eq!(info.transient_balance, transient.lamports);
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake_above_reserve() {
+    let (mut context, _, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let transaction_error = stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &validator_stake.vote.pubkey(),
+            RESERVE_LAMPORTS + 1,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ReserveStakeTooLow as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to increase stake above the reserve"),
+    }
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake_with_unknown_validator() {
+    let (mut context, _, stake_pool_accounts, _) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let transaction_error = stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &Keypair::new().pubkey(),
+            1_000_000_000,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ValidatorNotFound as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to increase stake of an unknown validator"),
+    }
+}
+
+#[tokio::test]
+async fn test_increase_validator_stake_by_malicious() {
+    let (mut context, _, mut stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let malicious = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &malicious.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+    stake_pool_accounts.owner = malicious;
+
+    let transaction_error = stake_pool_accounts
+        .increase_validator_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &validator_stake.vote.pubkey(),
+            1_000_000_000,
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while malicious try to increase validator stake"),
+    }
+}
@@ -7,7 +7,7 @@ use solana_program::hash::Hash;
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_pack::Pack,
-    system_instruction, sysvar,
+    system_instruction, system_program, sysvar,
 };
 use solana_program_test::BanksClient;
 use solana_sdk::{
@@ -58,6 +58,24 @@ async fn test_stake_pool_initialize() {
     assert_eq!(stake_pool.data.len(), state::StakePool::LEN);
     assert_eq!(stake_pool.owner, id());
 
+    // Stake pool extension created, the owner paid its rent
+    let stake_pool_extension =
+        get_account(&mut banks_client, &stake_pool_accounts.stake_pool_extension).await;
+    assert_eq!(stake_pool_extension.data.len(), state::StakePool::EXTENSION_LEN);
+    assert_eq!(stake_pool_extension.owner, id());
+
+    // Reserve created, an initialized stake account owned by the withdraw authority
+    let reserve = get_account(&mut banks_client, &stake_pool_accounts.reserve_stake).await;
+    assert_eq!(reserve.owner, stake::id());
+    match bincode::deserialize::<stake::StakeState>(&reserve.data).unwrap() {
+        stake::StakeState::Initialized(meta) => {
+            assert_eq!(meta.authorized.staker, stake_pool_accounts.withdraw_authority);
+            assert_eq!(meta.authorized.withdrawer, stake_pool_accounts.withdraw_authority);
+            assert_eq!(reserve.lamports, meta.rent_exempt_reserve);
+        }
+        _ => panic!("Reserve stake account is not initialized"),
+    }
+
     // Validator stake list storage initialized
     let validator_stake_list = get_account(
         &mut banks_client,
@@ -157,6 +175,32 @@ async fn test_initialize_stake_pool_with_high_fee() {
     }
 }
 
+#[tokio::test]
+async fn test_initialize_stake_pool_with_high_reward_fee() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts.reward_fee = instruction::Fee {
+        numerator: 11,
+        denominator: 10,
+    };
+
+    let transaction_error = stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .err()
+        .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::FeeTooHigh as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to initialize stake pool with high reward fee"),
+    }
+}
+
 #[tokio::test]
 async fn test_initialize_stake_pool_with_wrong_mint_authority() {
     let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
@@ -191,7 +235,7 @@ async fn test_initialize_stake_pool_with_wrong_mint_authority() {
         &wrong_mint.pubkey(),
         &stake_pool_accounts.pool_fee_account.pubkey(),
         &stake_pool_accounts.owner,
-        &stake_pool_accounts.fee,
+        stake_pool_accounts.init_args(),
     )
     .await
     .err()
@@ -247,9 +291,7 @@ async fn test_initialize_stake_pool_with_wrong_token_program_id() {
 
     let rent_stake_pool = rent.minimum_balance(state::StakePool::LEN);
     let rent_validator_stake_list = rent.minimum_balance(state::ValidatorStakeList::LEN);
-    let init_args = instruction::InitArgs {
-        fee: stake_pool_accounts.fee,
-    };
+    let init_args = stake_pool_accounts.init_args();
 
     let mut transaction = Transaction::new_with_payer(
         &[
@@ -348,7 +390,7 @@ async fn test_initialize_stake_pool_with_wrong_fee_accounts_owner() {
         &stake_pool_accounts.pool_mint.pubkey(),
         &stake_pool_accounts.pool_fee_account.pubkey(),
         &stake_pool_accounts.owner,
-        &stake_pool_accounts.fee,
+        stake_pool_accounts.init_args(),
     )
     .await
     .err()
@@ -410,9 +452,7 @@ async fn test_initialize_stake_pool_with_not_rent_exempt_pool() {
 
     let rent = banks_client.get_rent().await.unwrap();
     let rent_validator_stake_list = rent.minimum_balance(state::ValidatorStakeList::LEN);
-    let init_args = instruction::InitArgs {
-        fee: stake_pool_accounts.fee,
-    };
+    let init_args = stake_pool_accounts.init_args();
 
     let mut transaction = Transaction::new_with_payer(
         &[
@@ -488,9 +528,7 @@ async fn test_initialize_stake_pool_with_not_rent_exempt_validator_stake_list()
 
     let rent = banks_client.get_rent().await.unwrap();
     let rent_stake_pool = rent.minimum_balance(state::StakePool::LEN);
-    let init_args = instruction::InitArgs {
-        fee: stake_pool_accounts.fee,
-    };
+    let init_args = stake_pool_accounts.init_args();
 
     let mut transaction = Transaction::new_with_payer(
         &[
@@ -566,21 +604,21 @@ async fn test_initialize_stake_pool_without_owner_signature() {
 
     let rent = banks_client.get_rent().await.unwrap();
     let rent_stake_pool = rent.minimum_balance(state::StakePool::LEN);
-    let init_args = instruction::InitArgs {
-        fee: stake_pool_accounts.fee,
-    };
+    let init_args = stake_pool_accounts.init_args();
 
     let init_data = instruction::StakePoolInstruction::Initialize(init_args);
     let data = init_data.serialize().unwrap();
     let accounts = vec![
         AccountMeta::new(stake_pool_accounts.stake_pool.pubkey(), true),
-        AccountMeta::new_readonly(stake_pool_accounts.owner.pubkey(), false),
+        AccountMeta::new(stake_pool_accounts.stake_pool_extension, false),
+        AccountMeta::new(stake_pool_accounts.owner.pubkey(), false),
         AccountMeta::new(stake_pool_accounts.validator_stake_list.pubkey(), false),
         AccountMeta::new_readonly(stake_pool_accounts.pool_mint.pubkey(), false),
         AccountMeta::new_readonly(stake_pool_accounts.pool_fee_account.pubkey(), false),
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
     ];
     let stake_pool_init_instruction = Instruction {
         program_id: id(),
@@ -5,11 +5,12 @@ use helpers::*;
 
 use solana_program::pubkey::Pubkey;
 
-use solana_program::{hash::Hash};
-use solana_program_test::BanksClient;
+use solana_program::{hash::Hash, program_pack::Pack};
+use solana_program_test::{BanksClient, ProgramTestContext};
 use solana_sdk::{
     instruction::InstructionError,
     signature::{Keypair, Signer},
+    system_instruction,
     transaction::Transaction,
     transaction::TransactionError,
     transport::TransportError,
@@ -19,37 +20,29 @@ use spl_stake_pool::processor::Processor;
 use spl_token::error::TokenError;
 
 async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
+    ProgramTestContext,
     StakePoolAccounts,
     ValidatorStakeAccount,
 ) {
 
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     
     let stake_pool_accounts = 
         StakePoolAccounts::new();
     
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
     println!("--- about to call simple_add_validator_stake_account");
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    ).await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
     println!("validator_stake_account {:?}",validator_stake_account.stake_account);
 
     return (
-        banks_client,
-        payer,
-        recent_blockhash,
+        context,
         stake_pool_accounts,
         validator_stake_account,
     )
@@ -63,18 +56,16 @@ async fn test_add_liquidity() {
     println!("---------------------------------");
 
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         stake_pool_accounts,
         validator_stake_account,
     ) = setup().await;
 
     println!("--- about to call prepare_wsol_deposit");
     let deposit_info: DepositInfo = prepare_wsol_deposit(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
     )
     .await;
     println!("wsol_tokens balance={}",deposit_info.pool_tokens);
@@ -84,236 +75,2136 @@ async fn test_add_liquidity() {
     let metal_lp_user_dest = Keypair::new();
     println!("create_token_account metal_lp_user_dest");
     let result = create_token_account (
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &metal_lp_user_dest,
         &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
-        &payer.pubkey()
+        &context.payer.pubkey()
     )
     .await;
 
     // Save state before addliq
     let prev_liq_pool_wsol_dest_account_balance =
-    get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
     println!("prev_liq_pool_wsol_dest_account_balance {}",prev_liq_pool_wsol_dest_account_balance);
 
     // Save user token balance
     let user_token_balance_before =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
 
     //let new_authority = Pubkey::new_unique();
     //----------------------
     let mut transaction = Transaction::new_with_payer(
         &[instruction::instruction_add_liquidity(
             wsol_to_deposit,
+            0,
             &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
             &stake_pool_accounts.liq_pool_state_acc.pubkey(),
             &spl_token::id(),
             &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
             &stake_pool_accounts.liq_pool_authority,
             &deposit_info.user_pool_account,//  .user_wsol_source_account,
-            &payer.pubkey(), //user acc withdraw auth
+            &context.payer.pubkey(), //user acc withdraw auth
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &metal_lp_user_dest.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    println!("----------------------------------------");
+    println!("-- SEND TXN instruction_add_liquidity --");
+    println!("----------------------------------------");
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let result = context.banks_client.process_transaction(transaction)
+        .await;
+    //println!("{:?}",result);
+
+    assert!(!result.is_err(), "TXN ERROR");
+
+    // result.err()
+    // .unwrap();
+
+    // Check liq-pool stats
+    let liq_pool_wsol_dest_account_balance =
+    get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    println!("liq_pool_wsol_dest_account_balance {}",liq_pool_wsol_dest_account_balance);
+    assert_eq!(
+        liq_pool_wsol_dest_account_balance,
+        prev_liq_pool_wsol_dest_account_balance + wsol_to_deposit
+    );
+
+    // Check tokens deposited
+    let user_token_balance =
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    assert_eq!(
+        user_token_balance,
+        user_token_balance_before - wsol_to_deposit
+    );
+
+    // Check meta-lp tokens received
+    // {
+    //     let user_token_balance =
+    //         get_token_balance(&mut context.banks_client, &metal_lp_user_dest.pubkey()).await;
+    //     assert_eq!(
+    //         user_token_balance,
+    //         wsol_to_deposit
+    //     );
+    // }
+
+    // Check user recipient stake account balance
+    // let user_stake_recipient_account =
+    //     get_account(&mut context.banks_client, &user_stake_recipient.pubkey()).await;
+    // assert_eq!(
+    //     user_stake_recipient_account.lamports,
+    //     initial_stake_lamports + wsol_to_deposit
+    // );
+}
+
+#[tokio::test]
+async fn test_sell_st_sol() {
+    
+    println!("-------------------------------");
+    println!("---- START test_sell_st_sol ---");
+    println!("-------------------------------");
+
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    // Get stake pool stake (and check if it is initialized)
+    let stake_pool_data_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    if !stake_pool_data_before.is_initialized() {
+        panic!("stake_pool_data_before not initialized");
+    }
+
+    println!("--- about to call simple_deposit");
+    //call simple_deposit so the user_acc has some stSOL to sell
+    // simple_deposit  does the entire thing: creates acc, stakes and deposits, so the user acc gets stsOL
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+
+    // let deposit_info: DepositInfo = prepare_st_sol_deposit(
+    //     &mut context.banks_client,
+    //     &stake_pool_accounts.pool_mint.pubkey(),
+    //     &stake_pool_data_before.pool_mint_autho
+    //     &context.payer,
+    //     &context.last_blockhash,
+    // )
+    // .await;
+
+    let prev_user_stsol_account_balance =
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    println!("prev_user_stsol_account_balance={}",prev_user_stsol_account_balance);
+
+    // Create wsol dest account to send wsol
+    let wsol_user_dest_acc = Keypair::new();
+    println!("create_token_account wsol_user_dest_acc");
+    let result = create_token_account (
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey()
+    )
+    .await;
+
+    // Save state before sell
+    let prev_liq_pool_wsol_account_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let prev_liq_pool_st_sol_account_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    println!("--- prev_liq_pool wsol/stSOL {}/{}",prev_liq_pool_wsol_account_balance,prev_liq_pool_st_sol_account_balance);
+
+    let pre_user_stsol_balance = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    println!("-- pre_user_st_sol_balance {}",pre_user_stsol_balance);
+
+    let stsol_to_sell:u64 = 50_000;
+
+    //let new_authority = Pubkey::new_unique();
+    //----------------------
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            stsol_to_sell,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(), //where to send the wsol
+            &deposit_info.user_pool_account,//  .user_source_account,
+            &context.payer.pubkey(), //user acc withdraw auth
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    println!("-------------------------------------");
+    println!("-- SEND TXN instruction_sell_stsol --");
+    println!("-------------------------------------");
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let result = context.banks_client.process_transaction(transaction)
+        .await;
+    //println!("{:?}",result);
+
+    assert!(!result.is_err(), "TXN ERROR");
+
+    // result.err()
+    // .unwrap();
+
+    let valued = stsol_to_sell; //TODO compute value correctly
+    let fee = stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(valued, prev_liq_pool_wsol_account_balance).unwrap();
+
+    // Check liq-pool wsol balance after sell
+    // Check liq-pool st_sol_tokens after sell
+    let post_liq_pool_wsol_account_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let post_liq_pool_st_sol_account_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    println!("-- post_liq_pool wsol/stSOL {}/{}",post_liq_pool_wsol_account_balance,post_liq_pool_st_sol_account_balance);
+    assert_eq!(
+        post_liq_pool_wsol_account_balance,
+        prev_liq_pool_wsol_account_balance - stsol_to_sell + fee
+    );
+    assert_eq!(
+        post_liq_pool_st_sol_account_balance,
+        prev_liq_pool_st_sol_account_balance + stsol_to_sell
+    );
+
+    // Check user stSol balance after sell
+    let post_user_stsol_balance =
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    println!("-- post_user_st_sol_balance {}",post_user_stsol_balance);
+    assert_eq!(
+        post_user_stsol_balance,
+        prev_user_stsol_account_balance - stsol_to_sell
+    );
+
+    // Check user recipient stake account balance
+    // let user_stake_recipient_account =
+    //     get_account(&mut context.banks_client, &user_stake_recipient.pubkey()).await;
+    // assert_eq!(
+    //     user_stake_recipient_account.lamports,
+    //     initial_stake_lamports + wsol_to_deposit
+    // );
+}
+
+async fn add_liquidity(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    wsol_amount: u64,
+) -> Pubkey {
+    let deposit_info: DepositInfo = prepare_wsol_deposit(banks_client, payer, recent_blockhash).await;
+
+    // Create lp token account to send tokens to the user
+    let metal_lp_user_dest = Keypair::new();
+    create_token_account(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &metal_lp_user_dest,
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_add_liquidity(
+            wsol_amount,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &deposit_info.user_pool_account,
+            &payer.pubkey(),
             &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
             &metal_lp_user_dest.pubkey(),
         )
         .unwrap()],
         Some(&payer.pubkey()),
     );
-    println!("----------------------------------------");
-    println!("-- SEND TXN instruction_add_liquidity --");
-    println!("----------------------------------------");
-    transaction.sign(&[&payer], recent_blockhash);
-    let result = banks_client.process_transaction(transaction)
-        .await;
-    //println!("{:?}",result);
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    metal_lp_user_dest.pubkey()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn remove_liquidity(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    user_metalp_account: &Pubkey,
+    user_wsol_account: &Pubkey,
+    user_st_sol_account: &Pubkey,
+    metalp_amount: u64,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_remove_liquidity(
+            metalp_amount,
+            &id(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            user_metalp_account,
+            &payer.pubkey(),
+            user_wsol_account,
+            user_st_sol_account,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn sell_st_sol(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    user_st_sol_account: &Pubkey,
+    st_sol_amount: u64,
+) -> Keypair {
+    let wsol_user_dest_acc = Keypair::new();
+    create_token_account(
+        banks_client,
+        payer,
+        recent_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &payer.pubkey(),
+    )
+    .await
+    .unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            st_sol_amount,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            user_st_sol_account,
+            &payer.pubkey(),
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    wsol_user_dest_acc
+}
+
+#[tokio::test]
+async fn test_remove_liquidity() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let user_metalp_account = add_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        50_000,
+    )
+    .await;
+
+    // sell some stSOL so both legs of the LP have a balance
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_dest_acc = sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        40_000,
+    )
+    .await;
+
+    // Save state before remove
+    let metalp_supply = {
+        let mint = get_account(&mut context.banks_client, &stake_pool_accounts.meta_lp_mint_acc.pubkey()).await;
+        spl_token::state::Mint::unpack_from_slice(mint.data.as_slice()).unwrap().supply
+    };
+    let user_metalp_balance = get_token_balance(&mut context.banks_client, &user_metalp_account).await;
+    assert_eq!(user_metalp_balance, metalp_supply);
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let prev_liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    assert!(prev_liq_pool_st_sol_balance > 0);
+    let prev_user_wsol_balance = get_token_balance(&mut context.banks_client, &wsol_user_dest_acc.pubkey()).await;
+    let prev_user_st_sol_balance = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+
+    let metalp_to_burn = user_metalp_balance / 2;
+    remove_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &user_metalp_account,
+        &wsol_user_dest_acc.pubkey(),
+        &deposit_info.user_pool_account,
+        metalp_to_burn,
+    )
+    .await
+    .unwrap();
+
+    let wsol_out = processor::proportional(metalp_to_burn, prev_liq_pool_wsol_balance as u128, metalp_supply as u128).unwrap();
+    let st_sol_out = processor::proportional(metalp_to_burn, prev_liq_pool_st_sol_balance as u128, metalp_supply as u128).unwrap();
+
+    // Check $METALP burned
+    let user_metalp_balance_after = get_token_balance(&mut context.banks_client, &user_metalp_account).await;
+    assert_eq!(user_metalp_balance_after, user_metalp_balance - metalp_to_burn);
+
+    // Check both legs were paid out pro-rata
+    let liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    assert_eq!(liq_pool_wsol_balance, prev_liq_pool_wsol_balance - wsol_out);
+    assert_eq!(liq_pool_st_sol_balance, prev_liq_pool_st_sol_balance - st_sol_out);
+
+    let user_wsol_balance = get_token_balance(&mut context.banks_client, &wsol_user_dest_acc.pubkey()).await;
+    let user_st_sol_balance = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    assert_eq!(user_wsol_balance, prev_user_wsol_balance + wsol_out);
+    assert_eq!(user_st_sol_balance, prev_user_st_sol_balance + st_sol_out);
+}
+
+#[tokio::test]
+async fn test_remove_liquidity_with_not_enough_metalp() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let user_metalp_account = add_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        50_000,
+    )
+    .await;
+    let user_metalp_balance = get_token_balance(&mut context.banks_client, &user_metalp_account).await;
+
+    let user_wsol_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_wsol_account,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+    let user_st_sol_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_st_sol_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let transaction_error = remove_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &user_metalp_account,
+        &user_wsol_account.pubkey(),
+        &user_st_sol_account.pubkey(),
+        user_metalp_balance + 1,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = TokenError::InsufficientFunds as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to remove liquidity with not enough $METALP"),
+    }
+}
+
+#[tokio::test]
+async fn test_add_liquidity_values_st_sol_leg() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    add_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        50_000,
+    )
+    .await;
+
+    // sell some stSOL so the LP holds both legs
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_dest_acc = sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        40_000,
+    )
+    .await;
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    let metalp_supply = {
+        let mint = get_account(&mut context.banks_client, &stake_pool_accounts.meta_lp_mint_acc.pubkey()).await;
+        spl_token::state::Mint::unpack_from_slice(mint.data.as_slice()).unwrap().supply
+    };
+    let liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    assert!(liq_pool_st_sol_balance > 0);
+
+    // the second provider is priced against wSOL + stSOL value, not the wSOL leg alone
+    let wsol_amount = 30_000;
+    let total_value =
+        state::LiquidityPool::calc_total_value(liq_pool_wsol_balance, liq_pool_st_sol_balance, &stake_pool).unwrap();
+    assert!(total_value > liq_pool_wsol_balance);
+    let expected_metalp =
+        state::LiquidityPool::calc_metalp_amount(wsol_amount, total_value, metalp_supply).unwrap();
+
+    let user_metalp_account = add_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        wsol_amount,
+    )
+    .await;
+    let user_metalp_balance = get_token_balance(&mut context.banks_client, &user_metalp_account).await;
+    assert_eq!(user_metalp_balance, expected_metalp);
+
+    // removing it right away must not return more value than was added
+    let prev_user_wsol_balance = get_token_balance(&mut context.banks_client, &wsol_user_dest_acc.pubkey()).await;
+    let prev_user_st_sol_balance = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    remove_liquidity(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &user_metalp_account,
+        &wsol_user_dest_acc.pubkey(),
+        &deposit_info.user_pool_account,
+        user_metalp_balance,
+    )
+    .await
+    .unwrap();
+    let wsol_out = get_token_balance(&mut context.banks_client, &wsol_user_dest_acc.pubkey()).await - prev_user_wsol_balance;
+    let st_sol_out = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await - prev_user_st_sol_balance;
+    let value_out = state::LiquidityPool::calc_total_value(wsol_out, st_sol_out, &stake_pool).unwrap();
+    assert!(value_out <= wsol_amount);
+}
+
+#[tokio::test]
+async fn test_initialize_liquidity_pool() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert!(liq_pool.is_initialized());
+    assert_eq!(liq_pool.stake_pool, stake_pool_accounts.stake_pool.pubkey());
+    assert_eq!(liq_pool.wsol_account, stake_pool_accounts.liq_pool_wsol_acc.pubkey());
+    assert_eq!(liq_pool.stsol_account, stake_pool_accounts.liq_pool_st_sol_acc.pubkey());
+    assert_eq!(liq_pool.metalp_mint, stake_pool_accounts.meta_lp_mint_acc.pubkey());
+    assert_eq!(liq_pool.sell_fee, stake_pool_accounts.liq_pool_sell_fee);
+    assert_eq!(liq_pool.treasury_wsol_account, stake_pool_accounts.liq_pool_treasury_acc.pubkey());
+    assert_eq!(liq_pool.treasury_share_bps, 0);
+
+    // A second initialization must fail
+    let transaction_error = create_liquidity_pool(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &stake_pool_accounts.owner,
+        &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_authority,
+        &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_sell_fee,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::AlreadyInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to initialize an already initialized liquidity pool"),
+    }
+}
+
+#[tokio::test]
+async fn test_initialize_liquidity_pool_with_wrong_owner() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let liq_pool_state_acc = Keypair::new();
+    create_account(&mut context.banks_client, &context.payer, &context.last_blockhash, &liq_pool_state_acc)
+        .await
+        .unwrap();
+    let (liq_pool_authority, _) = Processor::find_authority_bump_seed(
+        &id(),
+        &liq_pool_state_acc.pubkey(),
+        Processor::AUTHORITY,
+    );
+
+    let wrong_owner = Keypair::new();
+    let transaction_error = create_liquidity_pool(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &liq_pool_state_acc.pubkey(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &wrong_owner,
+        &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &liq_pool_authority,
+        &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_sell_fee,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to initialize a liquidity pool with wrong owner"),
+    }
+}
+
+#[tokio::test]
+async fn test_initialize_liquidity_pool_with_stake_pool_of_another_program() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let liq_pool_state_acc = Keypair::new();
+    create_account(&mut context.banks_client, &context.payer, &context.last_blockhash, &liq_pool_state_acc)
+        .await
+        .unwrap();
+    let (liq_pool_authority, _) = Processor::find_authority_bump_seed(
+        &id(),
+        &liq_pool_state_acc.pubkey(),
+        Processor::AUTHORITY,
+    );
+
+    // stake pool sized account owned by another program
+    let forged_stake_pool = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[system_instruction::create_account(
+            &context.payer.pubkey(),
+            &forged_stake_pool.pubkey(),
+            rent.minimum_balance(state::StakePool::LEN),
+            state::StakePool::LEN as u64,
+            &Keypair::new().pubkey(),
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &forged_stake_pool], context.last_blockhash);
+    context.banks_client.process_transaction(transaction).await.unwrap();
+
+    let transaction_error = create_liquidity_pool(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &liq_pool_state_acc.pubkey(),
+        &forged_stake_pool.pubkey(),
+        &stake_pool_accounts.owner,
+        &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &liq_pool_authority,
+        &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        &stake_pool_accounts.liq_pool_sell_fee,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::IncorrectProgramId,
+        )) => {}
+        _ => panic!("Wrong error occurs while try to initialize a liquidity pool with a stake pool of another program"),
+    }
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_with_wrong_liq_pool_wsol_account() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+
+    // a wSOL account that is not the pool leg, even if it is owned by the liq pool authority
+    let wrong_liq_pool_wsol_acc = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wrong_liq_pool_wsol_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &stake_pool_accounts.liq_pool_authority,
+    )
+    .await
+    .unwrap();
+    let wsol_user_dest_acc = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            50_000,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &wrong_liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongLiquidityPoolAccount as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to sell stSOL with wrong liq pool wSOL account"),
+    }
+}
+
+async fn set_sell_fee(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    sell_fee: state::SellFeeCurve,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_sell_fee(
+            &id(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            sell_fee,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_sell_fee() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let new_sell_fee = state::SellFeeCurve {
+        min_fee_bps: 10,
+        max_fee_bps: 1_000,
+        target_liquidity: 10_000_000,
+    };
+    set_sell_fee(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        new_sell_fee,
+    )
+    .await
+    .unwrap();
+
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert_eq!(liq_pool.sell_fee, new_sell_fee);
+
+    // the next sell is charged with the new curve
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let stsol_to_sell = 50_000;
+    sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        stsol_to_sell,
+    )
+    .await;
+    let fee = new_sell_fee.calc_sell_fee(stsol_to_sell, prev_liq_pool_wsol_balance).unwrap();
+    assert!(fee > stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(stsol_to_sell, prev_liq_pool_wsol_balance).unwrap());
+    let liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    assert_eq!(liq_pool_wsol_balance, prev_liq_pool_wsol_balance - (stsol_to_sell - fee));
+}
+
+#[tokio::test]
+async fn test_set_sell_fee_with_wrong_fee_manager() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let wrong_fee_manager = Keypair::new();
+    let transaction_error = set_sell_fee(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wrong_fee_manager,
+        state::SellFeeCurve::default(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongFeeManager as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set the sell fee with wrong fee manager"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_sell_fee_with_invalid_curve() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let transaction_error = set_sell_fee(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        state::SellFeeCurve {
+            min_fee_bps: 500,
+            max_fee_bps: 100,
+            target_liquidity: 1_000_000,
+        },
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidSellFeeCurve as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set an invalid sell fee curve"),
+    }
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_with_slippage_exceeded() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_dest_acc = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let stsol_to_sell = 50_000;
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let fee = stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(stsol_to_sell, prev_liq_pool_wsol_balance).unwrap();
+    let prev_user_stsol_balance = get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+
+    // ask for 1 lamport more than the quote
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            stsol_to_sell,
+            stsol_to_sell - fee + 1,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::SlippageExceeded as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to sell stSOL with min wSOL out above the quote"),
+    }
+
+    // nothing moved
+    assert_eq!(get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await, prev_user_stsol_balance);
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await,
+        prev_liq_pool_wsol_balance
+    );
+}
+
+#[tokio::test]
+async fn test_add_liquidity_with_slippage_exceeded() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo = prepare_wsol_deposit(&mut context.banks_client, &context.payer, &context.last_blockhash).await;
+    let metal_lp_user_dest = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &metal_lp_user_dest,
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // the first provider gets 1:1 shares, ask for more
+    let wsol_amount = 50_000;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_add_liquidity(
+            wsol_amount,
+            wsol_amount + 1,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &metal_lp_user_dest.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::SlippageExceeded as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to add liquidity with min $METALP out above the quote"),
+    }
+    assert_eq!(get_token_balance(&mut context.banks_client, &metal_lp_user_dest.pubkey()).await, 0);
+}
+
+#[tokio::test]
+async fn test_add_liquidity_with_stake_pool_out_of_date() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo = prepare_wsol_deposit(&mut context.banks_client, &context.payer, &context.last_blockhash).await;
+    let metal_lp_user_dest = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &metal_lp_user_dest,
+        &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // a new epoch starts and the stake pool is not updated
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_add_liquidity(
+            50_000,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.meta_lp_mint_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &metal_lp_user_dest.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::StakeListAndPoolOutOfDate as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to add liquidity with an outdated stake pool"),
+    }
+    assert_eq!(get_token_balance(&mut context.banks_client, &metal_lp_user_dest.pubkey()).await, 0);
+}
+
+async fn set_treasury(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    treasury_wsol_account: &Pubkey,
+    treasury_share_bps: u64,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_treasury(
+            &id(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            treasury_wsol_account,
+            treasury_share_bps,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_with_treasury_share() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let treasury_share_bps = 3_333;
+    set_treasury(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        treasury_share_bps,
+    )
+    .await
+    .unwrap();
+
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert_eq!(liq_pool.treasury_share_bps, treasury_share_bps);
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let prev_treasury_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_treasury_acc.pubkey()).await;
+
+    let stsol_to_sell = 50_000;
+    sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        stsol_to_sell,
+    )
+    .await;
+
+    let fee = stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(stsol_to_sell, prev_liq_pool_wsol_balance).unwrap();
+    let treasury_cut = liq_pool.calc_treasury_cut(fee).unwrap();
+    assert!(treasury_cut > 0);
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_treasury_acc.pubkey()).await,
+        prev_treasury_balance + treasury_cut
+    );
+    // the rest of the fee stays in the pool
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await,
+        prev_liq_pool_wsol_balance - (stsol_to_sell - fee) - treasury_cut
+    );
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_above_exchange_rate_one() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+
+    // rewards raise the stSOL exchange rate above 1
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts.reserve_stake,
+        stake_pool.stake_total / 2,
+    )
+    .await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    assert!(stake_pool.stake_total > stake_pool.pool_total);
+
+    // the user gets the stSOL value minus the fee
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let stsol_to_sell = 10_000;
+    let wsol_user_dest_acc = sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        stsol_to_sell,
+    )
+    .await;
+    let sol_value = stake_pool.calc_lamports_amount(stsol_to_sell).unwrap();
+    assert!(sol_value > stsol_to_sell);
+    let fee = stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(sol_value, prev_liq_pool_wsol_balance).unwrap();
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &wsol_user_dest_acc.pubkey()).await,
+        100_000 + sol_value - fee
+    );
+
+    // fewer stSOL than the pool wSOL can still be worth more than the pool wSOL
+    let liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let stsol_to_sell = liq_pool_wsol_balance * 9 / 10;
+    let sol_value = stake_pool.calc_lamports_amount(stsol_to_sell).unwrap();
+    let fee = stake_pool_accounts.liq_pool_sell_fee.calc_sell_fee(sol_value, liq_pool_wsol_balance).unwrap();
+    assert!(sol_value - fee > liq_pool_wsol_balance);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            stsol_to_sell,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::NotEnoughTokensInThePool as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to sell stSOL worth more than the pool wSOL"),
+    }
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_with_stake_pool_out_of_date() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_dest_acc = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // a new epoch starts and the stake pool is not updated
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            10_000,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::StakeListAndPoolOutOfDate as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to sell stSOL with an outdated stake pool"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_treasury_with_wrong_owner() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        _validator_stake_account,
+    ) = setup().await;
+
+    let wrong_owner = Keypair::new();
+    let transaction_error = set_treasury(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wrong_owner,
+        &stake_pool_accounts.liq_pool_treasury_acc.pubkey(),
+        5_000,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set the treasury with wrong owner"),
+    }
+}
+
+#[tokio::test]
+async fn test_sell_st_sol_with_wrong_treasury_account() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_dest_acc = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wsol_user_dest_acc,
+        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // the seller tries to collect the treasury share
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_sell_stsol(
+            50_000,
+            0,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            &wsol_user_dest_acc.pubkey(),
+            &deposit_info.user_pool_account,
+            &context.payer.pubkey(),
+            &wsol_user_dest_acc.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongTreasuryAccount as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to sell stSOL with wrong treasury account"),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn buy_st_sol(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    user_wsol_account: &Pubkey,
+    user_st_sol_account: &Pubkey,
+    wsol_amount: u64,
+    min_stsol_out: u64,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_buy_stsol(
+            wsol_amount,
+            min_stsol_out,
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &spl_token::id(),
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.liq_pool_authority,
+            user_wsol_account,
+            user_st_sol_account,
+            &payer.pubkey(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+/// setup, then sell `stsol_to_sell` so the LP holds stSOL to buy.
+/// Returns the user stSOL account and a user wSOL account to pay with
+async fn setup_with_st_sol_in_liq_pool(
+    stsol_to_sell: u64,
+) -> (ProgramTestContext, StakePoolAccounts, Pubkey, Keypair) {
+    let (
+        mut context,
+        stake_pool_accounts,
+        validator_stake_account,
+    ) = setup().await;
+
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, true, &stake_pool_accounts, &validator_stake_account).await;
+    let wsol_user_acc = sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        stsol_to_sell,
+    )
+    .await;
+
+    (
+        context,
+        stake_pool_accounts,
+        deposit_info.user_pool_account,
+        wsol_user_acc,
+    )
+}
+
+#[tokio::test]
+async fn test_buy_st_sol() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        user_st_sol_account,
+        wsol_user_acc,
+    ) = setup_with_st_sol_in_liq_pool(40_000).await;
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert_eq!(liq_pool.buy_fee_bps, state::LiquidityPool::DEFAULT_BUY_FEE_BPS);
+
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    let prev_liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    let prev_user_wsol_balance = get_token_balance(&mut context.banks_client, &wsol_user_acc.pubkey()).await;
+    let prev_user_st_sol_balance = get_token_balance(&mut context.banks_client, &user_st_sol_account).await;
+
+    let wsol_to_pay = 20_000;
+    let (wsol_in, stsol_out) = liq_pool.calc_buy(wsol_to_pay, prev_liq_pool_st_sol_balance, &stake_pool).unwrap();
+    // not capped, the fee stays in the pool
+    assert_eq!(wsol_in, wsol_to_pay);
+    assert!(stake_pool.calc_lamports_amount(stsol_out).unwrap() < wsol_to_pay);
+
+    buy_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wsol_user_acc.pubkey(),
+        &user_st_sol_account,
+        wsol_to_pay,
+        stsol_out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(get_token_balance(&mut context.banks_client, &wsol_user_acc.pubkey()).await, prev_user_wsol_balance - wsol_in);
+    assert_eq!(get_token_balance(&mut context.banks_client, &user_st_sol_account).await, prev_user_st_sol_balance + stsol_out);
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await,
+        prev_liq_pool_wsol_balance + wsol_in
+    );
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await,
+        prev_liq_pool_st_sol_balance - stsol_out
+    );
+}
+
+#[tokio::test]
+async fn test_buy_st_sol_capped_at_liq_pool_st_sol() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        user_st_sol_account,
+        wsol_user_acc,
+    ) = setup_with_st_sol_in_liq_pool(10_000).await;
+
+    let prev_liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    let prev_user_wsol_balance = get_token_balance(&mut context.banks_client, &wsol_user_acc.pubkey()).await;
+    let prev_user_st_sol_balance = get_token_balance(&mut context.banks_client, &user_st_sol_account).await;
+
+    // pay more than the pool stSOL is worth
+    buy_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wsol_user_acc.pubkey(),
+        &user_st_sol_account,
+        50_000,
+        0,
+    )
+    .await
+    .unwrap();
+
+    // all the pool stSOL is sold, and only the wSOL needed for it is taken
+    assert_eq!(get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await, 0);
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &user_st_sol_account).await,
+        prev_user_st_sol_balance + prev_liq_pool_st_sol_balance
+    );
+    let wsol_paid = prev_user_wsol_balance - get_token_balance(&mut context.banks_client, &wsol_user_acc.pubkey()).await;
+    assert!(wsol_paid < 50_000);
+    assert!(wsol_paid >= prev_liq_pool_st_sol_balance);
+}
+
+#[tokio::test]
+async fn test_buy_st_sol_with_slippage_exceeded() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        user_st_sol_account,
+        wsol_user_acc,
+    ) = setup_with_st_sol_in_liq_pool(40_000).await;
+
+    // without a fee 20_000 wSOL would buy 20_000 stSOL, ask for that
+    let transaction_error = buy_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wsol_user_acc.pubkey(),
+        &user_st_sol_account,
+        20_000,
+        20_000,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::SlippageExceeded as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to buy stSOL with min stSOL out above the quote"),
+    }
+}
+
+#[tokio::test]
+async fn test_buy_st_sol_with_stake_pool_out_of_date() {
+    let (
+        mut context,
+        stake_pool_accounts,
+        user_st_sol_account,
+        wsol_user_acc,
+    ) = setup_with_st_sol_in_liq_pool(40_000).await;
+
+    // a new epoch starts and the stake pool is not updated
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let transaction_error = buy_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wsol_user_acc.pubkey(),
+        &user_st_sol_account,
+        20_000,
+        0,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::StakeListAndPoolOutOfDate as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to buy stSOL with an outdated stake pool"),
+    }
+}
+
+async fn liquidity_pool_unstake(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    validator_stake_account: &Pubkey,
+    st_sol_amount: u64,
+    ticket: u8,
+) -> Result<(), TransportError> {
+    let (ticket_stake_account, _) = Processor::find_unstake_ticket_address(
+        &id(),
+        &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+        ticket,
+    );
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_liquidity_pool_unstake(
+            st_sol_amount,
+            ticket as u64,
+            &id(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.withdraw_authority,
+            validator_stake_account,
+            &ticket_stake_account,
+            &stake_pool_accounts.liq_pool_authority,
+            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &payer.pubkey(),
+            &spl_token::id(),
+            &stake::id(),
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn liquidity_pool_claim(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    ticket: u8,
+    ticket_rent_payer: &Pubkey,
+) -> Result<(), TransportError> {
+    let (ticket_stake_account, _) = Processor::find_unstake_ticket_address(
+        &id(),
+        &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+        ticket,
+    );
+    let temp_wsol_account = Keypair::new();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::instruction_liquidity_pool_claim(
+            ticket as u64,
+            &id(),
+            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+            &ticket_stake_account,
+            &stake_pool_accounts.liq_pool_authority,
+            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
+            &temp_wsol_account.pubkey(),
+            &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
+            &payer.pubkey(),
+            ticket_rent_payer,
+            &spl_token::id(),
+            &stake::id(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, &temp_wsol_account], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
 
-    assert!(!result.is_err(), "TXN ERROR");
+/// initialize the pools and sell `stsol_to_sell` so the LP holds stSOL to unstake.
+/// Returns the validator stake account the deposit went to
+async fn setup_st_sol_to_unstake(
+    context: &mut ProgramTestContext,
+    stsol_to_sell: u64,
+) -> (StakePoolAccounts, ValidatorStakeAccount) {
+    setup_st_sol_to_unstake_with_accounts(context, StakePoolAccounts::new(), stsol_to_sell).await
+}
 
-    // result.err()
-    // .unwrap();
+async fn setup_st_sol_to_unstake_with_accounts(
+    context: &mut ProgramTestContext,
+    stake_pool_accounts: StakePoolAccounts,
+    stsol_to_sell: u64,
+) -> (StakePoolAccounts, ValidatorStakeAccount) {
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+    let validator_stake_account =
+        simple_add_validator_stake_account(context, &stake_pool_accounts).await;
+    let deposit_info: DepositInfo =
+        simple_deposit(context, true, &stake_pool_accounts, &validator_stake_account).await;
+    sell_st_sol(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &deposit_info.user_pool_account,
+        stsol_to_sell,
+    )
+    .await;
 
-    // Check liq-pool stats
-    let liq_pool_wsol_dest_account_balance =
-    get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
-    println!("liq_pool_wsol_dest_account_balance {}",liq_pool_wsol_dest_account_balance);
-    assert_eq!(
-        liq_pool_wsol_dest_account_balance,
-        prev_liq_pool_wsol_dest_account_balance + wsol_to_deposit
-    );
+    (stake_pool_accounts, validator_stake_account)
+}
 
-    // Check tokens deposited
-    let user_token_balance =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
+#[tokio::test]
+async fn test_liquidity_pool_unstake() {
+    let mut context = program_test().start_with_context().await;
+    let (stake_pool_accounts, validator_stake_account) =
+        setup_st_sol_to_unstake(&mut context, 40_000).await;
+
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    let prev_liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    let st_sol_to_unstake = 30_000;
+    let lamports_unstaked = stake_pool_before.calc_lamports_amount(st_sol_to_unstake).unwrap();
+
+    liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &validator_stake_account.stake_account,
+        st_sol_to_unstake,
+        3,
+    )
+    .await
+    .unwrap();
+
+    // stSOL burned from the LP, like a withdraw
     assert_eq!(
-        user_token_balance,
-        user_token_balance_before - wsol_to_deposit
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await,
+        prev_liq_pool_st_sol_balance - st_sol_to_unstake
     );
-
-    // Check meta-lp tokens received
-    // {
-    //     let user_token_balance =
-    //         get_token_balance(&mut banks_client, &metal_lp_user_dest.pubkey()).await;
-    //     assert_eq!(
-    //         user_token_balance,
-    //         wsol_to_deposit
-    //     );
-    // }
-
-    // Check user recipient stake account balance
-    // let user_stake_recipient_account =
-    //     get_account(&mut banks_client, &user_stake_recipient.pubkey()).await;
-    // assert_eq!(
-    //     user_stake_recipient_account.lamports,
-    //     initial_stake_lamports + wsol_to_deposit
-    // );
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    assert_eq!(stake_pool.pool_total, stake_pool_before.pool_total - st_sol_to_unstake);
+    assert_eq!(stake_pool.stake_total, stake_pool_before.stake_total - lamports_unstaked);
+
+    // the ticket holds the unstaked lamports, counted in the LP value
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    let ticket = liq_pool.unstake_tickets[3];
+    assert_eq!(ticket.lamports, lamports_unstaked);
+    assert_eq!(ticket.rent_payer, context.payer.pubkey());
+    assert_eq!(liq_pool.calc_pending_unstake_lamports(), Some(lamports_unstaked));
+
+    // the ticket stake account is deactivating, staker and withdrawer is the liq pool authority
+    let (ticket_stake_address, _) = Processor::find_unstake_ticket_address(
+        &id(),
+        &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+        3,
+    );
+    let ticket_stake_account = get_account(&mut context.banks_client, &ticket_stake_address).await;
+    assert_eq!(ticket_stake_account.lamports, ticket.rent_lamports + lamports_unstaked);
+    let ticket_stake_state =
+        bincode::deserialize::<stake::StakeState>(&ticket_stake_account.data).unwrap();
+    match ticket_stake_state {
+        stake::StakeState::Stake(meta, stake) => {
+            assert_eq!(meta.authorized.staker, stake_pool_accounts.liq_pool_authority);
+            assert_eq!(meta.authorized.withdrawer, stake_pool_accounts.liq_pool_authority);
+            assert_ne!(stake.delegation.deactivation_epoch, std::u64::MAX);
+        }
+        _ => panic!("Ticket stake account is not delegated"),
+    }
 }
 
 #[tokio::test]
-async fn test_sell_st_sol() {
-    
-    println!("-------------------------------");
-    println!("---- START test_sell_st_sol ---");
-    println!("-------------------------------");
-
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        validator_stake_account,
-    ) = setup().await;
-
-    // Get stake pool stake (and check if it is initialized)
-    let stake_pool_account = get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool_data_before =
-        state::StakePool::deserialize(&stake_pool_account.data.as_slice()).unwrap();
-    if !stake_pool_data_before.is_initialized() {
-        panic!("stake_pool_data_before not initialized");
-    }
+async fn test_liquidity_pool_unstake_with_withdrawal_fee_to_owner() {
+    let mut context = program_test().start_with_context().await;
+    let mut stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts.withdrawal_fee = instruction::Fee {
+        numerator: 1,
+        denominator: 10,
+    };
+    stake_pool_accounts.withdrawal_fee_to_owner = 1;
+    let (stake_pool_accounts, validator_stake_account) =
+        setup_st_sol_to_unstake_with_accounts(&mut context, stake_pool_accounts, 40_000).await;
+
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    let prev_liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    let pool_fee_token_balance_before = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    let st_sol_to_unstake = 30_000;
+    let fee = stake_pool_accounts.calculate_withdrawal_fee(st_sol_to_unstake);
+    assert!(fee > 0);
+    let lamports_unstaked = stake_pool_before
+        .calc_lamports_amount(st_sol_to_unstake - fee)
+        .unwrap();
 
-    println!("--- about to call simple_deposit");
-    //call simple_deposit so the user_acc has some stSOL to sell
-    // simple_deposit  does the entire thing: creates acc, stakes and deposits, so the user acc gets stsOL
-    let deposit_info: DepositInfo = simple_deposit(//banks_client: &mut BanksClient, payer: &Keypair, recent_blockhash: &Hash, 
-        //stake_pool_accounts: &StakePoolAccounts, validator_stake_account: &ValidatorStakeAccount,
-        &mut banks_client,
-        &payer, true,
-        &recent_blockhash,
+    liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &stake_pool_accounts,
-        &validator_stake_account
-    ).await;
-
-    // let deposit_info: DepositInfo = prepare_st_sol_deposit(
-    //     &mut banks_client,
-    //     &stake_pool_accounts.pool_mint.pubkey(),
-    //     &stake_pool_data_before.pool_mint_autho
-    //     &payer,
-    //     &recent_blockhash,
-    // )
-    // .await;
-
-    let prev_user_stsol_account_balance =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
-    println!("prev_user_stsol_account_balance={}",prev_user_stsol_account_balance);
+        &validator_stake_account.stake_account,
+        st_sol_to_unstake,
+        0,
+    )
+    .await
+    .unwrap();
 
-    // Create wsol dest account to send wsol
-    let wsol_user_dest_acc = Keypair::new();
-    println!("create_token_account wsol_user_dest_acc");
-    let result = create_token_account (
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &wsol_user_dest_acc,
-        &String::from(W_SOL_1111111_MINT_ACCOUNT).parse().unwrap(),
-        &payer.pubkey()
+    // the LP gives up all the stSOL, the ticket only holds the lamports of the tokens minus the fee
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await,
+        prev_liq_pool_st_sol_balance - st_sol_to_unstake
+    );
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert_eq!(liq_pool.unstake_tickets[0].lamports, lamports_unstaked);
+
+    // the fee tokens move to the owner and stay in circulation
+    let pool_fee_token_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
     )
     .await;
+    assert_eq!(pool_fee_token_balance - pool_fee_token_balance_before, fee);
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    assert_eq!(
+        stake_pool.pool_total,
+        stake_pool_before.pool_total - (st_sol_to_unstake - fee)
+    );
+    assert_eq!(stake_pool.stake_total, stake_pool_before.stake_total - lamports_unstaked);
+}
 
-    // Save state before sell
-    let prev_liq_pool_wsol_account_balance = get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
-    let prev_liq_pool_st_sol_account_balance = get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
-    println!("--- prev_liq_pool wsol/stSOL {}/{}",prev_liq_pool_wsol_account_balance,prev_liq_pool_st_sol_account_balance);
+#[tokio::test]
+async fn test_liquidity_pool_unstake_with_ticket_in_use() {
+    let mut context = program_test().start_with_context().await;
+    let (stake_pool_accounts, validator_stake_account) =
+        setup_st_sol_to_unstake(&mut context, 40_000).await;
+
+    liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &validator_stake_account.stake_account,
+        10_000,
+        0,
+    )
+    .await
+    .unwrap();
 
-    let pre_user_stsol_balance = get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
-    println!("-- pre_user_st_sol_balance {}",pre_user_stsol_balance);
+    let transaction_error = liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &validator_stake_account.stake_account,
+        20_000,
+        0,
+    )
+    .await
+    .err()
+    .unwrap();
 
-    let stsol_to_sell:u64 = 50_000;
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::UnstakeTicketInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to unstake into a pending ticket"),
+    }
+}
 
-    //let new_authority = Pubkey::new_unique();
-    //----------------------
-    let mut transaction = Transaction::new_with_payer(
-        &[instruction::instruction_sell_stsol(
-            stsol_to_sell,
-            &id(),
-            &stake_pool_accounts.stake_pool.pubkey(),
-            &stake_pool_accounts.liq_pool_state_acc.pubkey(),
-            &spl_token::id(),
-            &stake_pool_accounts.liq_pool_wsol_acc.pubkey(),
-            &stake_pool_accounts.liq_pool_st_sol_acc.pubkey(),
-            &stake_pool_accounts.liq_pool_authority,
-            &wsol_user_dest_acc.pubkey(), //where to send the wsol
-            &deposit_info.user_pool_account,//  .user_source_account,
-            &payer.pubkey(), //user acc withdraw auth
+#[tokio::test]
+async fn test_liquidity_pool_unstake_with_dust_amount() {
+    let mut context = program_test().start_with_context().await;
+    let (stake_pool_accounts, validator_stake_account) =
+        setup_st_sol_to_unstake(&mut context, 40_000).await;
+
+    // a ticket takes at least 1/8 of the LP stSOL
+    let liq_pool_st_sol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
+    let min_st_sol_to_unstake =
+        liq_pool_st_sol_balance / state::LiquidityPool::MAX_UNSTAKE_TICKETS as u64;
+    for st_sol_to_unstake in &[1, min_st_sol_to_unstake - 1] {
+        let transaction_error = liquidity_pool_unstake(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &stake_pool_accounts,
+            &validator_stake_account.stake_account,
+            *st_sol_to_unstake,
+            0,
         )
-        .unwrap()],
-        Some(&payer.pubkey()),
+        .await
+        .err()
+        .unwrap();
+
+        match transaction_error {
+            TransportError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(error_index),
+            )) => {
+                let program_error = error::StakePoolError::UnstakeAmountTooLow as u32;
+                assert_eq!(error_index, program_error);
+            }
+            _ => panic!("Wrong error occurs while try to unstake a dust amount"),
+        }
+    }
+
+    // the ticket is still free
+    liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &validator_stake_account.stake_account,
+        min_st_sol_to_unstake,
+        0,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await,
+        liq_pool_st_sol_balance - min_st_sol_to_unstake
     );
-    println!("-------------------------------------");
-    println!("-- SEND TXN instruction_sell_stsol --");
-    println!("-------------------------------------");
-    transaction.sign(&[&payer], recent_blockhash);
-    let result = banks_client.process_transaction(transaction)
-        .await;
-    //println!("{:?}",result);
+}
 
-    assert!(!result.is_err(), "TXN ERROR");
+#[tokio::test]
+async fn test_liquidity_pool_claim() {
+    let mut context = program_test().start_with_context().await;
+    let (stake_pool_accounts, validator_stake_account) =
+        setup_st_sol_to_unstake(&mut context, 40_000).await;
+
+    liquidity_pool_unstake(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &validator_stake_account.stake_account,
+        40_000,
+        0,
+    )
+    .await
+    .unwrap();
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    let ticket = liq_pool.unstake_tickets[0];
+
+    // still cooling down in the unstake epoch
+    let transaction_error = liquidity_pool_claim(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        0,
+        &context.payer.pubkey(),
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::UnstakeTicketNotReady as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to claim a ticket before cooldown"),
+    }
 
-    // result.err()
-    // .unwrap();
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
 
-    let valued = stsol_to_sell; //TODO compute value correctly
-    let fee = processor::proportional(valued,stake_pool_data_before.fee.numerator as u128, stake_pool_data_before.fee.denominator as u128).unwrap();
+    let prev_liq_pool_wsol_balance = get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
+    // anyone can claim, the ticket rent goes back to who paid it
+    let claimer = Keypair::new();
+    transfer(&mut context.banks_client, &context.payer, &recent_blockhash, &claimer.pubkey(), 10_000_000).await;
+    let prev_rent_payer_balance = get_account(&mut context.banks_client, &context.payer.pubkey()).await.lamports;
+
+    liquidity_pool_claim(
+        &mut context.banks_client,
+        &claimer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        0,
+        &context.payer.pubkey(),
+    )
+    .await
+    .unwrap();
 
-    // Check liq-pool wsol balance after sell
-    // Check liq-pool st_sol_tokens after sell
-    let post_liq_pool_wsol_account_balance = get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await;
-    let post_liq_pool_st_sol_account_balance = get_token_balance(&mut banks_client, &stake_pool_accounts.liq_pool_st_sol_acc.pubkey()).await;
-    println!("-- post_liq_pool wsol/stSOL {}/{}",post_liq_pool_wsol_account_balance,post_liq_pool_st_sol_account_balance);
     assert_eq!(
-        post_liq_pool_wsol_account_balance,
-        prev_liq_pool_wsol_account_balance - stsol_to_sell + fee
+        get_token_balance(&mut context.banks_client, &stake_pool_accounts.liq_pool_wsol_acc.pubkey()).await,
+        prev_liq_pool_wsol_balance + ticket.lamports
     );
     assert_eq!(
-        post_liq_pool_st_sol_account_balance,
-        prev_liq_pool_st_sol_account_balance + stsol_to_sell
+        get_account(&mut context.banks_client, &context.payer.pubkey()).await.lamports,
+        prev_rent_payer_balance + ticket.rent_lamports
     );
-
-    // Check user stSol balance after sell
-    let post_user_stsol_balance =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
-    println!("-- post_user_st_sol_balance {}",post_user_stsol_balance);
-    assert_eq!(
-        post_user_stsol_balance,
-        prev_user_stsol_account_balance - stsol_to_sell
+    let (ticket_stake_address, _) = Processor::find_unstake_ticket_address(
+        &id(),
+        &stake_pool_accounts.liq_pool_state_acc.pubkey(),
+        0,
     );
+    assert!(context.banks_client.get_account(ticket_stake_address).await.unwrap().is_none());
 
-    // Check user recipient stake account balance
-    // let user_stake_recipient_account =
-    //     get_account(&mut banks_client, &user_stake_recipient.pubkey()).await;
-    // assert_eq!(
-    //     user_stake_recipient_account.lamports,
-    //     initial_stake_lamports + wsol_to_deposit
-    // );
+    let liq_pool_account = get_account(&mut context.banks_client, &stake_pool_accounts.liq_pool_state_acc.pubkey()).await;
+    let liq_pool = state::LiquidityPool::deserialize(liq_pool_account.data.as_slice()).unwrap();
+    assert_eq!(liq_pool.unstake_tickets[0], state::UnstakeTicket::default());
 }
 
 /*
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_stake_program() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -336,16 +2227,17 @@ async fn test_stake_pool_withdraw_with_wrong_stake_program() {
             &user_stake_recipient.pubkey(),
             &new_authority,
             &deposit_info.user_pool_account,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
             &stake_pool_accounts.pool_mint.pubkey(),
             &spl_token::id(),
             &wrong_stake_program.pubkey(),
             tokens_to_burn,
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -362,9 +2254,7 @@ async fn test_stake_pool_withdraw_with_wrong_stake_program() {
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         mut stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -379,9 +2269,9 @@ async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -407,9 +2297,7 @@ async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -432,16 +2320,17 @@ async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
             &user_stake_recipient.pubkey(),
             &new_authority,
             &deposit_info.user_pool_account,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
             &stake_pool_accounts.pool_mint.pubkey(),
             &wrong_token_program.pubkey(),
             &stake::id(),
             tokens_to_burn,
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context.banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -458,9 +2347,7 @@ async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         mut stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -475,9 +2362,9 @@ async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -504,10 +2391,10 @@ async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
@@ -518,9 +2405,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
     let user_stake_authority = Keypair::new();
     create_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &validator_stake_account.stake_pool,
         &validator_stake_account.stake_account,
         &validator_stake_account.vote.pubkey(),
@@ -538,9 +2425,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
         withdrawer: stake_pool_accounts.deposit_authority,
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
@@ -549,9 +2436,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -560,15 +2447,15 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
     .unwrap();
 
     let user_pool_account = user_pool_account.pubkey();
-    let pool_tokens = get_token_balance(&mut banks_client, &user_pool_account).await;
+    let pool_tokens = get_token_balance(&mut context.banks_client, &user_pool_account).await;
 
     let tokens_to_burn = pool_tokens / 4;
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -583,9 +2470,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &user_pool_account,
             &validator_stake_account.stake_account,
@@ -610,10 +2497,10 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_from_unknown_validator() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
@@ -622,7 +2509,7 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     validator_stake_account
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await;
 
     let user_stake = ValidatorStakeAccount::new_with_target_authority(
@@ -630,15 +2517,15 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     user_stake
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await;
 
     let user_pool_account = Keypair::new();
     let user = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -655,9 +2542,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         withdrawer: stake_pool_accounts.deposit_authority,
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
@@ -666,9 +2553,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -677,15 +2564,15 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
     .unwrap();
 
     let user_pool_account = user_pool_account.pubkey();
-    let pool_tokens = get_token_balance(&mut banks_client, &user_pool_account).await;
+    let pool_tokens = get_token_balance(&mut context.banks_client, &user_pool_account).await;
 
     let tokens_to_burn = pool_tokens / 4;
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -700,9 +2587,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &user_pool_account,
             &validator_stake_account.stake_account,
@@ -728,9 +2615,7 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
 #[tokio::test]
 async fn test_stake_pool_double_withdraw_to_the_same_account() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -740,9 +2625,9 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -750,9 +2635,9 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
     let new_authority = Pubkey::new_unique();
     stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -762,12 +2647,12 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
         .await
         .unwrap();
 
-    let latest_blockhash = banks_client.get_recent_blockhash().await.unwrap();
+    let latest_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
+            &mut context.banks_client,
+            &context.payer,
             &latest_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
@@ -789,38 +2674,27 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
-    let deposit_info: DepositInfo = simple_deposit(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-        &validator_stake_account,
-    )
-    .await;
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, false, &stake_pool_accounts, &validator_stake_account).await;
 
     let tokens_to_burn = deposit_info.pool_tokens / 4;
 
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -828,9 +2702,9 @@ async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
     let new_authority = Pubkey::new_unique();
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -857,37 +2731,26 @@ async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_low_delegation() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
-    let deposit_info: DepositInfo = simple_deposit(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-        &validator_stake_account,
-    )
-    .await;
+    let deposit_info: DepositInfo =
+        simple_deposit(&mut context, false, &stake_pool_accounts, &validator_stake_account).await;
 
     let tokens_to_burn = deposit_info.pool_tokens / 4;
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &deposit_info.user_pool_account,
         &deposit_info.user,
         &stake_pool_accounts.withdraw_authority,
@@ -898,9 +2761,9 @@ async fn test_stake_pool_withdraw_with_low_delegation() {
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -908,9 +2771,9 @@ async fn test_stake_pool_withdraw_with_low_delegation() {
     let new_authority = Pubkey::new_unique();
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
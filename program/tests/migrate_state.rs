@@ -0,0 +1,534 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use bincode::{deserialize, serialize};
+use helpers::*;
+use solana_program::{
+    hash::Hash, program_option::COption, program_pack::Pack, pubkey::Pubkey, rent::Rent,
+};
+use solana_program_test::{BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account, instruction::InstructionError, signature::Keypair, signature::Signer,
+    system_instruction, transaction::Transaction, transaction::TransactionError,
+    transport::TransportError,
+};
+use spl_stake_pool::{processor::Processor, *};
+
+const STAKE_AMOUNT: u64 = 10_000_000_000;
+
+/// Stake pool created by the v1 program
+struct StakePoolV1 {
+    stake_pool: Pubkey,
+    owner: Keypair,
+    validator_stake_list: Pubkey,
+    withdraw_authority: Pubkey,
+    pool_mint: Pubkey,
+    vote: Pubkey,
+    /// Validators listed after `vote`, without stake account
+    other_validators: Vec<Pubkey>,
+}
+
+impl StakePoolV1 {
+    fn new() -> Self {
+        let stake_pool = Pubkey::new_unique();
+        let (withdraw_authority, _) =
+            Processor::find_authority_bump_seed(&id(), &stake_pool, Processor::AUTHORITY_WITHDRAW);
+        Self {
+            stake_pool,
+            owner: Keypair::new(),
+            validator_stake_list: Pubkey::new_unique(),
+            withdraw_authority,
+            pool_mint: Pubkey::new_unique(),
+            vote: Pubkey::new_unique(),
+            other_validators: vec![],
+        }
+    }
+
+    /// v1 stake pool account, the `#[repr(C)]` struct of the v1 program
+    fn stake_pool_account(&self) -> Account {
+        let (_, deposit_bump_seed) = Processor::find_authority_bump_seed(
+            &id(),
+            &self.stake_pool,
+            Processor::AUTHORITY_DEPOSIT,
+        );
+        let (_, withdraw_bump_seed) = Processor::find_authority_bump_seed(
+            &id(),
+            &self.stake_pool,
+            Processor::AUTHORITY_WITHDRAW,
+        );
+        let stake_total = self.validator_balance();
+        let mut data = vec![0; state::StakePool::LEN];
+        data[0] = state::StakePool::STAKE_POOL_VERSION_V1;
+        data[1..33].copy_from_slice(self.owner.pubkey().as_ref());
+        data[33] = deposit_bump_seed;
+        data[34] = withdraw_bump_seed;
+        data[35..67].copy_from_slice(self.validator_stake_list.as_ref());
+        data[67..99].copy_from_slice(self.pool_mint.as_ref());
+        data[99..131].copy_from_slice(Pubkey::new_unique().as_ref());
+        data[131..163].copy_from_slice(spl_token::id().as_ref());
+        data[168..176].copy_from_slice(&stake_total.to_le_bytes());
+        data[176..184].copy_from_slice(&stake_total.to_le_bytes());
+        data[192..200].copy_from_slice(&100u64.to_le_bytes());
+        data[200..208].copy_from_slice(&3u64.to_le_bytes());
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: id(),
+            ..Account::default()
+        }
+    }
+
+    fn validators(&self) -> Vec<Pubkey> {
+        let mut validators = vec![self.vote];
+        validators.extend_from_slice(&self.other_validators);
+        validators
+    }
+
+    /// v1 validator stake list, 1000 entries of 48 bytes listing the validators
+    fn validator_stake_list_account(&self) -> Account {
+        let validators = self.validators();
+        let mut data = vec![0; state::ValidatorStakeList::V1_LEN];
+        data[0] = state::ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION_V1;
+        data[1..3].copy_from_slice(&(validators.len() as u16).to_le_bytes());
+        for (index, validator) in validators.iter().enumerate() {
+            let from = 3 + index * 48;
+            data[from..from + 32].copy_from_slice(validator.as_ref());
+            data[from + 32..from + 40].copy_from_slice(&self.validator_balance().to_le_bytes());
+        }
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: id(),
+            ..Account::default()
+        }
+    }
+
+    fn pool_mint_account(&self) -> Account {
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            mint_authority: COption::Some(self.withdraw_authority),
+            supply: self.validator_balance(),
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        Account {
+            lamports: 10_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        }
+    }
+
+    /// Fully active validator stake account delegated since genesis
+    fn validator_stake_account(&self) -> Account {
+        let stake_rent = Rent::default().minimum_balance(std::mem::size_of::<stake::StakeState>());
+        let stake_state = stake::StakeState::Stake(
+            stake::Meta {
+                rent_exempt_reserve: stake_rent,
+                authorized: stake::Authorized {
+                    staker: self.withdraw_authority,
+                    withdrawer: self.withdraw_authority,
+                },
+                lockup: stake::Lockup::default(),
+            },
+            stake::Stake {
+                delegation: stake::Delegation::new(&self.vote, STAKE_AMOUNT, std::u64::MAX, 0.25),
+                credits_observed: 0,
+            },
+        );
+        let mut data = serialize(&stake_state).unwrap();
+        data.resize(std::mem::size_of::<stake::StakeState>(), 0);
+        Account {
+            lamports: stake_rent + STAKE_AMOUNT,
+            data,
+            owner: stake::id(),
+            ..Account::default()
+        }
+    }
+
+    fn validator_balance(&self) -> u64 {
+        Rent::default().minimum_balance(std::mem::size_of::<stake::StakeState>()) + STAKE_AMOUNT
+    }
+
+    fn validator_stake_address(&self) -> Pubkey {
+        Processor::find_stake_address_for_validator(&id(), &self.vote, &self.stake_pool).0
+    }
+
+    fn extension_address(&self) -> Pubkey {
+        Processor::find_stake_pool_extension_address(&id(), &self.stake_pool).0
+    }
+
+    fn program_test(&self) -> ProgramTest {
+        let mut program_test = program_test();
+        program_test.add_account(self.stake_pool, self.stake_pool_account());
+        program_test.add_account(
+            self.validator_stake_list,
+            self.validator_stake_list_account(),
+        );
+        program_test.add_account(self.pool_mint, self.pool_mint_account());
+        program_test.add_account(
+            self.validator_stake_address(),
+            self.validator_stake_account(),
+        );
+        // the owner pays the extension rent
+        program_test.add_account(
+            self.owner.pubkey(),
+            Account {
+                lamports: 1_000_000_000,
+                ..Account::default()
+            },
+        );
+        program_test
+    }
+}
+
+/// Validator stake list page for the validators the upgraded list can't hold
+async fn create_page(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    page: &Keypair,
+) {
+    let rent = banks_client.get_rent().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[system_instruction::create_account(
+            &payer.pubkey(),
+            &page.pubkey(),
+            rent.minimum_balance(state::ValidatorStakeList::LEN),
+            state::ValidatorStakeList::LEN as u64,
+            &id(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, page], *recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+}
+
+async fn migrate_state(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_v1: &StakePoolV1,
+    owner: &Keypair,
+    page: Option<&Pubkey>,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::migrate_state(
+            &id(),
+            &stake_pool_v1.stake_pool,
+            &owner.pubkey(),
+            &stake_pool_v1.validator_stake_list,
+            page,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn get_stake_pool(
+    banks_client: &mut BanksClient,
+    stake_pool_v1: &StakePoolV1,
+) -> state::StakePool {
+    let account = get_account(banks_client, &stake_pool_v1.stake_pool).await;
+    let extension = get_account(banks_client, &stake_pool_v1.extension_address()).await;
+    assert_eq!(extension.owner, id());
+    assert_eq!(extension.data.len(), state::StakePool::EXTENSION_LEN);
+    state::StakePool::deserialize(account.data.as_slice(), extension.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn test_migrate_state() {
+    let stake_pool_v1 = StakePoolV1::new();
+    let (mut banks_client, payer, recent_blockhash) = stake_pool_v1.program_test().start().await;
+
+    let account = get_account(&mut banks_client, &stake_pool_v1.stake_pool).await;
+    assert_eq!(
+        state::StakePool::deserialize(account.data.as_slice(), &[]),
+        Err(error::StakePoolError::StateNeedsMigration.into())
+    );
+    let stake_account_before =
+        get_account(&mut banks_client, &stake_pool_v1.validator_stake_address()).await;
+
+    migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &stake_pool_v1.owner,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // upgraded in place, the accounts keep their size and address
+    let account = get_account(&mut banks_client, &stake_pool_v1.stake_pool).await;
+    assert_eq!(account.data.len(), state::StakePool::LEN);
+    let (_, withdraw_bump_seed) = Processor::find_authority_bump_seed(
+        &id(),
+        &stake_pool_v1.stake_pool,
+        Processor::AUTHORITY_WITHDRAW,
+    );
+    let (reserve_stake, reserve_bump_seed) =
+        Processor::find_authority_bump_seed(&id(), &stake_pool_v1.stake_pool, Processor::RESERVE);
+    let stake_pool = get_stake_pool(&mut banks_client, &stake_pool_v1).await;
+    assert_eq!(stake_pool.version, state::StakePool::STAKE_POOL_VERSION);
+    assert_eq!(stake_pool.owner, stake_pool_v1.owner.pubkey());
+    assert_eq!(stake_pool.staker, stake_pool_v1.owner.pubkey());
+    assert_eq!(stake_pool.fee_manager, stake_pool_v1.owner.pubkey());
+    assert_eq!(stake_pool.withdraw_bump_seed, withdraw_bump_seed);
+    assert_eq!(stake_pool.reserve_bump_seed, reserve_bump_seed);
+    assert_eq!(
+        stake_pool.validator_stake_list,
+        stake_pool_v1.validator_stake_list
+    );
+    assert_eq!(stake_pool.validator_stake_list_pages_len, 0);
+    assert_eq!(stake_pool.pool_mint, stake_pool_v1.pool_mint);
+    assert_eq!(stake_pool.stake_total, stake_pool_v1.validator_balance());
+    assert_eq!(stake_pool.pool_total, stake_pool_v1.validator_balance());
+    assert_eq!(
+        stake_pool.fee,
+        instruction::Fee {
+            denominator: 100,
+            numerator: 3,
+        }
+    );
+
+    // v1 had no reserve, it is created
+    let reserve = get_account(&mut banks_client, &reserve_stake).await;
+    match deserialize::<stake::StakeState>(&reserve.data).unwrap() {
+        stake::StakeState::Initialized(meta) => {
+            assert_eq!(meta.authorized.staker, stake_pool_v1.withdraw_authority);
+            assert_eq!(meta.authorized.withdrawer, stake_pool_v1.withdraw_authority);
+            assert_eq!(reserve.lamports, meta.rent_exempt_reserve);
+        }
+        _ => panic!("Reserve stake account is not initialized"),
+    }
+
+    let account = get_account(&mut banks_client, &stake_pool_v1.validator_stake_list).await;
+    assert_eq!(account.data.len(), state::ValidatorStakeList::V1_LEN);
+    assert_eq!(
+        state::ValidatorStakeList::deserialize(account.data.as_slice()).unwrap(),
+        state::ValidatorStakeList {
+            version: state::ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
+            validators: vec![state::ValidatorStakeInfo {
+                validator_account: stake_pool_v1.vote,
+                balance: stake_pool_v1.validator_balance(),
+                ..state::ValidatorStakeInfo::default()
+            }],
+        }
+    );
+
+    // the pool mint and the validator stake accounts don't move
+    let account = get_account(&mut banks_client, &stake_pool_v1.pool_mint).await;
+    let pool_mint = spl_token::state::Mint::unpack(account.data.as_slice()).unwrap();
+    assert_eq!(
+        pool_mint.mint_authority,
+        COption::Some(stake_pool_v1.withdraw_authority)
+    );
+    let stake_account =
+        get_account(&mut banks_client, &stake_pool_v1.validator_stake_address()).await;
+    assert_eq!(stake_account, stake_account_before);
+    match deserialize::<stake::StakeState>(&stake_account.data).unwrap() {
+        stake::StakeState::Stake(meta, _) => {
+            assert_eq!(meta.authorized.staker, stake_pool_v1.withdraw_authority);
+            assert_eq!(meta.authorized.withdrawer, stake_pool_v1.withdraw_authority);
+        }
+        _ => panic!(),
+    }
+
+    // the upgraded pool takes the current instructions
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_delinquent_slots(
+            &id(),
+            &stake_pool_v1.stake_pool,
+            &stake_pool_v1.owner.pubkey(),
+            1_000,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &stake_pool_v1.owner], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+    let stake_pool = get_stake_pool(&mut banks_client, &stake_pool_v1).await;
+    assert_eq!(stake_pool.delinquent_slots, 1_000);
+}
+
+#[tokio::test]
+async fn test_migrate_state_with_full_validator_stake_list() {
+    let mut stake_pool_v1 = StakePoolV1::new();
+    stake_pool_v1.other_validators = (1..1000).map(|_| Pubkey::new_unique()).collect();
+    let (mut banks_client, payer, recent_blockhash) = stake_pool_v1.program_test().start().await;
+
+    // the upgraded list can't hold them all
+    let transaction_error = migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &stake_pool_v1.owner,
+        None,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ValidatorStakeListFull as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to migrate a full list without a page"),
+    }
+
+    let page = Keypair::new();
+    create_page(&mut banks_client, &payer, &recent_blockhash, &page).await;
+    migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &stake_pool_v1.owner,
+        Some(&page.pubkey()),
+    )
+    .await
+    .unwrap();
+
+    let stake_pool = get_stake_pool(&mut banks_client, &stake_pool_v1).await;
+    assert_eq!(stake_pool.validator_stake_list_pages_len, 1);
+    assert_eq!(stake_pool.validator_stake_list_pages[0], page.pubkey());
+
+    let validators = stake_pool_v1.validators();
+    let capacity = state::ValidatorStakeList::capacity(state::ValidatorStakeList::V1_LEN);
+    let account = get_account(&mut banks_client, &stake_pool_v1.validator_stake_list).await;
+    let listed = state::ValidatorStakeList::deserialize(account.data.as_slice()).unwrap();
+    let account = get_account(&mut banks_client, &page.pubkey()).await;
+    let paged = state::ValidatorStakeList::deserialize(account.data.as_slice()).unwrap();
+    assert_eq!(listed.validators.len(), capacity);
+    assert_eq!(paged.validators.len(), validators.len() - capacity);
+    assert_eq!(
+        listed
+            .validators
+            .iter()
+            .chain(paged.validators.iter())
+            .map(|validator| validator.validator_account)
+            .collect::<Vec<_>>(),
+        validators
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_state_with_wrong_owner() {
+    let stake_pool_v1 = StakePoolV1::new();
+    let (mut banks_client, payer, recent_blockhash) = stake_pool_v1.program_test().start().await;
+
+    let wrong_owner = Keypair::new();
+    let transaction_error = migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &wrong_owner,
+        None,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to migrate state with wrong owner"),
+    }
+}
+
+#[tokio::test]
+async fn test_migrate_state_with_current_version() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool_v1 = StakePoolV1 {
+        stake_pool: stake_pool_accounts.stake_pool.pubkey(),
+        owner: Keypair::new(),
+        validator_stake_list: stake_pool_accounts.validator_stake_list.pubkey(),
+        withdraw_authority: stake_pool_accounts.withdraw_authority,
+        pool_mint: stake_pool_accounts.pool_mint.pubkey(),
+        vote: Pubkey::new_unique(),
+        other_validators: vec![],
+    };
+    let transaction_error = migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &stake_pool_accounts.owner,
+        None,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidStateVersion as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to migrate a current version stake pool"),
+    }
+}
+
+#[tokio::test]
+async fn test_migrate_state_with_wrong_validator_stake_list() {
+    let mut stake_pool_v1 = StakePoolV1::new();
+    let mut program_test = stake_pool_v1.program_test();
+    // a v1 list of another pool
+    stake_pool_v1.validator_stake_list = Pubkey::new_unique();
+    program_test.add_account(
+        stake_pool_v1.validator_stake_list,
+        stake_pool_v1.validator_stake_list_account(),
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let transaction_error = migrate_state(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_v1,
+        &stake_pool_v1.owner,
+        None,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidValidatorStakeList as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to migrate the list of another pool"),
+    }
+}
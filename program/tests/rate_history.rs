@@ -0,0 +1,219 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+async fn setup() -> (ProgramTestContext, StakePoolAccounts) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    // the owner pays the rate history rent
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts.owner.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+
+    (context, stake_pool_accounts)
+}
+
+fn create_rate_history(stake_pool_accounts: &StakePoolAccounts, owner: &Pubkey) -> Instruction {
+    instruction::create_rate_history(&id(), &stake_pool_accounts.stake_pool.pubkey(), owner)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_rate_history() {
+    let (mut context, stake_pool_accounts) = setup().await;
+
+    let owner = &stake_pool_accounts.owner;
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        create_rate_history(&stake_pool_accounts, &owner.pubkey()),
+        &[owner],
+    )
+    .await
+    .unwrap();
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert!(stake_pool.has_rate_history());
+    let rate_history = stake_pool_accounts
+        .get_rate_history(&mut context.banks_client)
+        .await;
+    assert!(rate_history.is_initialized());
+    assert!(rate_history.entries.is_empty());
+
+    // each epoch update records the pool balances
+    stake_pool_accounts
+        .update_pool_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+    let first_epoch = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await
+        .last_update_epoch;
+
+    let first_normal_slot = context.genesis_config().epoch_schedule.first_normal_slot;
+    context.warp_to_slot(first_normal_slot + 1).unwrap();
+    context.last_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert!(stake_pool.last_update_epoch > first_epoch);
+    let rate_history = stake_pool_accounts
+        .get_rate_history(&mut context.banks_client)
+        .await;
+    let epochs: Vec<u64> = rate_history.iter().map(|entry| entry.epoch).collect();
+    assert_eq!(epochs, vec![first_epoch, stake_pool.last_update_epoch]);
+    assert_eq!(
+        *rate_history.newest().unwrap(),
+        state::RateHistoryEntry {
+            epoch: stake_pool.last_update_epoch,
+            stake_total: stake_pool.stake_total,
+            pool_total: stake_pool.pool_total,
+        }
+    );
+
+    // the pool has a single rate history
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        create_rate_history(&stake_pool_accounts, &owner.pubkey()),
+        &[owner],
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::AlreadyInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to create the rate history twice"),
+    }
+}
+
+#[tokio::test]
+async fn test_create_rate_history_with_wrong_owner() {
+    let (mut context, stake_pool_accounts) = setup().await;
+
+    let wrong_owner = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &wrong_owner.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        create_rate_history(&stake_pool_accounts, &wrong_owner.pubkey()),
+        &[&wrong_owner],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to create the rate history with wrong owner"),
+    }
+}
+
+#[tokio::test]
+async fn test_update_pool_balance_without_rate_history() {
+    let (mut context, stake_pool_accounts) = setup().await;
+
+    let owner = &stake_pool_accounts.owner;
+    process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        create_rate_history(&stake_pool_accounts, &owner.pubkey()),
+        &[owner],
+    )
+    .await
+    .unwrap();
+
+    // a pool with a rate history needs the account to update
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        instruction::update_pool_balance(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.reserve_stake,
+            &stake_pool_accounts.withdraw_authority,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &[],
+        )
+        .unwrap(),
+        &[],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidRateHistory as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update without the rate history"),
+    }
+}
@@ -0,0 +1,407 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::{epoch_schedule::EpochSchedule, hash::Hash, pubkey::Pubkey};
+use solana_program_test::{BanksClient, ProgramTestContext};
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer, transaction::Transaction,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+const RESERVE_LAMPORTS: u64 = 10_000_000_000;
+
+async fn set_validator_weights(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    weights: &[(Pubkey, u64)],
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_validator_weights(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            weights,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn rebalance(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    validator: &Pubkey,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::rebalance(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.withdraw_authority,
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.reserve_stake,
+            validator,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+/// Moves to the first slot of `epoch` and updates the pool
+async fn warp_and_update(
+    context: &mut ProgramTestContext,
+    epoch_schedule: &EpochSchedule,
+    epoch: u64,
+    stake_pool_accounts: &StakePoolAccounts,
+    validators: &[Pubkey],
+) -> Hash {
+    context
+        .warp_to_slot(epoch_schedule.get_first_slot_in_epoch(epoch) + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_list_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            validators,
+        )
+        .await
+        .unwrap();
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+    recent_blockhash
+}
+
+/// Pool with two validators and `RESERVE_LAMPORTS` in the reserve, updated in the first normal epoch
+async fn setup() -> (ProgramTestContext, EpochSchedule, StakePoolAccounts, Vec<Pubkey>) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+
+    let mut validators = vec![];
+    for _ in 0..2 {
+        let validator_stake =
+            simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+        validators.push(validator_stake.vote.pubkey());
+    }
+
+    let user = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user.pubkey(),
+        RESERVE_LAMPORTS + 1_000_000_000,
+    )
+    .await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+    stake_pool_accounts
+        .deposit_sol(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            RESERVE_LAMPORTS,
+        )
+        .await
+        .unwrap();
+
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    warp_and_update(
+        &mut context,
+        &epoch_schedule,
+        epoch_schedule.first_normal_epoch,
+        &stake_pool_accounts,
+        &validators,
+    )
+    .await;
+
+    (context, epoch_schedule, stake_pool_accounts, validators)
+}
+
+#[tokio::test]
+async fn test_set_validator_weights() {
+    let (mut context, _, stake_pool_accounts, validators) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    set_validator_weights(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &[(validators[0], 3), (validators[1], 1)],
+    )
+    .await
+    .unwrap();
+
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    assert_eq!(validator_stake_list.find(&validators[0]).unwrap().target_weight, 3);
+    assert_eq!(validator_stake_list.find(&validators[1]).unwrap().target_weight, 1);
+    assert_eq!(validator_stake_list.total_weight(), 4);
+}
+
+#[tokio::test]
+async fn test_set_validator_weights_by_malicious() {
+    let (mut context, _, stake_pool_accounts, validators) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let malicious = Keypair::new();
+    let transaction_error = set_validator_weights(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &malicious,
+        &[(validators[0], 1)],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while malicious try to set validator weights"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_validator_weights_with_missing_validator() {
+    let (mut context, _, stake_pool_accounts, validators) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    // two weights, one vote account
+    let mut instruction = instruction::set_validator_weights(
+        &id(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &stake_pool_accounts.owner.pubkey(),
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+        &[(validators[0], 3), (validators[1], 1)],
+    )
+    .unwrap();
+    instruction.accounts.pop();
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        recent_blockhash,
+    );
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(_, error)) => {
+            assert_eq!(error, InstructionError::InvalidArgument);
+        }
+        _ => {
+            panic!("Wrong error occurs while try to set validator weights with a missing validator")
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_rebalance() {
+    let (mut context, epoch_schedule, stake_pool_accounts, validators) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>());
+
+    set_validator_weights(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &[(validators[0], 1), (validators[1], 1)],
+    )
+    .await
+    .unwrap();
+
+    // both validators are far below half of the reserve, the epoch limit applies
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    let epoch_limit =
+        stake_pool.stake_total * state::StakePool::MAX_REBALANCE_BPS_PER_EPOCH / 10_000;
+    let reserve_before = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake)
+        .await
+        .lamports;
+    rebalance(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &validators[0],
+    )
+    .await
+    .unwrap();
+
+    // the reserve paid the moved lamports and the transient account rent
+    let reserve = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake).await;
+    assert_eq!(reserve.lamports, reserve_before - epoch_limit - stake_rent);
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let info = *validator_stake_list.find(&validators[0]).unwrap();
+    assert_eq!(info.transient_balance, epoch_limit + stake_rent);
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.rebalanced_lamports, epoch_limit);
+
+    // nothing left for the other validator this epoch
+    let transaction_error = rebalance(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &validators[1],
+    )
+    .await
+    .err()
+    .unwrap();
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::RebalanceNotNeeded as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to rebalance above the epoch limit"),
+    }
+
+    // next epoch the stake is merged, now move it away from the first validator
+    let recent_blockhash = warp_and_update(
+        &mut context,
+        &epoch_schedule,
+        epoch_schedule.first_normal_epoch + 1,
+        &stake_pool_accounts,
+        &validators,
+    )
+    .await;
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let merged = *validator_stake_list.find(&validators[0]).unwrap();
+    // the rent of the merged transient account went back to the reserve
+    assert_eq!(merged.balance, info.balance + epoch_limit);
+    assert_eq!(merged.transient_balance, 0);
+
+    set_validator_weights(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &[(validators[0], 0)],
+    )
+    .await
+    .unwrap();
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    let epoch_limit =
+        stake_pool.stake_total * state::StakePool::MAX_REBALANCE_BPS_PER_EPOCH / 10_000;
+    rebalance(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &validators[0],
+    )
+    .await
+    .unwrap();
+
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let info = *validator_stake_list.find(&validators[0]).unwrap();
+    let moved = epoch_limit.min(validator_stake_above_min(merged.balance, stake_rent));
+    assert_eq!(info.balance, merged.balance - moved);
+    assert_eq!(info.transient_balance, moved + stake_rent);
+}
+
+/// lamports the validator stake account can give, it keeps 1 SOL plus its rent
+fn validator_stake_above_min(balance: u64, stake_rent: u64) -> u64 {
+    balance - 1_000_000_000 - stake_rent
+}
+
+#[tokio::test]
+async fn test_rebalance_without_weights() {
+    let (mut context, _, stake_pool_accounts, validators) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let transaction_error = rebalance(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &validators[0],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::RebalanceNotNeeded as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to rebalance without target weights"),
+    }
+}
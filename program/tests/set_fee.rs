@@ -0,0 +1,183 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::hash::Hash;
+use solana_program_test::BanksClient;
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer, transaction::Transaction,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+async fn set_fee(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    fee: instruction::Fee,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_fee(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &owner.pubkey(),
+            fee,
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_fee() {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+
+    let new_fee = instruction::Fee {
+        numerator: 3,
+        denominator: 100,
+    };
+    set_fee(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        new_fee,
+    )
+    .await
+    .unwrap();
+
+    // the fee is only scheduled
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.fee, stake_pool_accounts.fee);
+    assert!(stake_pool.has_pending_fee());
+    assert_eq!(stake_pool.next_fee, new_fee);
+    assert_eq!(
+        stake_pool.next_fee_epoch,
+        stake_pool.last_update_epoch + state::StakePool::FEE_CHANGE_EPOCHS
+    );
+    let activation_epoch = stake_pool.next_fee_epoch;
+
+    // one epoch later the old fee still applies
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    context
+        .warp_to_slot(epoch_schedule.get_first_slot_in_epoch(activation_epoch - 1))
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.fee, stake_pool_accounts.fee);
+    assert_eq!(stake_pool.next_fee_epoch, activation_epoch);
+
+    // UpdatePoolBalance promotes it at the activation epoch
+    context
+        .warp_to_slot(epoch_schedule.get_first_slot_in_epoch(activation_epoch))
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.fee, new_fee);
+    assert!(!stake_pool.has_pending_fee());
+    assert_eq!(stake_pool.next_fee, instruction::Fee::default());
+}
+
+#[tokio::test]
+async fn test_set_fee_too_high() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let transaction_error = set_fee(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        instruction::Fee {
+            numerator: 101,
+            denominator: 100,
+        },
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::FeeTooHigh as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set a fee above 100%"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_fee_by_malicious() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let malicious = Keypair::new();
+    let transaction_error = set_fee(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &stake_pool_accounts,
+        &malicious,
+        instruction::Fee {
+            numerator: 1,
+            denominator: 2,
+        },
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongFeeManager as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while malicious try to set fee"),
+    }
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert!(!stake_pool.has_pending_fee());
+}
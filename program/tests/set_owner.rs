@@ -6,6 +6,8 @@ use helpers::*;
 use solana_program::hash::Hash;
 use solana_program::instruction::AccountMeta;
 use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_instruction;
 use solana_program_test::BanksClient;
 use solana_sdk::{
     instruction::InstructionError, signature::Keypair, signature::Signer, transaction::Transaction,
@@ -53,32 +55,60 @@ async fn setup() -> (
 
 #[tokio::test]
 async fn test_set_owner() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, new_pool_fee, new_owner) =
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, new_owner) =
         setup().await;
 
-    let mut transaction = Transaction::new_with_payer(
-        &[instruction::set_owner(
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_owner(
             &id(),
             &stake_pool_accounts.stake_pool.pubkey(),
             &stake_pool_accounts.owner.pubkey(),
             &new_owner.pubkey(),
-            &new_pool_fee.pubkey(),
         )
-        .unwrap()],
-        Some(&payer.pubkey()),
-    );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    banks_client.process_transaction(transaction).await.unwrap();
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .unwrap();
+
+    // The owner only changes once the new owner accepts
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.owner, stake_pool_accounts.owner.pubkey());
+    assert_eq!(stake_pool.pending_owner, new_owner.pubkey());
 
-    let stake_pool = get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool = state::StakePool::deserialize(&stake_pool.data.as_slice()).unwrap();
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::accept_owner(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &new_owner.pubkey(),
+        )
+        .unwrap(),
+        &[&new_owner],
+    )
+    .await
+    .unwrap();
 
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
     assert_eq!(stake_pool.owner, new_owner.pubkey());
+    assert_eq!(stake_pool.pending_owner, Pubkey::default());
+    // Other roles are not moved with the ownership
+    assert_eq!(stake_pool.staker, stake_pool_accounts.owner.pubkey());
+    assert_eq!(stake_pool.fee_manager, stake_pool_accounts.owner.pubkey());
+    assert_eq!(
+        stake_pool.owner_fee_account,
+        stake_pool_accounts.pool_fee_account.pubkey()
+    );
 }
 
 #[tokio::test]
 async fn test_set_owner_by_malicious() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, new_pool_fee, new_owner) =
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, new_owner) =
         setup().await;
 
     let mut transaction = Transaction::new_with_payer(
@@ -87,7 +117,6 @@ async fn test_set_owner_by_malicious() {
             &stake_pool_accounts.stake_pool.pubkey(),
             &new_owner.pubkey(),
             &new_owner.pubkey(),
-            &new_pool_fee.pubkey(),
         )
         .unwrap()],
         Some(&payer.pubkey()),
@@ -113,16 +142,16 @@ async fn test_set_owner_by_malicious() {
 
 #[tokio::test]
 async fn test_set_owner_without_signature() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, new_pool_fee, new_owner) =
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, new_owner) =
         setup().await;
 
     let args = instruction::StakePoolInstruction::SetOwner;
     let data = args.serialize().unwrap();
     let accounts = vec![
         AccountMeta::new(stake_pool_accounts.stake_pool.pubkey(), false),
+        AccountMeta::new(stake_pool_accounts.stake_pool_extension, false),
         AccountMeta::new_readonly(stake_pool_accounts.owner.pubkey(), false),
         AccountMeta::new_readonly(new_owner.pubkey(), false),
-        AccountMeta::new_readonly(new_pool_fee.pubkey(), false),
     ];
     let instruction = Instruction {
         program_id: id(),
@@ -151,7 +180,313 @@ async fn test_set_owner_without_signature() {
 }
 
 #[tokio::test]
-async fn test_set_owner_with_wrong_mint_for_pool_fee_acc() {
+async fn test_accept_owner_by_wrong_key() {
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, new_owner) =
+        setup().await;
+
+    // No transfer pending
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::accept_owner(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &new_owner.pubkey(),
+        )
+        .unwrap(),
+        &[&new_owner],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongPendingOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!(
+            "Wrong error occurs while try to accept owner while no owner transfer is pending"
+        ),
+    }
+
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_owner(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &new_owner.pubkey(),
+        )
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .unwrap();
+
+    // A mistyped new owner can't take over, the owner can propose again
+    let malicious = Keypair::new();
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::accept_owner(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &malicious.pubkey(),
+        )
+        .unwrap(),
+        &[&malicious],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongPendingOwner as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to accept owner with wrong key"),
+    }
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.owner, stake_pool_accounts.owner.pubkey());
+}
+
+#[tokio::test]
+async fn test_set_staker() {
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, _) = setup().await;
+
+    let new_staker = Keypair::new();
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_staker(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &new_staker.pubkey(),
+        )
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.staker, new_staker.pubkey());
+
+    // Staker instructions now need the staker, not the owner
+    let rent = banks_client.get_rent().await.unwrap();
+    let page = Keypair::new();
+    let mut transaction = Transaction::new_with_payer(
+        &[system_instruction::create_account(
+            &payer.pubkey(),
+            &page.pubkey(),
+            rent.minimum_balance(state::ValidatorStakeList::LEN),
+            state::ValidatorStakeList::LEN as u64,
+            &id(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &page], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::add_validator_stake_list_page(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &page.pubkey(),
+        )
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => {
+            panic!("Wrong error occurs while try to add a validator stake list page with the owner")
+        }
+    }
+
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::add_validator_stake_list_page(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &new_staker.pubkey(),
+            &page.pubkey(),
+        )
+        .unwrap(),
+        &[&new_staker],
+    )
+    .await
+    .unwrap();
+
+    // The staker can hand over its role
+    let next_staker = Keypair::new();
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_staker(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &new_staker.pubkey(),
+            &next_staker.pubkey(),
+        )
+        .unwrap(),
+        &[&new_staker],
+    )
+    .await
+    .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.staker, next_staker.pubkey());
+}
+
+#[tokio::test]
+async fn test_set_staker_by_malicious() {
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, _, _) = setup().await;
+
+    let malicious = Keypair::new();
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_staker(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &malicious.pubkey(),
+            &malicious.pubkey(),
+        )
+        .unwrap(),
+        &[&malicious],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to malicious try to set staker"),
+    }
+}
+
+#[tokio::test]
+async fn test_set_fee_manager() {
+    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, new_pool_fee, _) =
+        setup().await;
+
+    let new_fee_manager = Keypair::new();
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_fee_manager(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &new_fee_manager.pubkey(),
+            &new_pool_fee.pubkey(),
+        )
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.fee_manager, new_fee_manager.pubkey());
+    assert_eq!(stake_pool.owner_fee_account, new_pool_fee.pubkey());
+    assert_eq!(stake_pool.owner, stake_pool_accounts.owner.pubkey());
+
+    let fee = instruction::Fee {
+        denominator: 100,
+        numerator: 2,
+    };
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_fee(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            fee,
+        )
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongFeeManager as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to set fee with the owner"),
+    }
+
+    process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_fee(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &new_fee_manager.pubkey(),
+            fee,
+        )
+        .unwrap(),
+        &[&new_fee_manager],
+    )
+    .await
+    .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.next_fee, fee);
+}
+
+#[tokio::test]
+async fn test_set_fee_manager_with_wrong_mint_for_pool_fee_acc() {
     let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
@@ -162,7 +497,7 @@ async fn test_set_owner_with_wrong_mint_for_pool_fee_acc() {
     let new_mint = Keypair::new();
     let new_withdraw_auth = Keypair::new();
     let new_pool_fee = Keypair::new();
-    let new_owner = Keypair::new();
+    let new_fee_manager = Keypair::new();
 
     create_mint(
         &mut banks_client,
@@ -179,28 +514,28 @@ async fn test_set_owner_with_wrong_mint_for_pool_fee_acc() {
         &recent_blockhash,
         &new_pool_fee,
         &new_mint.pubkey(),
-        &new_owner.pubkey(),
+        &new_fee_manager.pubkey(),
     )
     .await
     .unwrap();
 
-    let mut transaction = Transaction::new_with_payer(
-        &[instruction::set_owner(
+    let transaction_error = process_instruction(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        instruction::set_fee_manager(
             &id(),
             &stake_pool_accounts.stake_pool.pubkey(),
             &stake_pool_accounts.owner.pubkey(),
-            &new_owner.pubkey(),
+            &new_fee_manager.pubkey(),
             &new_pool_fee.pubkey(),
         )
-        .unwrap()],
-        Some(&payer.pubkey()),
-    );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
-        .process_transaction(transaction)
-        .await
-        .err()
-        .unwrap();
+        .unwrap(),
+        &[&stake_pool_accounts.owner],
+    )
+    .await
+    .err()
+    .unwrap();
 
     match transaction_error {
         TransportError::TransactionError(TransactionError::InstructionError(
@@ -210,6 +545,6 @@ async fn test_set_owner_with_wrong_mint_for_pool_fee_acc() {
             let program_error = error::StakePoolError::WrongAccountMint as u32;
             assert_eq!(error_index, program_error);
         }
-        _ => panic!("Wrong error occurs while try to set new owner with wrong mint"),
+        _ => panic!("Wrong error occurs while try to set fee manager with wrong mint"),
     }
 }
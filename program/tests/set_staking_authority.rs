@@ -4,28 +4,25 @@ mod helpers;
 
 use bincode::deserialize;
 use helpers::*;
-use solana_program::hash::Hash;
 use solana_program::instruction::AccountMeta;
 use solana_program::instruction::Instruction;
 use solana_program::sysvar;
-use solana_program_test::BanksClient;
+use solana_program_test::ProgramTestContext;
 use solana_sdk::{
     instruction::InstructionError, signature::Keypair, signature::Signer, transaction::Transaction,
     transaction::TransactionError, transport::TransportError,
 };
 use spl_stake_pool::*;
 
-async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
-    StakePoolAccounts,
-    ValidatorStakeAccount,
-) {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+async fn setup() -> (ProgramTestContext, StakePoolAccounts, ValidatorStakeAccount) {
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -36,15 +33,30 @@ async fn setup() -> (
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     user_stake
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
+    // only fully active stake can be added
+    warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -54,28 +66,21 @@ async fn setup() -> (
 
     let error = stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
         )
         .await;
     assert!(error.is_none());
 
-    (
-        banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-    )
+    (context, stake_pool_accounts, user_stake)
 }
 
 #[tokio::test]
 async fn test_set_staking_authority() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, user_stake) =
-        setup().await;
+    let (mut context, stake_pool_accounts, user_stake) = setup().await;
 
     let new_staking_pubkey = Keypair::new().pubkey();
 
@@ -90,13 +95,20 @@ async fn test_set_staking_authority() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    banks_client.process_transaction(transaction).await.unwrap();
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
 
     // Check of stake account authority has changed
-    let stake = get_account(&mut banks_client, &user_stake.stake_account).await;
+    let stake = get_account(&mut context.banks_client, &user_stake.stake_account).await;
     let stake_state = deserialize::<stake::StakeState>(&stake.data).unwrap();
     match stake_state {
         stake::StakeState::Stake(meta, _) => {
@@ -112,8 +124,7 @@ async fn test_set_staking_authority() {
 
 #[tokio::test]
 async fn test_set_staking_authority_with_wrong_stake_program_id() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, user_stake) =
-        setup().await;
+    let (mut context, stake_pool_accounts, user_stake) = setup().await;
 
     let new_staking_pubkey = Keypair::new().pubkey();
 
@@ -128,10 +139,14 @@ async fn test_set_staking_authority_with_wrong_stake_program_id() {
             &Keypair::new().pubkey(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -149,8 +164,7 @@ async fn test_set_staking_authority_with_wrong_stake_program_id() {
 
 #[tokio::test]
 async fn test_set_staking_authority_with_wrong_withdraw_authority() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, user_stake) =
-        setup().await;
+    let (mut context, stake_pool_accounts, user_stake) = setup().await;
 
     let new_staking_pubkey = Keypair::new().pubkey();
 
@@ -165,10 +179,14 @@ async fn test_set_staking_authority_with_wrong_withdraw_authority() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -190,8 +208,7 @@ async fn test_set_staking_authority_with_wrong_withdraw_authority() {
 
 #[tokio::test]
 async fn test_set_staking_authority_with_wrong_owner() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, user_stake) =
-        setup().await;
+    let (mut context, stake_pool_accounts, user_stake) = setup().await;
 
     let new_staking_pubkey = Keypair::new().pubkey();
     let wrong_owner = Keypair::new();
@@ -207,10 +224,11 @@ async fn test_set_staking_authority_with_wrong_owner() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &wrong_owner], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer, &wrong_owner], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -230,8 +248,7 @@ async fn test_set_staking_authority_with_wrong_owner() {
 
 #[tokio::test]
 async fn test_set_staking_authority_without_signature() {
-    let (mut banks_client, payer, recent_blockhash, stake_pool_accounts, user_stake) =
-        setup().await;
+    let (mut context, stake_pool_accounts, user_stake) = setup().await;
 
     let new_staking_pubkey = Keypair::new().pubkey();
 
@@ -239,6 +256,7 @@ async fn test_set_staking_authority_without_signature() {
     let data = args.serialize().unwrap();
     let accounts = vec![
         AccountMeta::new(stake_pool_accounts.stake_pool.pubkey(), false),
+        AccountMeta::new(stake_pool_accounts.stake_pool_extension, false),
         AccountMeta::new_readonly(stake_pool_accounts.owner.pubkey(), false),
         AccountMeta::new_readonly(stake_pool_accounts.withdraw_authority, false),
         AccountMeta::new(user_stake.stake_account, false),
@@ -252,9 +270,11 @@ async fn test_set_staking_authority_without_signature() {
         data,
     };
 
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -4,9 +4,14 @@ mod helpers;
 
 use crate::helpers::TEST_STAKE_AMOUNT;
 use helpers::*;
-use solana_program::pubkey::Pubkey;
-use solana_program_test::BanksClient;
-use solana_sdk::signature::Signer;
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use solana_program_test::{BanksClient, ProgramTestContext};
+use solana_sdk::{
+    instruction::InstructionError,
+    signature::{Keypair, Signer},
+    transaction::TransactionError,
+    transport::TransportError,
+};
 use spl_stake_pool::*;
 
 async fn get_list_sum(banks_client: &mut BanksClient, validator_stake_list_key: &Pubkey) -> u64 {
@@ -27,10 +32,10 @@ async fn get_list_sum(banks_client: &mut BanksClient, validator_stake_list_key:
 
 #[tokio::test]
 async fn test_update_list_balance() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
         .await
         .unwrap();
 
@@ -39,13 +44,7 @@ async fn test_update_list_balance() {
     const STAKE_ACCOUNTS: u64 = 3;
     for _ in 0..STAKE_ACCOUNTS {
         stake_accounts.push(
-            simple_add_validator_stake_account(
-                &mut banks_client,
-                &payer,
-                &recent_blockhash,
-                &stake_pool_accounts,
-            )
-            .await,
+            simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await,
         );
     }
 
@@ -54,22 +53,22 @@ async fn test_update_list_balance() {
 
     for stake_account in stake_accounts {
         transfer(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &stake_account.stake_account,
             EXTRA_STAKE,
         )
         .await;
     }
 
-    let rent = banks_client.get_rent().await.unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
     let stake_rent = rent.minimum_balance(std::mem::size_of::<stake::StakeState>()) + 1;
 
     // Check current balance in the list
     assert_eq!(
         get_list_sum(
-            &mut banks_client,
+            &mut context.banks_client,
             &stake_pool_accounts.validator_stake_list.pubkey()
         )
         .await,
@@ -82,5 +81,262 @@ async fn test_update_list_balance() {
 #[tokio::test]
 async fn test_update_list_balance_with_uninitialized_validator_stake_list() {} // TODO
 
+/// Pool with one validator, warped to the first normal epoch. The reserve was
+/// created with the pool, no DepositSol funded it
+async fn setup() -> (ProgramTestContext, StakePoolAccounts, ValidatorStakeAccount) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+
+    let validator_stake =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + 1)
+        .unwrap();
+
+    (context, stake_pool_accounts, validator_stake)
+}
+
+fn update_list_balance_instruction(
+    stake_pool_accounts: &StakePoolAccounts,
+    validators: &[Pubkey],
+) -> Instruction {
+    instruction::update_list_balance(
+        &id(),
+        &stake_pool_accounts.stake_pool.pubkey(),
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+        &stake_pool_accounts.withdraw_authority,
+        &stake_pool_accounts.reserve_stake,
+        validators,
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_update_list_balance_moves_extra_lamports_to_reserve() {
+    let (mut context, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    const EXTRA_STAKE: u64 = 1_000_000;
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &validator_stake.stake_account,
+        EXTRA_STAKE,
+    )
+    .await;
+
+    let list_sum_before = get_list_sum(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let reserve_before = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake)
+        .await
+        .lamports;
+
+    stake_pool_accounts
+        .update_list_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &[validator_stake.vote.pubkey()],
+        )
+        .await
+        .unwrap();
+
+    // lamports sent to the validator stake account are not delegated stake
+    assert_eq!(
+        get_list_sum(
+            &mut context.banks_client,
+            &stake_pool_accounts.validator_stake_list.pubkey()
+        )
+        .await,
+        list_sum_before
+    );
+    let reserve = get_account(&mut context.banks_client, &stake_pool_accounts.reserve_stake).await;
+    assert_eq!(reserve.lamports, reserve_before + EXTRA_STAKE);
+}
+
+#[tokio::test]
+async fn test_update_list_balance_with_wrong_stake_pool() {
+    let (mut context, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    let other_stake_pool_accounts = StakePoolAccounts::new();
+    other_stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    // the list of the first pool with the other pool
+    let mut instruction =
+        update_list_balance_instruction(&stake_pool_accounts, &[validator_stake.vote.pubkey()]);
+    instruction.accounts[0].pubkey = other_stake_pool_accounts.stake_pool.pubkey();
+    instruction.accounts[1].pubkey = other_stake_pool_accounts.stake_pool_extension;
+
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        instruction,
+        &[],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidValidatorStakeList as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update the list with another stake pool"),
+    }
+}
+
+#[tokio::test]
+async fn test_update_list_balance_with_wrong_validator_stake_address() {
+    let (mut context, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    // a stake account delegated to a listed validator, but not owned by the pool
+    let user_stake = Keypair::new();
+    let user_stake_authority = Keypair::new();
+    create_independent_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &user_stake,
+        &stake::Authorized {
+            staker: user_stake_authority.pubkey(),
+            withdrawer: user_stake_authority.pubkey(),
+        },
+        &stake::Lockup::default(),
+    )
+    .await;
+    delegate_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &user_stake.pubkey(),
+        &user_stake_authority,
+        &validator_stake.vote.pubkey(),
+    )
+    .await;
+
+    let mut instruction =
+        update_list_balance_instruction(&stake_pool_accounts, &[validator_stake.vote.pubkey()]);
+    instruction.accounts[8].pubkey = user_stake.pubkey();
+
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        instruction,
+        &[],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidStakeAccountAddress as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update the list with a foreign stake account"),
+    }
+}
+
 #[tokio::test]
-async fn test_update_list_balance_with_wrong_stake_state() {} // TODO
+async fn test_update_list_balance_with_wrong_stake_state() {
+    let (mut context, stake_pool_accounts, validator_stake) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    // a system account in place of the validator stake account
+    let fake_stake = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &fake_stake.pubkey(),
+        1_000_000_000,
+    )
+    .await;
+
+    let mut instruction =
+        update_list_balance_instruction(&stake_pool_accounts, &[validator_stake.vote.pubkey()]);
+    instruction.accounts[8].pubkey = fake_stake.pubkey();
+
+    let transaction_error = process_instruction(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        instruction,
+        &[],
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStakeState as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update the list with a non stake account"),
+    }
+}
+
+#[tokio::test]
+async fn test_update_list_balance_with_unknown_validator() {
+    let (mut context, stake_pool_accounts, _) = setup().await;
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    // a pool stake account that was never added to the list
+    let validator_stake = ValidatorStakeAccount::new_with_target_authority(
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+    validator_stake
+        .create_and_delegate(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await;
+
+    let transaction_error = stake_pool_accounts
+        .update_list_balance(
+            &mut context.banks_client,
+            &context.payer,
+            &recent_blockhash,
+            &[validator_stake.vote.pubkey()],
+        )
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::ValidatorNotFound as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update the list with an unknown validator"),
+    }
+}
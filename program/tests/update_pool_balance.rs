@@ -21,6 +21,224 @@ async fn test_update_pool_balance() {
     // TODO: Waiting for the ability to advance clock (or modify account data) to finish the tests
 }
 
+#[tokio::test]
+async fn test_update_pool_balance_counts_reserve() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let user = Keypair::new();
+    transfer(&mut banks_client, &payer, &recent_blockhash, &user.pubkey(), 2_000_000_000).await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut banks_client,
+        &payer,
+        &recent_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+    let deposit_lamports = 1_000_000_000;
+    stake_pool_accounts
+        .deposit_sol(
+            &mut banks_client,
+            &payer,
+            &recent_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            deposit_lamports,
+        )
+        .await
+        .unwrap();
+
+    stake_pool_accounts
+        .update_pool_balance(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    // no validators, the reserve lamports above its rent are the whole stake
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut banks_client).await;
+    assert_eq!(stake_pool.stake_total, deposit_lamports);
+}
+
+#[tokio::test]
+async fn test_update_pool_balance_mints_reward_fee() {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut context.banks_client, &context.payer, &context.last_blockhash)
+        .await
+        .unwrap();
+
+    let user = Keypair::new();
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user.pubkey(),
+        2_000_000_000,
+    )
+    .await;
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+    stake_pool_accounts
+        .deposit_sol(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user,
+            &user_pool_account.pubkey(),
+            1_000_000_000,
+        )
+        .await
+        .unwrap();
+
+    let epoch_schedule = context.genesis_config().epoch_schedule.clone();
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+
+    // rewards land in the pool's stake accounts
+    let reward = 100_000_000;
+    transfer(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &stake_pool_accounts.reserve_stake,
+        reward,
+    )
+    .await;
+
+    let stake_pool_before = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    let fee_balance_before = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.stake_total, stake_pool_before.stake_total + reward);
+
+    let expected_fee_tokens = state::StakePool {
+        stake_total: stake_pool.stake_total,
+        ..stake_pool_before
+    }
+    .calc_reward_fee_amount(reward)
+    .unwrap();
+    assert!(expected_fee_tokens > 0);
+    let fee_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    assert_eq!(fee_balance, fee_balance_before + expected_fee_tokens);
+    assert_eq!(
+        stake_pool.pool_total,
+        stake_pool_before.pool_total + expected_fee_tokens
+    );
+
+    // at the published rate the owner holds reward_fee% of the rewards
+    let reward_fee_lamports = reward * stake_pool_accounts.reward_fee.numerator
+        / stake_pool_accounts.reward_fee.denominator;
+    let fee_value = stake_pool
+        .calc_lamports_amount(expected_fee_tokens)
+        .unwrap();
+    assert!(fee_value <= reward_fee_lamports && fee_value + 1 >= reward_fee_lamports);
+
+    // next epoch without rewards: nothing else is minted
+    context
+        .warp_to_slot(epoch_schedule.first_normal_slot + epoch_schedule.slots_per_epoch + 1)
+        .unwrap();
+    let recent_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
+    stake_pool_accounts
+        .update_pool_balance(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let stake_pool_after = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool_after.stake_total, stake_pool.stake_total);
+    assert_eq!(stake_pool_after.pool_total, stake_pool.pool_total);
+    assert!(stake_pool_after.last_update_epoch > stake_pool.last_update_epoch);
+    assert_eq!(
+        get_token_balance(
+            &mut context.banks_client,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+        )
+        .await,
+        fee_balance
+    );
+}
+
+#[tokio::test]
+async fn test_update_pool_balance_with_wrong_fee_account() {
+    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let wrong_fee_account = Keypair::new();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::update_pool_balance(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.reserve_stake,
+            &stake_pool_accounts.withdraw_authority,
+            &wrong_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &[],
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    let transaction_error = banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidFeeAccount as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update pool balance with wrong fee account"),
+    }
+}
+
 #[tokio::test]
 async fn test_update_pool_balance_with_wrong_validator_stake_list() {
     let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
@@ -36,6 +254,12 @@ async fn test_update_pool_balance_with_wrong_validator_stake_list() {
             &id(),
             &stake_pool_accounts.stake_pool.pubkey(),
             &wrong_stake_list_storage.pubkey(),
+            &stake_pool_accounts.reserve_stake,
+            &stake_pool_accounts.withdraw_authority,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &[],
         )
         .unwrap()],
         Some(&payer.pubkey()),
@@ -0,0 +1,465 @@
+#![cfg(feature = "test-bpf")]
+
+mod helpers;
+
+use helpers::*;
+use solana_program::{hash::Hash, system_instruction};
+use solana_program_test::{BanksClient, ProgramTestContext};
+use solana_sdk::{
+    instruction::InstructionError, signature::Keypair, signature::Signer, transaction::Transaction,
+    transaction::TransactionError, transport::TransportError,
+};
+use spl_stake_pool::*;
+
+async fn add_validator_stake_list_page(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: &Hash,
+    stake_pool_accounts: &StakePoolAccounts,
+    owner: &Keypair,
+    page: &Keypair,
+) -> Result<(), TransportError> {
+    let rent = banks_client.get_rent().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            system_instruction::create_account(
+                &payer.pubkey(),
+                &page.pubkey(),
+                rent.minimum_balance(state::ValidatorStakeList::LEN),
+                state::ValidatorStakeList::LEN as u64,
+                &id(),
+            ),
+            instruction::add_validator_stake_list_page(
+                &id(),
+                &stake_pool_accounts.stake_pool.pubkey(),
+                &owner.pubkey(),
+                &page.pubkey(),
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[payer, owner, page], *recent_blockhash);
+    banks_client.process_transaction(transaction).await?;
+    Ok(())
+}
+
+async fn setup() -> (ProgramTestContext, StakePoolAccounts, Keypair) {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    let page = Keypair::new();
+    add_validator_stake_list_page(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &page,
+    )
+    .await
+    .unwrap();
+
+    (context, stake_pool_accounts, page)
+}
+
+#[tokio::test]
+async fn test_add_validator_stake_list_page() {
+    let (mut context, stake_pool_accounts, page) = setup().await;
+
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.validator_stake_list_pages_len, 1);
+    assert_eq!(
+        stake_pool.validator_stake_list_pages().collect::<Vec<_>>(),
+        vec![
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &page.pubkey()
+        ]
+    );
+
+    let validator_stake_list =
+        get_validator_stake_list(&mut context.banks_client, &page.pubkey()).await;
+    assert!(validator_stake_list.is_initialized());
+    assert!(validator_stake_list.validators.is_empty());
+}
+
+#[tokio::test]
+async fn test_add_validator_stake_list_page_with_wrong_staker() {
+    let (mut context, stake_pool_accounts, _) = setup().await;
+
+    let wrong_staker = Keypair::new();
+    let transaction_error = add_validator_stake_list_page(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &wrong_staker,
+        &Keypair::new(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::WrongStaker as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!(
+            "Wrong error occurs while try to add a validator stake list page with wrong staker"
+        ),
+    }
+}
+
+#[tokio::test]
+async fn test_add_validator_stake_list_page_with_linked_page() {
+    let (mut context, stake_pool_accounts, page) = setup().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::add_validator_stake_list_page(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &page.pubkey(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
+    );
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::AlreadyInUse as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to add a validator stake list page twice"),
+    }
+}
+
+#[tokio::test]
+async fn test_add_validator_stake_list_page_above_limit() {
+    let (mut context, stake_pool_accounts, _) = setup().await;
+
+    // the pool list and the setup page are linked
+    for _ in 2..state::MAX_VALIDATOR_STAKE_LIST_PAGES {
+        add_validator_stake_list_page(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &stake_pool_accounts,
+            &stake_pool_accounts.owner,
+            &Keypair::new(),
+        )
+        .await
+        .unwrap();
+    }
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(
+        stake_pool.validator_stake_list_pages().count(),
+        state::MAX_VALIDATOR_STAKE_LIST_PAGES
+    );
+
+    let transaction_error = add_validator_stake_list_page(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &stake_pool_accounts,
+        &stake_pool_accounts.owner,
+        &Keypair::new(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::TooManyValidatorStakeListPages as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!(
+            "Wrong error occurs while try to add a validator stake list page above the limit"
+        ),
+    }
+}
+
+#[tokio::test]
+async fn test_validator_in_second_page() {
+    let (mut context, stake_pool_accounts, page) = setup().await;
+
+    let validator_stake = ValidatorStakeAccount::new_with_target_authority(
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+    validator_stake
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await;
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &Keypair::new().pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::add_validator_stake_account(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &stake_pool_accounts.deposit_authority,
+            &stake_pool_accounts.withdraw_authority,
+            &page.pubkey(),
+            &validator_stake.stake_account,
+            &user_pool_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &stake::id(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        recent_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let validator_stake_list =
+        get_validator_stake_list(&mut context.banks_client, &page.pubkey()).await;
+    assert!(validator_stake_list.contains(&validator_stake.vote.pubkey()));
+    let validator_stake_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    assert!(validator_stake_list.validators.is_empty());
+
+    // The pool balance needs every page
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::update_pool_balance(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.validator_stake_list.pubkey(),
+            &stake_pool_accounts.reserve_stake,
+            &stake_pool_accounts.withdraw_authority,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &[],
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], recent_blockhash);
+    let transaction_error = context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .err()
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::InvalidValidatorStakeList as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!("Wrong error occurs while try to update pool balance without all the pages"),
+    }
+
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    // Deposits locate the validator in its page
+    let user_stake = Keypair::new();
+    let stake_lamports = create_active_deposit_stake(
+        &mut context,
+        &stake_pool_accounts,
+        &validator_stake.vote.pubkey(),
+        &user_stake,
+    )
+    .await;
+    let validator_stake_list =
+        get_validator_stake_list(&mut context.banks_client, &page.pubkey()).await;
+    let validator = *validator_stake_list
+        .find(&validator_stake.vote.pubkey())
+        .unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::deposit(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &page.pubkey(),
+            &stake_pool_accounts.deposit_authority,
+            &stake_pool_accounts.withdraw_authority,
+            &user_stake.pubkey(),
+            &validator_stake.stake_account,
+            &user_pool_account.pubkey(),
+            &stake_pool_accounts.pool_fee_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &stake::id(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let validator_stake_list =
+        get_validator_stake_list(&mut context.banks_client, &page.pubkey()).await;
+    assert_eq!(
+        validator_stake_list
+            .find(&validator_stake.vote.pubkey())
+            .unwrap()
+            .balance,
+        validator.balance + stake_lamports
+    );
+}
+
+#[tokio::test]
+async fn test_update_pool_balance_with_pages() {
+    let (mut context, stake_pool_accounts, page) = setup().await;
+
+    let first_validator =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
+
+    let validator_stake = ValidatorStakeAccount::new_with_target_authority(
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+    validator_stake
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await;
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &recent_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &Keypair::new().pubkey(),
+    )
+    .await
+    .unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::add_validator_stake_account(
+            &id(),
+            &stake_pool_accounts.stake_pool.pubkey(),
+            &stake_pool_accounts.owner.pubkey(),
+            &stake_pool_accounts.deposit_authority,
+            &stake_pool_accounts.withdraw_authority,
+            &page.pubkey(),
+            &validator_stake.stake_account,
+            &user_pool_account.pubkey(),
+            &stake_pool_accounts.pool_mint.pubkey(),
+            &spl_token::id(),
+            &stake::id(),
+        )
+        .unwrap()],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        recent_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let recent_blockhash = warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(&mut context.banks_client, &context.payer, &recent_blockhash)
+        .await
+        .unwrap();
+
+    // The pool stake sums the validators of every page
+    let first_list = get_validator_stake_list(
+        &mut context.banks_client,
+        &stake_pool_accounts.validator_stake_list.pubkey(),
+    )
+    .await;
+    let second_list = get_validator_stake_list(&mut context.banks_client, &page.pubkey()).await;
+    let first = first_list
+        .find(&first_validator.vote.pubkey())
+        .unwrap()
+        .total_balance()
+        .unwrap();
+    let second = second_list
+        .find(&validator_stake.vote.pubkey())
+        .unwrap()
+        .total_balance()
+        .unwrap();
+    assert!(first > 0 && second > 0);
+    let stake_pool = stake_pool_accounts
+        .get_stake_pool(&mut context.banks_client)
+        .await;
+    assert_eq!(stake_pool.stake_total, first + second);
+}
@@ -5,11 +5,10 @@ mod helpers;
 use helpers::*;
 
 use bincode::deserialize;
-use solana_program::hash::Hash;
 use solana_program::instruction::AccountMeta;
 use solana_program::instruction::Instruction;
 use solana_program::sysvar;
-use solana_program_test::BanksClient;
+use solana_program_test::ProgramTestContext;
 use solana_sdk::{
     instruction::InstructionError,
     signature::{Keypair, Signer},
@@ -20,17 +19,19 @@ use solana_sdk::{
 use spl_stake_pool::*;
 
 async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
+    ProgramTestContext,
     StakePoolAccounts,
     ValidatorStakeAccount,
     Keypair,
 ) {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -41,15 +42,30 @@ async fn setup() -> (
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     user_stake
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
+    // only fully active stake can be added
+    warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -57,39 +73,26 @@ async fn setup() -> (
     .await
     .unwrap();
 
-    (
-        banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    )
+    (context, stake_pool_accounts, user_stake, user_pool_account)
 }
 
 #[tokio::test]
 async fn test_add_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let error = stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
         )
         .await;
     assert!(error.is_none());
 
-    let stake_account_balance = banks_client
+    let stake_account_balance = context
+        .banks_client
         .get_account(user_stake.stake_account)
         .await
         .unwrap()
@@ -97,10 +100,11 @@ async fn test_add_validator_stake_account() {
         .lamports;
     let deposit_tokens = stake_account_balance; // For now 1:1 math
                                                 // Check token account balance
-    let token_balance = get_token_balance(&mut banks_client, &user_pool_account.pubkey()).await;
+    let token_balance =
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
     assert_eq!(token_balance, deposit_tokens);
     let pool_fee_token_balance = get_token_balance(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.pool_fee_account.pubkey(),
     )
     .await;
@@ -108,7 +112,7 @@ async fn test_add_validator_stake_account() {
 
     // Check if validator account was added to the list
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -120,14 +124,18 @@ async fn test_add_validator_stake_account() {
             version: state::ValidatorStakeList::VALIDATOR_STAKE_LIST_VERSION,
             validators: vec![state::ValidatorStakeInfo {
                 validator_account: user_stake.vote.pubkey(),
-                last_update_epoch: 0,
+                // setup warps to epoch 1
+                last_update_epoch: 1,
                 balance: stake_account_balance,
+                transient_balance: 0,
+                target_weight: 0,
+                leaving: false,
             }]
         }
     );
 
     // Check of stake account authority has changed
-    let stake = get_account(&mut banks_client, &user_stake.stake_account).await;
+    let stake = get_account(&mut context.banks_client, &user_stake.stake_account).await;
     let stake_state = deserialize::<stake::StakeState>(&stake.data).unwrap();
     match stake_state {
         stake::StakeState::Stake(meta, _) => {
@@ -146,14 +154,7 @@ async fn test_add_validator_stake_account() {
 
 #[tokio::test]
 async fn test_add_validator_stake_account_with_wrong_token_program_id() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let mut transaction = Transaction::new_with_payer(
         &[instruction::add_validator_stake_account(
@@ -170,10 +171,14 @@ async fn test_add_validator_stake_account_with_wrong_token_program_id() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -189,14 +194,7 @@ async fn test_add_validator_stake_account_with_wrong_token_program_id() {
 
 #[tokio::test]
 async fn test_add_validator_stake_account_with_wrong_pool_mint_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let wrong_pool_mint = Keypair::new();
 
@@ -215,10 +213,14 @@ async fn test_add_validator_stake_account_with_wrong_pool_mint_account() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
+    );
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -238,14 +240,7 @@ async fn test_add_validator_stake_account_with_wrong_pool_mint_account() {
 
 #[tokio::test]
 async fn test_add_validator_stake_account_with_wrong_validator_stake_list_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let wrong_validator_stake_list = Keypair::new();
 
@@ -264,10 +259,14 @@ async fn test_add_validator_stake_account_with_wrong_validator_stake_list_accoun
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -287,31 +286,24 @@ async fn test_add_validator_stake_account_with_wrong_validator_stake_list_accoun
 
 #[tokio::test]
 async fn test_try_to_add_already_added_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
         )
         .await;
 
-    let latest_blockhash = banks_client.get_recent_blockhash().await.unwrap();
+    let latest_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
 
     let transaction_error = stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
+            &mut context.banks_client,
+            &context.payer,
             &latest_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
@@ -333,14 +325,7 @@ async fn test_try_to_add_already_added_validator_stake_account() {
 
 #[tokio::test]
 async fn test_not_owner_try_to_add_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let malicious = Keypair::new();
 
@@ -359,10 +344,11 @@ async fn test_not_owner_try_to_add_validator_stake_account() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &malicious], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer, &malicious], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -373,7 +359,7 @@ async fn test_not_owner_try_to_add_validator_stake_account() {
             _,
             InstructionError::Custom(error_index),
         )) => {
-            let program_error = error::StakePoolError::WrongOwner as u32;
+            let program_error = error::StakePoolError::WrongStaker as u32;
             assert_eq!(error_index, program_error);
         }
         _ => panic!("Wrong error occurs while malicious try to add validator stake account"),
@@ -382,17 +368,11 @@ async fn test_not_owner_try_to_add_validator_stake_account() {
 
 #[tokio::test]
 async fn test_not_owner_try_to_add_validator_stake_account_without_signature() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let accounts = vec![
         AccountMeta::new(stake_pool_accounts.stake_pool.pubkey(), false),
+        AccountMeta::new(stake_pool_accounts.stake_pool_extension, false),
         AccountMeta::new_readonly(stake_pool_accounts.owner.pubkey(), false),
         AccountMeta::new_readonly(stake_pool_accounts.deposit_authority, false),
         AccountMeta::new_readonly(stake_pool_accounts.withdraw_authority, false),
@@ -413,9 +393,11 @@ async fn test_not_owner_try_to_add_validator_stake_account_without_signature() {
             .unwrap(),
     };
 
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -435,10 +417,14 @@ async fn test_not_owner_try_to_add_validator_stake_account_without_signature() {
 
 #[tokio::test]
 async fn test_add_validator_stake_account_when_stake_acc_not_in_stake_state() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -450,9 +436,9 @@ async fn test_add_validator_stake_account_when_stake_acc_not_in_stake_state() {
     );
     let user_stake_authority = Keypair::new();
     create_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake.stake_pool,
         &user_stake.stake_account,
         &user_stake.vote.pubkey(),
@@ -463,9 +449,9 @@ async fn test_add_validator_stake_account_when_stake_acc_not_in_stake_state() {
 
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -475,9 +461,9 @@ async fn test_add_validator_stake_account_when_stake_acc_not_in_stake_state() {
 
     let transaction_error = stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
         )
@@ -498,14 +484,7 @@ async fn test_add_validator_stake_account_when_stake_acc_not_in_stake_state() {
 
 #[tokio::test]
 async fn test_add_validator_stake_account_with_wrong_stake_program_id() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account) = setup().await;
 
     let wrong_stake_program = Keypair::new();
 
@@ -524,10 +503,14 @@ async fn test_add_validator_stake_account_with_wrong_stake_program_id() {
             &wrong_stake_program.pubkey(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -543,6 +526,70 @@ async fn test_add_validator_stake_account_with_wrong_stake_program_id() {
     }
 }
 
+#[tokio::test]
+async fn test_add_validator_stake_account_with_activating_stake() {
+    let mut context = program_test().start_with_context().await;
+    let stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
+    // delegated in the current epoch, not active yet
+    let user_stake = ValidatorStakeAccount::new_with_target_authority(
+        &stake_pool_accounts.deposit_authority,
+        &stake_pool_accounts.stake_pool.pubkey(),
+    );
+    user_stake
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await;
+
+    let user = Keypair::new();
+    let user_pool_account = Keypair::new();
+    create_token_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_pool_account,
+        &stake_pool_accounts.pool_mint.pubkey(),
+        &user.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let transaction_error = stake_pool_accounts
+        .add_validator_stake_account(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake.stake_account,
+            &user_pool_account.pubkey(),
+        )
+        .await
+        .unwrap();
+
+    match transaction_error {
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(error_index),
+        )) => {
+            let program_error = error::StakePoolError::UserStakeNotActive as u32;
+            assert_eq!(error_index, program_error);
+        }
+        _ => panic!(
+            "Wrong error occurs while try to add validator stake account with activating stake"
+        ),
+    }
+}
+
 #[tokio::test]
 async fn test_add_validator_stake_account_to_unupdated_stake_pool() {} // TODO
 
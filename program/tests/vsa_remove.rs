@@ -4,12 +4,11 @@ mod helpers;
 
 use bincode::deserialize;
 use helpers::*;
-use solana_program::hash::Hash;
 use solana_program::instruction::AccountMeta;
 use solana_program::instruction::Instruction;
 use solana_program::pubkey::Pubkey;
 use solana_program::sysvar;
-use solana_program_test::BanksClient;
+use solana_program_test::ProgramTestContext;
 use solana_sdk::{
     instruction::InstructionError,
     signature::{Keypair, Signer},
@@ -20,18 +19,20 @@ use solana_sdk::{
 use spl_stake_pool::*;
 
 async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
+    ProgramTestContext,
     StakePoolAccounts,
     ValidatorStakeAccount,
     Keypair,
     Keypair,
 ) {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -42,15 +43,30 @@ async fn setup() -> (
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     user_stake
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
+    // only fully active stake can be added
+    warp_to_next_epoch(&mut context).await;
+    stake_pool_accounts
+        .update_all(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
+        .await
+        .unwrap();
+
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -60,9 +76,9 @@ async fn setup() -> (
 
     let error = stake_pool_accounts
         .add_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
         )
@@ -70,9 +86,7 @@ async fn setup() -> (
     assert!(error.is_none());
 
     (
-        banks_client,
-        payer,
-        recent_blockhash,
+        context,
         stake_pool_accounts,
         user_stake,
         user_pool_account,
@@ -82,21 +96,14 @@ async fn setup() -> (
 
 #[tokio::test]
 async fn test_remove_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        user,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, user) = setup().await;
 
-    let tokens_to_burn = get_token_balance(&mut banks_client, &user_pool_account.pubkey()).await;
+    let tokens_to_burn =
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account.pubkey(),
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -107,9 +114,9 @@ async fn test_remove_validator_stake_account() {
     let new_authority = Pubkey::new_unique();
     let error = stake_pool_accounts
         .remove_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
             &new_authority,
@@ -118,12 +125,13 @@ async fn test_remove_validator_stake_account() {
     assert!(error.is_none());
 
     // Check if all tokens were burned
-    let tokens_left = get_token_balance(&mut banks_client, &user_pool_account.pubkey()).await;
+    let tokens_left =
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
     assert_eq!(tokens_left, 0);
 
     // Check if account was removed from the list of stake accounts
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -138,7 +146,7 @@ async fn test_remove_validator_stake_account() {
     );
 
     // Check of stake account authority has changed
-    let stake = get_account(&mut banks_client, &user_stake.stake_account).await;
+    let stake = get_account(&mut context.banks_client, &user_stake.stake_account).await;
     let stake_state = deserialize::<stake::StakeState>(&stake.data).unwrap();
     match stake_state {
         stake::StakeState::Stake(meta, _) => {
@@ -151,15 +159,7 @@ async fn test_remove_validator_stake_account() {
 
 #[tokio::test]
 async fn test_remove_validator_stake_account_with_wrong_stake_program_id() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let wrong_stake_program = Keypair::new();
 
@@ -179,10 +179,14 @@ async fn test_remove_validator_stake_account_with_wrong_stake_program_id() {
             &wrong_stake_program.pubkey(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
+    );
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -201,15 +205,7 @@ async fn test_remove_validator_stake_account_with_wrong_stake_program_id() {
 
 #[tokio::test]
 async fn test_remove_validator_stake_account_with_wrong_token_program_id() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let wrong_token_program = Keypair::new();
 
@@ -229,10 +225,14 @@ async fn test_remove_validator_stake_account_with_wrong_token_program_id() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -248,15 +248,7 @@ async fn test_remove_validator_stake_account_with_wrong_token_program_id() {
 
 #[tokio::test]
 async fn test_remove_validator_stake_account_with_wrong_pool_mint_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let wrong_pool_mint = Keypair::new();
 
@@ -276,10 +268,14 @@ async fn test_remove_validator_stake_account_with_wrong_pool_mint_account() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
+    );
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -299,15 +295,7 @@ async fn test_remove_validator_stake_account_with_wrong_pool_mint_account() {
 
 #[tokio::test]
 async fn test_remove_validator_stake_account_with_wrong_validator_stake_list_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let wrong_validator_stake_list = Keypair::new();
 
@@ -327,10 +315,14 @@ async fn test_remove_validator_stake_account_with_wrong_validator_stake_list_acc
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&context.payer, &stake_pool_accounts.owner],
+        context.last_blockhash,
     );
-    transaction.sign(&[&payer, &stake_pool_accounts.owner], recent_blockhash);
-    let transaction_error = banks_client
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -350,21 +342,14 @@ async fn test_remove_validator_stake_account_with_wrong_validator_stake_list_acc
 
 #[tokio::test]
 async fn test_remove_already_removed_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        user,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, user) = setup().await;
 
-    let tokens_to_burn = get_token_balance(&mut banks_client, &user_pool_account.pubkey()).await;
+    let tokens_to_burn =
+        get_token_balance(&mut context.banks_client, &user_pool_account.pubkey()).await;
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account.pubkey(),
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -375,9 +360,9 @@ async fn test_remove_already_removed_validator_stake_account() {
     let new_authority = Pubkey::new_unique();
     let error = stake_pool_accounts
         .remove_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
             &new_authority,
@@ -385,12 +370,12 @@ async fn test_remove_already_removed_validator_stake_account() {
         .await;
     assert!(error.is_none());
 
-    let latest_blockhash = banks_client.get_recent_blockhash().await.unwrap();
+    let latest_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
 
     let transaction_error = stake_pool_accounts
         .remove_validator_stake_account(
-            &mut banks_client,
-            &payer,
+            &mut context.banks_client,
+            &context.payer,
             &latest_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
@@ -415,15 +400,7 @@ async fn test_remove_already_removed_validator_stake_account() {
 
 #[tokio::test]
 async fn test_not_owner_try_to_remove_validator_stake_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let malicious = Keypair::new();
 
@@ -443,10 +420,11 @@ async fn test_not_owner_try_to_remove_validator_stake_account() {
             &stake::id(),
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer, &malicious], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer, &malicious], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -457,7 +435,7 @@ async fn test_not_owner_try_to_remove_validator_stake_account() {
             _,
             InstructionError::Custom(error_index),
         )) => {
-            let program_error = error::StakePoolError::WrongOwner as u32;
+            let program_error = error::StakePoolError::WrongStaker as u32;
             assert_eq!(error_index, program_error);
         }
         _ => panic!("Wrong error occurs while not an owner try to remove validator stake address"),
@@ -466,20 +444,13 @@ async fn test_not_owner_try_to_remove_validator_stake_account() {
 
 #[tokio::test]
 async fn test_not_owner_try_to_remove_validator_stake_account_without_signature() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        user_stake,
-        user_pool_account,
-        _,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, user_stake, user_pool_account, _) = setup().await;
 
     let new_authority = Pubkey::new_unique();
 
     let accounts = vec![
         AccountMeta::new(stake_pool_accounts.stake_pool.pubkey(), false),
+        AccountMeta::new(stake_pool_accounts.stake_pool_extension, false),
         AccountMeta::new_readonly(stake_pool_accounts.owner.pubkey(), false),
         AccountMeta::new_readonly(stake_pool_accounts.withdraw_authority, false),
         AccountMeta::new_readonly(new_authority, false),
@@ -499,9 +470,11 @@ async fn test_not_owner_try_to_remove_validator_stake_account_without_signature(
             .unwrap(),
     };
 
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -521,10 +494,14 @@ async fn test_not_owner_try_to_remove_validator_stake_account_without_signature(
 
 #[tokio::test]
 async fn test_remove_validator_stake_account_when_stake_acc_not_in_stake_state() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -536,9 +513,9 @@ async fn test_remove_validator_stake_account_when_stake_acc_not_in_stake_state()
     );
     let user_stake_authority = Keypair::new();
     create_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake.stake_pool,
         &user_stake.stake_account,
         &user_stake.vote.pubkey(),
@@ -549,9 +526,9 @@ async fn test_remove_validator_stake_account_when_stake_acc_not_in_stake_state()
 
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -563,9 +540,9 @@ async fn test_remove_validator_stake_account_when_stake_acc_not_in_stake_state()
 
     let transaction_error = stake_pool_accounts
         .remove_validator_stake_account(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake.stake_account,
             &user_pool_account.pubkey(),
             &new_authority,
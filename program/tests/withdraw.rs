@@ -5,8 +5,7 @@ mod helpers;
 use helpers::*;
 use solana_program::pubkey::Pubkey;
 
-use solana_program::hash::Hash;
-use solana_program_test::BanksClient;
+use solana_program_test::ProgramTestContext;
 use solana_sdk::{
     instruction::InstructionError,
     signature::{Keypair, Signer},
@@ -18,33 +17,40 @@ use spl_stake_pool::*;
 use spl_token::error::TokenError;
 
 async fn setup() -> (
-    BanksClient,
-    Keypair,
-    Hash,
+    ProgramTestContext,
     StakePoolAccounts,
     ValidatorStakeAccount,
     DepositInfo,
     u64,
 ) {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
-    let stake_pool_accounts = StakePoolAccounts::new();
+    setup_with_accounts(StakePoolAccounts::new()).await
+}
+
+async fn setup_with_accounts(
+    stake_pool_accounts: StakePoolAccounts,
+) -> (
+    ProgramTestContext,
+    StakePoolAccounts,
+    ValidatorStakeAccount,
+    DepositInfo,
+    u64,
+) {
+    let mut context = program_test().start_with_context().await;
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
     let deposit_info: DepositInfo = simple_deposit(
-        &mut banks_client,
-        &payer,false,
-        &recent_blockhash,
+        &mut context,
+        false,
         &stake_pool_accounts,
         &validator_stake_account,
     )
@@ -54,9 +60,9 @@ async fn setup() -> (
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &deposit_info.user_pool_account,
         &deposit_info.user,
         &stake_pool_accounts.withdraw_authority,
@@ -65,9 +71,7 @@ async fn setup() -> (
     .await;
 
     (
-        banks_client,
-        payer,
-        recent_blockhash,
+        context,
         stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -77,35 +81,25 @@ async fn setup() -> (
 
 #[tokio::test]
 async fn test_stake_pool_withdraw() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        validator_stake_account,
-        deposit_info,
-        tokens_to_burn,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account, deposit_info, tokens_to_burn) =
+        setup().await;
 
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     let initial_stake_lamports = create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
 
     // Save stake pool state before withdrawal
-    let stake_pool_before =
-        get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool_before =
-        state::StakePool::deserialize(&stake_pool_before.data.as_slice()).unwrap();
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
 
     // Save validator stake account record before withdrawal
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -115,16 +109,25 @@ async fn test_stake_pool_withdraw() {
         .find(&validator_stake_account.vote.pubkey())
         .unwrap();
 
-    // Save user token balance
+    // Save user and owner token balances
     let user_token_balance_before =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    let pool_fee_token_balance_before = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    assert_eq!(
+        pool_fee_token_balance_before,
+        stake_pool_accounts.calculate_fee(deposit_info.stake_lamports)
+    );
 
     let new_authority = Pubkey::new_unique();
     stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -135,8 +138,7 @@ async fn test_stake_pool_withdraw() {
         .unwrap();
 
     // Check pool stats
-    let stake_pool = get_account(&mut banks_client, &stake_pool_accounts.stake_pool.pubkey()).await;
-    let stake_pool = state::StakePool::deserialize(&stake_pool.data.as_slice()).unwrap();
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
     assert_eq!(
         stake_pool.stake_total,
         stake_pool_before.stake_total - tokens_to_burn
@@ -148,7 +150,7 @@ async fn test_stake_pool_withdraw() {
 
     // Check validator stake list storage
     let validator_stake_list = get_account(
-        &mut banks_client,
+        &mut context.banks_client,
         &stake_pool_accounts.validator_stake_list.pubkey(),
     )
     .await;
@@ -164,15 +166,26 @@ async fn test_stake_pool_withdraw() {
 
     // Check tokens burned
     let user_token_balance =
-        get_token_balance(&mut banks_client, &deposit_info.user_pool_account).await;
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
     assert_eq!(
         user_token_balance,
         user_token_balance_before - tokens_to_burn
     );
 
+    // Withdrawals do not touch the owner's fee tokens
+    let pool_fee_token_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    assert_eq!(pool_fee_token_balance, pool_fee_token_balance_before);
+
     // Check validator stake account balance
-    let validator_stake_account =
-        get_account(&mut banks_client, &validator_stake_account.stake_account).await;
+    let validator_stake_account = get_account(
+        &mut context.banks_client,
+        &validator_stake_account.stake_account,
+    )
+    .await;
     assert_eq!(
         validator_stake_account.lamports,
         validator_stake_item.balance
@@ -180,24 +193,173 @@ async fn test_stake_pool_withdraw() {
 
     // Check user recipient stake account balance
     let user_stake_recipient_account =
-        get_account(&mut banks_client, &user_stake_recipient.pubkey()).await;
+        get_account(&mut context.banks_client, &user_stake_recipient.pubkey()).await;
     assert_eq!(
         user_stake_recipient_account.lamports,
         initial_stake_lamports + tokens_to_burn
     );
 }
 
+async fn withdraw_with_fee(
+    withdrawal_fee_to_owner: u8,
+) -> (
+    StakePoolAccounts,
+    state::StakePool,
+    state::StakePool,
+    u64,
+    u64,
+    u64,
+    u64,
+) {
+    let mut stake_pool_accounts = StakePoolAccounts::new();
+    stake_pool_accounts.withdrawal_fee = instruction::Fee {
+        numerator: 1,
+        denominator: 10,
+    };
+    stake_pool_accounts.withdrawal_fee_to_owner = withdrawal_fee_to_owner;
+    let (mut context, stake_pool_accounts, validator_stake_account, deposit_info, tokens_to_burn) =
+        setup_with_accounts(stake_pool_accounts).await;
+
+    let user_stake_recipient = Keypair::new();
+    let initial_stake_lamports = create_blank_stake_account(
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
+        &user_stake_recipient,
+    )
+    .await;
+
+    let stake_pool_before = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+    let user_token_balance_before =
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    let pool_fee_token_balance_before = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+
+    stake_pool_accounts
+        .withdraw_stake(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+            &user_stake_recipient.pubkey(),
+            &deposit_info.user_pool_account,
+            &validator_stake_account.stake_account,
+            &Pubkey::new_unique(),
+            tokens_to_burn,
+        )
+        .await
+        .unwrap();
+
+    let stake_pool = stake_pool_accounts.get_stake_pool(&mut context.banks_client).await;
+
+    // the user always gives up all the tokens
+    let user_token_balance =
+        get_token_balance(&mut context.banks_client, &deposit_info.user_pool_account).await;
+    assert_eq!(
+        user_token_balance,
+        user_token_balance_before - tokens_to_burn
+    );
+
+    let user_stake_recipient_account =
+        get_account(&mut context.banks_client, &user_stake_recipient.pubkey()).await;
+    let withdrawn_lamports = user_stake_recipient_account.lamports - initial_stake_lamports;
+    let pool_fee_token_balance = get_token_balance(
+        &mut context.banks_client,
+        &stake_pool_accounts.pool_fee_account.pubkey(),
+    )
+    .await;
+    let pool_fee_tokens_received = pool_fee_token_balance - pool_fee_token_balance_before;
+
+    (
+        stake_pool_accounts,
+        stake_pool_before,
+        stake_pool,
+        tokens_to_burn,
+        withdrawn_lamports,
+        pool_fee_tokens_received,
+        user_token_balance,
+    )
+}
+
 #[tokio::test]
-async fn test_stake_pool_withdraw_with_wrong_stake_program() {
+async fn test_stake_pool_withdraw_with_fee_kept_in_pool() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
         stake_pool_accounts,
-        validator_stake_account,
-        deposit_info,
+        stake_pool_before,
+        stake_pool,
         tokens_to_burn,
-    ) = setup().await;
+        withdrawn_lamports,
+        pool_fee_tokens_received,
+        user_token_balance,
+    ) = withdraw_with_fee(0).await;
+
+    let fee = stake_pool_accounts.calculate_withdrawal_fee(tokens_to_burn);
+    assert!(fee > 0);
+
+    // only the tokens minus the fee are paid out
+    let expected_lamports = stake_pool_before
+        .calc_lamports_amount(tokens_to_burn - fee)
+        .unwrap();
+    assert_eq!(withdrawn_lamports, expected_lamports);
+    assert_eq!(
+        stake_pool.stake_total,
+        stake_pool_before.stake_total - expected_lamports
+    );
+
+    // the fee tokens are burned too and the owner gets nothing
+    assert_eq!(
+        stake_pool.pool_total,
+        stake_pool_before.pool_total - tokens_to_burn
+    );
+    assert_eq!(pool_fee_tokens_received, 0);
+
+    // remaining holders gain: their tokens are worth more
+    assert!(
+        stake_pool.calc_lamports_amount(user_token_balance).unwrap()
+            > stake_pool_before
+                .calc_lamports_amount(user_token_balance)
+                .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_stake_pool_withdraw_with_fee_to_owner() {
+    let (
+        stake_pool_accounts,
+        stake_pool_before,
+        stake_pool,
+        tokens_to_burn,
+        withdrawn_lamports,
+        pool_fee_tokens_received,
+        _,
+    ) = withdraw_with_fee(1).await;
+
+    let fee = stake_pool_accounts.calculate_withdrawal_fee(tokens_to_burn);
+    assert!(fee > 0);
+
+    let expected_lamports = stake_pool_before
+        .calc_lamports_amount(tokens_to_burn - fee)
+        .unwrap();
+    assert_eq!(withdrawn_lamports, expected_lamports);
+    assert_eq!(
+        stake_pool.stake_total,
+        stake_pool_before.stake_total - expected_lamports
+    );
+
+    // the fee tokens move to the owner and stay in circulation
+    assert_eq!(pool_fee_tokens_received, fee);
+    assert_eq!(
+        stake_pool.pool_total,
+        stake_pool_before.pool_total - (tokens_to_burn - fee)
+    );
+}
+
+#[tokio::test]
+async fn test_stake_pool_withdraw_with_wrong_stake_program() {
+    let (mut context, stake_pool_accounts, validator_stake_account, deposit_info, tokens_to_burn) =
+        setup().await;
 
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
@@ -215,16 +377,18 @@ async fn test_stake_pool_withdraw_with_wrong_stake_program() {
             &user_stake_recipient.pubkey(),
             &new_authority,
             &deposit_info.user_pool_account,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
             &stake_pool_accounts.pool_mint.pubkey(),
             &spl_token::id(),
             &wrong_stake_program.pubkey(),
             tokens_to_burn,
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -241,9 +405,7 @@ async fn test_stake_pool_withdraw_with_wrong_stake_program() {
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         mut stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -258,9 +420,9 @@ async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -285,15 +447,8 @@ async fn test_stake_pool_withdraw_with_wrong_withdraw_authority() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        validator_stake_account,
-        deposit_info,
-        tokens_to_burn,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account, deposit_info, tokens_to_burn) =
+        setup().await;
 
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
@@ -311,16 +466,18 @@ async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
             &user_stake_recipient.pubkey(),
             &new_authority,
             &deposit_info.user_pool_account,
+            &stake_pool_accounts.pool_fee_account.pubkey(),
             &stake_pool_accounts.pool_mint.pubkey(),
             &wrong_token_program.pubkey(),
             &stake::id(),
             tokens_to_burn,
         )
         .unwrap()],
-        Some(&payer.pubkey()),
+        Some(&context.payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
-    let transaction_error = banks_client
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let transaction_error = context
+        .banks_client
         .process_transaction(transaction)
         .await
         .err()
@@ -337,9 +494,7 @@ async fn test_stake_pool_withdraw_with_wrong_token_program_id() {
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
     let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
+        mut context,
         mut stake_pool_accounts,
         validator_stake_account,
         deposit_info,
@@ -354,9 +509,9 @@ async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -383,10 +538,14 @@ async fn test_stake_pool_withdraw_with_wrong_validator_stake_list() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -397,9 +556,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
     let user_stake_authority = Keypair::new();
     create_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &validator_stake_account.stake_pool,
         &validator_stake_account.stake_account,
         &validator_stake_account.vote.pubkey(),
@@ -417,9 +576,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
         withdrawer: stake_pool_accounts.deposit_authority,
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
@@ -428,9 +587,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -439,15 +598,15 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
     .unwrap();
 
     let user_pool_account = user_pool_account.pubkey();
-    let pool_tokens = get_token_balance(&mut banks_client, &user_pool_account).await;
+    let pool_tokens = get_token_balance(&mut context.banks_client, &user_pool_account).await;
 
     let tokens_to_burn = pool_tokens / 4;
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -462,9 +621,9 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &user_pool_account,
             &validator_stake_account.stake_account,
@@ -489,10 +648,14 @@ async fn test_stake_pool_withdraw_when_stake_acc_not_in_stake_state() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_from_unknown_validator() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
@@ -501,7 +664,11 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     validator_stake_account
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
     let user_stake = ValidatorStakeAccount::new_with_target_authority(
@@ -509,15 +676,19 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         &stake_pool_accounts.stake_pool.pubkey(),
     );
     user_stake
-        .create_and_delegate(&mut banks_client, &payer, &recent_blockhash)
+        .create_and_delegate(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await;
 
     let user_pool_account = Keypair::new();
     let user = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -534,9 +705,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
         withdrawer: stake_pool_accounts.deposit_authority,
     };
     create_independent_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake,
         &authorized,
         &lockup,
@@ -545,9 +716,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
     // make pool token account
     let user_pool_account = Keypair::new();
     create_token_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &stake_pool_accounts.pool_mint.pubkey(),
         &user.pubkey(),
@@ -556,15 +727,15 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
     .unwrap();
 
     let user_pool_account = user_pool_account.pubkey();
-    let pool_tokens = get_token_balance(&mut banks_client, &user_pool_account).await;
+    let pool_tokens = get_token_balance(&mut context.banks_client, &user_pool_account).await;
 
     let tokens_to_burn = pool_tokens / 4;
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_pool_account,
         &user,
         &stake_pool_accounts.withdraw_authority,
@@ -579,9 +750,9 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &user_pool_account,
             &validator_stake_account.stake_account,
@@ -606,22 +777,15 @@ async fn test_stake_pool_withdraw_from_unknown_validator() {
 
 #[tokio::test]
 async fn test_stake_pool_double_withdraw_to_the_same_account() {
-    let (
-        mut banks_client,
-        payer,
-        recent_blockhash,
-        stake_pool_accounts,
-        validator_stake_account,
-        deposit_info,
-        tokens_to_burn,
-    ) = setup().await;
+    let (mut context, stake_pool_accounts, validator_stake_account, deposit_info, tokens_to_burn) =
+        setup().await;
 
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -629,9 +793,9 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
     let new_authority = Pubkey::new_unique();
     stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -641,12 +805,12 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
         .await
         .unwrap();
 
-    let latest_blockhash = banks_client.get_recent_blockhash().await.unwrap();
+    let latest_blockhash = context.banks_client.get_recent_blockhash().await.unwrap();
 
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
+            &mut context.banks_client,
+            &context.payer,
             &latest_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
@@ -668,25 +832,23 @@ async fn test_stake_pool_double_withdraw_to_the_same_account() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
     let deposit_info: DepositInfo = simple_deposit(
-        &mut banks_client,
-        &payer, false,
-        &recent_blockhash,
+        &mut context,
+        false,
         &stake_pool_accounts,
         &validator_stake_account,
     )
@@ -697,9 +859,9 @@ async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -707,9 +869,9 @@ async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
     let new_authority = Pubkey::new_unique();
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,
@@ -736,25 +898,23 @@ async fn test_stake_pool_withdraw_token_delegate_was_not_setup() {
 
 #[tokio::test]
 async fn test_stake_pool_withdraw_with_low_delegation() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+    let mut context = program_test().start_with_context().await;
     let stake_pool_accounts = StakePoolAccounts::new();
     stake_pool_accounts
-        .initialize_stake_pool(&mut banks_client, &payer, &recent_blockhash)
+        .initialize_stake_pool(
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
+        )
         .await
         .unwrap();
 
-    let validator_stake_account: ValidatorStakeAccount = simple_add_validator_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
-        &stake_pool_accounts,
-    )
-    .await;
+    let validator_stake_account: ValidatorStakeAccount =
+        simple_add_validator_stake_account(&mut context, &stake_pool_accounts).await;
 
     let deposit_info: DepositInfo = simple_deposit(
-        &mut banks_client,
-        &payer,false,
-        &recent_blockhash,
+        &mut context,
+        false,
         &stake_pool_accounts,
         &validator_stake_account,
     )
@@ -764,9 +924,9 @@ async fn test_stake_pool_withdraw_with_low_delegation() {
 
     // Delegate tokens for burning
     delegate_tokens(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &deposit_info.user_pool_account,
         &deposit_info.user,
         &stake_pool_accounts.withdraw_authority,
@@ -777,9 +937,9 @@ async fn test_stake_pool_withdraw_with_low_delegation() {
     // Create stake account to withdraw to
     let user_stake_recipient = Keypair::new();
     create_blank_stake_account(
-        &mut banks_client,
-        &payer,
-        &recent_blockhash,
+        &mut context.banks_client,
+        &context.payer,
+        &context.last_blockhash,
         &user_stake_recipient,
     )
     .await;
@@ -787,9 +947,9 @@ async fn test_stake_pool_withdraw_with_low_delegation() {
     let new_authority = Pubkey::new_unique();
     let transaction_error = stake_pool_accounts
         .withdraw_stake(
-            &mut banks_client,
-            &payer,
-            &recent_blockhash,
+            &mut context.banks_client,
+            &context.payer,
+            &context.last_blockhash,
             &user_stake_recipient.pubkey(),
             &deposit_info.user_pool_account,
             &validator_stake_account.stake_account,